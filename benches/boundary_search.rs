@@ -0,0 +1,119 @@
+//! How fast can `ChunkBuffer` find its boundaries?
+//!
+//! Searching for the chunk boundary used to eat half our runtime, so this
+//! compares the plain window-by-window scan in `contains_slice_pos` with
+//! the `Horspool` search which `ChunkBuffer` now uses.  Horspool wins more
+//! the longer the boundary is, since it can skip further.  Single bytes,
+//! like the ASCII record separator, and short boundaries starting with a
+//! rare byte take the `memchr_swar` fast path instead, scanning a word at
+//! a time.
+//!
+//! The last pair of benchmarks splits the CoNLL-X sample into sentences
+//! with `ChunkBuffer`, scanning a byte at a time or a word at a time.
+
+extern crate test;
+extern crate streaming;
+
+use std::io::{EndOfFile,File,IoError,MemReader};
+use streaming::buffers::{Bytewise,ChunkBuffer,Horspool,Scan,SliceContains};
+use streaming::buffers::WordAtATime;
+
+use common::check_throughput;
+
+mod common;
+
+/// About 64KiB of CoNLL-ish text, with the boundary only at the very end,
+/// so each search has to look at all of it.
+fn make_haystack(boundary: &[u8]) -> Vec<u8> {
+    let line = b"12\tmot\tmot\tN\tNC\t_\t3\tobj\t_\t_\n";
+    let mut haystack = vec![];
+    while haystack.len() < 64 * 1024 { haystack.push_all(line); }
+    haystack.push_all(boundary);
+    haystack
+}
+
+fn bench_naive(b: &mut test::Bencher, name: &str, boundary: &[u8]) {
+    let haystack = make_haystack(boundary);
+    b.bytes = haystack.len() as u64;
+    b.iter(|| {
+        let found = haystack.as_slice().contains_slice_pos(boundary);
+        test::black_box(found);
+    });
+    check_throughput(b, name);
+}
+
+fn bench_horspool(b: &mut test::Bencher, name: &str, boundary: &[u8]) {
+    let haystack = make_haystack(boundary);
+    let searcher = Horspool::new(boundary);
+    b.bytes = haystack.len() as u64;
+    b.iter(|| {
+        let found = haystack.as_slice().position_with(&searcher);
+        test::black_box(found);
+    });
+    check_throughput(b, name);
+}
+
+#[bench]
+fn naive_blank_line(b: &mut test::Bencher) {
+    bench_naive(b, "naive_blank_line", b"\n\n")
+}
+
+#[bench]
+fn horspool_blank_line(b: &mut test::Bencher) {
+    bench_horspool(b, "horspool_blank_line", b"\n\n")
+}
+
+#[bench]
+fn naive_mime_boundary(b: &mut test::Bencher) {
+    bench_naive(b, "naive_mime_boundary", b"\r\n--frontier-7d1a9c\r\n")
+}
+
+#[bench]
+fn horspool_mime_boundary(b: &mut test::Bencher) {
+    bench_horspool(b, "horspool_mime_boundary",
+                   b"\r\n--frontier-7d1a9c\r\n")
+}
+
+#[bench]
+fn naive_record_separator(b: &mut test::Bencher) {
+    bench_naive(b, "naive_record_separator", b"\x1e")
+}
+
+#[bench]
+fn memchr_record_separator(b: &mut test::Bencher) {
+    bench_horspool(b, "memchr_record_separator", b"\x1e")
+}
+
+/// Split the CoNLL-X sample, repeated until it's big enough to time, into
+/// sentences, using `scan`.
+fn bench_conll_chunks(b: &mut test::Bencher, name: &str, scan: Scan) {
+    let path = Path::new("test_data/fr/sample.conllx");
+    let sample = File::open(&path).read_to_end().unwrap();
+    let mut data = vec![];
+    while data.len() < 256 * 1024 { data.push_all(sample.as_slice()); }
+    b.bytes = data.len() as u64;
+    b.iter(|| {
+        let mut reader = MemReader::new(data.clone());
+        let mut chunks = ChunkBuffer::new(&mut reader, b"\n\n")
+            .with_scan(scan.clone());
+        loop {
+            let len = match chunks.fill_buf() {
+                Ok(chunk) => chunk.len(),
+                Err(IoError{kind: EndOfFile, ..}) => { break; }
+                Err(err) => { fail!("{}", err); }
+            };
+            chunks.consume(len);
+        }
+    });
+    check_throughput(b, name);
+}
+
+#[bench]
+fn conll_chunks_bytewise(b: &mut test::Bencher) {
+    bench_conll_chunks(b, "conll_chunks_bytewise", Bytewise)
+}
+
+#[bench]
+fn conll_chunks_word_at_a_time(b: &mut test::Bencher) {
+    bench_conll_chunks(b, "conll_chunks_word_at_a_time", WordAtATime)
+}
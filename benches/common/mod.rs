@@ -0,0 +1,33 @@
+//! Helpers shared by all our benchmarks.
+
+use std::os::getenv;
+use test::Bencher;
+
+/// If the environment variable `STREAMING_MIN_MBPS_<NAME>` is set to a
+/// number, where `<NAME>` is the benchmark `name` in upper case, fail
+/// unless the benchmark we just ran managed at least that many MB/s.  This
+/// lets CI-style runs catch hot-path regressions instead of quietly
+/// getting slower:
+///
+/// ```
+/// STREAMING_MIN_MBPS_ZERO_COPY_PARSER=1000 cargo bench
+/// ```
+pub fn check_throughput(b: &mut Bencher, name: &str) {
+    let upper: String = name.chars().map(|c| c.to_uppercase()).collect();
+    let var = format!("STREAMING_MIN_MBPS_{}", upper);
+    let min_mbps: u64 = match getenv(var.as_slice()) {
+        None => { return; }
+        Some(value) => match from_str(value.as_slice()) {
+            Some(min) => min,
+            None => { fail!("{} must be an integer, not {}", var, value); }
+        }
+    };
+    let ns = b.ns_per_iter();
+    if ns == 0 { return; }
+    // Bytes per nanosecond is GB/s, so scale up to MB/s.
+    let mbps = b.bytes * 1000 / ns;
+    if mbps < min_mbps {
+        fail!("throughput regression: {} MB/s < {} MB/s (from {})",
+              mbps, min_mbps, var);
+    }
+}
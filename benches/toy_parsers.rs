@@ -16,15 +16,36 @@
 //! And if Iterator does force us to copy, is there some way to change
 //! Iterator that allows us to use zero_copy_parser without causing ugly
 //! design issues elsewhere?
+//!
+//! To keep those numbers honest as we add parsers, `parser_benchmarks!`
+//! turns any zero-copy parser into a pair of benchmarks: one for the parser
+//! itself, and one which makes an owned copy of every item via
+//! `OwnedCopy`, just like an `Iterator` would have to.  It also generates
+//! a test which times both and prints the speedup.  Every format module
+//! with a zero-copy parser gets a pair at the bottom of this file.
 
-#![feature(phase)]
+#![feature(phase, macro_rules)]
 
 #[cfg(test)] extern crate test;
 #[phase(plugin)] extern crate streaming;
 extern crate streaming;
+extern crate time;
 
+use std::cmp::max;
+use std::io::{BufReader,File,IoResult};
+use std::io::stdio::stderr_raw;
 use std::iter::range;
-use streaming::iter::StreamingIterator;
+use std::os::getenv;
+use streaming::buffers::{ChunkBuffer,ChunkIter};
+use streaming::conll;
+use streaming::csv::{Dialect,split_fields};
+use streaming::indent::IndentBlocks;
+use streaming::iter::{IterAdapter,OwnedCopy,StreamingIterator};
+use streaming::tokens::{OwnedToken,Tokens};
+
+use common::check_throughput;
+
+mod common;
 
 
 //=========================================================================
@@ -125,6 +146,7 @@ fn copying_parser(b: &mut test::Bencher) {
             test::black_box(result);
         }
     });
+    check_throughput(b, "copying_parser");
 }
 
 
@@ -171,4 +193,223 @@ fn zero_copy_parser(b: &mut test::Bencher) {
             test::black_box(line);
         });
     });
+    check_throughput(b, "zero_copy_parser");
+}
+
+
+//=========================================================================
+// Paired benchmarks
+
+/// How long does `f` take per call, in nanoseconds?  Runs it for at least
+/// 100ms to smooth out the noise.
+pub fn ns_per_call(f: ||) -> u64 {
+    let start = time::precise_time_ns();
+    let mut calls = 0u64;
+    loop {
+        f();
+        calls += 1;
+        let elapsed = time::precise_time_ns() - start;
+        if elapsed >= 100_000_000 && calls >= 10 { return elapsed / calls; }
+    }
+}
+
+/// Print how much faster the zero-copy version of parser `name` was, and
+/// fail if it was slower than `STREAMING_MIN_SPEEDUP` times the copying
+/// version.  We write straight to stderr, because the test harness
+/// swallows ordinary output from passing tests.
+pub fn report_speedup(name: &str, bytes: u64, copying_ns: u64,
+                      zero_copy_ns: u64) {
+    let mbps = |ns: u64| bytes * 1000 / max(ns, 1);
+    let speedup = copying_ns as f64 / max(zero_copy_ns, 1) as f64;
+    let _ = writeln!(stderr_raw(),
+                     "{}: copying {} MB/s, zero-copy {} MB/s, {:.1}x faster",
+                     name, mbps(copying_ns), mbps(zero_copy_ns), speedup);
+    let min = getenv("STREAMING_MIN_SPEEDUP").and_then(|value| {
+        from_str::<f64>(value.as_slice())
+    });
+    match min {
+        Some(min) if speedup < min => {
+            fail!("{}: zero-copy speedup {:.1}x < {:.1}x", name, speedup, min);
+        }
+        _ => {}
+    }
+}
+
+/// Generate benchmarks comparing a zero-copy parser against a copying
+/// version of itself.  For example:
+///
+/// ```
+/// parser_benchmarks!(zero_copy_bridged, copying_bridged, toy_speedup,
+///                    make_pretend_file(), (String, String, String),
+///                    |file| {
+///                        let mut reader = BufferedReader::new(file);
+///                        let mut parser = ZeroCopyParser::new(&mut reader);
+///                    } => parser)
+/// ```
+///
+/// The first three names are for the zero-copy benchmark, the copying
+/// benchmark and the speedup test.  Then comes an expression producing the
+/// input, which must have an `as_slice` method, and the owned type of the
+/// parser's items.  Finally, some statements which set up a parser reading
+/// from `file`, and the name of the parser.  The copying version makes an
+/// owned copy of each item, so its items must implement `OwnedCopy`.
+/// (`IoResult`s of such items do.)  Both benchmarks check their throughput
+/// against `STREAMING_MIN_MBPS_<NAME>`, as `check_throughput` describes.
+macro_rules! parser_benchmarks {
+    ($zero_copy:ident, $copying:ident, $speedup:ident, $input:expr,
+     $owned:ty, |$file:ident| { $($setup:stmt;)+ } => $parser:ident) => {
+        #[bench]
+        fn $zero_copy(b: &mut test::Bencher) {
+            let input = $input;
+            b.bytes = input.len() as u64;
+            b.iter(|| {
+                let $file = input.as_slice();
+                $($setup;)+
+                streaming_for!(item in $parser, { test::black_box(item); });
+            });
+            check_throughput(b, stringify!($zero_copy));
+        }
+
+        #[bench]
+        fn $copying(b: &mut test::Bencher) {
+            let input = $input;
+            b.bytes = input.len() as u64;
+            b.iter(|| {
+                let $file = input.as_slice();
+                $($setup;)+
+                streaming_for!(item in $parser, {
+                    let copy: $owned = item.owned_copy();
+                    test::black_box(copy);
+                });
+            });
+            check_throughput(b, stringify!($copying));
+        }
+
+        #[test]
+        fn $speedup() {
+            let input = $input;
+            let zero_copy = ns_per_call(|| {
+                let $file = input.as_slice();
+                $($setup;)+
+                streaming_for!(item in $parser, { test::black_box(item); });
+            });
+            let copying = ns_per_call(|| {
+                let $file = input.as_slice();
+                $($setup;)+
+                streaming_for!(item in $parser, {
+                    let copy: $owned = item.owned_copy();
+                    test::black_box(copy);
+                });
+            });
+            report_speedup(stringify!($speedup), input.len() as u64, copying,
+                           zero_copy);
+        }
+    }
+}
+
+// `ZeroCopyParser` again, but with the copying version generated for us.
+parser_benchmarks!(zero_copy_bridged, copying_bridged, toy_parser_speedup,
+                   make_pretend_file(), (String, String, String),
+                   |file| {
+                       let mut reader = BufferedReader::new(file);
+                       let mut parser = ZeroCopyParser::new(&mut reader);
+                   } => parser)
+
+
+//=========================================================================
+// Format modules
+
+/// Read one of our test files.
+pub fn read_test_data(path: &str) -> Vec<u8> {
+    File::open(&Path::new(path)).read_to_end().unwrap()
 }
+
+/// Some CSV data with quoted fields, but no newlines inside them.
+pub fn make_csv_file() -> Vec<u8> {
+    let mut result = vec![];
+    for i in range(0u, 200) {
+        let line = format!("{},\"Smith, J.\",\"say \"\"hi\"\"\"\n", i);
+        result.push_all(line.as_bytes());
+    }
+    result
+}
+
+/// Some source code for the lexer.
+pub fn make_source_file() -> Vec<u8> {
+    let mut result = vec![];
+    for _ in range(0u, 200) {
+        result.push_all(b"let total = add(12.5, \"items\");\n");
+    }
+    result
+}
+
+/// Some outline-style data for `IndentBlocks`.
+pub fn make_outline_file() -> Vec<u8> {
+    let mut result = vec![];
+    for _ in range(0u, 100) {
+        result.push_all(b"section\n  first item\n  second item\n");
+    }
+    result
+}
+
+/// The first two fields of each CSV record, split out of the `ChunkBuffer`
+/// chunk holding its line.
+pub struct CsvFields<'b, 'a: 'b> {
+    chunks: ChunkIter<'b, 'a, BufReader<'a>>,
+    spans: Vec<(uint, uint)>
+}
+
+impl<'b, 'a> CsvFields<'b, 'a> {
+    pub fn new(chunks: ChunkIter<'b, 'a, BufReader<'a>>) -> CsvFields<'b, 'a> {
+        CsvFields{chunks: chunks, spans: vec![]}
+    }
+}
+
+impl<'c, 'b, 'a> StreamingIterator<'c, (&'c [u8], &'c [u8])>
+    for CsvFields<'b, 'a> {
+
+    fn next(&'c mut self) -> Option<(&'c [u8], &'c [u8])> {
+        match self.chunks.next() {
+            None => None,
+            Some(Err(err)) => { fail!("{}", err); }
+            Some(Ok(chunk)) => {
+                let record = chunk.data;
+                split_fields(record, &Dialect::csv(), &mut self.spans);
+                let ((s0, e0), (s1, e1)) = (self.spans[0], self.spans[1]);
+                Some((record.slice(s0, e0), record.slice(s1, e1)))
+            }
+        }
+    }
+}
+
+parser_benchmarks!(zero_copy_csv, copying_csv, csv_speedup,
+                   make_csv_file(), (Vec<u8>, Vec<u8>),
+                   |file| {
+                       let mut reader = BufReader::new(file);
+                       let mut lines = ChunkBuffer::new(&mut reader, b"\n")
+                           .with_strip_boundary(true);
+                       let mut parser = CsvFields::new(lines.chunks());
+                   } => parser)
+
+parser_benchmarks!(zero_copy_conll, copying_conll, conll_speedup,
+                   read_test_data("test_data/fr/sample.conllx"),
+                   IoResult<(Vec<u8>, Vec<u8>)>,
+                   |file| {
+                       let forms = conll::Tokens::new(file)
+                           .map(|t| t.map(|t| (t.form, t.cpostag)));
+                       let mut parser = IterAdapter::new(forms);
+                   } => parser)
+
+parser_benchmarks!(zero_copy_tokens, copying_tokens, tokens_speedup,
+                   make_source_file(), IoResult<OwnedToken>,
+                   |file| {
+                       let mut reader = BufReader::new(file);
+                       let mut parser = Tokens::new(&mut reader);
+                   } => parser)
+
+parser_benchmarks!(zero_copy_indent, copying_indent, indent_speedup,
+                   make_outline_file(), IoResult<Vec<u8>>,
+                   |file| {
+                       let mut reader = BufReader::new(file);
+                       let mut parser = IndentBlocks::new(&mut reader);
+                   } => parser)
@@ -0,0 +1,234 @@
+//! Files where each record is compressed on its own.
+//!
+//! Compressing a whole file gives the best ratio, but then you have to
+//! decompress everything before the record you want.  Archives which need
+//! random access compress each record separately instead:
+//!
+//! ```text
+//! [length: u32, little-endian] [zlib-compressed record: length bytes]
+//! [length: u32, little-endian] [zlib-compressed record: length bytes]
+//! ...
+//! ```
+//!
+//! `BlockReader` walks through the blocks, yielding a `Block` for each one
+//! which knows its index and offset.  Nothing is decompressed, or even
+//! read, until you call `Block::data`, so filtering on index or offset is
+//! nearly free: skipped blocks are simply seeked over.  If you already
+//! know where a block starts (from an external index, say), `seek` jumps
+//! straight there.
+
+use std::io::{Buffer,BufReader,EndOfFile,IoError,IoResult,SeekCur,SeekSet};
+
+use deflate::DeflateBuffer;
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::{File,MemReader,MemWriter};
+#[cfg(test)] use deflate::adler32;
+
+/// Reads individually-compressed blocks from a seekable input.
+pub struct BlockReader<R> {
+    input: R,
+    index: u64,
+    offset: u64,
+    // Bytes of the current block's body which we haven't read yet.
+    unread: u64,
+    compressed: Vec<u8>,
+    data: Vec<u8>,
+    // How decompressing the current block into `data` went, or `None` if
+    // we haven't tried yet.  We keep errors, so that a block which fails
+    // halfway never looks like it worked.
+    decoded: Option<IoResult<()>>
+}
+
+impl<R: Reader+Seek> BlockReader<R> {
+    /// Read blocks from `input`, which should be positioned at the start
+    /// of a block.
+    pub fn new(input: R) -> BlockReader<R> {
+        BlockReader{input: input, index: 0, offset: 0, unread: 0,
+                    compressed: vec![], data: vec![], decoded: None}
+    }
+
+    /// Jump to the block starting at byte `offset`, which is block number
+    /// `index`.  The next call to `next` will return that block.
+    pub fn seek(&mut self, offset: u64, index: u64) -> IoResult<()> {
+        try!(self.input.seek(offset as i64, SeekSet));
+        self.offset = offset;
+        self.index = index;
+        self.unread = 0;
+        self.decoded = None;
+        Ok(())
+    }
+
+    /// Get back our underlying input.
+    pub fn unwrap(self) -> R { self.input }
+
+    // Read the rest of the current block, and decompress it into `data`.
+    fn decode(&mut self) -> IoResult<()> {
+        self.compressed.clear();
+        let len = self.unread as uint;
+        try!(self.input.push_at_least(len, len, &mut self.compressed));
+        self.unread = 0;
+        let compressed = BufReader::new(self.compressed.as_slice());
+        let mut zlib = DeflateBuffer::zlib(compressed);
+        loop {
+            let len = match zlib.fill_buf() {
+                Ok(buf) => { self.data.push_all(buf); buf.len() }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(()); }
+                Err(err) => { return Err(err); }
+            };
+            zlib.consume(len);
+        }
+    }
+}
+
+impl<'a, R: Reader+Seek> StreamingIterator<'a, IoResult<Block<'a, R>>>
+    for BlockReader<R> {
+
+    fn next(&'a mut self) -> Option<IoResult<Block<'a, R>>> {
+        // Skip over whatever the caller didn't read of the last block.
+        if self.unread > 0 {
+            match self.input.seek(self.unread as i64, SeekCur) {
+                Ok(()) => { self.unread = 0; }
+                Err(err) => { return Some(Err(err)); }
+            }
+        }
+        let len = match self.input.read_le_u32() {
+            Ok(len) => len,
+            Err(IoError{kind: EndOfFile, ..}) => { return None; }
+            Err(err) => { return Some(Err(err)); }
+        };
+        let block = Block{index: self.index, offset: self.offset, len: len,
+                          reader: self};
+        block.reader.index += 1;
+        block.reader.offset += 4 + len as u64;
+        block.reader.unread = len as u64;
+        block.reader.decoded = None;
+        Some(Ok(block))
+    }
+}
+
+/// A single compressed block.  Call `data` to decompress it.
+pub struct Block<'a, R: 'a> {
+    reader: &'a mut BlockReader<R>,
+    index: u64,
+    offset: u64,
+    len: u32
+}
+
+impl<'a, R: Reader+Seek> Block<'a, R> {
+    /// This block's position in the file, counting from 0.
+    pub fn index(&self) -> u64 { self.index }
+
+    /// The byte offset of this block's header.  Pass this to
+    /// `BlockReader::seek` to come back here later.
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// The size of this block's compressed data, in bytes.
+    pub fn compressed_len(&self) -> u32 { self.len }
+
+    /// Read and decompress this block.  The buffers are reused from block
+    /// to block, so this doesn't allocate once they've warmed up.  If this
+    /// fails, it keeps failing for this block.
+    pub fn data<'b>(&'b mut self) -> IoResult<&'b [u8]> {
+        let reader = &mut *self.reader;
+        if reader.decoded.is_none() {
+            reader.data.clear();
+            let result = reader.decode();
+            if result.is_err() { reader.data.clear(); }
+            reader.decoded = Some(result);
+        }
+        match reader.decoded {
+            Some(Err(ref err)) => Err(err.clone()),
+            _ => Ok(reader.data.as_slice())
+        }
+    }
+}
+
+#[cfg(test)]
+fn sample_blocks() -> BlockReader<MemReader> {
+    let path = Path::new("test_data/fr/sample.blocks");
+    BlockReader::new(MemReader::new(File::open(&path).read_to_end().unwrap()))
+}
+
+#[test]
+fn reads_every_block() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let mut blocks = sample_blocks();
+    let mut all = vec![];
+    streaming_for!(block in blocks, {
+        let mut block = block.unwrap();
+        all.push_all(block.data().unwrap())
+    });
+    assert_eq!(expected, all);
+}
+
+#[test]
+fn decompresses_only_selected_blocks() {
+    let mut blocks = sample_blocks();
+    let mut offset = None;
+    streaming_for!(block in blocks, {
+        let mut block = block.unwrap();
+        if block.index() == 2 {
+            offset = Some(block.offset());
+            let data = block.data().unwrap();
+            assert!(data.starts_with(b"1\t"));
+            assert!(data.ends_with(b"\n\n"));
+        }
+    });
+    blocks.seek(offset.unwrap(), 2).unwrap();
+    let mut block = blocks.next().unwrap().unwrap();
+    assert_eq!(2, block.index());
+    assert!(block.data().unwrap().starts_with(b"1\t"));
+}
+
+// Build a block file from already-compressed `bodies`.
+#[cfg(test)]
+fn block_file(bodies: &[Vec<u8>]) -> BlockReader<MemReader> {
+    let mut out = MemWriter::new();
+    for body in bodies.iter() {
+        out.write_le_u32(body.len() as u32).unwrap();
+        out.write(body.as_slice()).unwrap();
+    }
+    BlockReader::new(MemReader::new(out.unwrap()))
+}
+
+// Wrap `data` in a zlib stream without compressing it.
+#[cfg(test)]
+fn stored_zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = MemWriter::new();
+    out.write(&[0x78, 0x01, 0x01]).unwrap();
+    out.write_le_u16(data.len() as u16).unwrap();
+    out.write_le_u16(!(data.len() as u16)).unwrap();
+    out.write(data).unwrap();
+    out.write_be_u32(adler32(1, data)).unwrap();
+    out.unwrap()
+}
+
+#[test]
+fn empty_block_does_not_return_the_last_one() {
+    let mut blocks = block_file(&[stored_zlib(b"first"), vec![]]);
+    {
+        let mut block = blocks.next().unwrap().unwrap();
+        assert_eq!(b"first".to_vec(), block.data().unwrap().to_vec());
+    }
+    let mut block = blocks.next().unwrap().unwrap();
+    // An empty body isn't even a zlib header.
+    assert!(block.data().is_err());
+}
+
+#[test]
+fn failed_block_stays_failed() {
+    let mut bad = stored_zlib(b"almost");
+    let last = bad.len() - 1;
+    bad.as_mut_slice()[last] ^= 0xff;
+    let mut blocks = block_file(&[bad, stored_zlib(b"fine")]);
+    {
+        let mut block = blocks.next().unwrap().unwrap();
+        // The checksum only fails after we've decompressed everything.
+        assert!(block.data().is_err());
+        assert!(block.data().is_err());
+    }
+    let mut block = blocks.next().unwrap().unwrap();
+    assert_eq!(b"fine".to_vec(), block.data().unwrap().to_vec());
+}
@@ -1,12 +1,14 @@
 //! Custom buffer support.
-//!
-//! WARNING: Don't believe the 'boundary' parameter.  It's a lie.
 
-use std::cmp::min;
+use std::cmp::{max,min};
 use std::iter::range;
-use std::io::{Buffer,EndOfFile,IoError,IoResult};
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError};
 use std::mem::transmute;
+use std::num::Int;
 use std::rand::{Rng,task_rng};
+use std::uint;
+
+use iter::StreamingIterator;
 
 #[cfg(test)] use std::io::{File,MemReader};
 #[cfg(test)] use std::str::from_utf8;
@@ -16,8 +18,12 @@ use std::rand::{Rng,task_rng};
 pub trait SliceContains {
     /// Does `needle` appear in this buffer?
     fn contains_slice(&self, needle: &[u8]) -> bool;
-    /// At what location does `needle` appear in this buffer?
+    /// Where does `needle` first appear in this buffer?
     fn contains_slice_pos(&self, needle: &[u8]) -> Option<uint>;
+    /// Where does the needle of `searcher` first appear in this buffer?
+    /// Faster than `contains_slice_pos` when searching for the same
+    /// needle many times.
+    fn position_with(&self, searcher: &Horspool) -> Option<uint>;
 }
 
 impl<'a> SliceContains for &'a [u8] {
@@ -26,42 +32,390 @@ impl<'a> SliceContains for &'a [u8] {
         self.contains_slice_pos(needle).is_some()
     }
 
-    // XXX - Ignores _needle for now, hardcoded for speed.
     #[inline(never)]
-    fn contains_slice_pos(&self, _needle: &[u8]) -> Option<uint> {
-        // This will burn 50% of our total program execution time if we let
-        // it.
-        //self.windows(needle.len()).position(|w| w == needle)
-        //if self.len() < needle.len() { return None; }
-        //'outer: for i in range(0, self.len()-(needle.len()+1)) {
-        //    if self[i] == needle[0] {
-        //        for j in range(0, needle.len()) {
-        //            if self[i+j] != needle[j] { continue 'outer; }
-        //        }
-        //        return Some(i);
-        //    }
-        //}
-        //return None;
-
-        // XXX - Hardcoded for performance.
-        if self.len() < 2 { return None; }
-        for i in range(0, self.len()-1) {
-            if self[i] == 10 && self[i+1] == 10 { return Some(i); }
+    fn contains_slice_pos(&self, needle: &[u8]) -> Option<uint> {
+        if needle.is_empty() { return Some(0); }
+        if self.len() < needle.len() { return None; }
+        let first = needle[0];
+        for i in range(0, self.len() - needle.len() + 1) {
+            if self[i] == first && self.slice(i, i + needle.len()) == needle {
+                return Some(i);
+            }
         }
         None
     }
+
+    #[inline(never)]
+    fn position_with(&self, searcher: &Horspool) -> Option<uint> {
+        searcher.find(*self)
+    }
+}
+
+/// How to scan for a single byte.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Scan {
+    /// Look at one byte at a time.
+    Bytewise,
+    /// Look at eight bytes at a time, using word-sized arithmetic ("SIMD
+    /// within a register").  Faster when the byte is rare, so this is the
+    /// default.
+    WordAtATime
+}
+
+/// Find the first `byte` in `haystack`, one byte at a time.  See
+/// `memchr_swar` for a faster version.
+pub fn memchr(byte: u8, haystack: &[u8]) -> Option<uint> {
+    haystack.iter().position(|&b| b == byte)
+}
+
+static LOW_BITS: u64 = 0x0101010101010101;
+static HIGH_BITS: u64 = 0x8080808080808080;
+
+/// Find the first `byte` in `haystack`, eight bytes at a time.  Returns
+/// the same answer as `memchr`.
+pub fn memchr_swar(byte: u8, haystack: &[u8]) -> Option<uint> {
+    let repeated = byte as u64 * LOW_BITS;
+    let mut i = 0;
+    while i + 8 <= haystack.len() {
+        // Assemble a little-endian word, so the first byte is the lowest.
+        let mut word = 0u64;
+        for (j, &b) in haystack.slice(i, i + 8).iter().enumerate() {
+            word |= (b as u64) << (8 * j);
+        }
+        // Bytes equal to `byte` become zero, and the classic trick below
+        // sets the high bit of the first zero byte.  Later high bits may
+        // be false positives, but we only want the first.
+        let x = word ^ repeated;
+        let found = (x - LOW_BITS) & !x & HIGH_BITS;
+        if found != 0 {
+            return Some(i + (found.trailing_zeros() / 8) as uint);
+        }
+        i += 8;
+    }
+    memchr(byte, haystack.slice_from(i)).map(|pos| i + pos)
+}
+
+// Bytes which are likely to be common in text.  If the first byte of a
+// needle isn't one of these, we expect few false candidates.
+fn is_common(b: u8) -> bool {
+    (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') ||
+        (b >= b'0' && b <= b'9') || b == b' '
+}
+
+/// A Boyer-Moore-Horspool substring search.  We build a table, once, of
+/// how far we can skip ahead when a window doesn't match, based on its
+/// last byte, which lets us step over most of the haystack without
+/// looking at it.
+///
+/// Short needles can't skip far, so if the needle is a single byte, or a
+/// few bytes starting with one which is rare in text, like `\n`, we use
+/// `memchr_swar` to jump straight to each occurrence of the first byte,
+/// and check the rest there.
+#[deriving(Clone)]
+pub struct Horspool {
+    needle: Vec<u8>,
+    skip: Vec<uint>,
+    use_memchr: bool,
+    scan: Scan
+}
+
+impl Horspool {
+    /// Prepare to search for `needle`, which must not be empty.
+    pub fn new(needle: &[u8]) -> Horspool {
+        assert!(needle.len() > 0);
+        let last = needle.len() - 1;
+        let mut skip = Vec::from_elem(256, needle.len());
+        for (i, &b) in needle.slice_to(last).iter().enumerate() {
+            skip.as_mut_slice()[b as uint] = last - i;
+        }
+        let use_memchr =
+            needle.len() == 1 || (needle.len() <= 4 && !is_common(needle[0]));
+        Horspool{needle: needle.to_vec(), skip: skip, use_memchr: use_memchr,
+                 scan: WordAtATime}
+    }
+
+    /// Use `scan` to look for the first byte of short needles.
+    pub fn with_scan(mut self, scan: Scan) -> Horspool {
+        self.scan = scan;
+        self
+    }
+
+    /// The needle we're searching for.
+    pub fn needle(&self) -> &[u8] { self.needle.as_slice() }
+
+    /// Where does our needle first appear in `haystack`?
+    pub fn find(&self, haystack: &[u8]) -> Option<uint> {
+        if self.use_memchr { return self.find_by_first_byte(haystack); }
+        let len = self.needle.len();
+        let last = self.needle[len - 1];
+        let mut i = 0;
+        while i + len <= haystack.len() {
+            let b = haystack[i + len - 1];
+            if b == last && haystack.slice(i, i + len) == self.needle() {
+                return Some(i);
+            }
+            i += self.skip[b as uint];
+        }
+        None
+    }
+
+    fn find_by_first_byte(&self, haystack: &[u8]) -> Option<uint> {
+        let len = self.needle.len();
+        let mut start = 0;
+        loop {
+            let rest = haystack.slice_from(start);
+            let found = match self.scan {
+                Bytewise => memchr(self.needle[0], rest),
+                WordAtATime => memchr_swar(self.needle[0], rest)
+            };
+            let pos = match found {
+                None => { return None; }
+                Some(i) => start + i
+            };
+            if pos + len > haystack.len() { return None; }
+            if haystack.slice(pos, pos + len) == self.needle() {
+                return Some(pos);
+            }
+            start = pos + 1;
+        }
+    }
+}
+
+/// A set of alternative boundaries, any of which can end a chunk.
+#[deriving(Clone)]
+pub struct Boundaries {
+    searchers: Vec<Horspool>
+}
+
+impl Boundaries {
+    /// Prepare to search for any of `boundaries`.  There must be at least
+    /// one, and none may be empty.
+    pub fn new(boundaries: &[&[u8]]) -> Boundaries {
+        assert!(boundaries.len() > 0);
+        Boundaries{searchers: boundaries.iter()
+                   .map(|b| Horspool::new(*b)).collect()}
+    }
+
+    /// Use `scan` to look for the first byte of short boundaries.
+    pub fn with_scan(self, scan: Scan) -> Boundaries {
+        Boundaries{searchers: self.searchers.into_iter()
+                   .map(|s| s.with_scan(scan.clone())).collect()}
+    }
+
+    /// Boundary number `index`.
+    pub fn get(&self, index: uint) -> &[u8] {
+        self.searchers[index].needle()
+    }
+
+    /// The length of the longest boundary.
+    pub fn max_len(&self) -> uint {
+        self.searchers.iter().map(|s| s.needle().len()).max().unwrap()
+    }
+
+    /// Find the earliest boundary in `haystack`, and return its position
+    /// and index.  If several start at the same place, we pick the
+    /// longest, so `\r\n\r\n` beats `\r\n`.
+    pub fn find(&self, haystack: &[u8]) -> Option<(uint, uint)> {
+        let mut best: Option<(uint, uint)> = None;
+        for (i, searcher) in self.searchers.iter().enumerate() {
+            // Nothing starting after the best match so far can beat it.
+            let limit = match best {
+                Some((pos, _)) => min(haystack.len(), pos + self.max_len()),
+                None => haystack.len()
+            };
+            match searcher.find(haystack.slice_to(limit)) {
+                Some(pos) => {
+                    let better = match best {
+                        None => true,
+                        Some((best_pos, j)) => {
+                            pos < best_pos || (pos == best_pos &&
+                                searcher.needle().len() > self.get(j).len())
+                        }
+                    };
+                    if better { best = Some((pos, i)); }
+                }
+                None => {}
+            }
+        }
+        best
+    }
+
+    /// Like `find`, but only once more data couldn't change the answer: a
+    /// longer boundary, like `\r\n\r\n` instead of `\r\n`, might still
+    /// match at or before the one we found, if it runs off the end of
+    /// `haystack`.  Unless `haystack` ends the input, `None` means we need
+    /// to read more.
+    pub fn find_complete(&self, haystack: &[u8]) -> Option<(uint, uint)> {
+        let (pos, i) = match self.find(haystack) {
+            Some(found) => found,
+            None => { return None; }
+        };
+        for searcher in self.searchers.iter() {
+            let needle = searcher.needle();
+            let start = max(haystack.len() + 1, needle.len()) - needle.len();
+            for p in range(start, pos + 1) {
+                if needle.starts_with(haystack.slice_from(p)) { return None; }
+            }
+        }
+        Some((pos, i))
+    }
+}
+
+// How a `ChunkBuffer` finds the end of a chunk.
+enum Splitter<'a> {
+    Fixed(Boundaries),
+    // The window size, and a function which returns the length of the
+    // boundary at the start of a window, if there is one.
+    Predicate(uint, |&[u8]|: 'a -> Option<uint>)
+}
+
+impl<'a> Splitter<'a> {
+    // Find the first boundary in `haystack`, and return its position,
+    // length and index.
+    fn find(&mut self, haystack: &[u8]) -> Option<(uint, uint, uint)> {
+        match *self {
+            Fixed(ref boundaries) => {
+                boundaries.find(haystack)
+                    .map(|(pos, i)| (pos, boundaries.get(i).len(), i))
+            }
+            Predicate(window, ref mut predicate) => {
+                if haystack.len() < window { return None; }
+                for pos in range(0, haystack.len() - window + 1) {
+                    match (*predicate)(haystack.slice(pos, pos + window)) {
+                        Some(len) => {
+                            assert!(len > 0 && len <= window);
+                            return Some((pos, len, 0));
+                        }
+                        None => {}
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    // Like `find`, but only if more data couldn't change the answer (see
+    // `Boundaries::find_complete`).  A predicate always sees a whole window.
+    fn find_complete(&mut self, haystack: &[u8])
+                     -> Option<(uint, uint, uint)> {
+        match *self {
+            Fixed(ref boundaries) => {
+                boundaries.find_complete(haystack)
+                    .map(|(pos, i)| (pos, boundaries.get(i).len(), i))
+            }
+            Predicate(..) => self.find(haystack)
+        }
+    }
+
+    // How many bytes we need to see to recognize any boundary.
+    fn window(&self) -> uint {
+        match *self {
+            Fixed(ref boundaries) => boundaries.max_len(),
+            Predicate(window, _) => window
+        }
+    }
+}
+
+/// Append bytes from `input` to `out` up to and including the next
+/// `delim`, without allocating a new buffer for each line.  Returns
+/// `false` if we were already at the end of `input`.
+pub fn read_until_into<Sized? T: Buffer>(input: &mut T, delim: u8,
+                                         out: &mut Vec<u8>)
+                                         -> IoResult<bool> {
+    let mut read_any = false;
+    loop {
+        let (consumed, done) = {
+            match input.fill_buf() {
+                Ok(read) => {
+                    match read.iter().position(|&b| b == delim) {
+                        Some(pos) => {
+                            out.push_all(read[..pos+1]);
+                            (pos + 1, true)
+                        }
+                        None => {
+                            out.push_all(read);
+                            (read.len(), false)
+                        }
+                    }
+                }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(read_any); }
+                Err(err) => { return Err(err); }
+            }
+        };
+        input.consume(consumed);
+        if consumed > 0 { read_any = true; }
+        if done { return Ok(true); }
+    }
+}
+
+/// How an internal buffer grows when it needs more room.  Buffers which
+/// accept a policy also report their high-water mark, so operators of
+/// long-running pipelines can see (and bound) the worst-case memory used
+/// per stream.
+#[deriving(PartialEq, Clone, Show)]
+pub enum GrowthPolicy {
+    /// Double the capacity each time.  This is the default.
+    Double,
+    /// Grow by a fixed number of bytes at a time, which wastes less memory
+    /// on huge records, at the price of more copying.
+    Increment(uint),
+    /// Double, but never hold more than this many bytes.  Input which
+    /// needs more is an error.
+    Capped(uint)
+}
+
+impl GrowthPolicy {
+    /// Make room in `buf` for `additional` more bytes.
+    pub fn reserve(&self, buf: &mut Vec<u8>, additional: uint)
+                   -> IoResult<()> {
+        let needed = buf.len() + additional;
+        let capacity = buf.capacity();
+        if needed <= capacity { return Ok(()); }
+        let target = match *self {
+            Double => max(needed, capacity * 2),
+            Increment(step) => {
+                assert!(step > 0);
+                let steps = (needed - capacity + step - 1) / step;
+                capacity + steps * step
+            }
+            Capped(limit) => {
+                if needed > limit {
+                    return Err(IoError{kind: OtherIoError,
+                                       desc: "buffer limit exceeded",
+                                       detail: Some(format!("{} > {} bytes",
+                                                            needed, limit))});
+                }
+                min(limit, max(needed, capacity * 2))
+            }
+        };
+        buf.reserve_exact(target);
+        Ok(())
+    }
+}
+
+/// Append `data` to `buf`, growing it according to `policy`, and keep
+/// track of the largest capacity we've seen in `high_water`.
+pub fn push_growing(buf: &mut Vec<u8>, policy: &GrowthPolicy,
+                    high_water: &mut uint, data: &[u8]) -> IoResult<()> {
+    try!(policy.reserve(buf, data.len()));
+    buf.push_all(data);
+    *high_water = max(*high_water, buf.capacity());
+    Ok(())
 }
 
 /// Used for testing other buffers.  Dribbles bytes through in small,
 /// random increments.
 pub struct DribbleBuffer<'a, T: Buffer+'a> {
-    input: &'a mut T
+    input: &'a mut T,
+    // How much `fill_buf` returns until the next `consume`, so that asking
+    // twice gets you the same data, as with any other buffer.
+    limit: Option<uint>
 }
 
 impl<'a,T: Buffer+'a> DribbleBuffer<'a, T> {
     /// Create a new wrapper around `input`.
     pub fn new(input: &'a mut T) -> DribbleBuffer<'a, T> {
-        DribbleBuffer{input: input}
+        DribbleBuffer{input: input, limit: None}
     }
 }
 
@@ -74,11 +428,19 @@ impl<'a,T: Buffer+'a> Reader for DribbleBuffer<'a,T> {
 impl<'a,T: Buffer+'a> Buffer for DribbleBuffer<'a,T> {
     fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
         let original = try!(self.input.fill_buf());
-        let limit = task_rng().gen::<uint>() % 6;
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => {
+                let limit = task_rng().gen::<uint>() % 6;
+                self.limit = Some(limit);
+                limit
+            }
+        };
         Ok(original[..min(original.len(), limit)])
     }
 
     fn consume(&mut self, amt: uint) {
+        self.limit = None;
         self.input.consume(amt)
     }
 }
@@ -99,62 +461,169 @@ fn dribble_buffer_read_to_string() {
     assert_eq!(from_utf8(data.as_slice()).unwrap(), via_buffer.as_slice());
 }
 
-/// A buffer which breaks chunks only after the specified boundary
-/// sequence, or at the end of a file, but nowhere else.
+/// A buffer which breaks chunks only after one of the specified boundary
+/// sequences, or at the end of a file, but nowhere else.
 pub struct ChunkBuffer<'a, T: Buffer+'a> {
     input:  &'a mut T,
-    boundary: Vec<u8>,
-    buffer: Vec<u8>
+    boundary: Splitter<'a>,
+    buffer: Vec<u8>,
+    growth: GrowthPolicy,
+    high_water: uint,
+    max_chunk: Option<uint>,
+    strip: bool,
+    // The position, length and index of the first boundary in the data
+    // `fill_any` last returned, so `consume` can skip a stripped boundary.
+    found: Option<(uint, uint, uint)>
 }
 
 impl<'a, T: Buffer+'a> ChunkBuffer<'a,T> {
     /// Create a new `ChunkBuffer` wrapping `input` and breaking at
-    /// `boundary`.
+    /// `boundary`, which must not be empty.
     pub fn new(input: &'a mut T, boundary: &[u8]) -> ChunkBuffer<'a,T> {
-        ChunkBuffer{input: input, boundary: boundary.to_vec(),
-                    buffer: vec![]}
+        ChunkBuffer::with_boundaries(input, &[boundary])
+    }
+
+    /// Create a new `ChunkBuffer` wrapping `input` and breaking at
+    /// whichever of `boundaries` comes first, such as `\r\n\r\n` or
+    /// `\n\n` for data with mixed line endings.
+    pub fn with_boundaries(input: &'a mut T, boundaries: &[&[u8]])
+                           -> ChunkBuffer<'a,T> {
+        ChunkBuffer::with_splitter(input, Fixed(Boundaries::new(boundaries)))
+    }
+
+    /// Create a new `ChunkBuffer` wrapping `input`, and breaking wherever
+    /// `predicate` says so.  `predicate` is called on each `window`-byte
+    /// slice of the input in turn, and returns the length of the boundary
+    /// at the start of the slice, if there is one.  Boundaries can't be
+    /// empty, but the rest of the window is lookahead, which lets us split
+    /// FASTA data before each `>` line:
+    ///
+    /// ```ignore
+    /// ChunkBuffer::with_predicate(&mut input, 2, |w| {
+    ///     if w == b"\n>" { Some(1) } else { None }
+    /// })
+    /// ```
+    pub fn with_predicate(input: &'a mut T, window: uint,
+                          predicate: |&[u8]|: 'a -> Option<uint>)
+                          -> ChunkBuffer<'a,T> {
+        assert!(window > 0);
+        ChunkBuffer::with_splitter(input, Predicate(window, predicate))
+    }
+
+    fn with_splitter(input: &'a mut T, splitter: Splitter<'a>)
+                     -> ChunkBuffer<'a,T> {
+        ChunkBuffer{input: input, boundary: splitter, buffer: vec![],
+                    growth: Double, high_water: 0, max_chunk: None,
+                    strip: false, found: None}
+    }
+
+    /// Grow our internal buffer according to `policy`.
+    pub fn with_growth(mut self, policy: GrowthPolicy) -> ChunkBuffer<'a,T> {
+        self.growth = policy;
+        self
+    }
+
+    /// Scan for short boundaries, like the default blank line, using
+    /// `scan`.  This has no effect on a predicate.
+    pub fn with_scan(mut self, scan: Scan) -> ChunkBuffer<'a,T> {
+        match self.boundary {
+            Fixed(ref mut boundaries) => {
+                *boundaries = boundaries.clone().with_scan(scan);
+            }
+            Predicate(..) => {}
+        }
+        self
+    }
+
+    /// Fail with an `OtherIoError` instead of returning a chunk longer than
+    /// `size` bytes, including its boundary.  Without this, input which
+    /// never contains a boundary will use as much memory as it likes.
+    pub fn with_max_chunk_size(mut self, size: uint) -> ChunkBuffer<'a,T> {
+        assert!(size > 0);
+        self.max_chunk = Some(size);
+        self
+    }
+
+    /// Leave the boundary off the end of each chunk.  `fill_buf` then
+    /// returns only the first chunk, and consuming all of it consumes its
+    /// boundary as well.
+    pub fn with_strip_boundary(mut self, strip: bool) -> ChunkBuffer<'a,T> {
+        self.strip = strip;
+        self
+    }
+
+    /// The most memory our internal buffer has ever used, in bytes.
+    pub fn high_water_mark(&self) -> uint { self.high_water }
+
+    /// Iterate over our chunks, one boundary-delimited chunk at a time.
+    /// Each chunk is consumed when you ask for the next one, so if you
+    /// stop early, the last chunk you saw is still in the buffer, and
+    /// `fill_buf` will return it again.  Chunks include their boundary,
+    /// unless we're stripping it.
+    pub fn chunks<'b>(&'b mut self) -> ChunkIter<'b, 'a, T> {
+        ChunkIter{buffer: self, pending: None, done: false}
     }
 
     // Called internally to make `buffer` valid.  This is where all our
-    // evil magic lives.
-    fn top_up<'b>(&'b mut self) -> IoResult<&'b [u8]> {
-        assert!(!self.buffer.as_slice()
-                .contains_slice(self.boundary.as_slice()));
+    // evil magic lives.  Returns the first boundary, as `fill_any` does.
+    fn top_up<'b>(&'b mut self)
+                  -> IoResult<(&'b [u8], Option<(uint, uint, uint)>)> {
+        assert!(self.boundary.find_complete(self.buffer.as_slice()).is_none());
+        let max_chunk = self.max_chunk.unwrap_or(uint::MAX);
         loop {
             let (consumed, done) = {
                 let read_or_err = self.input.fill_buf();
                 match read_or_err {
                     Err(IoError{kind: EndOfFile, ..}) => {
                         // Exit 1: We're at the end of the file, so use
-                        // whatever we've got.
-                        return Ok(self.buffer.as_slice())
+                        // whatever we've got.  Any boundary in it is
+                        // complete now.
+                        let found = self.boundary.find(self.buffer.as_slice());
+                        self.found = found;
+                        return Ok((self.buffer.as_slice(), found))
                     },
                     Err(err) => {
                         // Exit 2: We've got a hard error.
                         return Err(err)
                     },
                     Ok(read) => {
+                        let buf_len = self.buffer.len();
+                        let window = self.boundary.window();
+                        // We'll look here for a split boundary token, or
+                        // one we couldn't be sure of last time.
+                        let scan_start = buf_len - min(buf_len, window - 1);
                         // Try to grab enough so that we know we have a
-                        // chunk.
-                        match read.contains_slice_pos(self.boundary.as_slice()) {
-                            Some(pos) => {
-                                let bytes = pos + self.boundary.len();
-                                self.buffer.push_all(read[..bytes]);
-                                (bytes, true)
+                        // chunk: a full window past its boundary is enough
+                        // to rule out any longer one.
+                        let wanted = match self.boundary.find(read) {
+                            Some((pos, _, _)) => min(read.len(), pos + window),
+                            None => read.len()
+                        };
+                        try!(push_growing(
+                            &mut self.buffer, &self.growth,
+                            &mut self.high_water, read[..wanted]));
+                        let found = self.boundary.find_complete(
+                            self.buffer.slice_from(scan_start));
+                        match found {
+                            Some((pos, len, _)) => {
+                                // Keep only the chunk, so whatever follows
+                                // can still come straight from `input`.
+                                // We'll look past it again next time.  (We
+                                // can't give back what we've consumed.)
+                                let keep = max(buf_len, scan_start + pos + len);
+                                self.buffer.truncate(keep);
+                                (keep - buf_len, true)
                             }
                             None => {
-                                let buf_len = self.buffer.len();
-                                let bound_len = self.boundary.len();
-                                // We'll look here for a split boundary token.
-                                let scan_start =
-                                    buf_len - min(buf_len, bound_len-1);
-                                let scan_end = min(buf_len + (bound_len-1),
-                                                   buf_len + read.len());
-                                self.buffer.push_all(read);
-                                let check =
-                                    self.buffer.slice(scan_start, scan_end);
-                                (read.len(), 
-                                 check.contains_slice(self.boundary.as_slice()))
+                                // Exit 3: Our first chunk is already too
+                                // long, so give up before it gets any
+                                // longer.
+                                if self.buffer.len() > max_chunk &&
+                                    self.boundary.find(self.buffer
+                                        .slice_from(scan_start)).is_none() {
+                                    return Err(chunk_too_large(max_chunk));
+                                }
+                                (wanted, false)
                             }
                         }
                     }
@@ -162,10 +631,11 @@ impl<'a, T: Buffer+'a> ChunkBuffer<'a,T> {
             };
             self.input.consume(consumed);
             if done {
-                // Exit 3: We've got at least one boundary in our buffer.
-                assert!(self.buffer.as_slice()
-                        .contains_slice(self.boundary.as_slice()));
-                return Ok(self.buffer.as_slice())
+                // Exit 4: We've got at least one boundary in our buffer.
+                let found = self.boundary.find(self.buffer.as_slice());
+                assert!(found.is_some());
+                self.found = found;
+                return Ok((self.buffer.as_slice(), found))
             }
         }
     }    
@@ -180,11 +650,33 @@ impl<'a,T: Buffer+'a> Reader for ChunkBuffer<'a,T> {
     }
 }
 
-impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
-    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
-        if self.buffer.as_slice().contains_slice(self.boundary.as_slice()) {
+impl<'a,T: Buffer+'a> ChunkBuffer<'a,T> {
+    // Like `fill_buf`, but never strips the boundary.  Returns the first
+    // boundary along with the data, because `data` borrows all of `self`,
+    // and we couldn't search it afterwards.
+    fn fill_raw<'b>(&'b mut self)
+                    -> IoResult<(&'b [u8], Option<(uint, uint, uint)>)> {
+        let max_chunk = self.max_chunk;
+        let (data, found) = try!(self.fill_any());
+        match max_chunk {
+            Some(limit) if found.map(|(pos, len, _)| pos + len)
+                               .unwrap_or(data.len()) > limit => {
+                Err(chunk_too_large(limit))
+            }
+            _ => Ok((data, found))
+        }
+    }
+
+    // Like `fill_raw`, but returns chunks of any size.  We search for the
+    // first boundary here, where we can still borrow our fields one at a
+    // time, and remember it in `found`.
+    fn fill_any<'b>(&'b mut self)
+                    -> IoResult<(&'b [u8], Option<(uint, uint, uint)>)> {
+        let found = self.boundary.find_complete(self.buffer.as_slice());
+        if found.is_some() {
             // Exit 1: Valid data in our local buffer.
-            Ok(self.buffer.as_slice())
+            self.found = found;
+            Ok((self.buffer.as_slice(), found))
         } else if self.buffer.len() > 0 {
             // Exit 2: Add some more data to our local buffer so that it's
             // valid (see invariants for top_up).
@@ -196,7 +688,9 @@ impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
                 match read_or_err {
                     Err(err) => { return Err(err); }
                     Ok(read) => {
-                        if read.contains_slice(self.boundary.as_slice()) {
+                        let found = self.boundary.find_complete(read);
+                        if found.is_some() {
+                            self.found = found;
                             // Exit 4: We can return this directly, but see
                             // https://github.com/rust-lang/rust/issues/6393
                             // https://github.com/rust-lang/rust/issues/12147
@@ -205,7 +699,7 @@ impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
                             // propagation between `read` and our return
                             // value, so `read` can be allowed to lapse
                             // when we leave this lexical scope.
-                            return Ok(unsafe { transmute(read) });
+                            return Ok((unsafe { transmute(read) }, found));
                         }
                     }
                 }
@@ -217,12 +711,14 @@ impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
         }
     }
 
-    fn consume(&mut self, amt: uint) {
+    fn consume_raw(&mut self, amt: uint) {
         if self.buffer.len() > 0 {
             assert!(amt <= self.buffer.len());
+            // Slide whatever's left down to the front.
             let keeping = self.buffer.len() - amt;
             for i in range(0, keeping) {
-                self.buffer.swap_remove(keeping-(i+1));
+                let b = self.buffer[amt + i];
+                self.buffer.as_mut_slice()[i] = b;
             }
             self.buffer.truncate(keeping);
         } else {
@@ -231,6 +727,85 @@ impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
     }
 }
 
+impl<'a,T: Buffer+'a> Buffer for ChunkBuffer<'a,T> {
+    fn fill_buf<'b>(&'b mut self) -> IoResult<&'b [u8]> {
+        let strip = self.strip;
+        let (data, found) = try!(self.fill_raw());
+        match found {
+            Some((pos, _, _)) if strip => Ok(data.slice_to(pos)),
+            _ => Ok(data)
+        }
+    }
+
+    fn consume(&mut self, amt: uint) {
+        // Consuming everything we returned consumes the boundary, too.
+        let amt = match self.found.take() {
+            Some((pos, len, _)) if self.strip && amt == pos => amt + len,
+            _ => amt
+        };
+        self.consume_raw(amt)
+    }
+}
+
+// The error we return for chunks longer than `limit` bytes.
+fn chunk_too_large(limit: uint) -> IoError {
+    IoError{kind: OtherIoError, desc: "chunk too large",
+            detail: Some(format!("no boundary within {} bytes", limit))}
+}
+
+/// A chunk from a `ChunkIter`.
+pub struct Chunk<'c> {
+    /// The contents of the chunk, ending with its boundary, unless we're
+    /// stripping it.
+    pub data: &'c [u8],
+    /// The index of the boundary which ended this chunk, or `None` for a
+    /// final chunk without one.  Predicate boundaries are always 0.
+    pub boundary: Option<uint>
+}
+
+/// A streaming iterator over the chunks of a `ChunkBuffer`.  If an error
+/// occurs, we return it, and stop.  Dropping the iterator doesn't consume
+/// the last chunk it returned.
+pub struct ChunkIter<'b, 'a: 'b, T: Buffer+'a> {
+    buffer: &'b mut ChunkBuffer<'a, T>,
+    // How much of the last chunk we still need to consume.
+    pending: Option<uint>,
+    done: bool
+}
+
+impl<'c, 'b, 'a, T: Buffer+'a> StreamingIterator<'c, IoResult<Chunk<'c>>>
+    for ChunkIter<'b, 'a, T> {
+
+    fn next(&'c mut self) -> Option<IoResult<Chunk<'c>>> {
+        if self.done { return None; }
+        match self.pending.take() {
+            Some(len) => { self.buffer.consume_raw(len); }
+            None => {}
+        }
+        let strip = self.buffer.strip;
+        let (data, found) = match self.buffer.fill_raw() {
+            Err(IoError{kind: EndOfFile, ..}) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+            Ok(filled) => filled
+        };
+        let (len, end, boundary) = match found {
+            Some((pos, len, i)) => {
+                let end = pos + len;
+                (if strip { pos } else { end }, end, Some(i))
+            }
+            None => (data.len(), data.len(), None)
+        };
+        self.pending = Some(end);
+        Some(Ok(Chunk{data: data.slice_to(len), boundary: boundary}))
+    }
+}
+
 #[cfg(test)]
 fn read_chunks<T: Buffer>(chunked: &mut T, boundary: &[u8]) -> Vec<u8> {
     let boundary_len = boundary.len();
@@ -272,3 +847,244 @@ fn reading_chunks_via_dribble() {
     let read = read_chunks(&mut chunked, &[10, 10]);
     assert_eq!(data, read);
 }
+
+#[test]
+fn partial_consume_keeps_the_rest_in_order() {
+    // Consuming a byte at a time makes us slide our local buffer down
+    // over and over, which used to scramble it.
+    let data = test_data();
+    let mut reader = MemReader::new(data.clone());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::new(&mut dribble, b"\n\n");
+    let mut read = vec![];
+    loop {
+        match chunked.fill_buf() {
+            Ok(data) => { read.push(data[0]); }
+            Err(IoError{kind: EndOfFile, ..}) => { break; }
+            Err(err) => { fail!("{}", err); }
+        }
+        chunked.consume(1);
+    }
+    assert_eq!(data, read);
+}
+
+#[test]
+fn slice_contains_honors_needle() {
+    let haystack: &[u8] = b"ab;cd<>ef\n\ngh";
+    assert_eq!(Some(2), haystack.contains_slice_pos(b";"));
+    assert_eq!(Some(5), haystack.contains_slice_pos(b"<>"));
+    assert_eq!(Some(9), haystack.contains_slice_pos(b"\n\n"));
+    assert_eq!(None, haystack.contains_slice_pos(b"<<"));
+    assert_eq!(None, haystack.contains_slice_pos(b"ab;cd<>ef\n\nghi"));
+    // A failed partial match mustn't skip past the real one.
+    let overlapping: &[u8] = b"aaab";
+    assert_eq!(Some(1), overlapping.contains_slice_pos(b"aab"));
+}
+
+#[test]
+fn horspool_agrees_with_naive_search() {
+    let haystack: &[u8] = b"abracadabra, abracadabrax\n\nabaabaab";
+    let needles: [&[u8], ..8] =
+        [b"a", b"x", b"\n\n", b"cad", b"abrax", b"aab", b"baab", b"zz"];
+    for &needle in needles.iter() {
+        assert_eq!(haystack.contains_slice_pos(needle),
+                   haystack.position_with(&Horspool::new(needle)));
+    }
+    assert_eq!(None, Horspool::new(b"abc").find(b"ab"));
+    assert_eq!(None, Horspool::new(b"\n\n").find(b"a\nb\n"));
+    assert_eq!(Some(3), memchr(b'\n', b"abc\n"));
+}
+
+#[test]
+fn swar_scan_matches_bytewise_scan() {
+    let data = test_data();
+    for len in range(0u, 40) {
+        for &byte in [b'\n', b'\t', b'z', 0xff].iter() {
+            let haystack = data.slice_to(len);
+            assert_eq!(memchr(byte, haystack), memchr_swar(byte, haystack));
+        }
+    }
+    assert_eq!(memchr(b'\n', data.as_slice()),
+               memchr_swar(b'\n', data.as_slice()));
+    let mut reader = MemReader::new(data.clone());
+    let mut chunked = ChunkBuffer::new(&mut reader, b"\n\n")
+        .with_scan(Bytewise);
+    assert_eq!(data, read_chunks(&mut chunked, b"\n\n"));
+}
+
+#[test]
+fn reading_chunks_with_other_boundaries() {
+    let boundaries: [&[u8], ..3] = [b";", b"<>", b"aab"];
+    for &boundary in boundaries.iter() {
+        let mut data = vec![];
+        for i in range(0u, 50) {
+            data.push_all(format!("record {} a", i).as_bytes());
+            data.push_all(boundary);
+        }
+        let mut reader = MemReader::new(data.clone());
+        let mut dribble = DribbleBuffer::new(&mut reader);
+        let mut chunked = ChunkBuffer::new(&mut dribble, boundary);
+        assert_eq!(data, read_chunks(&mut chunked, boundary));
+    }
+}
+
+#[test]
+fn chunk_iter_yields_one_chunk_at_a_time() {
+    let data = test_data();
+    let mut reader = MemReader::new(data.clone());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::new(&mut dribble, b"\n\n");
+    let mut chunks = chunked.chunks();
+    let mut read = vec![];
+    let mut count = 0u;
+    streaming_for!(chunk in chunks, {
+        let chunk = chunk.unwrap().data;
+        assert!(chunk.ends_with(b"\n\n"));
+        assert_eq!(Some(chunk.len() - 2), chunk.contains_slice_pos(b"\n\n"));
+        read.push_all(chunk);
+        count += 1;
+    });
+    assert_eq!(data, read);
+    assert_eq!(4, count);
+}
+
+#[test]
+fn chunk_iter_leaves_the_last_chunk_unconsumed() {
+    let mut reader = MemReader::new(b"one;two;three".to_vec());
+    let mut chunked = ChunkBuffer::new(&mut reader, b";");
+    {
+        let mut chunks = chunked.chunks();
+        assert_eq!(b"one;".to_vec(),
+                   chunks.next().unwrap().unwrap().data.to_vec());
+        assert_eq!(b"two;".to_vec(),
+                   chunks.next().unwrap().unwrap().data.to_vec());
+    }
+    assert_eq!(b"two;".to_vec(), chunked.fill_buf().unwrap().to_vec());
+    chunked.consume(4);
+    assert_eq!(b"three".to_vec(), chunked.fill_buf().unwrap().to_vec());
+}
+
+#[test]
+fn chunks_can_strip_their_boundary() {
+    let data = b"one;;two;;;;three";
+    let mut reader = MemReader::new(data.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::new(&mut dribble, b";;")
+        .with_strip_boundary(true);
+    let mut chunks = chunked.chunks();
+    let mut seen = vec![];
+    streaming_for!(chunk in chunks, {
+        seen.push(chunk.unwrap().data.to_vec());
+    });
+    assert_eq!(vec![b"one".to_vec(), b"two".to_vec(), vec![],
+                    b"three".to_vec()], seen);
+}
+
+#[test]
+fn chunks_split_at_the_first_of_several_boundaries() {
+    let data = b"a\r\n\r\nb\n\nc\r\n\r\n\n\nd";
+    let mut reader = MemReader::new(data.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked =
+        ChunkBuffer::with_boundaries(&mut dribble, &[b"\n\n", b"\r\n\r\n"])
+        .with_strip_boundary(true);
+    let mut chunks = chunked.chunks();
+    let mut seen = vec![];
+    streaming_for!(chunk in chunks, {
+        let chunk = chunk.unwrap();
+        seen.push((chunk.data.to_vec(), chunk.boundary));
+    });
+    assert_eq!(vec![(b"a".to_vec(), Some(1)), (b"b".to_vec(), Some(0)),
+                    (b"c".to_vec(), Some(1)), (vec![], Some(0)),
+                    (b"d".to_vec(), None)],
+               seen);
+
+    let found = Boundaries::new(&[b"\r\n", b"\r\n\r\n"]).find(b"x\r\n\r\n");
+    assert_eq!(Some((1, 1)), found);
+}
+
+#[test]
+fn chunks_wait_for_a_longer_boundary_with_the_same_prefix() {
+    let data = b"a\r\n\r\nb\r\nc\r\n\r\n\r\nd";
+    // Dribbling means a chunk often ends right after `\r\n`, so try a few
+    // times.
+    for _ in range(0u, 20) {
+        let mut reader = MemReader::new(data.to_vec());
+        let mut dribble = DribbleBuffer::new(&mut reader);
+        let mut chunked =
+            ChunkBuffer::with_boundaries(&mut dribble, &[b"\r\n", b"\r\n\r\n"])
+            .with_strip_boundary(true);
+        let mut chunks = chunked.chunks();
+        let mut seen = vec![];
+        streaming_for!(chunk in chunks, {
+            let chunk = chunk.unwrap();
+            seen.push((chunk.data.to_vec(), chunk.boundary));
+        });
+        assert_eq!(vec![(b"a".to_vec(), Some(1)), (b"b".to_vec(), Some(0)),
+                        (b"c".to_vec(), Some(1)), (vec![], Some(0)),
+                        (b"d".to_vec(), None)],
+                   seen);
+    }
+
+    let boundaries = Boundaries::new(&[b"\r\n", b"\r\n\r\n"]);
+    assert_eq!(None, boundaries.find_complete(b"x\r\n"));
+    assert_eq!(None, boundaries.find_complete(b"x\r\n\r"));
+    assert_eq!(Some((1, 0)), boundaries.find_complete(b"x\r\ny"));
+    assert_eq!(Some((1, 1)), boundaries.find_complete(b"x\r\n\r\n"));
+}
+
+#[test]
+fn chunks_can_split_where_a_predicate_says() {
+    let data = b">one\nACGT\nAC\n>two\n>three\nGG";
+    let mut reader = MemReader::new(data.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::with_predicate(&mut dribble, 2, |w| {
+        if w == b"\n>" { Some(1) } else { None }
+    });
+    let mut chunks = chunked.chunks();
+    let mut seen = vec![];
+    streaming_for!(chunk in chunks, {
+        seen.push(chunk.unwrap().data.to_vec());
+    });
+    assert_eq!(vec![b">one\nACGT\nAC\n".to_vec(), b">two\n".to_vec(),
+                    b">three\nGG".to_vec()],
+               seen);
+}
+
+#[test]
+fn chunks_longer_than_the_maximum_are_errors() {
+    let data = b"short\n\nmuch too long for us\n\nnever seen\n\n";
+    let mut reader = MemReader::new(data.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::new(&mut dribble, b"\n\n")
+        .with_growth(Increment(4))
+        .with_max_chunk_size(10);
+    {
+        let mut chunks = chunked.chunks();
+        assert_eq!(b"short\n\n", chunks.next().unwrap().unwrap().data);
+        let err = chunks.next().unwrap().unwrap_err();
+        assert_eq!(OtherIoError, err.kind);
+        assert_eq!("chunk too large", err.desc);
+        assert!(chunks.next().is_none());
+    }
+    assert!(chunked.high_water_mark() <= 16);
+}
+
+#[test]
+fn growth_policies_bound_chunk_buffers() {
+    let data = test_data();
+    let mut reader = MemReader::new(data.clone());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut chunked = ChunkBuffer::new(&mut dribble, &[10, 10])
+        .with_growth(Increment(100));
+    assert_eq!(data, read_chunks(&mut chunked, &[10, 10]));
+    let high_water = chunked.high_water_mark();
+    assert!(high_water > 0 && high_water % 100 == 0);
+
+    let mut reader = MemReader::new(data.clone());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut capped = ChunkBuffer::new(&mut dribble, &[10, 10])
+        .with_growth(Capped(16));
+    let err = capped.fill_buf().unwrap_err();
+    assert_eq!("buffer limit exceeded", err.desc);
+}
@@ -0,0 +1,485 @@
+//! Decompressing bzip2 data.
+//!
+//! Lots of public datasets, including the Wikipedia dumps, ship as `.bz2`.
+//! bzip2 compresses data in independent blocks of up to 900K, using the
+//! Burrows-Wheeler transform, move-to-front coding and Huffman coding, and
+//! a couple of layers of run-length encoding.  `Bzip2Buffer` decodes one
+//! block at a time, so its memory use is bounded by the block size.
+//!
+//! Like `DeflateBuffer`, we read straight through concatenated streams, as
+//! produced by `pbzip2` or by appending to a file with `bzip2 -c >>`.
+//!
+//! Blocks using the long-obsolete "randomized" mode are rejected.
+
+use std::cmp::min;
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError,standard_error};
+
+#[cfg(test)] use std::io::{File,MemReader};
+#[cfg(test)] use buffers::DribbleBuffer;
+#[cfg(test)] use compress::check_damaged_streams;
+
+static BLOCK_MAGIC: u64 = 0x314159265359;
+static END_MAGIC: u64 = 0x177245385090;
+
+/// Symbols are coded in groups this long, each using one Huffman table.
+static GROUP_SIZE: uint = 50;
+
+fn corrupt(detail: &str) -> IoError {
+    IoError{kind: OtherIoError, desc: "corrupt bzip2 data",
+            detail: Some(detail.to_string())}
+}
+
+// Reads bits, most significant first.  (The opposite of DEFLATE.)
+struct Bits<R> {
+    input: R,
+    bits: u32,
+    count: uint
+}
+
+impl<R: Buffer> Bits<R> {
+    // Read `need` bits, where `need` is at most 24.
+    fn bits(&mut self, need: uint) -> IoResult<u32> {
+        while self.count < need {
+            let byte = try!(self.byte());
+            self.bits = self.bits << 8 | byte as u32;
+            self.count += 8;
+        }
+        self.count -= need;
+        Ok((self.bits >> self.count) & ((1 << need) - 1))
+    }
+
+    fn bit(&mut self) -> IoResult<bool> { Ok(try!(self.bits(1)) == 1) }
+
+    fn u32(&mut self) -> IoResult<u32> {
+        let hi = try!(self.bits(16));
+        let lo = try!(self.bits(16));
+        Ok(hi << 16 | lo)
+    }
+
+    fn magic(&mut self) -> IoResult<u64> {
+        let hi = try!(self.bits(24)) as u64;
+        let lo = try!(self.bits(24)) as u64;
+        Ok(hi << 24 | lo)
+    }
+
+    fn align(&mut self) { self.count = 0; }
+
+    // Is there no more input?  Call `align` first.
+    fn at_eof(&mut self) -> IoResult<bool> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => { if buf.len() > 0 { return Ok(false); } }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(true); }
+                Err(err) => { return Err(err); }
+            }
+        }
+    }
+
+    fn byte(&mut self) -> IoResult<u8> {
+        loop {
+            let byte = match self.input.fill_buf() {
+                Ok(buf) => if buf.len() > 0 { Some(buf[0]) } else { None },
+                Err(IoError{kind: EndOfFile, ..}) => {
+                    return Err(corrupt("unexpected end of compressed data"));
+                }
+                Err(err) => { return Err(err); }
+            };
+            match byte {
+                Some(byte) => {
+                    self.input.consume(1);
+                    return Ok(byte);
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Decode one symbol using `code`.  See `deflate` for how this works.
+    fn decode(&mut self, code: &Huffman) -> IoResult<uint> {
+        let mut value = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in range(1u, 21) {
+            value |= try!(self.bits(1)) as i32;
+            let count = code.counts[len] as i32;
+            if value - count < first {
+                let i = (index + value - first) as uint;
+                return Ok(code.symbols[i] as uint);
+            }
+            index += count;
+            first = (first + count) << 1;
+            value <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+// A canonical Huffman code with lengths of up to 20 bits.
+struct Huffman {
+    counts: [u16, ..21],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16, ..21];
+        for &len in lengths.iter() { counts[len as uint] += 1; }
+        let mut offsets = [0u16, ..21];
+        for len in range(1u, 20) {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let offset = &mut offsets[len as uint];
+                symbols.as_mut_slice()[*offset as uint] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        Huffman{counts: counts, symbols: symbols}
+    }
+}
+
+// Move `list[index]` to the front of `list`, and return it.
+fn move_to_front(list: &mut [u8], index: uint) -> u8 {
+    let value = list[index];
+    for i in range(0, index).rev() {
+        list[i + 1] = list[i];
+    }
+    list[0] = value;
+    value
+}
+
+fn crc_table() -> Vec<u32> {
+    range(0u32, 256).map(|n| {
+        let mut c = n << 24;
+        for _ in range(0u, 8) {
+            c = if c & 0x80000000 != 0 {
+                (c << 1) ^ 0x04c11db7
+            } else {
+                c << 1
+            };
+        }
+        c
+    }).collect()
+}
+
+#[deriving(PartialEq)]
+enum State {
+    StreamHeader,
+    Blocks,
+    End
+}
+
+/// A `Buffer` which decompresses bzip2 data from another `Buffer`.
+pub struct Bzip2Buffer<R> {
+    bits: Bits<R>,
+    state: State,
+    block_size: uint,
+    stream_crc: u32,
+    crc_table: Vec<u32>,
+    // The block after Huffman and move-to-front decoding, and the
+    // permutation which undoes the Burrows-Wheeler transform.
+    block: Vec<u8>,
+    links: Vec<u32>,
+    out: Vec<u8>,
+    pos: uint
+}
+
+impl<R: Buffer> Bzip2Buffer<R> {
+    /// Decompress bzip2 data from `input`.
+    pub fn new(input: R) -> Bzip2Buffer<R> {
+        Bzip2Buffer{bits: Bits{input: input, bits: 0, count: 0},
+                    state: StreamHeader, block_size: 0, stream_crc: 0,
+                    crc_table: crc_table(), block: vec![], links: vec![],
+                    out: vec![], pos: 0}
+    }
+
+    /// Get back our underlying buffer.
+    pub fn unwrap(self) -> R { self.bits.input }
+
+    fn read_stream_header(&mut self) -> IoResult<()> {
+        let magic = try!(self.bits.bits(24));
+        let level = try!(self.bits.bits(8)) as u8;
+        if magic != 0x425a68 || level < b'1' || level > b'9' {
+            return Err(corrupt("bad bzip2 header"));
+        }
+        self.block_size = (level - b'0') as uint * 100_000;
+        self.stream_crc = 0;
+        Ok(())
+    }
+
+    // Huffman and move-to-front decode a block into `self.block`,
+    // returning the block's CRC and its original pointer.
+    fn read_block(&mut self) -> IoResult<(u32, uint)> {
+        let bits = &mut self.bits;
+        let crc = try!(bits.u32());
+        if try!(bits.bit()) {
+            return Err(corrupt("randomized blocks are not supported"));
+        }
+        let orig_ptr = try!(bits.bits(24)) as uint;
+
+        // Which byte values appear in this block?
+        let mut symbol_bytes = vec![];
+        let used = try!(bits.bits(16));
+        for i in range(0u, 16) {
+            if used & (0x8000 >> i) == 0 { continue; }
+            let word = try!(bits.bits(16));
+            for j in range(0u, 16) {
+                if word & (0x8000 >> j) != 0 {
+                    symbol_bytes.push((i * 16 + j) as u8);
+                }
+            }
+        }
+        if symbol_bytes.len() == 0 {
+            return Err(corrupt("block uses no symbols"));
+        }
+        // We also have RUNA, RUNB and end-of-block, less one for the byte
+        // at the front of the MTF list, which is always coded as a run.
+        let alpha_size = symbol_bytes.len() + 2;
+        let end_of_block = alpha_size - 1;
+
+        // Which Huffman table does each group of symbols use?
+        let table_count = try!(bits.bits(3)) as uint;
+        let selector_count = try!(bits.bits(15)) as uint;
+        if table_count < 2 || table_count > 6 || selector_count == 0 {
+            return Err(corrupt("bad Huffman table count"));
+        }
+        let mut table_mtf: Vec<u8> = range(0, table_count as u8).collect();
+        let mut selectors = Vec::with_capacity(selector_count);
+        for _ in range(0, selector_count) {
+            let mut index = 0u;
+            while try!(bits.bit()) {
+                index += 1;
+                if index >= table_count {
+                    return Err(corrupt("bad Huffman table selector"));
+                }
+            }
+            selectors.push(move_to_front(table_mtf.as_mut_slice(), index));
+        }
+
+        // The code lengths are delta-coded.
+        let mut tables = Vec::with_capacity(table_count);
+        let mut lengths = Vec::from_elem(alpha_size, 0u8);
+        for _ in range(0, table_count) {
+            let mut len = try!(bits.bits(5)) as uint;
+            for i in range(0, alpha_size) {
+                loop {
+                    if len < 1 || len > 20 {
+                        return Err(corrupt("bad Huffman code length"));
+                    }
+                    if !try!(bits.bit()) { break; }
+                    if try!(bits.bit()) { len -= 1; } else { len += 1; }
+                }
+                lengths.as_mut_slice()[i] = len as u8;
+            }
+            tables.push(Huffman::new(lengths.as_slice()));
+        }
+
+        // Decode the symbols.  RUNA and RUNB spell out the length of a run
+        // of the byte at the front of the MTF list, in bijective base 2.
+        let block = &mut self.block;
+        block.clear();
+        let mut mtf = symbol_bytes.clone();
+        let mut run = 0u;
+        let mut run_bit = 0u;
+        let mut group = 0u;
+        let mut left = 0u;
+        let mut table = 0u;
+        loop {
+            if left == 0 {
+                if group >= selectors.len() {
+                    return Err(corrupt("ran out of Huffman table selectors"));
+                }
+                table = selectors[group] as uint;
+                group += 1;
+                left = GROUP_SIZE;
+            }
+            left -= 1;
+            let symbol = try!(bits.decode(&tables[table]));
+            if symbol <= 1 {
+                if run_bit > 20 {
+                    return Err(corrupt("run too long"));
+                }
+                run += (symbol + 1) << run_bit;
+                run_bit += 1;
+                continue;
+            }
+            if run_bit > 0 {
+                if block.len() + run > self.block_size {
+                    return Err(corrupt("block too long"));
+                }
+                let byte = mtf[0];
+                for _ in range(0, run) { block.push(byte); }
+                run = 0;
+                run_bit = 0;
+            }
+            if symbol == end_of_block { break; }
+            if block.len() >= self.block_size {
+                return Err(corrupt("block too long"));
+            }
+            block.push(move_to_front(mtf.as_mut_slice(), symbol - 1));
+        }
+        if orig_ptr >= block.len() {
+            return Err(corrupt("original pointer out of range"));
+        }
+        Ok((crc, orig_ptr))
+    }
+
+    // Undo the Burrows-Wheeler transform and the initial run-length
+    // encoding of `self.block`, writing the result to `self.out`.
+    fn unsort_block(&mut self, orig_ptr: uint) {
+        let block = self.block.as_slice();
+
+        // `links[i]` tells us which byte of `block` follows byte `i` in
+        // the original data.
+        let mut starts = [0u, ..256];
+        for &byte in block.iter() { starts[byte as uint] += 1; }
+        let mut total = 0;
+        for start in starts.iter_mut() {
+            let count = *start;
+            *start = total;
+            total += count;
+        }
+        self.links.clear();
+        for _ in range(0, block.len()) { self.links.push(0); }
+        {
+            let links = self.links.as_mut_slice();
+            for (i, &byte) in block.iter().enumerate() {
+                links[starts[byte as uint]] = i as u32;
+                starts[byte as uint] += 1;
+            }
+        }
+
+        // Four identical bytes are followed by a count of how many more
+        // there are.
+        self.out.clear();
+        self.pos = 0;
+        let mut p = self.links[orig_ptr] as uint;
+        let mut last = 0u8;
+        let mut same = 0u;
+        for _ in range(0, block.len()) {
+            let byte = block[p];
+            p = self.links[p] as uint;
+            if same == 4 {
+                for _ in range(0, byte as uint) { self.out.push(last); }
+                same = 0;
+                continue;
+            }
+            if same > 0 && byte == last {
+                same += 1;
+            } else {
+                last = byte;
+                same = 1;
+            }
+            self.out.push(byte);
+        }
+    }
+
+    fn block_crc(&self) -> u32 {
+        let mut crc = !0u32;
+        for &byte in self.out.iter() {
+            let i = ((crc >> 24) ^ byte as u32) as uint;
+            crc = (crc << 8) ^ self.crc_table[i];
+        }
+        !crc
+    }
+}
+
+impl<R: Buffer> Reader for Bzip2Buffer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(data.len(), buf.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: Buffer> Buffer for Bzip2Buffer<R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        while self.pos == self.out.len() {
+            match self.state {
+                End => { return Err(standard_error(EndOfFile)); }
+                StreamHeader => {
+                    try!(self.read_stream_header());
+                    self.state = Blocks;
+                }
+                Blocks => {
+                    let magic = try!(self.bits.magic());
+                    if magic == BLOCK_MAGIC {
+                        let (crc, orig_ptr) = try!(self.read_block());
+                        self.unsort_block(orig_ptr);
+                        if self.block_crc() != crc {
+                            return Err(corrupt("block checksum mismatch"));
+                        }
+                        let old = self.stream_crc;
+                        self.stream_crc = (old << 1 | old >> 31) ^ crc;
+                    } else if magic == END_MAGIC {
+                        if try!(self.bits.u32()) != self.stream_crc {
+                            return Err(corrupt("stream checksum mismatch"));
+                        }
+                        self.bits.align();
+                        self.state = if try!(self.bits.at_eof()) {
+                            End
+                        } else {
+                            StreamHeader
+                        };
+                    } else {
+                        return Err(corrupt("bad block header"));
+                    }
+                }
+            }
+        }
+        Ok(self.out.slice_from(self.pos))
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.pos += amt;
+        assert!(self.pos <= self.out.len());
+    }
+}
+
+#[test]
+fn decompresses_bzip2_files() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.bz2"))
+        .read_to_end().unwrap();
+    let mut reader = MemReader::new(compressed);
+    let mut bzip2 = Bzip2Buffer::new(DribbleBuffer::new(&mut reader));
+    assert_eq!(expected, bzip2.read_to_end().unwrap());
+}
+
+#[test]
+fn decompresses_concatenated_streams() {
+    let mut compressed =
+        File::open(&Path::new("test_data/fr/sample.conllx.bz2"))
+        .read_to_end().unwrap();
+    let copy = compressed.clone();
+    compressed.push_all(copy.as_slice());
+    let mut bzip2 = Bzip2Buffer::new(MemReader::new(compressed));
+    assert_eq!(4367 * 2, bzip2.read_to_end().unwrap().len());
+}
+
+#[test]
+fn rejects_corrupt_bzip2() {
+    let mut compressed =
+        File::open(&Path::new("test_data/fr/sample.conllx.bz2"))
+        .read_to_end().unwrap();
+    compressed.as_mut_slice()[100] ^= 0x10;
+    let mut bzip2 = Bzip2Buffer::new(MemReader::new(compressed));
+    assert!(bzip2.read_to_end().is_err());
+}
+
+#[test]
+fn survives_truncated_and_damaged_streams() {
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.bz2"))
+        .read_to_end().unwrap();
+    check_damaged_streams(compressed.as_slice(), |data| {
+        Bzip2Buffer::new(MemReader::new(data)).read_to_end()
+    });
+}
@@ -0,0 +1,59 @@
+//! Checkpoints for restarting long-running jobs.
+//!
+//! A checkpoint is just a small file recording how many bytes of input
+//! have been fully processed.  It's written atomically, so a crash while
+//! saving never leaves a corrupt checkpoint behind.  On restart, `resume`
+//! reopens the input at the saved offset via its `RangeSource`.
+
+use std::io::{Buffer,File,InvalidInput,IoError,IoResult};
+use std::io::fs::PathExtensions;
+
+use retry::{RangeSource,ResumingBuffer,RetryPolicy};
+use sinks::SafeFileWriter;
+
+#[cfg(test)] use std::io::TempDir;
+
+/// Record in `path` that the first `offset` bytes have been processed.
+pub fn save(path: &Path, offset: u64) -> IoResult<()> {
+    let mut writer = try!(SafeFileWriter::create(path));
+    try!(write!(writer, "{}\n", offset));
+    writer.commit()
+}
+
+/// Load the offset saved in `path`, or 0 if there's no checkpoint yet.
+pub fn load(path: &Path) -> IoResult<u64> {
+    if !path.exists() { return Ok(0); }
+    let text = try!(File::open(path).read_to_string());
+    match from_str(text.as_slice().trim()) {
+        Some(offset) => Ok(offset),
+        None => Err(IoError{kind: InvalidInput, desc: "corrupt checkpoint",
+                            detail: Some(text)})
+    }
+}
+
+/// Open `source` at the offset saved in `checkpoint`, recovering from
+/// errors according to `policy`.  Call `save` with the buffer's
+/// `position()` whenever you've finished with everything before it.
+pub fn resume<R: Buffer, S: RangeSource<R>>(source: S, checkpoint: &Path,
+                                            policy: RetryPolicy)
+                                            -> IoResult<ResumingBuffer<S, R>> {
+    let offset = try!(load(checkpoint));
+    ResumingBuffer::new(source, offset, policy)
+}
+
+#[test]
+fn resume_from_checkpoint() {
+    let dir = TempDir::new("checkpoint").unwrap();
+    let checkpoint = dir.path().join("job.checkpoint");
+    assert_eq!(0, load(&checkpoint).unwrap());
+
+    let data = Path::new("test_data/fr/sample.conllx");
+    let mut first = resume(data.clone(), &checkpoint, RetryPolicy::new())
+        .unwrap();
+    first.read_line().unwrap();
+    save(&checkpoint, first.position()).unwrap();
+    let expected = first.read_line().unwrap();
+
+    let mut second = resume(data, &checkpoint, RetryPolicy::new()).unwrap();
+    assert_eq!(expected, second.read_line().unwrap());
+}
@@ -0,0 +1,208 @@
+//! Stripping comments out of a byte stream.
+//!
+//! Plenty of simple data formats allow `#` comments or `/* */` blocks, and
+//! it's a nuisance to handle them in every parser.  `CommentBuffer` removes
+//! them before the parser ever sees them.  Note that we don't know anything
+//! about quoting, so a `#` inside a string still starts a comment.
+
+use std::cmp::{max,min};
+use std::io::{Buffer,EndOfFile,IoError,IoResult,standard_error};
+
+#[cfg(test)] use std::io::MemReader;
+#[cfg(test)] use buffers::DribbleBuffer;
+
+/// Which kinds of comments should we remove?
+#[deriving(Clone, Show)]
+pub struct CommentSyntax {
+    /// Starts a comment which runs to the end of the line.  The newline
+    /// itself is kept.
+    pub line: Option<Vec<u8>>,
+    /// The start and end markers of a block comment.  Block comments don't
+    /// nest.
+    pub block: Option<(Vec<u8>, Vec<u8>)>
+}
+
+impl CommentSyntax {
+    /// Shell-style `#` comments.
+    pub fn hash() -> CommentSyntax {
+        CommentSyntax{line: Some(b"#".to_vec()), block: None}
+    }
+
+    /// C-style `//` and `/* */` comments.
+    pub fn c() -> CommentSyntax {
+        CommentSyntax{line: Some(b"//".to_vec()),
+                      block: Some((b"/*".to_vec(), b"*/".to_vec()))}
+    }
+
+    // The length of our longest marker.  We never make a decision about a
+    // byte until we can see this many bytes past it.
+    fn lookahead(&self) -> uint {
+        let line = self.line.as_ref().map_or(0, |l| l.len());
+        let block = self.block.as_ref()
+            .map_or(0, |&(ref start, ref end)| max(start.len(), end.len()));
+        max(1, max(line, block))
+    }
+}
+
+enum State {
+    Normal,
+    InLine,
+    InBlock
+}
+
+/// A buffer which removes comments from `input`.
+pub struct CommentBuffer<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    syntax: CommentSyntax,
+    state: State,
+    // Input bytes we haven't been able to classify yet, because they
+    // might be the start of a marker split across reads.
+    carry: Vec<u8>,
+    // Stripped data waiting to be returned is `output[out_pos..]`.
+    output: Vec<u8>,
+    out_pos: uint
+}
+
+impl<'a, T: Buffer+'a> CommentBuffer<'a, T> {
+    /// Create a new `CommentBuffer` which strips `syntax` from `input`.
+    pub fn new(input: &'a mut T, syntax: CommentSyntax)
+               -> CommentBuffer<'a, T> {
+        CommentBuffer{input: input, syntax: syntax, state: Normal,
+                      carry: vec![], output: vec![], out_pos: 0}
+    }
+}
+
+// If `marker` appears at `data[i]`, return its length.  Otherwise, return
+// 0.
+fn marker_len(data: &[u8], i: uint, marker: Option<&Vec<u8>>) -> uint {
+    match marker {
+        Some(m) if data.slice_from(i).starts_with(m.as_slice()) => m.len(),
+        _ => 0
+    }
+}
+
+// Run our state machine over as much of `data` as we safely can, appending
+// non-comment bytes to `output`.  Returns the number of bytes processed.
+fn strip(syntax: &CommentSyntax, state: &mut State, data: &[u8], eof: bool,
+         output: &mut Vec<u8>) -> uint {
+    let limit = if eof {
+        data.len()
+    } else {
+        data.len() - min(data.len(), syntax.lookahead() - 1)
+    };
+    let line_start = syntax.line.as_ref();
+    let block_start = syntax.block.as_ref().map(|&(ref start, _)| start);
+    let block_end = syntax.block.as_ref().map(|&(_, ref end)| end);
+    let mut i = 0;
+    while i < limit {
+        match *state {
+            Normal => {
+                let line = marker_len(data, i, line_start);
+                let block = marker_len(data, i, block_start);
+                if line > 0 {
+                    *state = InLine;
+                    i += line;
+                } else if block > 0 {
+                    *state = InBlock;
+                    i += block;
+                } else {
+                    output.push(data[i]);
+                    i += 1;
+                }
+            }
+            InLine => {
+                if data[i] == b'\n' {
+                    output.push(b'\n');
+                    *state = Normal;
+                }
+                i += 1;
+            }
+            InBlock => {
+                let end = marker_len(data, i, block_end);
+                if end > 0 {
+                    *state = Normal;
+                    i += end;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+    // A marker which matched near the end may have run past `limit`.
+    min(i, data.len())
+}
+
+impl<'a, T: Buffer+'a> Reader for CommentBuffer<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(buf.len(), data.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<'a, T: Buffer+'a> Buffer for CommentBuffer<'a, T> {
+    fn fill_buf<'b>(&'b mut self) -> IoResult<&'b [u8]> {
+        while self.out_pos == self.output.len() {
+            self.output.clear();
+            self.out_pos = 0;
+            let (consumed, eof) = {
+                match self.input.fill_buf() {
+                    Ok(read) => {
+                        self.carry.push_all(read);
+                        (read.len(), false)
+                    }
+                    Err(IoError{kind: EndOfFile, ..}) => (0, true),
+                    Err(err) => { return Err(err); }
+                }
+            };
+            self.input.consume(consumed);
+            if eof && self.carry.len() == 0 {
+                return Err(standard_error(EndOfFile));
+            }
+            let used = strip(&self.syntax, &mut self.state,
+                             self.carry.as_slice(), eof, &mut self.output);
+            let keeping = self.carry.len() - used;
+            for i in range(0, keeping) {
+                self.carry.as_mut_slice()[i] = self.carry[used + i];
+            }
+            self.carry.truncate(keeping);
+            if eof && self.output.len() == 0 {
+                return Err(standard_error(EndOfFile));
+            }
+        }
+        Ok(self.output.slice_from(self.out_pos))
+    }
+
+    fn consume(&mut self, amt: uint) {
+        assert!(self.out_pos + amt <= self.output.len());
+        self.out_pos += amt;
+    }
+}
+
+#[cfg(test)]
+static COMMENTED: &'static str =
+    "a /* one\n */b # two\nc // three /* four */\nd /* five */ e";
+
+#[test]
+fn strips_c_comments() {
+    let mut reader = MemReader::new(COMMENTED.as_bytes().to_vec());
+    let mut stripped = CommentBuffer::new(&mut reader, CommentSyntax::c());
+    let result = stripped.read_to_string().unwrap();
+    assert_eq!("a b # two\nc \nd  e", result.as_slice());
+}
+
+#[test]
+fn strips_hash_comments_via_dribble() {
+    let mut reader = MemReader::new(COMMENTED.as_bytes().to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut stripped = CommentBuffer::new(&mut dribble,
+                                          CommentSyntax::hash());
+    let result = stripped.read_to_string().unwrap();
+    assert_eq!("a /* one\n */b \nc // three /* four */\nd /* five */ e",
+               result.as_slice());
+}
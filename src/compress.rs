@@ -0,0 +1,289 @@
+//! Recognizing compressed input by its magic bytes.
+//!
+//! Pipelines shouldn't need to be told whether each input file is
+//! compressed.  `detect` looks at the first few bytes of a stream and
+//! guesses its format, and `decompress_auto` uses that guess to insert the
+//! right decompression buffer, or to pass the data through untouched.
+//!
+//! On the output side, `from_extension` picks a format based on the
+//! destination's file name, and `compress` wraps a writer to match.
+//!
+//! We only detect formats we can decode, so zstd and xz input is passed
+//! through as is; see `detect`.  We can only encode gzip and Snappy's
+//! framing format, and neither encoder actually compresses yet: both store
+//! the data as is, in a container any decoder can read.  `compress` fails
+//! for the other formats.  Use `check_encoder` before creating an output
+//! file, so you don't truncate it for nothing.
+
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError};
+
+use bzip2::Bzip2Buffer;
+use deflate::{DeflateBuffer,GzipWriter};
+use lz4::Lz4Buffer;
+use snappy::{SnappyFramedBuffer,SnappyFramedWriter};
+
+#[cfg(test)] use std::cmp::{max,min};
+#[cfg(test)] use std::io::{BufferedReader,File,MemReader,MemWriter};
+#[cfg(test)] use std::rand::{Rng,SeedableRng,XorShiftRng};
+
+/// A compression format.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Compression {
+    /// Not compressed (or at least, not in any format we recognize).
+    Uncompressed,
+    /// gzip (RFC 1952).
+    Gzip,
+    /// A raw zlib stream (RFC 1950).
+    Zlib,
+    /// Snappy's framing format.
+    SnappyFramed,
+    /// Zstandard.
+    Zstd,
+    /// bzip2.
+    Bzip2,
+    /// The LZ4 frame format.
+    Lz4,
+    /// xz.
+    Xz
+}
+
+static SNAPPY_MAGIC: &'static [u8] =
+    &[0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59];
+
+/// Guess the compression format of a stream which starts with `peek`.  We
+/// need at most 10 bytes to make a decision; with fewer, we may miss some
+/// formats.
+///
+/// We only recognize formats which `decompress_auto` can decode: gzip,
+/// zlib, Snappy's framing format, bzip2 and LZ4 frames.  Zstandard and xz
+/// aren't supported, so their input comes back as `Uncompressed`, and will
+/// be passed through as is.
+pub fn detect(peek: &[u8]) -> Compression {
+    if peek.starts_with(&[0x1f, 0x8b]) {
+        Gzip
+    } else if peek.starts_with(SNAPPY_MAGIC) {
+        SnappyFramed
+    } else if peek.starts_with(b"BZh") {
+        Bzip2
+    } else if peek.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        Lz4
+    } else if peek.len() >= 2 && peek[0] == 0x78 && peek[1] & 0x20 == 0 &&
+        ((peek[0] as uint) << 8 | peek[1] as uint) % 31 == 0 {
+        // zlib has no real magic number, just a method of 8 (deflate), a
+        // window size, and a header checksum, which plenty of text passes.
+        // So we insist on the 32KiB window and no preset dictionary, which
+        // is what every encoder we know of writes.
+        Zlib
+    } else {
+        Uncompressed
+    }
+}
+
+fn no_decoder(format: Compression) -> IoError {
+    IoError{kind: OtherIoError, desc: "no decoder for compression format",
+            detail: Some(format!("{}", format))}
+}
+
+fn no_encoder(format: Compression) -> IoError {
+    IoError{kind: OtherIoError, desc: "no encoder for compression format",
+            detail: Some(format!("{}", format))}
+}
+
+/// Peek at the start of `input`, and wrap it in the appropriate
+/// decompression buffer.  Uncompressed input is passed through as is.
+pub fn decompress_auto<'a, R: Buffer+'a>(mut input: R)
+                                         -> IoResult<Box<Buffer+'a>> {
+    let format = match input.fill_buf() {
+        Ok(peek) => detect(peek),
+        Err(IoError{kind: EndOfFile, ..}) => Uncompressed,
+        Err(err) => { return Err(err); }
+    };
+    match format {
+        Uncompressed => Ok(box input as Box<Buffer+'a>),
+        Gzip => Ok(box DeflateBuffer::gzip(input) as Box<Buffer+'a>),
+        Zlib => Ok(box DeflateBuffer::zlib(input) as Box<Buffer+'a>),
+        Bzip2 => Ok(box Bzip2Buffer::new(input) as Box<Buffer+'a>),
+        Lz4 => Ok(box Lz4Buffer::new(input) as Box<Buffer+'a>),
+        SnappyFramed => {
+            Ok(box SnappyFramedBuffer::new(input) as Box<Buffer+'a>)
+        }
+        other => Err(no_decoder(other))
+    }
+}
+
+/// Guess the compression format we should use when writing to `path`,
+/// based on its extension.
+pub fn from_extension(path: &Path) -> Compression {
+    match path.extension() {
+        Some(b"gz") => Gzip,
+        Some(b"zz") => Zlib,
+        Some(b"sz") => SnappyFramed,
+        Some(b"zst") => Zstd,
+        Some(b"bz2") => Bzip2,
+        Some(b"lz4") => Lz4,
+        Some(b"xz") => Xz,
+        _ => Uncompressed
+    }
+}
+
+/// Return an error if `compress` can't write `format`.
+pub fn check_encoder(format: Compression) -> IoResult<()> {
+    match format {
+        Uncompressed | Gzip | SnappyFramed => Ok(()),
+        other => Err(no_encoder(other))
+    }
+}
+
+/// A writer which compresses its output in one of the formats `compress`
+/// supports.  Call `finish` once you're done, to write out any buffered
+/// data and trailers; flushing isn't enough.
+pub struct CompressWriter<W> {
+    encoder: Encoder<W>
+}
+
+enum Encoder<W> {
+    PlainOutput(W),
+    GzipOutput(GzipWriter<W>),
+    SnappyOutput(SnappyFramedWriter<W>)
+}
+
+impl<W: Writer> CompressWriter<W> {
+    /// Write out everything we've buffered, and any trailer the format
+    /// needs, and flush the underlying writer.
+    pub fn finish(&mut self) -> IoResult<()> {
+        match self.encoder {
+            PlainOutput(ref mut w) => w.flush(),
+            GzipOutput(ref mut w) => w.finish(),
+            SnappyOutput(ref mut w) => w.finish()
+        }
+    }
+
+    /// Get back the underlying writer.
+    pub fn unwrap(self) -> W {
+        match self.encoder {
+            PlainOutput(w) => w,
+            GzipOutput(w) => w.unwrap(),
+            SnappyOutput(w) => w.unwrap()
+        }
+    }
+}
+
+impl<W: Writer> Writer for CompressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        match self.encoder {
+            PlainOutput(ref mut w) => w.write(buf),
+            GzipOutput(ref mut w) => w.write(buf),
+            SnappyOutput(ref mut w) => w.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self.encoder {
+            PlainOutput(ref mut w) => w.flush(),
+            GzipOutput(ref mut w) => w.flush(),
+            SnappyOutput(ref mut w) => w.flush()
+        }
+    }
+}
+
+/// Wrap `output` in a writer which compresses using `format`.
+pub fn compress<W: Writer>(output: W, format: Compression)
+                           -> IoResult<CompressWriter<W>> {
+    let encoder = match format {
+        Uncompressed => PlainOutput(output),
+        Gzip => GzipOutput(GzipWriter::new(output)),
+        SnappyFramed => SnappyOutput(SnappyFramedWriter::new(output)),
+        other => { return Err(no_encoder(other)); }
+    };
+    Ok(CompressWriter{encoder: encoder})
+}
+
+/// Check that `decode` copes with damaged copies of `data`, a valid
+/// compressed stream.  It must reject every truncated copy, and it mustn't
+/// fail the task on random corruption, or on garbage after a good header,
+/// whatever it returns.  The seed is fixed, so failures are reproducible.
+#[cfg(test)]
+pub fn check_damaged_streams(data: &[u8],
+                             decode: |Vec<u8>| -> IoResult<Vec<u8>>) {
+    let step = max(1, data.len() / 64);
+    let cuts = range(1, data.len()).filter(|&cut| {
+        cut % step == 0 || cut + 8 >= data.len()
+    });
+    for cut in cuts {
+        assert!(decode(data.slice_to(cut).to_vec()).is_err(),
+                "accepted {} of {} bytes", cut, data.len());
+    }
+
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    for _ in range(0u, 200) {
+        let mut damaged = data.to_vec();
+        for _ in range(0, rng.gen_range(1u, 4)) {
+            let i = rng.gen_range(0, damaged.len());
+            damaged.as_mut_slice()[i] = rng.gen();
+        }
+        let _ = decode(damaged);
+    }
+    for _ in range(0u, 50) {
+        let mut garbage = data.slice_to(min(data.len(), 10)).to_vec();
+        for _ in range(0, rng.gen_range(0u, 300)) { garbage.push(rng.gen()); }
+        let _ = decode(garbage);
+    }
+}
+
+#[test]
+fn detects_magic_bytes() {
+    assert_eq!(Gzip, detect(&[0x1f, 0x8b, 0x08, 0x00]));
+    assert_eq!(Zlib, detect(&[0x78, 0x9c]));
+    assert_eq!(SnappyFramed, detect(SNAPPY_MAGIC));
+    // Formats we can't decode aren't detected.
+    assert_eq!(Uncompressed, detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]));
+    assert_eq!(Uncompressed, detect(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]));
+    assert_eq!(Bzip2, detect(b"BZh91AY&SY"));
+    assert_eq!(Lz4, detect(&[0x04, 0x22, 0x4d, 0x18]));
+    assert_eq!(Uncompressed, detect(b"1\tchapitre\tchapitre"));
+    assert_eq!(Uncompressed, detect(b""));
+}
+
+#[test]
+fn text_is_not_zlib() {
+    assert_eq!(Zlib, detect(&[0x78, 0x01]));
+    assert_eq!(Zlib, detect(&[0x78, 0xda]));
+    for text in ["800,1\n", "x y", "H,e", "(4)", "80,90"].iter() {
+        assert_eq!(Uncompressed, detect(text.as_bytes()));
+    }
+}
+
+#[test]
+fn guesses_format_from_extension() {
+    assert_eq!(Gzip, from_extension(&Path::new("out/data.csv.gz")));
+    assert_eq!(SnappyFramed, from_extension(&Path::new("data.sz")));
+    assert_eq!(Uncompressed, from_extension(&Path::new("data.csv")));
+    assert_eq!(Uncompressed, from_extension(&Path::new("data")));
+}
+
+#[test]
+fn decompresses_gzip_automatically() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let file = File::open(&Path::new("test_data/fr/sample.conllx.gz"));
+    let mut input = decompress_auto(BufferedReader::new(file)).unwrap();
+    assert_eq!(expected, input.read_to_end().unwrap());
+}
+
+#[test]
+fn compressed_output_reads_back() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    for &format in [Uncompressed, Gzip, SnappyFramed].iter() {
+        let mut out = compress(MemWriter::new(), format).unwrap();
+        out.write(expected.as_slice()).unwrap();
+        out.finish().unwrap();
+        let compressed = out.unwrap().unwrap();
+        assert_eq!(format, detect(compressed.as_slice()));
+        let mut input = decompress_auto(MemReader::new(compressed)).unwrap();
+        assert_eq!(expected, input.read_to_end().unwrap());
+    }
+    assert!(check_encoder(SnappyFramed).is_ok());
+    assert!(check_encoder(Zstd).is_err());
+    assert!(compress(MemWriter::new(), Xz).is_err());
+}
@@ -0,0 +1,151 @@
+//! CoNLL-X dependency treebanks.
+//!
+//! Each token is a line of ten tab-separated columns:
+//!
+//! ```text
+//! ID FORM LEMMA CPOSTAG POSTAG FEATS HEAD DEPREL PHEAD PDEPREL
+//! ```
+//!
+//! Sentences are separated by blank lines, and lines starting with `#` are
+//! comments.  `Tokens` parses a slice holding whole sentences without
+//! copying anything, and numbers the sentences as it goes, so that
+//! `group_by` can split its output back into sentences.
+
+use std::io::{InvalidInput,IoError,IoResult};
+use std::str::from_utf8;
+
+use buffers::memchr;
+
+/// A single token, borrowed from the line it was parsed from.  We don't
+/// bother with the projective head columns.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Token<'a> {
+    /// The number of the sentence this token belongs to, counting from 0
+    /// at the start of the input.
+    pub sentence: uint,
+    /// The token's position in its sentence, counting from 1.
+    pub id: uint,
+    /// The word itself.
+    pub form: &'a [u8],
+    /// Its lemma.
+    pub lemma: &'a [u8],
+    /// The coarse part-of-speech tag.
+    pub cpostag: &'a [u8],
+    /// The fine part-of-speech tag.
+    pub postag: &'a [u8],
+    /// Morphological features, separated by `|`.
+    pub feats: &'a [u8],
+    /// The `id` of this token's head, or 0 for the root.
+    pub head: uint,
+    /// The dependency relation to the head.
+    pub deprel: &'a [u8]
+}
+
+impl<'a> Token<'a> {
+    /// Parse `line`, without its newline, as a token of sentence number
+    /// `sentence`.
+    pub fn parse(sentence: uint, line: &'a [u8]) -> IoResult<Token<'a>> {
+        let mut columns = line.split(|&b| b == b'\t');
+        let id = try!(next_column(&mut columns, line));
+        let form = try!(next_column(&mut columns, line));
+        let lemma = try!(next_column(&mut columns, line));
+        let cpostag = try!(next_column(&mut columns, line));
+        let postag = try!(next_column(&mut columns, line));
+        let feats = try!(next_column(&mut columns, line));
+        let head = try!(next_column(&mut columns, line));
+        let deprel = try!(next_column(&mut columns, line));
+        Ok(Token{sentence: sentence, id: try!(parse_number(id)), form: form,
+                 lemma: lemma, cpostag: cpostag, postag: postag,
+                 feats: feats, head: try!(parse_number(head)),
+                 deprel: deprel})
+    }
+
+    /// How far this token is from its head, or `None` for the root.
+    pub fn dependency_length(&self) -> Option<uint> {
+        if self.head == 0 { return None; }
+        Some(if self.id > self.head { self.id - self.head }
+             else { self.head - self.id })
+    }
+}
+
+fn next_column<'a, I: Iterator<&'a [u8]>>(columns: &mut I, line: &[u8])
+                                         -> IoResult<&'a [u8]> {
+    match columns.next() {
+        Some(column) => Ok(column),
+        None => Err(malformed("CoNLL line has too few columns", line))
+    }
+}
+
+fn malformed(desc: &'static str, text: &[u8]) -> IoError {
+    IoError{kind: InvalidInput, desc: desc,
+            detail: from_utf8(text).map(|t| t.to_string())}
+}
+
+fn parse_number(column: &[u8]) -> IoResult<uint> {
+    match from_utf8(column).and_then(from_str) {
+        Some(n) => Ok(n),
+        None => Err(malformed("expected a number in CoNLL column", column))
+    }
+}
+
+/// An iterator over the tokens in a slice of whole sentences.  Wrap it in
+/// an `IterAdapter` to use it in a streaming pipeline.
+pub struct Tokens<'a> {
+    rest: &'a [u8],
+    sentence: uint,
+    // Have we seen a token since the last blank line?
+    in_sentence: bool
+}
+
+impl<'a> Tokens<'a> {
+    /// Parse the tokens in `data`.
+    pub fn new(data: &'a [u8]) -> Tokens<'a> {
+        Tokens{rest: data, sentence: 0, in_sentence: false}
+    }
+}
+
+impl<'a> Iterator<IoResult<Token<'a>>> for Tokens<'a> {
+    fn next(&mut self) -> Option<IoResult<Token<'a>>> {
+        while !self.rest.is_empty() {
+            let (line, rest) = match memchr(b'\n', self.rest) {
+                Some(pos) => (self.rest[..pos], self.rest[pos + 1..]),
+                None => (self.rest, self.rest[self.rest.len()..])
+            };
+            self.rest = rest;
+            if line.is_empty() {
+                if self.in_sentence {
+                    self.sentence += 1;
+                    self.in_sentence = false;
+                }
+            } else if !line.starts_with(b"#") {
+                self.in_sentence = true;
+                return Some(Token::parse(self.sentence, line));
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn parses_tokens() {
+    let line = b"2\tpremier\tpremier\tA\tADJ\t_\t1\tmod\t_\t_";
+    let token = Token::parse(0, line).unwrap();
+    assert_eq!((2, 1), (token.id, token.head));
+    assert_eq!(b"premier".to_vec(), token.form.to_vec());
+    assert_eq!(b"A".to_vec(), token.cpostag.to_vec());
+    assert_eq!(b"mod".to_vec(), token.deprel.to_vec());
+    assert_eq!(Some(1), token.dependency_length());
+    assert!(Token::parse(0, b"2\tpremier\tpremier").is_err());
+    let bad_id = b"x\tpremier\tpremier\tA\tADJ\t_\t1\tmod";
+    assert!(Token::parse(0, bad_id).is_err());
+}
+
+#[test]
+fn numbers_sentences() {
+    let data = b"# doc\n1\ta\ta\tD\tDET\t_\t2\tdet\n\
+                 2\tb\tb\tN\tNC\t_\t0\troot\n\n\n\
+                 1\tc\tc\tV\tV\t_\t0\troot\n";
+    let tokens: Vec<(uint, uint)> = Tokens::new(data)
+        .map(|t| { let t = t.unwrap(); (t.sentence, t.id) }).collect();
+    assert_eq!(vec![(0, 1), (0, 2), (1, 1)], tokens);
+}
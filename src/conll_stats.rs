@@ -0,0 +1,186 @@
+//! A worked example: corpus statistics for CoNLL-X files.
+//!
+//! This puts several pieces of the library together the way a real tool
+//! would.  A `ChunkBuffer` cuts the input at the blank lines between
+//! sentences, so we always have whole sentences in hand, no matter how the
+//! underlying reader splits things up.  The `conll` parser turns them into
+//! tokens, `group_by` splits the tokens back into sentences, and two
+//! aggregation sinks keep running totals:
+//!
+//! ```ignore
+//! let mut input = BufferedReader::new(try!(File::open(&path)));
+//! let stats = try!(conll_stats(&mut input));
+//! try!(stats.write_report(&mut stdout()));
+//! ```
+//!
+//! We count tokens by coarse part-of-speech tag (the fourth column) with a
+//! `CountSink`, and build a `HistogramSink` of dependency lengths: the
+//! distance between each token and its head, ignoring the root.  Comment
+//! lines are skipped.
+
+use std::collections::TreeMap;
+use std::io::{Buffer,EndOfFile,IoError,IoResult};
+
+use buffers::ChunkBuffer;
+use conll::Tokens;
+use iter::{IterAdapter,StreamingIterator,StreamingIteratorExt};
+use sinks::{CountSink,HistogramSink,RecordSink};
+
+#[cfg(test)] use std::io::{File,MemReader,MemWriter};
+#[cfg(test)] use buffers::DribbleBuffer;
+
+/// Running statistics for a CoNLL-X corpus.
+pub struct ConllStats {
+    sentences: u64,
+    pos_counts: CountSink,
+    dependency_lengths: HistogramSink
+}
+
+impl ConllStats {
+    /// Create an empty set of statistics.
+    pub fn new() -> ConllStats {
+        ConllStats{sentences: 0, pos_counts: CountSink::new(),
+                   dependency_lengths: HistogramSink::new()}
+    }
+
+    /// The number of sentences seen.
+    pub fn sentences(&self) -> u64 { self.sentences }
+
+    /// The number of tokens seen.
+    pub fn tokens(&self) -> u64 { self.pos_counts.total() }
+
+    /// The average number of tokens per sentence.
+    pub fn average_sentence_length(&self) -> f64 {
+        if self.sentences == 0 { return 0.0; }
+        self.tokens() as f64 / self.sentences as f64
+    }
+
+    /// The number of tokens with each coarse part-of-speech tag.
+    pub fn pos_counts(&self) -> &TreeMap<Vec<u8>, u64> {
+        self.pos_counts.counts()
+    }
+
+    /// How many dependencies have each length, indexed by length.
+    pub fn dependency_lengths(&self) -> &[u64] {
+        self.dependency_lengths.counts()
+    }
+
+    /// Print a human-readable summary to `writer`.
+    pub fn write_report<W: Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(write!(writer, "sentences: {}\ntokens: {}\n",
+                    self.sentences, self.tokens()));
+        try!(write!(writer, "average sentence length: {:.2}\n",
+                    self.average_sentence_length()));
+        try!(writer.write(b"tokens by POS:\n"));
+        for (pos, count) in self.pos_counts().iter() {
+            try!(writer.write(b"  "));
+            try!(writer.write(pos.as_slice()));
+            try!(write!(writer, ": {}\n", count));
+        }
+        try!(writer.write(b"dependency lengths:\n"));
+        for (len, &count) in self.dependency_lengths().iter().enumerate() {
+            if count > 0 { try!(write!(writer, "  {}: {}\n", len, count)); }
+        }
+        Ok(())
+    }
+}
+
+impl RecordSink for ConllStats {
+    /// Add one or more whole sentences.
+    fn write_record(&mut self, sentences: &[u8]) -> IoResult<()> {
+        let tokens = IterAdapter::new(Tokens::new(sentences));
+        let mut groups = tokens.group_by(|token| match *token {
+            Ok(ref token) => Some(token.sentence),
+            Err(_) => None
+        });
+        streaming_for!(mut sentence in groups, {
+            streaming_for!(token in sentence, {
+                let token = try!(token);
+                try!(self.pos_counts.write_record(token.cpostag));
+                match token.dependency_length() {
+                    Some(len) => { self.dependency_lengths.add(len); }
+                    None => {}
+                }
+            });
+            self.sentences += 1;
+        });
+        Ok(())
+    }
+}
+
+// How much of `data` is whole sentences, up to and including the last
+// blank line.  If there isn't one, it's all one sentence.
+fn whole_sentences(data: &[u8]) -> uint {
+    let mut end = data.len();
+    while end >= 2 {
+        if data[end - 2] == b'\n' && data[end - 1] == b'\n' { return end; }
+        end -= 1;
+    }
+    data.len()
+}
+
+/// Read a CoNLL-X corpus from `input`, and compute its statistics.
+pub fn conll_stats<R: Buffer>(input: &mut R) -> IoResult<ConllStats> {
+    let mut stats = ConllStats::new();
+    let mut chunks = ChunkBuffer::new(input, b"\n\n");
+    loop {
+        let consumed = {
+            let data = match chunks.fill_buf() {
+                Err(IoError{kind: EndOfFile, ..}) => { break; }
+                Err(err) => { return Err(err); }
+                Ok(data) => data
+            };
+            // Feed in every whole sentence we've got.  If there aren't
+            // any, we're at the end of the input, and what's left is the
+            // last sentence.
+            let len = whole_sentences(data);
+            try!(stats.write_record(data[..len]));
+            len
+        };
+        chunks.consume(consumed);
+    }
+    try!(stats.finish());
+    Ok(stats)
+}
+
+#[cfg(test)]
+fn sample() -> Vec<u8> {
+    let path = Path::new("test_data/fr/sample.conllx");
+    File::open(&path).read_to_end().unwrap()
+}
+
+#[test]
+fn computes_corpus_statistics() {
+    let mut input = MemReader::new(sample());
+    let stats = conll_stats(&mut input).unwrap();
+    assert_eq!(4, stats.sentences());
+    assert_eq!(131, stats.tokens());
+    assert_eq!(32.75, stats.average_sentence_length());
+    assert_eq!(Some(&32), stats.pos_counts().find(&b"N".to_vec()));
+    assert_eq!(Some(&5), stats.pos_counts().find(&b"P+D".to_vec()));
+    let lengths = stats.dependency_lengths();
+    assert_eq!((71, 25, 1), (lengths[1], lengths[2], lengths[51]));
+    assert_eq!(127, lengths.iter().fold(0, |a, &b| a + b));
+
+    let mut report = MemWriter::new();
+    stats.write_report(&mut report).unwrap();
+    let report = String::from_utf8(report.unwrap()).unwrap();
+    assert!(report.as_slice().starts_with(
+        "sentences: 4\ntokens: 131\naverage sentence length: 32.75\n"));
+    assert!(report.as_slice().contains("  PONCT: 15\n"));
+}
+
+#[test]
+fn reports_malformed_tokens() {
+    let mut input = MemReader::new(b"1\ta\ta\tN\n\n".to_vec());
+    assert!(conll_stats(&mut input).is_err());
+}
+
+#[test]
+fn statistics_survive_dribbled_input() {
+    let mut reader = MemReader::new(sample());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let stats = conll_stats(&mut dribble).unwrap();
+    assert_eq!((4, 131), (stats.sentences(), stats.tokens()));
+    assert_eq!(127, stats.dependency_lengths().iter().fold(0, |a, &b| a + b));
+}
@@ -0,0 +1,220 @@
+//! Joining continued lines into logical lines.
+//!
+//! Lots of line-oriented formats let you continue a long line by ending it
+//! with a marker: a backslash in shell scripts and Makefiles, a trailing
+//! comma in some config formats.  `ContinuationBuffer` hides this from
+//! downstream parsers, which only ever see complete logical lines.
+
+use std::cmp::min;
+use std::io::{Buffer,EndOfFile,IoError,IoResult};
+
+use buffers::{Double,GrowthPolicy,push_growing};
+
+#[cfg(test)] use std::io::MemReader;
+#[cfg(test)] use buffers::{Capped,DribbleBuffer};
+
+/// A buffer which returns exactly one logical line from each call to
+/// `fill_buf`, joining physical lines which end in a continuation marker.
+///
+/// When a line is already complete in the underlying buffer, we return it
+/// directly.  We only copy data into our scratch buffer when we actually
+/// need to stitch lines together, or when a line straddles two reads from
+/// the underlying buffer.
+pub struct ContinuationBuffer<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    marker: Vec<u8>,
+    strip_marker: bool,
+    scratch: Vec<u8>,
+    // Does `scratch` hold the (rest of the) current logical line?
+    complete: bool,
+    growth: GrowthPolicy,
+    high_water: uint
+}
+
+impl<'a, T: Buffer+'a> ContinuationBuffer<'a, T> {
+    /// Join lines in `input` which end with `marker`.  If `strip_marker`
+    /// is true, the marker is removed when lines are joined (as you'd want
+    /// for a backslash); otherwise it's kept (as you'd want for a comma).
+    /// The newline after a marker is always removed.
+    pub fn new(input: &'a mut T, marker: &[u8], strip_marker: bool)
+               -> ContinuationBuffer<'a, T> {
+        assert!(marker.len() > 0);
+        ContinuationBuffer{input: input, marker: marker.to_vec(),
+                           strip_marker: strip_marker, scratch: vec![],
+                           complete: false, growth: Double, high_water: 0}
+    }
+
+    /// Grow our scratch buffer according to `policy`.  A `Capped` policy
+    /// bounds the length of a logical line.
+    pub fn with_growth(mut self, policy: GrowthPolicy)
+                       -> ContinuationBuffer<'a, T> {
+        self.growth = policy;
+        self
+    }
+
+    /// The most memory our scratch buffer has ever used, in bytes.
+    pub fn high_water_mark(&self) -> uint { self.high_water }
+}
+
+impl<'a, T: Buffer+'a> Reader for ContinuationBuffer<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(buf.len(), data.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<'a, T: Buffer+'a> Buffer for ContinuationBuffer<'a, T> {
+    fn fill_buf<'b>(&'b mut self) -> IoResult<&'b [u8]> {
+        if self.complete {
+            // Exit 1: We've already assembled a logical line.
+            return Ok(self.scratch.as_slice());
+        }
+        loop {
+            let (consumed, line) = {
+                let read = match self.input.fill_buf() {
+                    Err(IoError{kind: EndOfFile, ..})
+                        if self.scratch.len() > 0 => {
+                        // Exit 2: The input ended without a newline, so
+                        // return what we have.
+                        self.complete = true;
+                        return Ok(self.scratch.as_slice());
+                    }
+                    // Exit 3: EOF or a real error.
+                    Err(err) => { return Err(err); }
+                    Ok(read) => read
+                };
+                match read.iter().position(|&b| b == b'\n') {
+                    None => {
+                        // A partial line; we'll need to stitch.
+                        try!(push_growing(&mut self.scratch, &self.growth,
+                                          &mut self.high_water, read));
+                        (read.len(), None)
+                    }
+                    Some(pos) => {
+                        let marker = self.marker.as_slice();
+                        if self.scratch.len() == 0 &&
+                            !read.slice_to(pos).ends_with(marker) {
+                            // A complete line, with no stitching required.
+                            (0, Some(pos + 1))
+                        } else {
+                            // The marker may straddle two reads, so check
+                            // the stitched line, not just this piece.
+                            try!(push_growing(&mut self.scratch, &self.growth,
+                                              &mut self.high_water,
+                                              read.slice_to(pos + 1)));
+                            let len = self.scratch.len() - 1;
+                            if !self.scratch.slice_to(len).ends_with(marker) {
+                                self.complete = true;
+                            } else {
+                                // Drop the newline, and the marker if asked.
+                                let keep = if self.strip_marker {
+                                    len - marker.len()
+                                } else {
+                                    len
+                                };
+                                self.scratch.truncate(keep);
+                            }
+                            (pos + 1, None)
+                        }
+                    }
+                }
+            };
+            match line {
+                Some(len) => {
+                    // Exit 4: Return the complete line straight from the
+                    // input.  Returning `read` from inside the block above
+                    // would keep `input` borrowed for the rest of the loop
+                    // (see rust-lang/rust#12147), so we ask for it again.
+                    // Nothing was consumed, so it's the same data.
+                    let read = try!(self.input.fill_buf());
+                    return Ok(read.slice_to(len));
+                }
+                None => {}
+            }
+            self.input.consume(consumed);
+            if self.complete {
+                // Exit 5: We've stitched together a logical line.
+                return Ok(self.scratch.as_slice());
+            }
+        }
+    }
+
+    fn consume(&mut self, amt: uint) {
+        if self.complete {
+            assert!(amt <= self.scratch.len());
+            let keeping = self.scratch.len() - amt;
+            for i in range(0, keeping) {
+                self.scratch.as_mut_slice()[i] = self.scratch[amt + i];
+            }
+            self.scratch.truncate(keeping);
+            if keeping == 0 { self.complete = false; }
+        } else {
+            self.input.consume(amt);
+        }
+    }
+}
+
+#[cfg(test)]
+fn logical_lines<T: Buffer>(input: &mut T) -> Vec<String> {
+    let mut joined = ContinuationBuffer::new(input, b"\\", true);
+    let mut lines = vec![];
+    loop {
+        let consumed = {
+            match joined.fill_buf() {
+                Ok(data) => {
+                    lines.push(String::from_utf8(data.to_vec()).unwrap());
+                    data.len()
+                }
+                Err(IoError{kind: EndOfFile, ..}) => { break; }
+                Err(err) => { fail!("{}", err); }
+            }
+        };
+        joined.consume(consumed);
+    }
+    lines
+}
+
+#[cfg(test)]
+static CONTINUED: &'static [u8] = b"one\ntwo \\\nthree \\\nfour\nfive\\\n";
+
+#[test]
+fn continued_lines_are_joined() {
+    let mut reader = MemReader::new(CONTINUED.to_vec());
+    assert_eq!(vec!["one\n".to_string(), "two three four\n".to_string(),
+                    "five".to_string()],
+               logical_lines(&mut reader));
+}
+
+#[test]
+fn continued_lines_are_joined_via_dribble() {
+    let mut reader = MemReader::new(CONTINUED.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let joined: String = logical_lines(&mut dribble).concat();
+    assert_eq!("one\ntwo three four\nfive", joined.as_slice());
+}
+
+#[test]
+fn continuation_buffer_reports_high_water_mark() {
+    let mut reader = MemReader::new(CONTINUED.to_vec());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut joined = ContinuationBuffer::new(&mut dribble, b"\\", true)
+        .with_growth(Capped(64));
+    let mut text = vec![];
+    loop {
+        let len = match joined.fill_buf() {
+            Ok(data) => { text.push_all(data); data.len() }
+            Err(IoError{kind: EndOfFile, ..}) => { break; }
+            Err(err) => { fail!("{}", err); }
+        };
+        joined.consume(len);
+    }
+    assert_eq!(b"one\ntwo three four\nfive", text.as_slice());
+    let high_water = joined.high_water_mark();
+    assert!(high_water > 0 && high_water <= 64);
+}
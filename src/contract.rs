@@ -0,0 +1,353 @@
+//! Checking records against a data contract.
+//!
+//! When two teams swap large extracts, they usually agree on more than a
+//! list of columns: which fields must be present, what ranges numbers fall
+//! in, which codes are allowed.  A `Contract` writes those rules down, and
+//! a `ValidationSink` checks every record against them as it streams past.
+//! Records which pass go on to an output sink, and each violation is
+//! reported as a line of NDJSON:
+//!
+//! ```text
+//! {"record":3,"column":1,"field":"age","problem":"out of range","value":"-1"}
+//! ```
+//!
+//! Patterns are a small subset of regular expressions, which must match the
+//! whole value: literal bytes, `.` for any byte, the classes `\d`, `\w` and
+//! `\s`, backslash escapes for everything else, and the repetitions `*`,
+//! `+` and `?`.
+
+use std::io::IoResult;
+use std::str::from_utf8;
+
+use csv::{Dialect,split_fields};
+use schema::{ColumnType,Float,Integer};
+use sinks::RecordSink;
+
+#[cfg(test)] use std::io::MemWriter;
+#[cfg(test)] use schema::Text;
+#[cfg(test)] use sinks::WriterSink;
+
+/// Ways in which a field can break its rule.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Problem {
+    /// A required field was absent or empty.
+    MissingField,
+    /// The value isn't of the field's type.
+    WrongType,
+    /// A number was outside the allowed range.
+    OutOfRange,
+    /// The value wasn't one of the allowed values.
+    NotAllowed,
+    /// The value didn't match the field's pattern.
+    NoMatch,
+    /// The record had more fields than the contract.
+    ExtraField
+}
+
+impl Problem {
+    /// A short description of this problem, as used in reports.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            MissingField => "missing",
+            WrongType => "wrong type",
+            OutOfRange => "out of range",
+            NotAllowed => "not allowed",
+            NoMatch => "does not match pattern",
+            ExtraField => "unexpected field"
+        }
+    }
+}
+
+/// The rules for a single field.
+#[deriving(PartialEq, Clone, Show)]
+pub struct FieldRule {
+    /// The field's name, used in reports.
+    pub name: String,
+    /// The type of the field's values.
+    pub ty: ColumnType,
+    /// Must the field be present and non-empty?
+    pub required: bool,
+    /// The smallest allowed number, if any.
+    pub min: Option<f64>,
+    /// The largest allowed number, if any.
+    pub max: Option<f64>,
+    /// The allowed values.  Empty means anything goes.
+    pub allowed: Vec<Vec<u8>>,
+    /// A pattern which the whole value must match, if any.
+    pub pattern: Option<Vec<u8>>
+}
+
+impl FieldRule {
+    /// An optional field of type `ty`, with no other rules.
+    pub fn new(name: &str, ty: ColumnType) -> FieldRule {
+        FieldRule{name: name.to_string(), ty: ty, required: false, min: None,
+                  max: None, allowed: vec![], pattern: None}
+    }
+
+    /// Require this field to be present and non-empty.
+    pub fn required(mut self) -> FieldRule {
+        self.required = true;
+        self
+    }
+
+    /// Only allow numbers from `min` to `max`, inclusive.
+    pub fn range(mut self, min: f64, max: f64) -> FieldRule {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    /// Only allow the listed values.
+    pub fn one_of(mut self, values: &[&[u8]]) -> FieldRule {
+        self.allowed = values.iter().map(|v| v.to_vec()).collect();
+        self
+    }
+
+    /// Only allow values matching `pattern`.  See the module documentation
+    /// for the syntax.
+    pub fn pattern(mut self, pattern: &str) -> FieldRule {
+        self.pattern = Some(pattern.as_bytes().to_vec());
+        self
+    }
+
+    /// Check `value` against this rule, returning the first problem found.
+    /// Pass `None` if the field was absent altogether.
+    pub fn check(&self, value: Option<&[u8]>) -> Option<Problem> {
+        let value = match value {
+            Some(value) if !value.is_empty() => value,
+            _ if self.required => { return Some(MissingField); }
+            _ => { return None; }
+        };
+        if !self.ty.matches(value) { return Some(WrongType); }
+        if self.ty == Integer || self.ty == Float {
+            let n = from_utf8(value).and_then(|s| from_str::<f64>(s));
+            let low = n.map_or(false, |n| self.min.map_or(false, |m| n < m));
+            let high = n.map_or(false, |n| self.max.map_or(false, |m| n > m));
+            if low || high { return Some(OutOfRange); }
+        }
+        if !self.allowed.is_empty() &&
+            !self.allowed.iter().any(|a| a.as_slice() == value) {
+            return Some(NotAllowed);
+        }
+        match self.pattern {
+            Some(ref pattern) if !matches(pattern.as_slice(), value) => {
+                Some(NoMatch)
+            }
+            _ => None
+        }
+    }
+}
+
+/// The rules for a whole record, one per field, in order.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Contract {
+    /// The rules for each field.
+    pub fields: Vec<FieldRule>
+}
+
+impl Contract {
+    /// Create a contract with the specified field rules.
+    pub fn new(fields: Vec<FieldRule>) -> Contract {
+        Contract{fields: fields}
+    }
+
+    /// Check `fields` against our rules, storing the column index and
+    /// problem for each violation in `problems` (which is cleared first).
+    pub fn check(&self, fields: &[&[u8]], problems: &mut Vec<(uint, Problem)>) {
+        problems.clear();
+        for (i, rule) in self.fields.iter().enumerate() {
+            let value = fields.get(i).map(|v| *v);
+            match rule.check(value) {
+                Some(problem) => { problems.push((i, problem)); }
+                None => {}
+            }
+        }
+        for i in range(self.fields.len(), fields.len()) {
+            problems.push((i, ExtraField));
+        }
+    }
+}
+
+/// A `RecordSink` which checks CSV records against a `Contract`.  Valid
+/// records are passed on to `output`, and each violation is written to
+/// `report` as an NDJSON record.
+pub struct ValidationSink<S, R> {
+    contract: Contract,
+    dialect: Dialect,
+    output: S,
+    report: R,
+    records: u64,
+    invalid: u64,
+    spans: Vec<(uint, uint)>,
+    problems: Vec<(uint, Problem)>,
+    line: Vec<u8>
+}
+
+impl<S: RecordSink, R: RecordSink> ValidationSink<S, R> {
+    /// Check records written in `dialect` against `contract`.
+    pub fn new(contract: Contract, dialect: Dialect, output: S, report: R)
+               -> ValidationSink<S, R> {
+        ValidationSink{contract: contract, dialect: dialect, output: output,
+                       report: report, records: 0, invalid: 0, spans: vec![],
+                       problems: vec![], line: vec![]}
+    }
+
+    /// How many records we've checked.
+    pub fn records(&self) -> u64 { self.records }
+
+    /// How many of those records broke the contract.
+    pub fn invalid_records(&self) -> u64 { self.invalid }
+
+    /// Get back our output and report sinks.
+    pub fn unwrap(self) -> (S, R) { (self.output, self.report) }
+}
+
+impl<S: RecordSink, R: RecordSink> RecordSink for ValidationSink<S, R> {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        let ValidationSink{ref contract, ref dialect, ref mut output,
+                           ref mut report, ref mut records, ref mut invalid,
+                           ref mut spans, ref mut problems,
+                           ref mut line} = *self;
+        *records += 1;
+        split_fields(record, dialect, spans);
+        let fields: Vec<&[u8]> =
+            spans.iter().map(|&(s, e)| record.slice(s, e)).collect();
+        contract.check(fields.as_slice(), problems);
+        if problems.is_empty() { return output.write_record(record); }
+
+        *invalid += 1;
+        for &(i, ref problem) in problems.iter() {
+            line.clear();
+            line.push_all(format!("{{\"record\":{},\"column\":{},\"field\":",
+                                  *records, i).as_bytes());
+            match contract.fields.get(i) {
+                Some(rule) => push_json_string(line, rule.name.as_bytes()),
+                None => line.push_all(b"null")
+            }
+            line.push_all(b",\"problem\":");
+            push_json_string(line, problem.description().as_bytes());
+            line.push_all(b",\"value\":");
+            match fields.get(i) {
+                Some(value) => push_json_string(line, *value),
+                None => line.push_all(b"null")
+            }
+            line.push(b'}');
+            try!(report.write_record(line.as_slice()));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        try!(self.output.finish());
+        self.report.finish()
+    }
+}
+
+// Append `value` to `out` as a JSON string, replacing invalid UTF-8.
+fn push_json_string(out: &mut Vec<u8>, value: &[u8]) {
+    let text = String::from_utf8_lossy(value);
+    out.push(b'"');
+    for &b in text.as_slice().as_bytes().iter() {
+        match b {
+            b'"' => out.push_all(b"\\\""),
+            b'\\' => out.push_all(b"\\\\"),
+            b if b < b' ' => out.push_all(format!("\\u{:04x}", b).as_bytes()),
+            b => out.push(b)
+        }
+    }
+    out.push(b'"');
+}
+
+//=========================================================================
+// Patterns
+
+// Does `pattern` match all of `text`?
+fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    if pattern.is_empty() { return text.is_empty(); }
+    let atom_len = if pattern[0] == b'\\' && pattern.len() > 1 { 2 } else { 1 };
+    let atom = pattern.slice_to(atom_len);
+    let rest = pattern.slice_from(atom_len);
+    let first = !text.is_empty() && atom_matches(atom, text[0]);
+    match rest.head() {
+        Some(&b'*') => matches_repeated(atom, rest.slice_from(1), text),
+        Some(&b'+') => {
+            first && matches_repeated(atom, rest.slice_from(1),
+                                      text.slice_from(1))
+        }
+        Some(&b'?') => {
+            (first && matches(rest.slice_from(1), text.slice_from(1))) ||
+                matches(rest.slice_from(1), text)
+        }
+        _ => first && matches(rest, text.slice_from(1))
+    }
+}
+
+// Does `pattern` match all of `text` after zero or more copies of `atom`?
+fn matches_repeated(atom: &[u8], pattern: &[u8], text: &[u8]) -> bool {
+    let mut i = 0;
+    loop {
+        if matches(pattern, text.slice_from(i)) { return true; }
+        if i < text.len() && atom_matches(atom, text[i]) {
+            i += 1;
+        } else {
+            return false;
+        }
+    }
+}
+
+fn atom_matches(atom: &[u8], b: u8) -> bool {
+    if atom.len() == 1 { return atom[0] == b'.' || atom[0] == b; }
+    match atom[1] {
+        b'd' => b >= b'0' && b <= b'9',
+        b'w' => {
+            (b >= b'0' && b <= b'9') || (b >= b'a' && b <= b'z') ||
+                (b >= b'A' && b <= b'Z') || b == b'_'
+        }
+        b's' => b == b' ' || b == b'\t' || b == b'\r' || b == b'\n',
+        c => b == c
+    }
+}
+
+#[test]
+fn patterns_match_whole_values() {
+    assert!(matches(b"\\d\\d\\d-\\d+", b"555-1234"));
+    assert!(!matches(b"\\d\\d\\d-\\d+", b"555-"));
+    assert!(!matches(b"\\d\\d\\d", b"5555"));
+    assert!(matches(b".+@\\w+\\.com", b"ann@example.com"));
+    assert!(!matches(b".+@\\w+\\.com", b"ann@examplexcom"));
+    assert!(matches(b"colou?r", b"color") && matches(b"colou?r", b"colour"));
+    assert!(matches(b"a*b", b"b") && matches(b"a*b", b"aaab"));
+}
+
+#[test]
+fn validation_sink_reports_violations() {
+    let contract = Contract::new(vec![
+        FieldRule::new("id", Integer).required().range(1.0, 1000.0),
+        FieldRule::new("status", Text).one_of(&[b"open", b"closed"]),
+        FieldRule::new("email", Text).pattern(".+@.+")
+    ]);
+    let output = WriterSink::new(MemWriter::new(), b"\n");
+    let report = WriterSink::new(MemWriter::new(), b"\n");
+    let mut sink = ValidationSink::new(contract, Dialect::csv(), output,
+                                       report);
+    sink.write_record(b"1,open,ann@example.com").unwrap();
+    sink.write_record(b"2,,").unwrap();
+    sink.write_record(b"0,\"lost\",bob").unwrap();
+    sink.write_record(b",open,x@y,extra").unwrap();
+    sink.finish().unwrap();
+    assert_eq!(4, sink.records());
+    assert_eq!(2, sink.invalid_records());
+
+    let (output, report) = sink.unwrap();
+    assert_eq!(b"1,open,ann@example.com\n2,,\n", output.unwrap().get_ref());
+    let report = String::from_utf8(report.unwrap().unwrap()).unwrap();
+    let lines: Vec<&str> = report.as_slice().lines().collect();
+    assert_eq!(5, lines.len());
+    let first = concat!(r#"{"record":3,"column":0,"field":"id","#,
+                        r#""problem":"out of range","value":"0"}"#);
+    assert_eq!(first, lines[0]);
+    assert!(lines[1].contains(r#""field":"status","problem":"not allowed""#));
+    assert!(lines[2].contains(r#""problem":"does not match pattern""#));
+    assert!(lines[3].contains(r#""field":"id","problem":"missing""#));
+    assert!(lines[4].contains(r#""field":null,"problem":"unexpected field""#));
+}
@@ -2,11 +2,17 @@
 #![allow(dead_code)]
 #![allow(unused_variable)]
 
+use std::io::{Buffer,IoResult};
 use std::path::BytesContainer;
 
-trait StreamIterator<Sized? A> {
-    fn next_item<'a>(&'a mut self) -> Option<&'a A>;
-}
+use buffers::{ChunkBuffer,memchr_swar};
+use iter::StreamingIterator;
+
+#[cfg(feature = "experimental")]
+use experimental::{AssocStreamingIterator,Slices};
+
+#[cfg(test)] use std::io::BufReader;
+#[cfg(test)] use buffers::DribbleBuffer;
 
 struct CsvRdr;
 struct CsvWtr;
@@ -22,8 +28,8 @@ impl CsvRdr {
 /// Subsequent invocations of the iterator yield fields from the next
 /// record. If the underlying data stream has been exhausted (or if there
 /// was an error parsing the data), `None` is returned indefinitely.
-impl StreamIterator<[u8]> for CsvRdr {
-    fn next_item<'a>(&'a mut self) -> Option<&'a [u8]> {
+impl<'a> StreamingIterator<'a, &'a [u8]> for CsvRdr {
+    fn next(&'a mut self) -> Option<&'a [u8]> {
         // In real usage, this would return a slice of bytes from the CSV's
         // underlying data stream.
         // The slow version is allocating a new `Vec<u8>` and yielding that
@@ -34,11 +40,20 @@ impl StreamIterator<[u8]> for CsvRdr {
     }
 }
 
+/// The same iterator, with the item as an associated type.  Note that the
+/// impl header no longer needs a lifetime.
+#[cfg(feature = "experimental")]
+impl AssocStreamingIterator for CsvRdr {
+    type Items = Slices;
+
+    fn next<'a>(&'a mut self) -> Option<&'a [u8]> { None }
+}
+
 impl CsvWtr {
     /// Writes a single record to the CSV data.
     ///
     /// The input is an iterator of things that can produce a `&[u8]`.
-    fn write_record<A: BytesContainer, I: StreamIterator<A>>
+    fn write_record<'a, A: BytesContainer, I: StreamingIterator<'a, A>>
                    (&mut self, it: I) -> Result<(), String> {
         Ok(())
     }
@@ -73,7 +88,7 @@ fn main() {
     while !rdr.done() {
         // This should be `wtr.write_record`.
         wtr.write_record_regular_iter(
-            // None of these methods work on `StreamIterator`, but AFAIK,
+            // None of these methods work on `StreamingIterator`, but AFAIK,
             // there is no *fundamental* reason why they can't. It just may
             // not be expressible in Rust.
             rdr.enumerate()
@@ -81,3 +96,165 @@ fn main() {
                .map(|(_, field)| field)).unwrap();
     }
 }
+
+
+//=========================================================================
+// Dialects and field splitting
+
+/// Describes the flavor of CSV we're reading or writing.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Dialect {
+    /// Separates fields within a record.
+    pub delimiter: u8,
+    /// Surrounds fields containing delimiters, quotes or newlines.  Quotes
+    /// inside a quoted field are doubled.
+    pub quote: u8
+}
+
+impl Dialect {
+    /// Standard comma-separated values.
+    pub fn csv() -> Dialect { Dialect{delimiter: b',', quote: b'"'} }
+
+    /// Tab-separated values.
+    pub fn tsv() -> Dialect { Dialect{delimiter: b'\t', quote: b'"'} }
+
+    /// Does `field` need to be quoted when we write it?
+    pub fn needs_quotes(&self, field: &[u8]) -> bool {
+        field.iter().any(|&b| {
+            b == self.delimiter || b == self.quote || b == b'\n' || b == b'\r'
+        })
+    }
+
+    /// Write `field` to `out`, quoting it if necessary.
+    pub fn write_field(&self, out: &mut Writer, field: &[u8]) -> IoResult<()> {
+        if !self.needs_quotes(field) { return out.write(field); }
+        try!(out.write_u8(self.quote));
+        for &b in field.iter() {
+            if b == self.quote { try!(out.write_u8(b)); }
+            try!(out.write_u8(b));
+        }
+        out.write_u8(self.quote)
+    }
+}
+
+/// Split `record` into fields, storing the `(start, end)` offsets of each
+/// field in `spans` (which is cleared first).  Quoted fields are returned
+/// without their outer quotes, but doubled quotes are left alone.
+pub fn split_fields(record: &[u8], dialect: &Dialect,
+                    spans: &mut Vec<(uint, uint)>) {
+    spans.clear();
+    let mut pos = 0;
+    loop {
+        if pos < record.len() && record[pos] == dialect.quote {
+            let start = pos + 1;
+            pos = start;
+            while pos < record.len() {
+                if record[pos] == dialect.quote {
+                    if pos + 1 < record.len() &&
+                        record[pos+1] == dialect.quote {
+                        pos += 2;
+                        continue;
+                    }
+                    break;
+                }
+                pos += 1;
+            }
+            spans.push((start, pos));
+            // Skip the closing quote, and anything between it and the
+            // next delimiter.
+            while pos < record.len() && record[pos] != dialect.delimiter {
+                pos += 1;
+            }
+        } else {
+            let start = pos;
+            while pos < record.len() && record[pos] != dialect.delimiter {
+                pos += 1;
+            }
+            spans.push((start, pos));
+        }
+        if pos >= record.len() { return; }
+        pos += 1;
+    }
+}
+
+/// Count the records in `input`, which is read to the end.  Unlike
+/// `wc -l`, we don't count newlines inside quoted fields.  We split the
+/// input into lines with a `ChunkBuffer`, and only scan each line for
+/// quotes to track whether its newline is quoted, so this is about as fast
+/// as counting lines, and never looks at individual fields.  A final
+/// record with no newline still counts; blank lines count as (empty)
+/// records.
+pub fn count_records<R: Buffer>(input: &mut R, dialect: &Dialect)
+                                -> IoResult<u64> {
+    let mut records = 0u64;
+    let mut in_quotes = false;
+    // Have we seen any data since the last record boundary?
+    let mut partial = false;
+    let mut lines = ChunkBuffer::new(input, b"\n");
+    let mut chunks = lines.chunks();
+    loop {
+        let chunk = match chunks.next() {
+            None => { break; }
+            Some(chunk) => try!(chunk)
+        };
+        // A doubled quote flips us out and back in again, which is just
+        // what we want.
+        let mut rest = chunk.data;
+        loop {
+            match memchr_swar(dialect.quote, rest) {
+                Some(pos) => {
+                    in_quotes = !in_quotes;
+                    rest = rest.slice_from(pos + 1);
+                }
+                None => { break; }
+            }
+        }
+        if chunk.boundary.is_some() && !in_quotes {
+            records += 1;
+            partial = false;
+        } else {
+            partial = true;
+        }
+    }
+    if partial { records += 1; }
+    Ok(records)
+}
+
+#[test]
+fn split_fields_handles_quotes() {
+    let record = b"a,\"b,\"\"c\"\"\",,d";
+    let mut spans = vec![];
+    split_fields(record, &Dialect::csv(), &mut spans);
+    let fields: Vec<&[u8]> =
+        spans.iter().map(|&(s, e)| record.slice(s, e)).collect();
+    assert_eq!(vec![b"a", b"b,\"\"c\"\"", b"", b"d"], fields);
+}
+
+#[test]
+fn count_records_ignores_quoted_newlines() {
+    let data = b"id,note\n1,\"two\nlines\"\n2,\"say \"\"hi\"\"\n!\"\n3,x";
+    let mut input = BufReader::new(data);
+    assert_eq!(4, count_records(&mut input, &Dialect::csv()).unwrap());
+    let mut reader = BufReader::new(data);
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    assert_eq!(4, count_records(&mut dribble, &Dialect::csv()).unwrap());
+    let mut empty = BufReader::new(b"");
+    assert_eq!(0, count_records(&mut empty, &Dialect::csv()).unwrap());
+    let mut tsv = BufReader::new(b"a\tb\n\n");
+    assert_eq!(2, count_records(&mut tsv, &Dialect::tsv()).unwrap());
+}
+
+#[test]
+fn count_records_handles_escaped_and_doubled_quotes() {
+    let count = |data: &[u8]| {
+        let mut reader = BufReader::new(data);
+        let mut dribble = DribbleBuffer::new(&mut reader);
+        count_records(&mut dribble, &Dialect::csv()).unwrap()
+    };
+    // Doubled quotes, including one right before a quoted newline.
+    assert_eq!(2, count(b"\"a\"\"\nb\"\"\",1\n\"\"\"\"\n"));
+    // A field which is nothing but an escaped quote.
+    assert_eq!(3, count(b"\"\"\"\"\n\"\"\"\"\"\"\n\"x\"\"\"\n"));
+    // An empty quoted field, and a quoted field with no newline after it.
+    assert_eq!(2, count(b"\"\",1\n2,\"a\"\"\nb\""));
+}
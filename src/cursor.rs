@@ -0,0 +1,160 @@
+//! Cursors: an alternative to `StreamingIterator::next`.
+//!
+//! `next` both moves to the next item and hands it back, so the item
+//! borrows the iterator mutably until it's dropped.  A cursor splits those
+//! two steps: `advance` moves, and `get` borrows the current item
+//! immutably.  Generic code can then look at the current item several
+//! times, or hold onto it while asking the cursor other questions, without
+//! fighting the borrow checker.
+//!
+//! Sources only need to implement one style.  `CursorIter` turns any
+//! cursor into a streaming iterator for free, and `IterCursor` turns a
+//! streaming iterator into a cursor by keeping an owned copy of the current
+//! item.
+
+use std::io::{Buffer,IoError};
+
+use buffers::read_until_into;
+use iter::{OwnedCopy,SliceStream,StreamingIterator};
+
+#[cfg(test)] use std::io::BufReader;
+
+/// A position in a stream of items, which can be moved forward and
+/// inspected separately.
+pub trait StreamingCursor<'a, T> {
+    /// Move to the next item.  Returns `false` once there are no more
+    /// items.
+    fn advance(&mut self) -> bool;
+
+    /// The current item.  Only valid after `advance` has returned `true`.
+    fn get(&'a self) -> T;
+}
+
+/// A streaming iterator over the items of a cursor.
+pub struct CursorIter<C> {
+    cursor: C
+}
+
+impl<C> CursorIter<C> {
+    /// Iterate over the items of `cursor`.
+    pub fn new(cursor: C) -> CursorIter<C> {
+        CursorIter{cursor: cursor}
+    }
+
+    /// Get back our cursor.
+    pub fn unwrap(self) -> C { self.cursor }
+}
+
+impl<'a, T, C: StreamingCursor<'a, T>> StreamingIterator<'a, T>
+    for CursorIter<C> {
+
+    fn next(&'a mut self) -> Option<T> {
+        if self.cursor.advance() { Some(self.cursor.get()) } else { None }
+    }
+}
+
+/// A cursor over the items of a streaming iterator over byte slices.
+/// Since the iterator can't lend us its items between calls, we keep an
+/// owned copy, of type `O`, of the current one.  The copy's storage is
+/// reused from item to item.
+pub struct IterCursor<I, O> {
+    iter: I,
+    current: Option<O>
+}
+
+impl<I, O> IterCursor<I, O> {
+    /// Walk over the items of `iter`.
+    pub fn new(iter: I) -> IterCursor<I, O> {
+        IterCursor{iter: iter, current: None}
+    }
+}
+
+impl<'b, O: 'b, I> StreamingCursor<'b, &'b O> for IterCursor<I, O>
+    where I: SliceStream, for<'c> &'c [u8]: OwnedCopy<O> {
+
+    fn advance(&mut self) -> bool {
+        match self.iter.next() {
+            None => { self.current = None; false }
+            Some(item) => {
+                match self.current {
+                    Some(ref mut copy) => { item.copy_into(copy); }
+                    None => { self.current = Some(item.owned_copy()); }
+                }
+                true
+            }
+        }
+    }
+
+    fn get(&'b self) -> &'b O {
+        self.current.as_ref().expect("no current item")
+    }
+}
+
+/// A cursor over the lines of a `Buffer`, including their terminators.
+/// Since `advance` can't return an error, we stop at the first one and
+/// keep it for you to check with `error`.
+pub struct LineCursor<B> {
+    input: B,
+    line: Vec<u8>,
+    error: Option<IoError>
+}
+
+impl<B: Buffer> LineCursor<B> {
+    /// Read lines from `input`.
+    pub fn new(input: B) -> LineCursor<B> {
+        LineCursor{input: input, line: vec![], error: None}
+    }
+
+    /// The error which stopped us, if any.
+    pub fn error(&self) -> Option<&IoError> { self.error.as_ref() }
+}
+
+impl<'a, B: Buffer> StreamingCursor<'a, &'a [u8]> for LineCursor<B> {
+    fn advance(&mut self) -> bool {
+        if self.error.is_some() { return false; }
+        self.line.clear();
+        match read_until_into(&mut self.input, b'\n', &mut self.line) {
+            Ok(more) => more,
+            Err(err) => { self.error = Some(err); false }
+        }
+    }
+
+    fn get(&'a self) -> &'a [u8] { self.line.as_slice() }
+}
+
+#[cfg(test)]
+struct Lines<'b> {
+    lines: &'b [&'b [u8]]
+}
+
+#[cfg(test)]
+impl<'a, 'b> StreamingIterator<'a, &'a [u8]> for Lines<'b> {
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        if self.lines.is_empty() { return None; }
+        let line = self.lines[0];
+        self.lines = self.lines.slice_from(1);
+        Some(line)
+    }
+}
+
+#[test]
+fn cursors_and_iterators_convert_both_ways() {
+    let input = BufReader::new(b"one\ntwo\nthree");
+    let mut lines = CursorIter::new(LineCursor::new(input));
+    let mut seen = vec![];
+    streaming_for!(line in lines, { seen.push(line.to_vec()); });
+    assert_eq!(vec![b"one\n".to_vec(), b"two\n".to_vec(), b"three".to_vec()],
+               seen);
+    assert!(lines.unwrap().error().is_none());
+
+    let source: &[&[u8]] = &[b"a", b"bb"];
+    let mut cursor: IterCursor<Lines, Vec<u8>> =
+        IterCursor::new(Lines{lines: source});
+    assert!(cursor.advance());
+    assert_eq!(b"a", cursor.get().as_slice());
+    // Looking again doesn't move us.
+    assert_eq!(1, cursor.get().len());
+    assert!(cursor.advance());
+    assert_eq!(b"bb", cursor.get().as_slice());
+    assert!(!cursor.advance());
+}
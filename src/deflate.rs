@@ -0,0 +1,839 @@
+//! Reading and writing gzip and zlib data.
+//!
+//! Both formats wrap the same DEFLATE (RFC 1951) compressed data in a
+//! different header and trailer.  `Inflater` decodes the DEFLATE data a
+//! little at a time, and `DeflateBuffer` wraps it up as a `Buffer`, checking
+//! headers and checksums as it goes.
+//!
+//! A gzip file may contain several "members", each a complete gzip stream.
+//! This happens whenever somebody appends to a log with `gzip -c >>`.  By
+//! default, `DeflateBuffer` reads straight through all of them, just like
+//! `gunzip` does.  If you care where one member ends and the next begins,
+//! call `stop_at_members`: we'll then report EOF at the end of each member,
+//! and you can call `next_member` to carry on.
+//!
+//! This is a straightforward decoder in the style of zlib's `puff.c`, and
+//! it favors clarity over speed.
+//!
+//! `GzipWriter` goes the other way, but it only writes DEFLATE's "stored"
+//! blocks, which hold the data as is.  Its output is a valid gzip file which
+//! any decoder can read, but it's slightly bigger than the input.
+
+use std::cmp::min;
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError,standard_error};
+
+#[cfg(test)] use std::io::{File,MemReader,MemWriter};
+#[cfg(test)] use buffers::DribbleBuffer;
+#[cfg(test)] use compress::check_damaged_streams;
+
+/// How much history DEFLATE back-references can reach.
+static WINDOW_SIZE: uint = 32 * 1024;
+
+/// How much we try to decompress per call to `fill_buf`.
+static CHUNK_SIZE: uint = 32 * 1024;
+
+/// The most data a stored block can hold.
+static STORED_BLOCK_SIZE: uint = 65535;
+
+static LENGTH_BASE: [u16, ..29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51,
+     59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8, ..29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4,
+     5, 5, 5, 5, 0];
+static DIST_BASE: [u16, ..30] =
+    [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+     513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+     24577];
+static DIST_EXTRA: [u8, ..30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+     11, 11, 12, 12, 13, 13];
+
+/// The order in which code length code lengths are stored.  (Really.)
+static CODE_LENGTH_ORDER: [uint, ..19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(detail: &str) -> IoError {
+    IoError{kind: OtherIoError, desc: "corrupt compressed data",
+            detail: Some(detail.to_string())}
+}
+
+/// Reads individual bits, least significant first, from a `Buffer`.
+pub struct BitReader<R> {
+    input: R,
+    bits: u32,
+    count: uint
+}
+
+impl<R: Buffer> BitReader<R> {
+    /// Read bits from `input`.
+    pub fn new(input: R) -> BitReader<R> {
+        BitReader{input: input, bits: 0, count: 0}
+    }
+
+    /// Read the next `need` bits, where `need` is at most 16.
+    pub fn bits(&mut self, need: uint) -> IoResult<u32> {
+        while self.count < need {
+            let byte = try!(self.byte_unaligned());
+            self.bits |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << need) - 1);
+        self.bits >>= need;
+        self.count -= need;
+        Ok(value)
+    }
+
+    /// Throw away any bits left over from the current byte.
+    pub fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Read a whole byte.  Call `align` first.
+    pub fn byte(&mut self) -> IoResult<u8> {
+        assert!(self.count == 0);
+        self.byte_unaligned()
+    }
+
+    /// Read a little-endian `u16`.  Call `align` first.
+    pub fn le_u16(&mut self) -> IoResult<u16> {
+        let lo = try!(self.byte()) as u16;
+        let hi = try!(self.byte()) as u16;
+        Ok(hi << 8 | lo)
+    }
+
+    /// Read a little-endian `u32`.  Call `align` first.
+    pub fn le_u32(&mut self) -> IoResult<u32> {
+        let lo = try!(self.le_u16()) as u32;
+        let hi = try!(self.le_u16()) as u32;
+        Ok(hi << 16 | lo)
+    }
+
+    /// Is there no more input?  Call `align` first.
+    pub fn at_eof(&mut self) -> IoResult<bool> {
+        assert!(self.count == 0);
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => { if buf.len() > 0 { return Ok(false); } }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(true); }
+                Err(err) => { return Err(err); }
+            }
+        }
+    }
+
+    /// Get back our underlying buffer.
+    pub fn unwrap(self) -> R { self.input }
+
+    fn byte_unaligned(&mut self) -> IoResult<u8> {
+        loop {
+            let byte = match self.input.fill_buf() {
+                Ok(buf) => if buf.len() > 0 { Some(buf[0]) } else { None },
+                Err(IoError{kind: EndOfFile, ..}) => {
+                    return Err(corrupt("unexpected end of compressed data"));
+                }
+                Err(err) => { return Err(err); }
+            };
+            match byte {
+                Some(byte) => {
+                    self.input.consume(1);
+                    return Ok(byte);
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Decode one symbol using `code`.  We walk the code one bit at a time,
+    // using the fact that canonical Huffman codes of each length are
+    // consecutive integers.
+    fn decode(&mut self, code: &Huffman) -> IoResult<uint> {
+        let mut value = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in range(1u, 16) {
+            value |= try!(self.bits(1)) as i32;
+            let count = code.counts[len] as i32;
+            if value - count < first {
+                let i = (index + value - first) as uint;
+                return Ok(code.symbols[i] as uint);
+            }
+            index += count;
+            first = (first + count) << 1;
+            value <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+// A canonical Huffman code: how many codes there are of each length, and
+// the symbols, sorted by code.
+struct Huffman {
+    counts: [u16, ..16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16, ..16];
+        for &len in lengths.iter() { counts[len as uint] += 1; }
+        let mut offsets = [0u16, ..16];
+        for len in range(1u, 15) {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let offset = &mut offsets[len as uint];
+                symbols.as_mut_slice()[*offset as uint] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        Huffman{counts: counts, symbols: symbols}
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8, ..288];
+    for i in range(0u, 144) { lengths[i] = 8; }
+    for i in range(144u, 256) { lengths[i] = 9; }
+    for i in range(256u, 280) { lengths[i] = 7; }
+    for i in range(280u, 288) { lengths[i] = 8; }
+    (Huffman::new(lengths.as_slice()), Huffman::new(&[5u8, ..30]))
+}
+
+fn dynamic_codes<R: Buffer>(bits: &mut BitReader<R>)
+                            -> IoResult<(Huffman, Huffman)> {
+    let nlen = try!(bits.bits(5)) as uint + 257;
+    let ndist = try!(bits.bits(5)) as uint + 1;
+    let ncode = try!(bits.bits(4)) as uint + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(corrupt("too many length or distance codes"));
+    }
+
+    let mut lengths = [0u8, ..316];
+    for i in range(0, ncode) {
+        lengths[CODE_LENGTH_ORDER[i]] = try!(bits.bits(3)) as u8;
+    }
+    let length_code = Huffman::new(lengths.slice_to(19));
+
+    let mut index = 0u;
+    while index < nlen + ndist {
+        let symbol = try!(bits.decode(&length_code));
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+            continue;
+        }
+        let (value, repeat) = match symbol {
+            16 => {
+                if index == 0 {
+                    return Err(corrupt("repeated length with no previous"));
+                }
+                (lengths[index - 1], 3 + try!(bits.bits(2)) as uint)
+            }
+            17 => (0, 3 + try!(bits.bits(3)) as uint),
+            _ => (0, 11 + try!(bits.bits(7)) as uint)
+        };
+        if index + repeat > nlen + ndist {
+            return Err(corrupt("too many code lengths"));
+        }
+        for _ in range(0, repeat) {
+            lengths[index] = value;
+            index += 1;
+        }
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("no end-of-block code"));
+    }
+    Ok((Huffman::new(lengths.slice_to(nlen)),
+        Huffman::new(lengths.slice(nlen, nlen + ndist))))
+}
+
+enum BlockState {
+    BlockHeader,
+    Stored(uint),
+    Codes(Huffman, Huffman),
+    Finished
+}
+
+// Read a block header, returning whether it's the last block, and how to
+// decode it.
+fn block_header<R: Buffer>(bits: &mut BitReader<R>)
+                           -> IoResult<(bool, BlockState)> {
+    let last = try!(bits.bits(1)) == 1;
+    let state = match try!(bits.bits(2)) {
+        0 => {
+            bits.align();
+            let len = try!(bits.le_u16());
+            let check = try!(bits.le_u16());
+            if len != !check {
+                return Err(corrupt("bad stored block length"));
+            }
+            Stored(len as uint)
+        }
+        1 => { let (lit, dist) = fixed_codes(); Codes(lit, dist) }
+        2 => { let (lit, dist) = try!(dynamic_codes(bits)); Codes(lit, dist) }
+        _ => { return Err(corrupt("invalid block type")); }
+    };
+    Ok((last, state))
+}
+
+/// Decodes a raw DEFLATE stream, a little at a time.
+pub struct Inflater {
+    state: BlockState,
+    last: bool,
+    copy_len: uint,
+    copy_dist: uint
+}
+
+impl Inflater {
+    /// Prepare to decode a new stream.
+    pub fn new() -> Inflater {
+        Inflater{state: BlockHeader, last: false, copy_len: 0, copy_dist: 0}
+    }
+
+    /// Have we reached the end of the stream?
+    pub fn is_finished(&self) -> bool {
+        match self.state { Finished => true, _ => false }
+    }
+
+    /// Decode data from `bits`, appending it to `out`, until `out` is at
+    /// least `target` bytes long or the stream ends.  Back-references are
+    /// resolved against the end of `out`, so it must hold at least the
+    /// last 32K of output from any previous calls.
+    pub fn inflate<R: Buffer>(&mut self, bits: &mut BitReader<R>,
+                              out: &mut Vec<u8>, target: uint)
+                              -> IoResult<()> {
+        while out.len() < target {
+            // Finish copying any back-reference we were in the middle of.
+            while self.copy_len > 0 && out.len() < target {
+                let byte = out[out.len() - self.copy_dist];
+                out.push(byte);
+                self.copy_len -= 1;
+            }
+            if out.len() >= target { break; }
+
+            let after_block = if self.last { Finished } else { BlockHeader };
+            let next = match self.state {
+                Finished => { return Ok(()); }
+                BlockHeader => {
+                    let (last, state) = try!(block_header(bits));
+                    self.last = last;
+                    Some(state)
+                }
+                Stored(ref mut remaining) => {
+                    while *remaining > 0 && out.len() < target {
+                        out.push(try!(bits.byte()));
+                        *remaining -= 1;
+                    }
+                    if *remaining == 0 { Some(after_block) } else { None }
+                }
+                Codes(ref lit, ref dist) => {
+                    let symbol = try!(bits.decode(lit));
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        None
+                    } else if symbol == 256 {
+                        Some(after_block)
+                    } else {
+                        let symbol = symbol - 257;
+                        if symbol >= 29 {
+                            return Err(corrupt("invalid length symbol"));
+                        }
+                        let extra = LENGTH_EXTRA[symbol] as uint;
+                        let len = LENGTH_BASE[symbol] as uint +
+                            try!(bits.bits(extra)) as uint;
+                        let symbol = try!(bits.decode(dist));
+                        if symbol >= 30 {
+                            return Err(corrupt("invalid distance symbol"));
+                        }
+                        let extra = DIST_EXTRA[symbol] as uint;
+                        let dist = DIST_BASE[symbol] as uint +
+                            try!(bits.bits(extra)) as uint;
+                        if dist > out.len() {
+                            return Err(corrupt("distance too far back"));
+                        }
+                        self.copy_len = len;
+                        self.copy_dist = dist;
+                        None
+                    }
+                }
+            };
+            match next {
+                Some(state) => { self.state = state; }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the CRC-32 checksum used by gzip, or the CRC-32C variant.
+pub struct Crc32 {
+    table: Vec<u32>,
+    value: u32
+}
+
+impl Crc32 {
+    /// Start a new checksum.
+    pub fn new() -> Crc32 { Crc32::with_polynomial(0xedb88320) }
+
+    /// Start a new CRC-32C checksum, as used by Snappy's framing format.
+    pub fn castagnoli() -> Crc32 { Crc32::with_polynomial(0x82f63b78) }
+
+    // `poly` is bit-reversed, with the lowest-order term in the top bit.
+    fn with_polynomial(poly: u32) -> Crc32 {
+        let table = range(0u32, 256).map(|n| {
+            let mut c = n;
+            for _ in range(0u, 8) {
+                c = if c & 1 != 0 { poly ^ (c >> 1) } else { c >> 1 };
+            }
+            c
+        }).collect();
+        Crc32{table: table, value: 0}
+    }
+
+    /// Reset the checksum, so we can use it for new data.
+    pub fn reset(&mut self) { self.value = 0; }
+
+    /// Add `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut c = !self.value;
+        for &byte in data.iter() {
+            c = self.table[((c ^ byte as u32) & 0xff) as uint] ^ (c >> 8);
+        }
+        self.value = !c;
+    }
+
+    /// The checksum of all the data so far.
+    pub fn value(&self) -> u32 { self.value }
+}
+
+/// Compute the Adler-32 checksum of `data`, continuing from `adler`.  Start
+/// with an `adler` of 1.
+pub fn adler32(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[deriving(PartialEq)]
+enum Container { Gzip, Zlib }
+
+#[deriving(PartialEq)]
+enum StreamState {
+    StreamHeader,
+    Body,
+    MemberEnd,
+    StreamEnd
+}
+
+/// A `Buffer` which decompresses gzip or zlib data from another `Buffer`.
+pub struct DeflateBuffer<R> {
+    bits: BitReader<R>,
+    container: Container,
+    state: StreamState,
+    inflater: Inflater,
+    out: Vec<u8>,
+    pos: uint,
+    crc: Crc32,
+    adler: u32,
+    size: u32,
+    stop_at_members: bool,
+    members: uint
+}
+
+impl<R: Buffer> DeflateBuffer<R> {
+    fn new(input: R, container: Container) -> DeflateBuffer<R> {
+        DeflateBuffer{bits: BitReader::new(input), container: container,
+                      state: StreamHeader, inflater: Inflater::new(),
+                      out: Vec::with_capacity(WINDOW_SIZE + CHUNK_SIZE),
+                      pos: 0, crc: Crc32::new(), adler: 1, size: 0,
+                      stop_at_members: false, members: 0}
+    }
+
+    /// Decompress gzip data from `input`, reading through all members.
+    pub fn gzip(input: R) -> DeflateBuffer<R> {
+        DeflateBuffer::new(input, Gzip)
+    }
+
+    /// Decompress zlib data from `input`.
+    pub fn zlib(input: R) -> DeflateBuffer<R> {
+        DeflateBuffer::new(input, Zlib)
+    }
+
+    /// Report EOF at the end of each gzip member, instead of carrying on
+    /// into the next one.  Use `next_member` to continue.
+    pub fn stop_at_members(mut self) -> DeflateBuffer<R> {
+        self.stop_at_members = true;
+        self
+    }
+
+    /// If we stopped at the end of a member, move on to the next one,
+    /// returning false if there are no more.  Only needed after calling
+    /// `stop_at_members`.
+    pub fn next_member(&mut self) -> IoResult<bool> {
+        if self.pos < self.out.len() || self.state == Body {
+            return Err(IoError{kind: OtherIoError,
+                               desc: "current gzip member is not finished",
+                               detail: None});
+        }
+        match self.state {
+            MemberEnd => { self.state = StreamHeader; Ok(true) }
+            _ => Ok(false)
+        }
+    }
+
+    /// How many gzip members (or zlib streams) we've started reading.
+    pub fn members(&self) -> uint { self.members }
+
+    /// Get back our underlying buffer.  Any input after the end of the
+    /// compressed data is still there.
+    pub fn unwrap(self) -> R { self.bits.unwrap() }
+
+    fn read_header(&mut self) -> IoResult<()> {
+        let bits = &mut self.bits;
+        match self.container {
+            Gzip => {
+                let id1 = try!(bits.byte());
+                let id2 = try!(bits.byte());
+                let method = try!(bits.byte());
+                if id1 != 0x1f || id2 != 0x8b || method != 8 {
+                    return Err(corrupt("bad gzip header"));
+                }
+                let flags = try!(bits.byte());
+                // Skip the modification time, extra flags and OS.
+                for _ in range(0u, 6) { try!(bits.byte()); }
+                if flags & 0x04 != 0 {
+                    let len = try!(bits.le_u16());
+                    for _ in range(0, len) { try!(bits.byte()); }
+                }
+                // The file name and comment are NUL-terminated.
+                for &flag in [0x08u8, 0x10].iter() {
+                    if flags & flag != 0 {
+                        while try!(bits.byte()) != 0 {}
+                    }
+                }
+                if flags & 0x02 != 0 { try!(bits.le_u16()); }
+            }
+            Zlib => {
+                let cmf = try!(bits.byte());
+                let flg = try!(bits.byte());
+                let check = (cmf as uint) << 8 | flg as uint;
+                if cmf & 0x0f != 8 || check % 31 != 0 {
+                    return Err(corrupt("bad zlib header"));
+                }
+                if flg & 0x20 != 0 {
+                    return Err(corrupt("zlib preset dictionaries unsupported"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> IoResult<()> {
+        self.bits.align();
+        match self.container {
+            Gzip => {
+                let crc = try!(self.bits.le_u32());
+                let size = try!(self.bits.le_u32());
+                if crc != self.crc.value() || size != self.size {
+                    return Err(corrupt("gzip checksum mismatch"));
+                }
+            }
+            Zlib => {
+                let mut adler = 0u32;
+                for _ in range(0u, 4) {
+                    adler = adler << 8 | try!(self.bits.byte()) as u32;
+                }
+                if adler != self.adler {
+                    return Err(corrupt("zlib checksum mismatch"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Once everything has been consumed, drop everything except the last
+    // window's worth of history.
+    fn compact(&mut self) {
+        if self.pos < self.out.len() || self.out.len() <= WINDOW_SIZE {
+            return;
+        }
+        let drop = self.out.len() - WINDOW_SIZE;
+        for i in range(0, WINDOW_SIZE) {
+            let byte = self.out[drop + i];
+            self.out.as_mut_slice()[i] = byte;
+        }
+        self.out.truncate(WINDOW_SIZE);
+        self.pos -= drop;
+    }
+}
+
+impl<R: Buffer> Reader for DeflateBuffer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(data.len(), buf.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: Buffer> Buffer for DeflateBuffer<R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        while self.pos == self.out.len() {
+            match self.state {
+                StreamEnd | MemberEnd => {
+                    return Err(standard_error(EndOfFile));
+                }
+                StreamHeader => {
+                    try!(self.read_header());
+                    self.inflater = Inflater::new();
+                    self.crc.reset();
+                    self.adler = 1;
+                    self.size = 0;
+                    self.out.clear();
+                    self.pos = 0;
+                    self.members += 1;
+                    self.state = Body;
+                }
+                Body => {
+                    self.compact();
+                    let start = self.out.len();
+                    try!(self.inflater.inflate(&mut self.bits, &mut self.out,
+                                               start + CHUNK_SIZE));
+                    {
+                        let new = self.out.slice_from(start);
+                        match self.container {
+                            Gzip => { self.crc.update(new); }
+                            Zlib => { self.adler = adler32(self.adler, new); }
+                        }
+                        self.size += new.len() as u32;
+                    }
+                    if self.inflater.is_finished() {
+                        try!(self.read_trailer());
+                        let more = self.container == Gzip &&
+                            !try!(self.bits.at_eof());
+                        self.state = match (more, self.stop_at_members) {
+                            (false, _) => StreamEnd,
+                            (true, false) => StreamHeader,
+                            (true, true) => MemberEnd
+                        };
+                    }
+                }
+            }
+        }
+        Ok(self.out.slice_from(self.pos))
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.pos += amt;
+        assert!(self.pos <= self.out.len());
+    }
+}
+
+/// A `Writer` which produces gzip data, using stored blocks.  Call
+/// `finish` when you're done, to write the trailer; until then, the output
+/// is a truncated gzip stream.
+pub struct GzipWriter<W> {
+    output: W,
+    // Data for the next stored block.
+    block: Vec<u8>,
+    crc: Crc32,
+    size: u32,
+    started: bool,
+    finished: bool
+}
+
+impl<W: Writer> GzipWriter<W> {
+    /// Write gzip data to `output`.
+    pub fn new(output: W) -> GzipWriter<W> {
+        GzipWriter{output: output,
+                   block: Vec::with_capacity(STORED_BLOCK_SIZE),
+                   crc: Crc32::new(), size: 0, started: false,
+                   finished: false}
+    }
+
+    /// Write any buffered data and the gzip trailer, and flush the
+    /// underlying writer.  Writing anything else afterwards is an error.
+    pub fn finish(&mut self) -> IoResult<()> {
+        if self.finished { return Ok(()); }
+        try!(self.write_block(true));
+        try!(self.output.write_le_u32(self.crc.value()));
+        try!(self.output.write_le_u32(self.size));
+        self.finished = true;
+        self.output.flush()
+    }
+
+    /// Get back the underlying writer.
+    pub fn unwrap(self) -> W { self.output }
+
+    // Write out `block`, preceded by the gzip header if it's our first.
+    fn write_block(&mut self, last: bool) -> IoResult<()> {
+        if !self.started {
+            // No file name or timestamp, and an unknown OS.
+            try!(self.output.write(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]));
+            self.started = true;
+        }
+        // The block type (0, for stored) and final flag take up three bits,
+        // padded to a byte.  Then come the length and its complement.
+        let len = self.block.len() as u16;
+        try!(self.output.write_u8(last as u8));
+        try!(self.output.write_le_u16(len));
+        try!(self.output.write_le_u16(!len));
+        try!(self.output.write(self.block.as_slice()));
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl<W: Writer> Writer for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        if self.finished {
+            return Err(IoError{kind: OtherIoError,
+                               desc: "gzip stream already finished",
+                               detail: None});
+        }
+        self.crc.update(buf);
+        self.size += buf.len() as u32;
+        let mut rest = buf;
+        while rest.len() > 0 {
+            let n = min(rest.len(), STORED_BLOCK_SIZE - self.block.len());
+            self.block.push_all(rest.slice_to(n));
+            rest = rest.slice_from(n);
+            if self.block.len() == STORED_BLOCK_SIZE {
+                try!(self.write_block(false));
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if !self.finished && self.block.len() > 0 {
+            try!(self.write_block(false));
+        }
+        self.output.flush()
+    }
+}
+
+#[test]
+fn crc32_matches_reference() {
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(0xcbf43926, crc.value());
+    let mut crc = Crc32::castagnoli();
+    crc.update(b"123456789");
+    assert_eq!(0xe3069283, crc.value());
+}
+
+#[test]
+fn inflates_gzip_files() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.gz"))
+        .read_to_end().unwrap();
+    let mut reader = MemReader::new(compressed);
+    let mut gzip = DeflateBuffer::gzip(DribbleBuffer::new(&mut reader));
+    assert_eq!(expected, gzip.read_to_end().unwrap());
+    assert_eq!(1, gzip.members());
+}
+
+#[test]
+fn inflates_zlib_files() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.zz"))
+        .read_to_end().unwrap();
+    let mut zlib = DeflateBuffer::zlib(MemReader::new(compressed));
+    assert_eq!(expected, zlib.read_to_end().unwrap());
+}
+
+#[test]
+fn reads_through_gzip_members() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed =
+        File::open(&Path::new("test_data/fr/sample_members.conllx.gz"))
+        .read_to_end().unwrap();
+    let mut gzip = DeflateBuffer::gzip(MemReader::new(compressed));
+    assert_eq!(expected, gzip.read_to_end().unwrap());
+    assert_eq!(2, gzip.members());
+}
+
+#[test]
+fn stops_at_gzip_members_on_request() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed =
+        File::open(&Path::new("test_data/fr/sample_members.conllx.gz"))
+        .read_to_end().unwrap();
+    let mut gzip = DeflateBuffer::gzip(MemReader::new(compressed))
+        .stop_at_members();
+    let mut first = gzip.read_to_end().unwrap();
+    assert!(first.len() > 0 && first.len() < expected.len());
+    assert!(gzip.next_member().unwrap());
+    first.push_all(gzip.read_to_end().unwrap().as_slice());
+    assert_eq!(expected, first);
+    assert!(!gzip.next_member().unwrap());
+}
+
+#[test]
+fn rejects_corrupt_gzip() {
+    let mut compressed =
+        File::open(&Path::new("test_data/fr/sample.conllx.gz"))
+        .read_to_end().unwrap();
+    let len = compressed.len();
+    compressed[len - 5] ^= 0xff;
+    let mut gzip = DeflateBuffer::gzip(MemReader::new(compressed));
+    assert!(gzip.read_to_end().is_err());
+}
+
+#[test]
+fn survives_truncated_and_damaged_streams() {
+    let gzip = File::open(&Path::new("test_data/fr/sample.conllx.gz"))
+        .read_to_end().unwrap();
+    check_damaged_streams(gzip.as_slice(), |data| {
+        DeflateBuffer::gzip(MemReader::new(data)).read_to_end()
+    });
+    let zlib = File::open(&Path::new("test_data/fr/sample.conllx.zz"))
+        .read_to_end().unwrap();
+    check_damaged_streams(zlib.as_slice(), |data| {
+        DeflateBuffer::zlib(MemReader::new(data)).read_to_end()
+    });
+}
+
+#[test]
+fn gzip_writer_round_trips() {
+    let sample = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    // Enough to need several stored blocks.
+    let mut expected = vec![];
+    for _ in range(0u, 20) { expected.push_all(sample.as_slice()); }
+    for &size in [7u, 1000, 100000].iter() {
+        let mut gzip = GzipWriter::new(MemWriter::new());
+        for piece in expected.as_slice().chunks(size) {
+            gzip.write(piece).unwrap();
+            if size == 1000 { gzip.flush().unwrap(); }
+        }
+        gzip.finish().unwrap();
+        assert!(gzip.write(b"late").is_err());
+        let compressed = gzip.unwrap().unwrap();
+        let mut reader = DeflateBuffer::gzip(MemReader::new(compressed));
+        assert_eq!(expected, reader.read_to_end().unwrap());
+    }
+
+    let mut empty = GzipWriter::new(MemWriter::new());
+    empty.finish().unwrap();
+    let empty = empty.unwrap().unwrap();
+    let mut reader = DeflateBuffer::gzip(MemReader::new(empty));
+    assert!(reader.read_to_end().unwrap().is_empty());
+}
@@ -0,0 +1,242 @@
+//! Guessing what sort of data a file holds.
+//!
+//! Generic ingestion tools get handed files with missing or misleading
+//! extensions.  `detect_format` looks at the first few kilobytes of a
+//! stream and scores every format it could plausibly be, best guess first,
+//! so the caller can pick a parser, or ask a human when the top guesses
+//! are close.
+//!
+//! These are heuristics.  A CoNLL file is also a perfectly good TSV file,
+//! and a one-line NDJSON file is also JSON, so expect several guesses.
+//! Compression is checked first, using `compress::detect`; if the input is
+//! compressed, decompress some of it and ask again.
+
+use std::cmp::{Equal,max};
+
+use compress::{detect,Compression,Uncompressed};
+use csv::{Dialect,split_fields};
+
+#[cfg(test)] use std::io::File;
+#[cfg(test)] use compress::Gzip;
+
+/// A data format we know how to recognize.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Format {
+    /// Delimited text, such as CSV or TSV.
+    Delimited(Dialect),
+    /// One JSON value per line.
+    Ndjson,
+    /// A single JSON document.
+    Json,
+    /// XML, or something which looks a lot like it.
+    Xml,
+    /// Tab-separated CoNLL columns, with blank lines between sentences.
+    Conll,
+    /// A compressed stream.
+    Compressed(Compression),
+    /// Binary data with no magic number we recognize.
+    Binary
+}
+
+/// A possible format, and how confident we are in it, from 0 to 1.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Guess {
+    /// The format.
+    pub format: Format,
+    /// How sure we are.
+    pub confidence: f64
+}
+
+// We don't look at more lines than this.
+static MAX_LINES: uint = 100;
+
+// The delimiters we try, most likely first.
+static DELIMITERS: &'static [u8] = b",\t;|";
+
+/// Score the formats which a stream starting with `peek` might be in, best
+/// first.  A few kilobytes is plenty; a final partial line is ignored
+/// unless it's the only line we have.  Returns an empty vector if we can't
+/// tell at all, for example because `peek` is empty.
+pub fn detect_format(peek: &[u8]) -> Vec<Guess> {
+    let mut guesses = vec![];
+
+    let compression = detect(peek);
+    if compression != Uncompressed {
+        guesses.push(guess(Compressed(compression), 1.0));
+        return guesses;
+    }
+    if looks_binary(peek) {
+        guesses.push(guess(Binary, 0.9));
+        return guesses;
+    }
+
+    let lines = complete_lines(peek);
+    let text: Vec<&[u8]> =
+        lines.iter().map(|l| *l).filter(|l| trim(*l).len() > 0).collect();
+    if text.is_empty() { return guesses; }
+    let first = trim(text[0]);
+
+    if first.starts_with(b"<?xml") {
+        guesses.push(guess(Xml, 1.0));
+    } else if first.starts_with(b"<") {
+        guesses.push(guess(Xml, 0.6));
+    }
+
+    if first.starts_with(b"{") || first.starts_with(b"[") {
+        let objects = fraction(text.as_slice(), |line| {
+            let line = trim(line);
+            (line.starts_with(b"{") && line.ends_with(b"}")) ||
+                (line.starts_with(b"[") && line.ends_with(b"]"))
+        });
+        if text.len() > 1 && objects == 1.0 {
+            guesses.push(guess(Ndjson, 0.95));
+            guesses.push(guess(Json, 0.2));
+        } else if text.len() == 1 && objects == 1.0 {
+            guesses.push(guess(Json, 0.7));
+            guesses.push(guess(Ndjson, 0.5));
+        } else {
+            guesses.push(guess(Json, 0.8));
+            if objects > 0.5 { guesses.push(guess(Ndjson, objects * 0.5)); }
+        }
+    }
+
+    let conll = conll_score(lines.as_slice(), text.as_slice());
+    if conll > 0.0 { guesses.push(guess(Conll, conll)); }
+
+    for &delimiter in DELIMITERS.iter() {
+        let dialect = Dialect{delimiter: delimiter, quote: b'"'};
+        let score = delimited_score(text.as_slice(), &dialect);
+        if score > 0.0 { guesses.push(guess(Delimited(dialect), score)); }
+    }
+
+    guesses.sort_by(|a, b| {
+        b.confidence.partial_cmp(&a.confidence).unwrap_or(Equal)
+    });
+    guesses
+}
+
+fn guess(format: Format, confidence: f64) -> Guess {
+    Guess{format: format, confidence: confidence}
+}
+
+// Text rarely contains NUL bytes or lots of control characters.
+fn looks_binary(peek: &[u8]) -> bool {
+    if peek.contains(&0) { return true; }
+    let control = peek.iter().filter(|&&b| {
+        b < 0x20 && b != b'\n' && b != b'\r' && b != b'\t'
+    }).count();
+    control * 10 > peek.len()
+}
+
+// Split `peek` into lines without their terminators, dropping a final
+// partial line if there are complete ones.
+fn complete_lines<'a>(peek: &'a [u8]) -> Vec<&'a [u8]> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (i, &b) in peek.iter().enumerate() {
+        if b == b'\n' {
+            let end = if i > start && peek[i - 1] == b'\r' { i - 1 } else { i };
+            lines.push(peek.slice(start, end));
+            start = i + 1;
+            if lines.len() == MAX_LINES { return lines; }
+        }
+    }
+    if lines.is_empty() && start < peek.len() {
+        lines.push(peek.slice_from(start));
+    }
+    lines
+}
+
+fn is_space(b: u8) -> bool { b == b' ' || b == b'\t' }
+
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|&b| !is_space(b))
+        .unwrap_or(line.len());
+    let end = line.iter().rposition(|&b| !is_space(b))
+        .map_or(start, |i| i + 1);
+    line.slice(start, end)
+}
+
+// The fraction of `lines` for which `f` returns true.
+fn fraction(lines: &[&[u8]], f: |&[u8]| -> bool) -> f64 {
+    let mut matching = 0u;
+    for &line in lines.iter() {
+        if f(line) { matching += 1; }
+    }
+    matching as f64 / lines.len() as f64
+}
+
+// CoNLL has at least 6 tab-separated columns, a numeric token ID in the
+// first, and blank lines between sentences.  Comment lines are allowed.
+fn conll_score(lines: &[&[u8]], text: &[&[u8]]) -> f64 {
+    let tokens: Vec<&[u8]> =
+        text.iter().map(|l| *l).filter(|l| !l.starts_with(b"#")).collect();
+    if tokens.is_empty() { return 0.0; }
+    let score = fraction(tokens.as_slice(), |line| {
+        let columns = line.split(|&b| b == b'\t').count();
+        let id_len = line.iter().position(|&b| b == b'\t')
+            .unwrap_or(line.len());
+        columns >= 6 && id_len > 0 &&
+            line.slice_to(id_len).iter().all(|&b| {
+                (b >= b'0' && b <= b'9') || b == b'-' || b == b'.'
+            })
+    });
+    if score < 0.5 { return 0.0; }
+    let blank = lines.iter().any(|l| trim(*l).is_empty());
+    if blank { score } else { score * 0.8 }
+}
+
+// Delimited data has the same number of fields, more than one, on every
+// line.  The header may not be typical, so we score against the most
+// common field count.
+fn delimited_score(text: &[&[u8]], dialect: &Dialect) -> f64 {
+    let mut spans = vec![];
+    let counts: Vec<uint> = text.iter().map(|line| {
+        split_fields(*line, dialect, &mut spans);
+        spans.len()
+    }).collect();
+    let mut times = 0;
+    for &count in counts.iter() {
+        if count < 2 { continue; }
+        times = max(times, counts.iter().filter(|&&c| c == count).count());
+    }
+    if times == 0 { return 0.0; }
+    let consistency = times as f64 / counts.len() as f64;
+    // A single line is weak evidence.
+    if counts.len() == 1 { consistency * 0.5 } else { consistency * 0.9 }
+}
+
+#[cfg(test)]
+fn best(peek: &[u8]) -> Format {
+    detect_format(peek)[0].format.clone()
+}
+
+#[test]
+fn detects_text_formats() {
+    assert_eq!(Delimited(Dialect::csv()),
+               best(b"name,age\n\"Smith, J\",42\nDoe,7\n"));
+    assert_eq!(Delimited(Dialect::tsv()), best(b"a\tb\tc\n1\t2\t3\n4\t5"));
+    // These start with valid-looking zlib headers.
+    assert_eq!(Delimited(Dialect::csv()), best(b"800,1\n900,2\n"));
+    assert_eq!(Delimited(Dialect::csv()), best(b"H,x\n1,2\n"));
+    assert_eq!(Ndjson, best(b"{\"a\": 1}\n{\"a\": 2}\n"));
+    assert_eq!(Json, best(b"{\n  \"a\": [1, 2]\n}\n"));
+    assert_eq!(Xml, best(b"<?xml version=\"1.0\"?>\n<doc/>\n"));
+    assert!(detect_format(b"").is_empty());
+    assert_eq!(Binary, best(b"\x7fELF\x02\x01\x01\x00\x00"));
+}
+
+#[test]
+fn detects_conll_and_compression() {
+    let path = Path::new("test_data/fr/sample.conllx");
+    let conll = File::open(&path).read_exact(4096).unwrap();
+    let guesses = detect_format(conll.as_slice());
+    assert_eq!(Conll, guesses[0].format);
+    // It's valid TSV too, just less likely.
+    assert!(guesses.iter().any(|g| g.format == Delimited(Dialect::tsv())));
+
+    let path = Path::new("test_data/fr/sample.conllx.gz");
+    let gzip = File::open(&path).read_exact(64).unwrap();
+    assert_eq!(vec![Guess{format: Compressed(Gzip), confidence: 1.0}],
+               detect_format(gzip.as_slice()));
+}
@@ -0,0 +1,199 @@
+//! Expanding and compacting enumerated columns.
+//!
+//! Extracts often replace repetitive string columns (country, status,
+//! product category) with small integer codes, plus a dictionary to map
+//! them back.  `DictionaryTransform` rewrites CSV records in either
+//! direction as they stream past: `expand` turns codes into strings, and
+//! `compact` turns strings into codes, adding new strings to the
+//! dictionary as it sees them.
+
+use std::collections::HashMap;
+use std::io::{InvalidInput,IoError,IoResult};
+use std::str::from_utf8;
+
+use csv::{Dialect,split_fields};
+
+#[cfg(test)] use std::io::MemWriter;
+
+/// A two-way mapping between byte strings and small integer codes.  Codes
+/// are assigned in order, starting from 0.
+#[deriving(Clone)]
+pub struct Interner {
+    codes: HashMap<Vec<u8>, uint>,
+    values: Vec<Vec<u8>>
+}
+
+impl Interner {
+    /// Create an empty interner.
+    pub fn new() -> Interner {
+        Interner{codes: HashMap::new(), values: vec![]}
+    }
+
+    /// Create an interner from an existing dictionary, where `values[i]`
+    /// has the code `i`.  Fails if a value appears twice, because it can
+    /// only have one code.
+    pub fn from_values(values: &[&[u8]]) -> IoResult<Interner> {
+        let mut interner = Interner::new();
+        for value in values.iter() {
+            if interner.code(*value).is_some() {
+                return Err(bad_value("duplicate dictionary value", *value));
+            }
+            interner.intern(*value);
+        }
+        Ok(interner)
+    }
+
+    /// Return the code for `value`, assigning it the next free code if
+    /// it doesn't have one yet.
+    pub fn intern(&mut self, value: &[u8]) -> uint {
+        match self.code(value) {
+            Some(code) => code,
+            None => {
+                let code = self.values.len();
+                self.values.push(value.to_vec());
+                self.codes.insert(value.to_vec(), code);
+                code
+            }
+        }
+    }
+
+    /// The code for `value`, if it has one.
+    pub fn code(&self, value: &[u8]) -> Option<uint> {
+        self.codes.find_equiv(&value).map(|code| *code)
+    }
+
+    /// The value with the code `code`, if any.
+    pub fn value<'a>(&'a self, code: uint) -> Option<&'a [u8]> {
+        self.values.as_slice().get(code).map(|value| value.as_slice())
+    }
+
+    /// How many values we know about.
+    pub fn len(&self) -> uint { self.values.len() }
+}
+
+fn bad_value(desc: &'static str, value: &[u8]) -> IoError {
+    IoError{kind: InvalidInput, desc: desc,
+            detail: Some(String::from_utf8_lossy(value).into_string())}
+}
+
+// Where in `columns` is the entry for column `index`?
+fn find_column(columns: &[(uint, Interner)], index: uint) -> Option<uint> {
+    columns.iter().position(|&(i, _)| i == index)
+}
+
+/// Rewrites enumerated columns of CSV records using an `Interner` per
+/// column.
+pub struct DictionaryTransform {
+    dialect: Dialect,
+    columns: Vec<(uint, Interner)>,
+    spans: Vec<(uint, uint)>
+}
+
+impl DictionaryTransform {
+    /// Create a transform for records in `dialect`, with no columns.
+    pub fn new(dialect: Dialect) -> DictionaryTransform {
+        DictionaryTransform{dialect: dialect, columns: vec![], spans: vec![]}
+    }
+
+    /// Translate the column at `index` using `dictionary`.
+    pub fn column(mut self, index: uint, dictionary: Interner)
+                  -> DictionaryTransform {
+        self.columns.push((index, dictionary));
+        self
+    }
+
+    /// The dictionary for the column at `index`.  After calling `compact`,
+    /// this includes any new values we've seen, so save it alongside the
+    /// output.
+    pub fn dictionary<'a>(&'a self, index: uint) -> Option<&'a Interner> {
+        match find_column(self.columns.as_slice(), index) {
+            Some(n) => {
+                let (_, ref dictionary) = self.columns[n];
+                Some(dictionary)
+            }
+            None => None
+        }
+    }
+
+    /// Write `record` to `out`, replacing the codes in our columns with
+    /// the strings they stand for.  Unknown codes are an error.
+    pub fn expand(&mut self, record: &[u8], out: &mut Writer)
+                  -> IoResult<()> {
+        let DictionaryTransform{ref dialect, ref columns,
+                                ref mut spans} = *self;
+        split_fields(record, dialect, spans);
+        for (i, &(start, end)) in spans.iter().enumerate() {
+            if i > 0 { try!(out.write_u8(dialect.delimiter)); }
+            let field = record.slice(start, end);
+            match find_column(columns.as_slice(), i) {
+                None => { try!(dialect.write_field(out, field)); }
+                Some(n) => {
+                    let (_, ref dictionary) = columns[n];
+                    let code = from_utf8(field).and_then(|text| {
+                        from_str::<uint>(text)
+                    });
+                    match code.and_then(|code| dictionary.value(code)) {
+                        Some(value) => {
+                            try!(dialect.write_field(out, value));
+                        }
+                        None => {
+                            return Err(bad_value("unknown code", field));
+                        }
+                    }
+                }
+            }
+        }
+        out.write_u8(b'\n')
+    }
+
+    /// Write `record` to `out`, replacing the strings in our columns with
+    /// their codes.  Strings we haven't seen before get new codes.
+    pub fn compact(&mut self, record: &[u8], out: &mut Writer)
+                   -> IoResult<()> {
+        let DictionaryTransform{ref dialect, ref mut columns,
+                                ref mut spans} = *self;
+        split_fields(record, dialect, spans);
+        for (i, &(start, end)) in spans.iter().enumerate() {
+            if i > 0 { try!(out.write_u8(dialect.delimiter)); }
+            let field = record.slice(start, end);
+            match find_column(columns.as_slice(), i) {
+                None => { try!(dialect.write_field(out, field)); }
+                Some(n) => {
+                    let (_, ref mut dictionary) = columns.as_mut_slice()[n];
+                    let code = dictionary.intern(field);
+                    try!(write!(out, "{}", code));
+                }
+            }
+        }
+        out.write_u8(b'\n')
+    }
+}
+
+#[test]
+fn expands_and_compacts_columns() {
+    let statuses =
+        Interner::from_values(&[b"active", b"closed, for good"]).unwrap();
+    let mut transform = DictionaryTransform::new(Dialect::csv())
+        .column(1, statuses)
+        .column(2, Interner::new());
+
+    let mut compacted = MemWriter::new();
+    transform.compact(b"a,active,fr", &mut compacted).unwrap();
+    transform.compact(b"b,\"closed, for good\",de", &mut compacted).unwrap();
+    transform.compact(b"c,paused,fr", &mut compacted).unwrap();
+    assert_eq!(b"a,0,0\nb,1,1\nc,2,0\n", compacted.get_ref());
+    assert_eq!(3, transform.dictionary(1).unwrap().len());
+
+    let mut expanded = MemWriter::new();
+    transform.expand(b"b,1,1", &mut expanded).unwrap();
+    assert_eq!(b"b,\"closed, for good\",de\n", expanded.get_ref());
+    assert!(transform.expand(b"x,7,0", &mut expanded).is_err());
+}
+
+#[test]
+fn from_values_rejects_duplicates() {
+    let interner = Interner::from_values(&[b"a", b"b", b"c"]).unwrap();
+    assert_eq!((Some(1), Some(b"c".to_vec())),
+               (interner.code(b"b"), interner.value(2).map(|v| v.to_vec())));
+    assert!(Interner::from_values(&[b"a", b"b", b"a"]).is_err());
+}
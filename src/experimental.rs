@@ -0,0 +1,279 @@
+//! Redesigns of the core traits which aren't ready yet.
+//!
+//! Everything in `iter` is meant to stay put, so that code built on it
+//! keeps compiling.  New shapes for `StreamingIterator`, especially ones
+//! which depend on unfinished compiler features, go here instead.  This
+//! module is only built with the `experimental` Cargo feature, and it may
+//! change or disappear at any time.
+//!
+//! ## Items as an associated type
+//!
+//! `StreamingIterator<'a, T>` makes every implementor and adapter repeat
+//! `'a` in its impl header, its item type and its `next` signature.
+//! `AssocStreamingIterator` moves the item into an associated type, chosen
+//! once per implementor, and leaves the borrow lifetime on `next` where it
+//! belongs.  Since associated types can't yet take lifetime parameters
+//! directly, the item is named by a "family" type implementing `ItemFor`,
+//! such as `Slices` for `&'a [u8]`.
+//!
+//! Adapters which pass items through, like `Fuse` and `Take`, just reuse
+//! their source's family.  Adapters which change the item type define a
+//! family of their own, as `Enumerated` does for `Enumerate`.
+//! `streaming_for!` works unchanged with the trait in scope, since it only
+//! calls `next`.
+//!
+//! To mix the two designs, `Compat` adapts the new trait to the old one,
+//! so the adapters in `iter` can be used, and `FromSlices` adapts any
+//! `SliceStream` to the new trait.
+
+use std::cmp::min;
+
+use iter::{SliceStream,StreamingIterator};
+
+#[cfg(test)] use std::io::BufReader;
+#[cfg(test)] use cursor::{CursorIter,LineCursor};
+#[cfg(test)] use iter::Fuse as OldFuse;
+
+/// A family of item types, one for each borrow lifetime.
+pub trait ItemFor<'a> {
+    /// The item type when the iterator is borrowed for `'a`.
+    type Item;
+}
+
+/// Items which borrow byte slices from the iterator.
+pub struct Slices;
+
+impl<'a> ItemFor<'a> for Slices {
+    type Item = &'a [u8];
+}
+
+/// Items of type `T`, which don't borrow from the iterator at all.
+pub struct Owned<T>;
+
+impl<'a, T> ItemFor<'a> for Owned<T> {
+    type Item = T;
+}
+
+/// Items from the family `F`, paired with their index.  See `Enumerate`.
+pub struct Enumerated<F>;
+
+impl<'a, F: ItemFor<'a>> ItemFor<'a> for Enumerated<F> {
+    type Item = (uint, <F as ItemFor<'a>>::Item);
+}
+
+/// `StreamingIterator`, with the item type as an associated type.
+pub trait AssocStreamingIterator {
+    /// The family of items we return.
+    type Items: for<'a> ItemFor<'a>;
+
+    /// Return either the next item in the sequence, or `None` if all items
+    /// have been consumed.
+    fn next<'a>(&'a mut self) -> Option<<Self::Items as ItemFor<'a>>::Item>;
+
+    /// Return a lower bound and an optional upper bound on the number of
+    /// items left, as `StreamingIterator::size_hint` does.
+    fn size_hint(&self) -> (uint, Option<uint>) { (0, None) }
+
+    /// Consume the iterator, counting the items.
+    fn count(&mut self) -> uint {
+        let mut count = 0;
+        loop {
+            match self.next() {
+                None => { break; }
+                Some(_) => { count += 1; }
+            }
+        }
+        count
+    }
+
+    /// Stop for good once `next` has returned `None`.
+    fn fuse(self) -> Fuse<Self> { Fuse{iter: self, done: false} }
+
+    /// Return at most `n` items.
+    fn take(self, n: uint) -> Take<Self> { Take{iter: self, left: n} }
+
+    /// Pair each item with its index, starting from 0.
+    fn enumerate(self) -> Enumerate<Self> { Enumerate{iter: self, count: 0} }
+}
+
+/// An iterator which stays exhausted.  See `AssocStreamingIterator::fuse`.
+pub struct Fuse<I> {
+    iter: I,
+    done: bool
+}
+
+impl<I: AssocStreamingIterator> AssocStreamingIterator for Fuse<I> {
+    type Items = I::Items;
+
+    fn next<'a>(&'a mut self) -> Option<<I::Items as ItemFor<'a>>::Item> {
+        if self.done { return None; }
+        let item = self.iter.next();
+        if item.is_none() { self.done = true; }
+        item
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        if self.done { (0, Some(0)) } else { self.iter.size_hint() }
+    }
+}
+
+/// An iterator which returns at most `n` items.  See
+/// `AssocStreamingIterator::take`.
+pub struct Take<I> {
+    iter: I,
+    left: uint
+}
+
+impl<I: AssocStreamingIterator> AssocStreamingIterator for Take<I> {
+    type Items = I::Items;
+
+    fn next<'a>(&'a mut self) -> Option<<I::Items as ItemFor<'a>>::Item> {
+        if self.left == 0 { return None; }
+        self.left -= 1;
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        let upper = match upper {
+            Some(upper) => min(upper, self.left),
+            None => self.left
+        };
+        (min(lower, self.left), Some(upper))
+    }
+}
+
+/// An iterator which pairs items with their index.  See
+/// `AssocStreamingIterator::enumerate`.
+pub struct Enumerate<I> {
+    iter: I,
+    count: uint
+}
+
+impl<I: AssocStreamingIterator> AssocStreamingIterator for Enumerate<I> {
+    type Items = Enumerated<I::Items>;
+
+    fn next<'a>(&'a mut self)
+                -> Option<(uint, <I::Items as ItemFor<'a>>::Item)> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => {
+                self.count += 1;
+                Some((self.count - 1, item))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// Wraps an `AssocStreamingIterator` so it can be used anywhere a
+/// `StreamingIterator` is expected.
+pub struct Compat<I> {
+    iter: I
+}
+
+impl<I> Compat<I> {
+    /// Adapt `iter` to the old trait.
+    pub fn new(iter: I) -> Compat<I> { Compat{iter: iter} }
+
+    /// Get back our iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<'a, I: AssocStreamingIterator>
+    StreamingIterator<'a, <I::Items as ItemFor<'a>>::Item> for Compat<I> {
+
+    fn next(&'a mut self) -> Option<<I::Items as ItemFor<'a>>::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// Wraps a `SliceStream` so it can be used anywhere an
+/// `AssocStreamingIterator` is expected.
+pub struct FromSlices<I> {
+    iter: I
+}
+
+impl<I: SliceStream> FromSlices<I> {
+    /// Adapt `iter` to the new trait.
+    pub fn new(iter: I) -> FromSlices<I> { FromSlices{iter: iter} }
+
+    /// Get back our iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<I: SliceStream> AssocStreamingIterator for FromSlices<I> {
+    type Items = Slices;
+
+    fn next<'a>(&'a mut self) -> Option<&'a [u8]> { self.iter.next() }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+// Splits a byte string into words, reusing a buffer.  Note how little
+// lifetime bookkeeping this needs compared to `StreamingIterator`.
+#[cfg(test)]
+struct Words {
+    text: Vec<u8>,
+    pos: uint,
+    word: Vec<u8>
+}
+
+#[cfg(test)]
+impl Words {
+    fn new(text: &[u8]) -> Words {
+        Words{text: text.to_vec(), pos: 0, word: vec![]}
+    }
+}
+
+#[cfg(test)]
+impl AssocStreamingIterator for Words {
+    type Items = Slices;
+
+    fn next<'a>(&'a mut self) -> Option<&'a [u8]> {
+        while self.pos < self.text.len() && self.text[self.pos] == b' ' {
+            self.pos += 1;
+        }
+        if self.pos == self.text.len() { return None; }
+        self.word.clear();
+        while self.pos < self.text.len() && self.text[self.pos] != b' ' {
+            self.word.push(self.text[self.pos]);
+            self.pos += 1;
+        }
+        Some(self.word.as_slice())
+    }
+}
+
+#[test]
+fn associated_items_work_with_old_adapters() {
+    let mut compat = OldFuse::new(Compat::new(Words::new(b" to be  or")));
+    let mut seen = vec![];
+    streaming_for!(word in compat, { seen.push(word.to_vec()); });
+    assert_eq!(vec![b"to".to_vec(), b"be".to_vec(), b"or".to_vec()], seen);
+    assert_eq!(2, Words::new(b"a b").count());
+}
+
+#[test]
+fn associated_items_have_their_own_adapters() {
+    let mut words = Words::new(b"to be or not").enumerate().take(3).fuse();
+    let mut seen = vec![];
+    streaming_for!((i, word) in words, { seen.push((i, word.to_vec())); });
+    assert_eq!(vec![(0, b"to".to_vec()), (1, b"be".to_vec()),
+                    (2, b"or".to_vec())],
+               seen);
+    assert!(words.next().is_none());
+    assert_eq!((0, Some(0)), words.size_hint());
+}
+
+#[test]
+fn slice_streams_convert_to_associated_items() {
+    let lines = CursorIter::new(LineCursor::new(BufReader::new(
+        b"a\nb\nc\n")));
+    let mut lines = FromSlices::new(lines).take(2);
+    let mut seen = vec![];
+    streaming_for!(line in lines, { seen.push(line.to_vec()); });
+    assert_eq!(vec![b"a\n".to_vec(), b"b\n".to_vec()], seen);
+}
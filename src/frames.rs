@@ -0,0 +1,189 @@
+//! Fixed-size binary frames with sync markers.
+//!
+//! Telemetry logs and serial captures are often a sequence of fixed-length
+//! frames, each starting with a sync marker (say, `0xAA 0x55`) and ending
+//! with a checksum.  Bytes get dropped, so we need to hunt for the marker,
+//! check the frame, and resynchronize one byte later if it's bad.
+
+use std::io::{Buffer,EndOfFile,IoError,IoResult};
+
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::MemReader;
+#[cfg(test)] use buffers::DribbleBuffer;
+
+/// A frame is valid if its last byte is the 8-bit sum of all the bytes
+/// before it, including the sync marker.
+pub fn sum8(frame: &[u8]) -> bool {
+    match frame.last() {
+        None => false,
+        Some(&check) => {
+            frame.init().iter().fold(0u8, |acc, &b| acc + b) == check
+        }
+    }
+}
+
+/// Yields each valid frame in `input` as a slice, skipping garbage and
+/// frames which fail their checksum.
+pub struct FrameReader<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    sync: Vec<u8>,
+    frame_len: uint,
+    is_valid: fn(&[u8]) -> bool,
+    // Our unconsumed data is `buffer[start..]`.
+    buffer: Vec<u8>,
+    start: uint,
+    // How much of our buffer was returned last time?
+    returned: uint
+}
+
+impl<'a, T: Buffer+'a> FrameReader<'a, T> {
+    /// Read frames of `frame_len` bytes (including `sync`) from `input`,
+    /// using `is_valid` to check each candidate frame.
+    pub fn new(input: &'a mut T, sync: &[u8], frame_len: uint,
+               is_valid: fn(&[u8]) -> bool) -> FrameReader<'a, T> {
+        assert!(sync.len() > 0 && sync.len() <= frame_len);
+        FrameReader{input: input, sync: sync.to_vec(), frame_len: frame_len,
+                    is_valid: is_valid, buffer: vec![], start: 0,
+                    returned: 0}
+    }
+
+    // Make sure we have at least `len` unconsumed bytes, if possible.
+    // Returns false at the end of the input.
+    fn fill_to(&mut self, len: uint) -> IoResult<bool> {
+        while self.buffer.len() - self.start < len {
+            let consumed = {
+                match self.input.fill_buf() {
+                    Ok(read) => {
+                        let free = self.buffer.capacity() - self.buffer.len();
+                        if self.start > 0 && free < read.len() {
+                            // We're out of room, so shuffle our unconsumed
+                            // data down to the front.  Doing this only
+                            // when we must keeps us from copying the whole
+                            // buffer for every frame.
+                            let remaining = self.buffer.len() - self.start;
+                            for i in range(0, remaining) {
+                                self.buffer.as_mut_slice()[i] =
+                                    self.buffer[self.start + i];
+                            }
+                            self.buffer.truncate(remaining);
+                            self.start = 0;
+                        }
+                        self.buffer.push_all(read);
+                        read.len()
+                    }
+                    Err(IoError{kind: EndOfFile, ..}) => { return Ok(false); }
+                    Err(err) => { return Err(err); }
+                }
+            };
+            self.input.consume(consumed);
+        }
+        Ok(true)
+    }
+}
+
+impl<'a, 'b, T: Buffer+'b> StreamingIterator<'a, IoResult<&'a [u8]>>
+    for FrameReader<'b, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        self.start += self.returned;
+        self.returned = 0;
+        loop {
+            match self.fill_to(self.frame_len) {
+                Ok(true) => {}
+                Ok(false) => { return None; }
+                Err(err) => { return Some(Err(err)); }
+            }
+            let sync_len = self.sync.len();
+            let found = self.buffer.slice_from(self.start)
+                .windows(sync_len)
+                .position(|w| w == self.sync.as_slice());
+            match found {
+                None => {
+                    // Keep just enough to catch a marker split across
+                    // reads.
+                    self.start = self.buffer.len() - (sync_len - 1);
+                }
+                Some(0) => {
+                    let frame_end = self.start + self.frame_len;
+                    let ok = (self.is_valid)(
+                        self.buffer.slice(self.start, frame_end));
+                    if ok {
+                        self.returned = self.frame_len;
+                        return Some(Ok(self.buffer.slice(self.start,
+                                                         frame_end)));
+                    }
+                    // Bad frame, so resynchronize one byte later.
+                    self.start += 1;
+                }
+                Some(pos) => { self.start += pos; }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut result = vec![0xAA, 0x55];
+    result.push_all(payload);
+    let sum = result.iter().fold(0u8, |acc, &b| acc + b);
+    result.push(sum);
+    result
+}
+
+#[cfg(test)]
+fn test_stream() -> Vec<u8> {
+    let mut data = vec![1, 2, 0xAA];
+    data.push_all(frame(&[1, 2, 3]).as_slice());
+    let mut bad = frame(&[4, 5, 6]);
+    bad.as_mut_slice()[3] = 0;
+    data.push_all(bad.as_slice());
+    data.push_all(frame(&[0xAA, 0x55, 7]).as_slice());
+    data.push_all(&[0xAA, 0x55, 9]);
+    data
+}
+
+#[cfg(test)]
+fn read_frames<T: Buffer>(input: &mut T) -> Vec<Vec<u8>> {
+    let mut frames = FrameReader::new(input, &[0xAA, 0x55], 6, sum8);
+    let mut result = vec![];
+    streaming_for!(f in frames, {
+        result.push(f.unwrap().to_vec())
+    });
+    result
+}
+
+#[test]
+fn frame_reader_resynchronizes() {
+    let expected = vec![frame(&[1, 2, 3]), frame(&[0xAA, 0x55, 7])];
+    let mut reader = MemReader::new(test_stream());
+    assert_eq!(expected, read_frames(&mut reader));
+}
+
+#[test]
+fn frame_reader_via_dribble() {
+    let expected = vec![frame(&[1, 2, 3]), frame(&[0xAA, 0x55, 7])];
+    let mut reader = MemReader::new(test_stream());
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    assert_eq!(expected, read_frames(&mut dribble));
+}
+
+#[test]
+fn frame_reader_reuses_its_buffer() {
+    let mut data = vec![];
+    for i in range(0u8, 200) { data.push_all(frame(&[i, 1, 2]).as_slice()); }
+    let mut reader = MemReader::new(data);
+    let mut dribble = DribbleBuffer::new(&mut reader);
+    let mut frames = FrameReader::new(&mut dribble, &[0xAA, 0x55], 6, sum8);
+    let mut count = 0u;
+    loop {
+        match frames.next() {
+            Some(f) => { f.unwrap(); count += 1; }
+            None => { break; }
+        }
+    }
+    assert_eq!(200, count);
+    // We only shuffle data down when we run out of room, but we do run
+    // out, so we never hold more than a few frames.
+    assert!(frames.buffer.capacity() <= 64);
+}
@@ -0,0 +1,84 @@
+//! Splitting text into indentation-based blocks.
+//!
+//! A surprising number of formats group lines by indentation: YAML-ish
+//! configs, outlines, and Python tracebacks in log files.  Rather than
+//! writing a parser for each one, `IndentBlocks` yields each line together
+//! with the more-indented lines which follow it.
+
+use std::io::{Buffer,IoResult};
+
+use buffers::read_until_into;
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::MemReader;
+
+/// Count the spaces and tabs at the start of `line`.
+pub fn indentation(line: &[u8]) -> uint {
+    line.iter().take_while(|&&b| b == b' ' || b == b'\t').count()
+}
+
+// Is `line` nothing but whitespace?
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(|&b| b == b' ' || b == b'\t' || b == b'\r' || b == b'\n')
+}
+
+/// Yields blocks of lines from `input`.  A block is a line, plus all the
+/// following lines which are indented more deeply than it.  Blank lines
+/// never end a block.
+pub struct IndentBlocks<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    block: Vec<u8>,
+    // The first line of the next block, if we've already read it.
+    pending: Vec<u8>
+}
+
+impl<'a, T: Buffer+'a> IndentBlocks<'a, T> {
+    /// Split `input` into indentation-based blocks.
+    pub fn new(input: &'a mut T) -> IndentBlocks<'a, T> {
+        IndentBlocks{input: input, block: vec![], pending: vec![]}
+    }
+}
+
+impl<'a, 'b, T: Buffer+'b> StreamingIterator<'a, IoResult<&'a [u8]>>
+    for IndentBlocks<'b, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        self.block.clear();
+        if self.pending.len() == 0 {
+            match read_until_into(self.input, b'\n', &mut self.pending) {
+                Ok(true) => {}
+                Ok(false) => { return None; }
+                Err(err) => { return Some(Err(err)); }
+            }
+        }
+        self.block.push_all(self.pending.as_slice());
+        let base = indentation(self.block.as_slice());
+        loop {
+            self.pending.clear();
+            match read_until_into(self.input, b'\n', &mut self.pending) {
+                Ok(true) => {}
+                Ok(false) => { break; }
+                Err(err) => { return Some(Err(err)); }
+            }
+            let line = self.pending.as_slice();
+            if !is_blank(line) && indentation(line) <= base { break; }
+            self.block.push_all(line);
+        }
+        Some(Ok(self.block.as_slice()))
+    }
+}
+
+#[test]
+fn splits_on_indentation() {
+    let text = "Traceback:\n  File \"a.py\"\n    x()\n\n  Error\nnext\n  more";
+    let mut reader = MemReader::new(text.as_bytes().to_vec());
+    let mut blocks = IndentBlocks::new(&mut reader);
+    let mut result = vec![];
+    streaming_for!(block in blocks, {
+        result.push(String::from_utf8(block.unwrap().to_vec()).unwrap())
+    });
+    assert_eq!(vec!["Traceback:\n  File \"a.py\"\n    x()\n\n  Error\n"
+                    .to_string(),
+                    "next\n  more".to_string()],
+               result);
+}
@@ -0,0 +1,199 @@
+//! Owned byte strings which store short values inline.
+//!
+//! Adapters which need to hang onto a copy of an item (to compare it
+//! against the next one, say) would normally allocate a `Vec<u8>`.  But
+//! most records in CSV files and logs are short, so `InlineBytes` keeps
+//! anything which fits in a fixed-size array on the stack, and only falls
+//! back to the heap for long values.
+//!
+//! Any adapter which copies items with `OwnedCopy` can use it, such as
+//! `Dedup` or `Windows`:
+//!
+//! ```ignore
+//! let mut deduped: Dedup<_, InlineBytes<[u8, ..64]>> = records.dedup();
+//! ```
+
+use std::fmt;
+
+use iter::OwnedCopy;
+
+#[cfg(test)] use std::io::BufReader;
+#[cfg(test)] use cursor::{CursorIter,LineCursor};
+#[cfg(test)] use iter::{Dedup,IterAdapter,OwnedStreamingIterator};
+#[cfg(test)] use iter::{StreamingIterator,Windows};
+
+/// A fixed-size byte array which can be used as inline storage by
+/// `InlineBytes`.  Implemented for `[u8, ..N]` for a handful of useful
+/// sizes.
+pub trait InlineArray {
+    /// Create a new, zero-filled array.
+    fn zeroed() -> Self;
+    /// View the entire array as a slice.
+    fn as_slice<'a>(&'a self) -> &'a [u8];
+    /// View the entire array as a mutable slice.
+    fn as_mut_slice<'a>(&'a mut self) -> &'a mut [u8];
+}
+
+macro_rules! inline_array {
+    ($($n:expr),*) => {
+        $(
+            impl InlineArray for [u8, ..$n] {
+                fn zeroed() -> [u8, ..$n] { [0, ..$n] }
+                // Coerce to a slice, rather than calling `as_slice`, which
+                // would find this method again.
+                fn as_slice<'a>(&'a self) -> &'a [u8] {
+                    let s: &[u8] = self;
+                    s
+                }
+                fn as_mut_slice<'a>(&'a mut self) -> &'a mut [u8] {
+                    let s: &mut [u8] = self;
+                    s
+                }
+            }
+        )*
+    }
+}
+
+inline_array!(8, 16, 24, 32, 48, 64, 128, 256)
+
+enum Storage<A> {
+    Inline(uint, A),
+    Heap(Vec<u8>)
+}
+
+/// An owned byte string which stores values of up to `A`'s length inline,
+/// and spills anything longer onto the heap.
+pub struct InlineBytes<A> {
+    storage: Storage<A>
+}
+
+impl<A: InlineArray> InlineBytes<A> {
+    /// Create a new, empty `InlineBytes`.
+    pub fn new() -> InlineBytes<A> {
+        InlineBytes{storage: Inline(0, InlineArray::zeroed())}
+    }
+
+    /// Create a new `InlineBytes` holding a copy of `bytes`.
+    pub fn from_slice(bytes: &[u8]) -> InlineBytes<A> {
+        let mut result = InlineBytes::new();
+        result.set(bytes);
+        result
+    }
+
+    /// Replace our contents with a copy of `bytes`.  If we've already
+    /// spilled onto the heap, we reuse that allocation for long values.
+    pub fn set(&mut self, bytes: &[u8]) {
+        let fits = {
+            let capacity: A = InlineArray::zeroed();
+            bytes.len() <= capacity.as_slice().len()
+        };
+        match self.storage {
+            Heap(ref mut vec) if !fits => {
+                vec.clear();
+                vec.push_all(bytes);
+                return;
+            }
+            _ => {}
+        }
+        if fits {
+            let mut array: A = InlineArray::zeroed();
+            array.as_mut_slice().slice_to_mut(bytes.len())
+                .clone_from_slice(bytes);
+            self.storage = Inline(bytes.len(), array);
+        } else {
+            self.storage = Heap(bytes.to_vec());
+        }
+    }
+
+    /// Our contents, as a slice.
+    pub fn as_slice<'a>(&'a self) -> &'a [u8] {
+        match self.storage {
+            Inline(len, ref array) => array.as_slice().slice_to(len),
+            Heap(ref vec) => vec.as_slice()
+        }
+    }
+
+    /// The length of our contents, in bytes.
+    pub fn len(&self) -> uint { self.as_slice().len() }
+
+    /// Have we had to allocate heap storage?
+    pub fn spilled(&self) -> bool {
+        match self.storage { Inline(..) => false, Heap(..) => true }
+    }
+}
+
+impl<A: InlineArray> PartialEq for InlineBytes<A> {
+    fn eq(&self, other: &InlineBytes<A>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<A: InlineArray> Eq for InlineBytes<A> {}
+
+impl<A: InlineArray> Clone for InlineBytes<A> {
+    fn clone(&self) -> InlineBytes<A> {
+        InlineBytes::from_slice(self.as_slice())
+    }
+}
+
+impl<A: InlineArray> fmt::Show for InlineBytes<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl<'a, A: InlineArray> OwnedCopy<InlineBytes<A>> for &'a [u8] {
+    fn owned_copy(&self) -> InlineBytes<A> { InlineBytes::from_slice(*self) }
+    fn copy_into(&self, dest: &mut InlineBytes<A>) { dest.set(*self); }
+    fn same_as(&self, copy: &InlineBytes<A>) -> bool {
+        *self == copy.as_slice()
+    }
+}
+
+#[test]
+fn short_values_stay_inline() {
+    let mut bytes: InlineBytes<[u8, ..8]> = InlineBytes::from_slice(b"abc");
+    assert_eq!(b"abc", bytes.as_slice());
+    assert!(!bytes.spilled());
+    bytes.set(b"12345678");
+    assert_eq!(b"12345678", bytes.as_slice());
+    assert!(!bytes.spilled());
+}
+
+#[test]
+fn long_values_spill_to_heap() {
+    let mut bytes: InlineBytes<[u8, ..8]> = InlineBytes::new();
+    bytes.set(b"123456789");
+    assert_eq!(b"123456789", bytes.as_slice());
+    assert!(bytes.spilled());
+    bytes.set(b"xy");
+    assert_eq!(b"xy", bytes.as_slice());
+    assert!(!bytes.spilled());
+}
+
+#[test]
+fn works_as_dedup_storage() {
+    let records = b"a,1\na,1\nb,2\na,1".split(|&b| b == b'\n');
+    let mut deduped: Dedup<_, InlineBytes<[u8, ..16]>> =
+        Dedup::new(IterAdapter::new(records));
+    let mut items = vec![];
+    deduped.for_each(|record| items.push(record.to_vec()));
+    assert_eq!(vec![b"a,1".to_vec(), b"b,2".to_vec(), b"a,1".to_vec()],
+               items);
+}
+
+#[test]
+fn works_as_windows_storage() {
+    let lines = CursorIter::new(LineCursor::new(BufReader::new(
+        b"a\nb\nlong enough to spill\n")));
+    let mut windows: Windows<_, InlineBytes<[u8, ..8]>> =
+        Windows::new(lines, 2);
+    let mut pairs = vec![];
+    streaming_for!(window in windows, {
+        pairs.push((window[0].as_slice().to_vec(),
+                    window[1].as_slice().to_vec()));
+    });
+    assert_eq!(vec![(b"a\n".to_vec(), b"b\n".to_vec()),
+                    (b"b\n".to_vec(), b"long enough to spill\n".to_vec())],
+               pairs);
+}
@@ -0,0 +1,123 @@
+//! Editing records in place.
+//!
+//! `StreamingIterator` doesn't care whether its items are shared or
+//! mutable references, so a source can lend out `&mut` access to its own
+//! buffer.  That lets a pipeline lowercase a field or patch a few bytes
+//! before writing a record back out, without copying it into a second
+//! buffer first.
+//!
+//! `MutLines` is such a source, and `Edit` applies a function to each
+//! mutable item and hands on a shared view of the result, ready for a
+//! `RecordSink` or any other adapter.
+
+use std::io::{Buffer,IoError,SeekSet};
+
+use buffers::read_until_into;
+use iter::{Resettable,StreamingIterator};
+
+#[cfg(test)] use std::io::BufReader;
+
+/// A streaming iterator over the lines of a `Buffer`, including their
+/// terminators, which yields each one as a mutable buffer.  The buffer is
+/// reused for the next line, and may be grown or shrunk freely.  Like
+/// `LineCursor`, we stop at the first error and keep it for `error`.
+pub struct MutLines<B> {
+    input: B,
+    line: Vec<u8>,
+    error: Option<IoError>
+}
+
+impl<B: Buffer> MutLines<B> {
+    /// Read lines from `input`.
+    pub fn new(input: B) -> MutLines<B> {
+        MutLines{input: input, line: vec![], error: None}
+    }
+
+    /// The error which stopped us, if any.
+    pub fn error(&self) -> Option<&IoError> { self.error.as_ref() }
+}
+
+impl<'a, B: Buffer> StreamingIterator<'a, &'a mut Vec<u8>> for MutLines<B> {
+    fn next(&'a mut self) -> Option<&'a mut Vec<u8>> {
+        if self.error.is_some() { return None; }
+        self.line.clear();
+        match read_until_into(&mut self.input, b'\n', &mut self.line) {
+            Ok(true) => Some(&mut self.line),
+            Ok(false) => None,
+            Err(err) => { self.error = Some(err); None }
+        }
+    }
+}
+
+/// Rewinds to the start of the input.  If that fails, we stop, and
+/// `error` returns the reason.
+impl<B: Buffer+Seek> Resettable for MutLines<B> {
+    fn reset(&mut self) {
+        self.error = self.input.seek(0, SeekSet).err();
+    }
+}
+
+/// Runs `f` over each mutable item of a streaming iterator, and yields a
+/// shared view of the edited item.
+pub struct Edit<'f, I> {
+    iter: I,
+    f: |&mut Vec<u8>|: 'f
+}
+
+impl<'f, I> Edit<'f, I> {
+    /// Edit each item of `iter` with `f`.
+    pub fn new(iter: I, f: |&mut Vec<u8>|: 'f) -> Edit<'f, I> {
+        Edit{iter: iter, f: f}
+    }
+}
+
+impl<'f, I: Resettable> Resettable for Edit<'f, I> {
+    fn reset(&mut self) { self.iter.reset(); }
+}
+
+impl<'a, 'f, I> StreamingIterator<'a, &'a [u8]> for Edit<'f, I>
+    where I: StreamingIterator<'a, &'a mut Vec<u8>> {
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => {
+                (self.f)(&mut *item);
+                Some(item.as_slice())
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+#[test]
+fn edits_lines_in_place() {
+    let mut lines = MutLines::new(BufReader::new(b"Hello\nWORLD\n"));
+    {
+        let first = lines.next().unwrap();
+        first.as_mut_slice()[0] = b'J';
+        assert_eq!(b"Jello\n", first.as_slice());
+    }
+    let mut edited = Edit::new(lines, |line| {
+        for b in line.iter_mut() {
+            if *b >= b'A' && *b <= b'Z' { *b += b'a' - b'A'; }
+        }
+        line.insert(0, b'>');
+    });
+    assert_eq!(b">world\n", edited.next().unwrap());
+    assert_eq!(None, edited.next());
+}
+
+#[test]
+fn mut_lines_reset_rewinds_the_input() {
+    let mut lines = MutLines::new(BufReader::new(b"one\ntwo\n"));
+    lines.next().unwrap().as_mut_slice()[0] = b'O';
+    assert_eq!(b"two\n", lines.next().unwrap().as_slice());
+    lines.reset();
+    assert!(lines.error().is_none());
+    // Our edit only changed our copy of the line, not the input.
+    assert_eq!(b"one\n", lines.next().unwrap().as_slice());
+    assert_eq!(b"two\n", lines.next().unwrap().as_slice());
+    assert!(lines.next().is_none());
+}
@@ -2,30 +2,3137 @@
 
 #![macro_escape]
 
+use std::cmp::{Greater,Ordering,min};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::io::{IoError,IoResult};
+use std::iter::ExactSize;
+use std::mem::swap;
+use std::uint;
+
+#[cfg(test)] use std::io::{BufReader,EndOfFile,InvalidInput,IoErrorKind,
+                           standard_error};
+#[cfg(test)] use std::io::{BufWriter,MemWriter};
+#[cfg(test)] use cursor::{CursorIter,LineCursor};
+
 /// Like `Iterator`, but it allows you to store temporary data in the
 /// iterator itself, and return temporary references from `next`.
 ///
+/// This is the one streaming trait the rest of the crate builds on, and we
+/// try hard not to change its shape.  Redesigns which need a newer
+/// compiler or a different signature live in the `experimental` module,
+/// behind the `experimental` Cargo feature, until they're ready.  In
+/// particular, `experimental::AssocStreamingIterator` makes the item an
+/// associated type, so that `'a` doesn't need repeating in every impl
+/// header, and `experimental::Compat` adapts it back to this trait.
+///
 /// Massive thanks to Sharp for figuring out how to do this.
+#[stable]
 pub trait StreamingIterator<'a, T> {
     /// Return either the next item in the sequence, or `None` if all items
     /// have been consumed.
     fn next(&'a mut self) -> Option<T>;
+
+    /// Borrow this iterator, so that you can run some items through an
+    /// adapter and then keep using the iterator afterwards.
+    fn by_ref<'b>(&'b mut self) -> ByRef<'b, Self> {
+        ByRef{iter: self}
+    }
+
+    /// Bounds on the number of items left: a lower bound, and an upper
+    /// bound if there is one.  Sources which know how much data they have
+    /// left should override this, so consumers can preallocate.  The
+    /// default, `(0, None)`, is always correct.
+    fn size_hint(&self) -> (uint, Option<uint>) { (0, None) }
+
+    /// The old name for `next`.
+    #[deprecated = "use `next`"]
+    fn next_in_stream(&'a mut self) -> Option<T> { self.next() }
+}
+
+/// Adapter methods for every `StreamingIterator`, so that pipelines can be
+/// written as a chain of method calls, instead of by nesting constructors:
+///
+/// ```ignore
+/// let mut top = numbers.filter(|n| *n > 0).step_by(2).take(10);
+/// ```
+///
+/// These live in their own trait, implemented for every streaming
+/// iterator, so that adding one never changes `StreamingIterator` itself.
+/// Each method just calls the adapter's `new`.
+pub trait StreamingIteratorExt<'a, T>: StreamingIterator<'a, T> {
+    /// Transform each item with `f`.  See `Map`.
+    fn map<'f, U>(self, f: |T|: 'f -> U) -> Map<'f, Self, T, U> {
+        Map::new(self, f)
+    }
+
+    /// Keep only the items matching `predicate`.  See `Filter`.
+    fn filter<'f>(self, predicate: |&T|: 'f -> bool) -> Filter<'f, Self, T> {
+        Filter::new(self, predicate)
+    }
+
+    /// Yield at most `n` items.  See `Take`.
+    fn take(self, n: uint) -> Take<Self> { Take::new(self, n) }
+
+    /// Keep returning `None` once we've returned it once.  See `Fuse`.
+    fn fuse(self) -> Fuse<Self> { Fuse::new(self) }
+
+    /// Yield the first item, and then every `step`th one.  See `StepBy`.
+    fn step_by(self, step: uint) -> StepBy<Self> { StepBy::new(self, step) }
+
+    /// Replace each item with the items of the iterator `f` returns.  See
+    /// `FlatMap`.
+    fn flat_map<'f, J>(self, f: |T|: 'f -> J) -> FlatMap<'f, Self, T, J> {
+        FlatMap::new(self, f)
+    }
+
+    /// Thread some state through the items.  See `Scan`.
+    fn scan<'f, St, U>(self, initial: St, f: |&mut St, T|: 'f -> Option<U>)
+                       -> Scan<'f, Self, St, T, U> {
+        Scan::new(self, initial, f)
+    }
+
+    /// Transform items with `f` until it returns `None`.  See `MapWhile`.
+    fn map_while<'f, U>(self, f: |T|: 'f -> Option<U>)
+                        -> MapWhile<'f, Self, T, U> {
+        MapWhile::new(self, f)
+    }
+
+    /// Drop items equal to the one before.  See `Dedup`.
+    fn dedup<O>(self) -> Dedup<Self, O> { Dedup::new(self) }
+
+    /// Drop items whose key we've already seen.  See `Unique`.
+    fn unique<'f, K: Hash + Eq>(self, key_fn: |&T|: 'f -> K)
+                                -> Unique<'f, Self, T, K> {
+        Unique::new(self, key_fn)
+    }
+
+    /// Group runs of items with the same key.  See `GroupBy`.
+    fn group_by<'f, K>(self, key_fn: |&T|: 'f -> K)
+                       -> GroupBy<'f, Self, K, T> {
+        GroupBy::new(self, key_fn)
+    }
+
+    /// Copy items into `buffer`, `size` at a time.  See `Chunks`.
+    fn chunks<'b, O>(self, size: uint, buffer: &'b mut Vec<O>)
+                     -> Chunks<'b, Self, O> {
+        Chunks::new(self, size, buffer)
+    }
+
+    /// Yield overlapping windows of `size` items.  See `Windows`.
+    fn windows<O>(self, size: uint) -> Windows<Self, O> {
+        Windows::new(self, size)
+    }
+
+    /// Alternate between our items and those of `other`.  See
+    /// `Interleave`.
+    fn interleave<J>(self, other: J) -> Interleave<Self, J> {
+        Interleave::new(self, other)
+    }
+
+    /// Merge with another iterator sorted by `cmp`.  See `Merge`.
+    fn merge<'f, J>(self, other: J, cmp: |&T, &T|: 'f -> Ordering)
+                    -> Merge<'f, Self, J, T> {
+        Merge::new(self, other, cmp)
+    }
+
+    /// Pair each item with its index, counting from 0.  See `Enumerate`.
+    fn enumerate(self) -> Enumerate<Self> { Enumerate::new(self) }
+
+    /// Start over from the beginning when we run out.  See `Cycle`.
+    fn cycle(self) -> Cycle<Self> where Self: SliceStream + Resettable {
+        Cycle::new(self)
+    }
+
+    /// Transform the `Ok` value of each `Result` item.  See `MapOk`.
+    fn map_ok<'f, A, U>(self, f: |A|: 'f -> U) -> MapOk<'f, Self, A, U> {
+        MapOk::new(self, f)
+    }
+
+    /// Drop `Ok` values which don't match `predicate`.  See `FilterOk`.
+    fn filter_ok<'f, A>(self, predicate: |&A|: 'f -> bool)
+                        -> FilterOk<'f, Self, A> {
+        FilterOk::new(self, predicate)
+    }
+
+    /// Run each `Ok` value through `f`, which may fail.  See `AndThen`.
+    fn and_then<'f, A, U, E>(self, f: |A|: 'f -> Result<U, E>)
+                             -> AndThen<'f, Self, A, U, E> {
+        AndThen::new(self, f)
+    }
+}
+
+impl<'a, T, I: StreamingIterator<'a, T>> StreamingIteratorExt<'a, T> for I {}
+
+/// A streaming iterator which knows exactly how many items it has left, so
+/// that `size_hint` returns the same lower and upper bound.  Adapters
+/// which don't change the number of items, or change it predictably, like
+/// `Take` and `Enumerate`, pass this on.
+pub trait ExactSizeStreamingIterator<'a, T>: StreamingIterator<'a, T> {
+    /// The number of items left.
+    fn len(&self) -> uint {
+        let (lower, upper) = self.size_hint();
+        assert_eq!(upper, Some(lower));
+        lower
+    }
+}
+
+/// A streaming iterator which can also take items from the back, such as
+/// an in-memory slice or a seekable file.  `next` and `next_back` work
+/// inwards from either end, and both return `None` once they meet.  Wrap
+/// one in `Rev` to read it backwards with ordinary adapters.
+pub trait StreamingDoubleEndedIterator<'a, T>: StreamingIterator<'a, T> {
+    /// Return the last remaining item, or `None` if all items have been
+    /// consumed.
+    fn next_back(&'a mut self) -> Option<T>;
+}
+
+/// A streaming iterator which can fail.  Parsers reading real I/O can
+/// report errors from `next` directly, rather than yielding `Result` items
+/// or failing the task.
+///
+/// Use `TryResults` to turn one into an ordinary `StreamingIterator` over
+/// `IoResult` items, which works with `MapOk`, `AndThen` and friends, and
+/// `FromResults` or `Infallible` to go the other way.
+pub trait TryStreamingIterator<'a, T> {
+    /// Return the next item, `None` if all items have been consumed, or an
+    /// error.  Don't call this again after an error, unless the
+    /// implementation says that's safe.
+    fn next(&'a mut self) -> IoResult<Option<T>>;
+}
+
+/// A streaming iterator over byte slices which can be borrowed for any
+/// lifetime.  Implemented automatically.
+///
+/// A function which takes `I: StreamingIterator<'a, &'a [u8]>` for one
+/// caller-chosen `'a` can only call `next` once, because the first item
+/// borrows the iterator for all of `'a`.  Asking for an iterator which
+/// works at *every* lifetime gives each call to `next` a fresh, short
+/// borrow instead, so generic functions can be written as:
+///
+/// ```ignore
+/// fn count<I: SliceStream>(mut iter: I) -> uint {
+///     let mut n = 0;
+///     streaming_for!(_ in iter, { n += 1; });
+///     n
+/// }
+/// ```
+///
+/// Every source written as `impl<'a> StreamingIterator<'a, &'a [u8]> for
+/// X` already qualifies.
+///
+/// For the same reason, consumers which call `next` in a loop live here,
+/// and in `OwnedStreamingIterator`, rather than on `StreamingIterator`.
+pub trait SliceStream: for<'a> StreamingIterator<'a, &'a [u8]> {
+    /// Run `f` on each item, threading an accumulator through, and return
+    /// the final value of the accumulator.
+    fn fold<B>(&mut self, init: B, f: |B, &[u8]| -> B) -> B {
+        let mut acc = init;
+        loop {
+            match self.next() {
+                None => { return acc; }
+                Some(item) => { acc = f(acc, item); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item.
+    fn for_each(&mut self, f: |&[u8]|) {
+        loop {
+            match self.next() {
+                None => { return; }
+                Some(item) => { f(item); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item until it returns `false`.  Returns
+    /// `true` if we ran out of items, or `false` if `f` stopped us.
+    ///
+    /// This is internal iteration: `f` never has to hold onto an item
+    /// across calls, so simple consumers don't need any lifetime
+    /// gymnastics.
+    fn each(&mut self, f: |&[u8]| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return true; }
+                Some(item) => { if !f(item) { return false; } }
+            }
+        }
+    }
+
+    /// Consume all remaining items, and return how many there were.
+    fn count(&mut self) -> uint {
+        let mut count = 0;
+        loop {
+            match self.next() {
+                None => { return count; }
+                Some(_) => { count += 1; }
+            }
+        }
+    }
+
+    /// Skip `n` items, and return the one after that.  The skipped items
+    /// are consumed, but we never look at them.
+    fn nth(&mut self, n: uint) -> Option<&[u8]> {
+        for _ in range(0, n) {
+            if self.next().is_none() { return None; }
+        }
+        self.next()
+    }
+
+    /// Return an owned copy of the first item for which `predicate`
+    /// returns true.  We can't return the item itself: each item we
+    /// rejected borrowed us until we asked for the next one, and the
+    /// borrow checker can't tell that apart from the one we keep.
+    fn find<O>(&mut self, predicate: |&[u8]| -> bool) -> Option<O>
+        where for<'b> &'b [u8]: OwnedCopy<O> {
+        loop {
+            match self.next() {
+                None => { return None; }
+                Some(item) => {
+                    if predicate(item) { return Some(item.owned_copy()); }
+                }
+            }
+        }
+    }
+
+    /// Return the index of the first item for which `predicate` returns
+    /// true.  Everything up to and including that item is consumed.
+    fn position(&mut self, predicate: |&[u8]| -> bool) -> Option<uint> {
+        let mut index = 0;
+        loop {
+            match self.next() {
+                None => { return None; }
+                Some(item) => {
+                    if predicate(item) { return Some(index); }
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    /// Does `predicate` hold for every item?  Stops at the first item for
+    /// which it doesn't.
+    fn all(&mut self, predicate: |&[u8]| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return true; }
+                Some(item) => { if !predicate(item) { return false; } }
+            }
+        }
+    }
+
+    /// Does `predicate` hold for any item?  Stops at the first item for
+    /// which it does.
+    fn any(&mut self, predicate: |&[u8]| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return false; }
+                Some(item) => { if predicate(item) { return true; } }
+            }
+        }
+    }
+
+    /// Append an owned copy of each remaining item to `dest`.  Any
+    /// capacity `dest` already has is reused, so you can clear it and
+    /// collect into it again without reallocating.
+    fn collect_into<O>(&mut self, dest: &mut Vec<O>)
+        where for<'b> &'b [u8]: OwnedCopy<O> {
+        let (lower, _) = self.size_hint();
+        dest.reserve_additional(lower);
+        loop {
+            match self.next() {
+                None => { return; }
+                Some(item) => { dest.push(item.owned_copy()); }
+            }
+        }
+    }
+
+    /// Return an owned copy of the item for which `f` returns the largest
+    /// key.  If several items are equally large, we return the last one.
+    /// Only the best item so far is copied, into a single reused `O`.
+    fn max_by_key<O, K: Ord>(&mut self, f: |&[u8]| -> K) -> Option<O>
+        where for<'b> &'b [u8]: OwnedCopy<O> {
+        let mut best: Option<(K, O)> = None;
+        loop {
+            match self.next() {
+                None => { return best.map(|(_, copy)| copy); }
+                Some(item) => {
+                    let key = f(item);
+                    match best {
+                        Some((ref mut best_key, ref mut copy)) => {
+                            if key >= *best_key {
+                                *best_key = key;
+                                item.copy_into(copy);
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                    best = Some((key, item.owned_copy()));
+                }
+            }
+        }
+    }
+
+    /// Return an owned copy of the item for which `f` returns the smallest
+    /// key.  If several items are equally small, we return the first one.
+    fn min_by_key<O, K: Ord>(&mut self, f: |&[u8]| -> K) -> Option<O>
+        where for<'b> &'b [u8]: OwnedCopy<O> {
+        let mut best: Option<(K, O)> = None;
+        loop {
+            match self.next() {
+                None => { return best.map(|(_, copy)| copy); }
+                Some(item) => {
+                    let key = f(item);
+                    match best {
+                        Some((ref mut best_key, ref mut copy)) => {
+                            if key < *best_key {
+                                *best_key = key;
+                                item.copy_into(copy);
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                    best = Some((key, item.owned_copy()));
+                }
+            }
+        }
+    }
+
+    /// Consume all remaining items, and return an owned copy of the last
+    /// one.  Handy for trailer records which summarize a file.  We copy
+    /// each item into the same `O` as we go, so this doesn't allocate once
+    /// the copy has grown big enough.
+    fn last<O>(&mut self) -> Option<O>
+        where for<'b> &'b [u8]: OwnedCopy<O> {
+        let mut last: Option<O> = None;
+        loop {
+            match self.next() {
+                None => { return last; }
+                Some(item) => {
+                    match last {
+                        Some(ref mut copy) => {
+                            item.copy_into(copy);
+                            continue;
+                        }
+                        None => {}
+                    }
+                    last = Some(item.owned_copy());
+                }
+            }
+        }
+    }
+
+    /// Like `fold`, but `f` may fail.  Stops at the first `Err`, and
+    /// returns it, leaving any later items unconsumed.  With `IoResult`,
+    /// this lets I/O and parse errors end a pipeline cleanly.
+    fn try_fold<B, E>(&mut self, init: B, f: |B, &[u8]| -> Result<B, E>)
+                      -> Result<B, E> {
+        let mut acc = init;
+        loop {
+            match self.next() {
+                None => { return Ok(acc); }
+                Some(item) => { acc = try!(f(acc, item)); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item, stopping at the first `Err` and
+    /// returning it.  Handy when `f` writes each item somewhere.
+    fn try_for_each<E>(&mut self, f: |&[u8]| -> Result<(), E>)
+                       -> Result<(), E> {
+        loop {
+            match self.next() {
+                None => { return Ok(()); }
+                Some(item) => { try!(f(item)); }
+            }
+        }
+    }
+
+    /// Split the remaining items in a single pass: those for which
+    /// `predicate` returns true go to `matching`, and the rest go to
+    /// `others`.  Stops at the first `Err` from either, and otherwise
+    /// returns how many items each one received.
+    fn partition<E>(&mut self, predicate: |&[u8]| -> bool,
+                    matching: |&[u8]| -> Result<(), E>,
+                    others: |&[u8]| -> Result<(), E>)
+                    -> Result<(uint, uint), E> {
+        let (mut matched, mut unmatched) = (0u, 0u);
+        loop {
+            match self.next() {
+                None => { return Ok((matched, unmatched)); }
+                Some(item) => {
+                    if predicate(item) {
+                        try!(matching(item));
+                        matched += 1;
+                    } else {
+                        try!(others(item));
+                        unmatched += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<I> SliceStream for I where I: for<'a> StreamingIterator<'a, &'a [u8]> {}
+
+/// Consumers for streaming iterators whose items don't borrow from the
+/// iterator, like `uint` or `String`.  Implemented automatically, for any
+/// iterator which yields the same item type at every lifetime.
+///
+/// A default method on `StreamingIterator` only has the one `'a` from its
+/// `&'a mut self`, so it could only call `next` once.  Like `SliceStream`,
+/// this asks for an iterator which works at every lifetime instead, so
+/// each call to `next` gets a fresh borrow.
+pub trait OwnedStreamingIterator<T>: for<'a> StreamingIterator<'a, T> {
+    /// Run `f` on each item, threading an accumulator through, and return
+    /// the final value of the accumulator.
+    fn fold<B>(&mut self, init: B, f: |B, T| -> B) -> B {
+        let mut acc = init;
+        loop {
+            match self.next() {
+                None => { return acc; }
+                Some(item) => { acc = f(acc, item); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item.
+    fn for_each(&mut self, f: |T|) {
+        loop {
+            match self.next() {
+                None => { return; }
+                Some(item) => { f(item); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item until it returns `false`.  Returns
+    /// `true` if we ran out of items, or `false` if `f` stopped us.
+    fn each(&mut self, f: |T| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return true; }
+                Some(item) => { if !f(item) { return false; } }
+            }
+        }
+    }
+
+    /// Consume all remaining items, and return how many there were.
+    fn count(&mut self) -> uint {
+        let mut count = 0;
+        loop {
+            match self.next() {
+                None => { return count; }
+                Some(_) => { count += 1; }
+            }
+        }
+    }
+
+    /// Skip `n` items, and return the one after that.  The skipped items
+    /// are consumed, but we never look at them.
+    fn nth(&mut self, n: uint) -> Option<T> {
+        for _ in range(0, n) {
+            if self.next().is_none() { return None; }
+        }
+        self.next()
+    }
+
+    /// Return the first item for which `predicate` returns true.
+    fn find(&mut self, predicate: |&T| -> bool) -> Option<T> {
+        loop {
+            match self.next() {
+                None => { return None; }
+                Some(item) => { if predicate(&item) { return Some(item); } }
+            }
+        }
+    }
+
+    /// Return the index of the first item for which `predicate` returns
+    /// true.  Everything up to and including that item is consumed.
+    fn position(&mut self, predicate: |T| -> bool) -> Option<uint> {
+        let mut index = 0;
+        loop {
+            match self.next() {
+                None => { return None; }
+                Some(item) => {
+                    if predicate(item) { return Some(index); }
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    /// Does `predicate` hold for every item?  Stops at the first item for
+    /// which it doesn't.
+    fn all(&mut self, predicate: |T| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return true; }
+                Some(item) => { if !predicate(item) { return false; } }
+            }
+        }
+    }
+
+    /// Does `predicate` hold for any item?  Stops at the first item for
+    /// which it does.
+    fn any(&mut self, predicate: |T| -> bool) -> bool {
+        loop {
+            match self.next() {
+                None => { return false; }
+                Some(item) => { if predicate(item) { return true; } }
+            }
+        }
+    }
+
+    /// Append an owned copy of each remaining item to `dest`.  Any
+    /// capacity `dest` already has is reused, so you can clear it and
+    /// collect into it again without reallocating.
+    fn collect_into<O>(&mut self, dest: &mut Vec<O>) where T: OwnedCopy<O> {
+        let (lower, _) = self.size_hint();
+        dest.reserve_additional(lower);
+        loop {
+            match self.next() {
+                None => { return; }
+                Some(item) => { dest.push(item.owned_copy()); }
+            }
+        }
+    }
+
+    /// Return an owned copy of the item for which `f` returns the largest
+    /// key.  If several items are equally large, we return the last one.
+    /// Only the best item so far is copied, into a single reused `O`.
+    fn max_by_key<O, K: Ord>(&mut self, f: |&T| -> K) -> Option<O>
+        where T: OwnedCopy<O> {
+        let mut best: Option<(K, O)> = None;
+        loop {
+            match self.next() {
+                None => { return best.map(|(_, copy)| copy); }
+                Some(item) => {
+                    let key = f(&item);
+                    match best {
+                        Some((ref mut best_key, ref mut copy)) => {
+                            if key >= *best_key {
+                                *best_key = key;
+                                item.copy_into(copy);
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                    best = Some((key, item.owned_copy()));
+                }
+            }
+        }
+    }
+
+    /// Return an owned copy of the item for which `f` returns the smallest
+    /// key.  If several items are equally small, we return the first one.
+    fn min_by_key<O, K: Ord>(&mut self, f: |&T| -> K) -> Option<O>
+        where T: OwnedCopy<O> {
+        let mut best: Option<(K, O)> = None;
+        loop {
+            match self.next() {
+                None => { return best.map(|(_, copy)| copy); }
+                Some(item) => {
+                    let key = f(&item);
+                    match best {
+                        Some((ref mut best_key, ref mut copy)) => {
+                            if key < *best_key {
+                                *best_key = key;
+                                item.copy_into(copy);
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                    best = Some((key, item.owned_copy()));
+                }
+            }
+        }
+    }
+
+    /// Consume all remaining items, and return an owned copy of the last
+    /// one.  Handy for trailer records which summarize a file.  We copy
+    /// each item into the same `O` as we go, so this doesn't allocate once
+    /// the copy has grown big enough.
+    fn last<O>(&mut self) -> Option<O> where T: OwnedCopy<O> {
+        let mut last: Option<O> = None;
+        loop {
+            match self.next() {
+                None => { return last; }
+                Some(item) => {
+                    match last {
+                        Some(ref mut copy) => {
+                            item.copy_into(copy);
+                            continue;
+                        }
+                        None => {}
+                    }
+                    last = Some(item.owned_copy());
+                }
+            }
+        }
+    }
+
+    /// Like `fold`, but `f` may fail.  Stops at the first `Err`, and
+    /// returns it, leaving any later items unconsumed.  With `IoResult`,
+    /// this lets I/O and parse errors end a pipeline cleanly.
+    fn try_fold<B, E>(&mut self, init: B, f: |B, T| -> Result<B, E>)
+                      -> Result<B, E> {
+        let mut acc = init;
+        loop {
+            match self.next() {
+                None => { return Ok(acc); }
+                Some(item) => { acc = try!(f(acc, item)); }
+            }
+        }
+    }
+
+    /// Call `f` on each remaining item, stopping at the first `Err` and
+    /// returning it.  Handy when `f` writes each item somewhere.
+    fn try_for_each<E>(&mut self, f: |T| -> Result<(), E>)
+                       -> Result<(), E> {
+        loop {
+            match self.next() {
+                None => { return Ok(()); }
+                Some(item) => { try!(f(item)); }
+            }
+        }
+    }
+
+    /// Split the remaining items in a single pass: those for which
+    /// `predicate` returns true go to `matching`, and the rest go to
+    /// `others`.  Stops at the first `Err` from either, and otherwise
+    /// returns how many items each one received.
+    fn partition<E>(&mut self, predicate: |&T| -> bool,
+                    matching: |T| -> Result<(), E>,
+                    others: |T| -> Result<(), E>)
+                    -> Result<(uint, uint), E> {
+        let (mut matched, mut unmatched) = (0u, 0u);
+        loop {
+            match self.next() {
+                None => { return Ok((matched, unmatched)); }
+                Some(item) => {
+                    if predicate(&item) {
+                        try!(matching(item));
+                        matched += 1;
+                    } else {
+                        try!(others(item));
+                        unmatched += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T, I> OwnedStreamingIterator<T> for I
+    where I: for<'a> StreamingIterator<'a, T> {}
+
+/// An older version of `StreamingIterator`, which could only yield
+/// references.  Wrap implementations in `FromStreamIterator` to use them
+/// with everything else.
+#[deprecated = "implement `StreamingIterator` instead"]
+pub trait StreamIterator<Sized? A> {
+    /// Return a reference to the next item, if any.
+    fn next_item<'a>(&'a mut self) -> Option<&'a A>;
+}
+
+/// Adapts an old `StreamIterator` into a `StreamingIterator`.
+#[deprecated = "implement `StreamingIterator` instead"]
+pub struct FromStreamIterator<S> {
+    iter: S
+}
+
+#[allow(deprecated)]
+impl<S> FromStreamIterator<S> {
+    /// Wrap `iter`.
+    pub fn new(iter: S) -> FromStreamIterator<S> {
+        FromStreamIterator{iter: iter}
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, Sized? A, S: StreamIterator<A>> StreamingIterator<'a, &'a A>
+    for FromStreamIterator<S> {
+
+    fn next(&'a mut self) -> Option<&'a A> { self.iter.next_item() }
+}
+
+// Combine the size hints of two iterators, as if chained together.
+fn add_hints((a_lower, a_upper): (uint, Option<uint>),
+             (b_lower, b_upper): (uint, Option<uint>))
+             -> (uint, Option<uint>) {
+    let lower = if a_lower > uint::MAX - b_lower {
+        uint::MAX
+    } else {
+        a_lower + b_lower
+    };
+    let upper = match (a_upper, b_upper) {
+        (Some(a), Some(b)) if a <= uint::MAX - b => Some(a + b),
+        _ => None
+    };
+    (lower, upper)
+}
+
+/// Borrowed items which can make an owned copy of themselves.  Adapters
+/// which need to remember an item after the underlying iterator has moved
+/// on (like `Dedup`) require this, so the copying is always opt-in and
+/// you get to pick the owned type, such as `Vec<u8>` or `InlineBytes`.
+pub trait OwnedCopy<O> {
+    /// Make an owned copy of this item.
+    fn owned_copy(&self) -> O;
+
+    /// Overwrite `dest` with a copy of this item, reusing any storage it
+    /// already has.
+    fn copy_into(&self, dest: &mut O) {
+        *dest = self.owned_copy();
+    }
+
+    /// Is this item equal to `copy`?  Lets adapters compare items against
+    /// their copies without making a new copy.
+    fn same_as(&self, copy: &O) -> bool;
+}
+
+impl<'a> OwnedCopy<Vec<u8>> for &'a [u8] {
+    fn owned_copy(&self) -> Vec<u8> { self.to_vec() }
+    fn copy_into(&self, dest: &mut Vec<u8>) {
+        dest.clear();
+        dest.push_all(*self);
+    }
+    fn same_as(&self, copy: &Vec<u8>) -> bool { *self == copy.as_slice() }
+}
+
+impl<'a> OwnedCopy<String> for &'a str {
+    fn owned_copy(&self) -> String { self.to_string() }
+    fn copy_into(&self, dest: &mut String) {
+        dest.clear();
+        dest.push_str(*self);
+    }
+    fn same_as(&self, copy: &String) -> bool { *self == copy.as_slice() }
+}
+
+macro_rules! owned_copy_for_primitives {
+    ($($t:ty),*) => {
+        $(
+            impl OwnedCopy<$t> for $t {
+                fn owned_copy(&self) -> $t { *self }
+                fn same_as(&self, copy: &$t) -> bool { *self == *copy }
+            }
+        )*
+    }
+}
+
+owned_copy_for_primitives!(bool, char, u8, u16, u32, u64, uint,
+                           i8, i16, i32, i64, int)
+
+impl<A: OwnedCopy<OA>, B: OwnedCopy<OB>, OA, OB> OwnedCopy<(OA, OB)>
+    for (A, B) {
+
+    fn owned_copy(&self) -> (OA, OB) {
+        let (ref a, ref b) = *self;
+        (a.owned_copy(), b.owned_copy())
+    }
+
+    fn copy_into(&self, dest: &mut (OA, OB)) {
+        let (ref a, ref b) = *self;
+        let (ref mut dest_a, ref mut dest_b) = *dest;
+        a.copy_into(dest_a);
+        b.copy_into(dest_b);
+    }
+
+    fn same_as(&self, copy: &(OA, OB)) -> bool {
+        let (ref a, ref b) = *self;
+        let (ref copy_a, ref copy_b) = *copy;
+        a.same_as(copy_a) && b.same_as(copy_b)
+    }
+}
+
+impl<A: OwnedCopy<OA>, B: OwnedCopy<OB>, C: OwnedCopy<OC>, OA, OB, OC>
+    OwnedCopy<(OA, OB, OC)> for (A, B, C) {
+
+    fn owned_copy(&self) -> (OA, OB, OC) {
+        let (ref a, ref b, ref c) = *self;
+        (a.owned_copy(), b.owned_copy(), c.owned_copy())
+    }
+
+    fn copy_into(&self, dest: &mut (OA, OB, OC)) {
+        let (ref a, ref b, ref c) = *self;
+        let (ref mut dest_a, ref mut dest_b, ref mut dest_c) = *dest;
+        a.copy_into(dest_a);
+        b.copy_into(dest_b);
+        c.copy_into(dest_c);
+    }
+
+    fn same_as(&self, copy: &(OA, OB, OC)) -> bool {
+        let (ref a, ref b, ref c) = *self;
+        let (ref copy_a, ref copy_b, ref copy_c) = *copy;
+        a.same_as(copy_a) && b.same_as(copy_b) && c.same_as(copy_c)
+    }
+}
+
+/// Errors are copied as they are, so parsers which yield `IoResult`s can be
+/// copied too.
+impl<T: OwnedCopy<O>, O, E: Clone + PartialEq> OwnedCopy<Result<O, E>>
+    for Result<T, E> {
+
+    fn owned_copy(&self) -> Result<O, E> {
+        match *self {
+            Ok(ref item) => Ok(item.owned_copy()),
+            Err(ref err) => Err(err.clone())
+        }
+    }
+
+    fn copy_into(&self, dest: &mut Result<O, E>) {
+        match *self {
+            Ok(ref item) => match *dest {
+                Ok(ref mut copy) => { item.copy_into(copy); return; }
+                Err(_) => {}
+            },
+            Err(_) => {}
+        }
+        *dest = self.owned_copy();
+    }
+
+    fn same_as(&self, copy: &Result<O, E>) -> bool {
+        match (self, copy) {
+            (&Ok(ref item), &Ok(ref copy)) => item.same_as(copy),
+            (&Err(ref err), &Err(ref copy)) => err == copy,
+            _ => false
+        }
+    }
+}
+
+/// Run every item of `source` through `f`.  This does the same job as
+/// `streaming_for!`, for generic code and other macros, which can't always
+/// expand the macro conveniently.  The items mustn't borrow from `source`;
+/// byte slices can use `SliceStream::for_each` instead.
+pub fn drive<T, I: OwnedStreamingIterator<T>>(source: &mut I, f: |T|) {
+    source.for_each(f)
 }
 
 /// Similar to `for`, but doesn't enforce any trait restrictions on the
-/// iterator.
+/// iterator.  The body is an ordinary block, and any value it ends with is
+/// ignored.
+///
+/// Since `break` can't carry a value, searches can name a result variable
+/// after `=>`.  It starts out as `None`, the body can set it before
+/// breaking, and the whole expression evaluates to it:
+///
+/// ```ignore
+/// let found = streaming_for!(line in lines => found, {
+///     if line.starts_with(b"id=") { found = Some(line.to_vec()); break; }
+/// });
+/// ```
+///
+/// The result can't borrow from the iterator, so copy what you need.
+///
+/// Like `for`, the loop can be given a label, so that `break` and
+/// `continue` in nested loops can refer to it:
+///
+/// ```ignore
+/// streaming_for!('records: record in records, {
+///     for field in record.split(|&b| b == b',') {
+///         if field.is_empty() { continue 'records; }
+///     }
+///     keep(record);
+/// });
+/// ```
+///
+/// An `else` block after the body runs once the iterator is exhausted, but
+/// not if the loop ends with `break`.  The iterator is no longer borrowed
+/// by then, so it's a good place to flush output or ask for statistics:
+///
+/// ```ignore
+/// streaming_for!(record in records, { sink.write_record(record); } else {
+///     sink.finish();
+/// });
+/// ```
 #[macro_export]
 macro_rules! streaming_for {
-    ($var:pat in $expr:expr, $b:stmt) => {
+    // These need to come first: trying to parse a label as a pattern
+    // would be an error, not just a failed match.
+    ($label:tt: $var:pat in $expr:expr, $b:block) => {
+        {
+            let ref mut iter = &mut $expr;
+            $label: loop {
+                match iter.next() {
+                    None => { break; }
+                    Some($var) => { $b; }
+                }
+            }
+        }
+    };
+    ($label:tt: $var:pat in $expr:expr, $b:block else $e:block) => {
+        {
+            let mut exhausted = false;
+            {
+                let ref mut iter = &mut $expr;
+                $label: loop {
+                    match iter.next() {
+                        None => { exhausted = true; break; }
+                        Some($var) => { $b; }
+                    }
+                }
+            }
+            if exhausted $e
+        }
+    };
+    ($label:tt: $var:pat in $expr:expr => $result:ident, $b:block) => {
+        {
+            let mut $result = None;
+            streaming_for!($label: $var in $expr, $b);
+            $result
+        }
+    };
+    ($var:pat in $expr:expr, $b:block) => {
         {
             // Only evaluate once!
             let ref mut iter = &mut $expr;
             loop {
                 match iter.next() {
                     None => { break; }
-                    Some($var) => { $b }
+                    Some($var) => { $b; }
+                }
+            }
+        }
+    };
+    ($var:pat in $expr:expr, $b:block else $e:block) => {
+        {
+            let mut exhausted = false;
+            {
+                let ref mut iter = &mut $expr;
+                loop {
+                    match iter.next() {
+                        None => { exhausted = true; break; }
+                        Some($var) => { $b; }
+                    }
                 }
             }
+            if exhausted $e
         }
     };
+    ($var:pat in $expr:expr => $result:ident, $b:block) => {
+        {
+            let mut $result = None;
+            streaming_for!($var in $expr, $b);
+            $result
+        }
+    };
+}
+
+/// Build a streaming iterator from an initial state and a closure which
+/// takes `&mut` state and returns `Option<&T>`, borrowing from the state:
+///
+/// ```ignore
+/// let mut lines = streaming_iter!((input, vec![]), |state| {
+///     let (ref mut input, ref mut line) = *state;
+///     line.clear();
+///     match read_until_into(input, b'\n', line) {
+///         Ok(true) => Some(line.as_slice()),
+///         _ => None
+///     }
+/// });
+/// ```
+///
+/// This expands to `FromFn::new`, so `iter::FromFn` must be in scope.
+#[macro_export]
+macro_rules! streaming_iter {
+    ($state:expr, |$s:ident| $body:expr) => {
+        FromFn::new($state, |$s| $body)
+    };
+}
+
+/// Compose adapters left to right, instead of nesting their constructors:
+///
+/// ```ignore
+/// let mut top = pipeline!(numbers => filter(|n| *n > 0)
+///                                 => map(|n| n * 2)
+///                                 => take(10));
+/// ```
+///
+/// `map`, `filter` and `take` build `Map`, `Filter` and `Take`, so the
+/// `filter` step has the same limits as `Filter`.  Any other
+/// step is written as the adapter's type name, followed by the arguments
+/// to its `new` function after the iterator, as in `StepBy(2)` or
+/// `Fuse()`.  The adapters must be in scope.
+#[macro_export]
+macro_rules! pipeline {
+    ($source:expr) => { $source };
+    ($source:expr => map($f:expr) $($rest:tt)*) => {
+        pipeline!(Map::new($source, $f) $($rest)*)
+    };
+    ($source:expr => filter($p:expr) $($rest:tt)*) => {
+        pipeline!(Filter::new($source, $p) $($rest)*)
+    };
+    ($source:expr => take($n:expr) $($rest:tt)*) => {
+        pipeline!(Take::new($source, $n) $($rest)*)
+    };
+    ($source:expr => $adapter:ident($($arg:expr),*) $($rest:tt)*) => {
+        pipeline!($adapter::new($source $(, $arg)*) $($rest)*)
+    };
+}
+
+/// An iterator which keeps returning `None` once the underlying iterator
+/// has returned `None` for the first time.  Useful for sources (like
+/// `CsvRdr`) which don't promise anything about what happens after they're
+/// exhausted.
+pub struct Fuse<I> {
+    iter: I,
+    done: bool
+}
+
+impl<I> Fuse<I> {
+    /// Wrap `iter` so that it stays exhausted.
+    pub fn new(iter: I) -> Fuse<I> {
+        Fuse{iter: iter, done: false}
+    }
+}
+
+impl<'a, T, I: StreamingIterator<'a, T>> StreamingIterator<'a, T> for Fuse<I> {
+    fn next(&'a mut self) -> Option<T> {
+        if self.done { return None; }
+        let item = self.iter.next();
+        if item.is_none() { self.done = true; }
+        item
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        if self.done { (0, Some(0)) } else { self.iter.size_hint() }
+    }
+}
+
+/// An iterator which yields the first item of the underlying iterator,
+/// and then every `step`th item after that.  The skipped items are still
+/// pulled from the underlying iterator, so any buffer space they use gets
+/// consumed.  Since that takes several calls to `next` per item, the
+/// items mustn't borrow from the underlying iterator.
+pub struct StepBy<I> {
+    iter: I,
+    step: uint,
+    first: bool
+}
+
+impl<I> StepBy<I> {
+    /// Wrap `iter`, yielding only every `step`th item.
+    pub fn new(iter: I, step: uint) -> StepBy<I> {
+        assert!(step > 0);
+        StepBy{iter: iter, step: step, first: true}
+    }
+}
+
+impl<'a, T, I> StreamingIterator<'a, T> for StepBy<I>
+    where I: for<'b> StreamingIterator<'b, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        if self.first {
+            self.first = false;
+        } else {
+            for _ in range(1, self.step) {
+                if self.iter.next().is_none() { return None; }
+            }
+        }
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        let (first, step) = (self.first, self.step);
+        let steps = |n: uint| match n {
+            0 => 0,
+            n if first => 1 + (n - 1) / step,
+            n => n / step
+        };
+        (steps(lower), upper.map(steps))
+    }
+}
+
+/// A mutable borrow of a streaming iterator, which is itself a streaming
+/// iterator.  See `StreamingIterator::by_ref`.
+pub struct ByRef<'b, I: 'b> {
+    iter: &'b mut I
+}
+
+impl<'a, 'b, T, I: StreamingIterator<'a, T>> StreamingIterator<'a, T>
+    for ByRef<'b, I> {
+
+    fn next(&'a mut self) -> Option<T> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// An iterator which turns each item of `iter` into a streaming iterator of
+/// its own, using `f`, and yields all their items in turn.  This is the
+/// usual shape of a two-level parser: chunks into records, or records into
+/// fields.
+///
+/// We keep asking both iterators for items until one turns up, so neither
+/// one's items may borrow from the iterator itself.  We still never
+/// advance `iter` until the inner iterator has been exhausted and dropped.
+pub struct FlatMap<'f, I, T, J> {
+    iter: I,
+    f: |T|: 'f -> J,
+    inner: Option<J>
+}
+
+impl<'f, I, T, J> FlatMap<'f, I, T, J> {
+    /// Create a new iterator which flattens `f(item)` for each item in
+    /// `iter`.
+    pub fn new(iter: I, f: |T|: 'f -> J) -> FlatMap<'f, I, T, J> {
+        FlatMap{iter: iter, f: f, inner: None}
+    }
+}
+
+impl<'a, 'f, T, U, I, J> StreamingIterator<'a, U> for FlatMap<'f, I, T, J>
+    where I: for<'b> StreamingIterator<'b, T>,
+          J: for<'b> StreamingIterator<'b, U> {
+
+    fn next(&'a mut self) -> Option<U> {
+        loop {
+            let exhausted = match self.inner {
+                Some(ref mut inner) => match inner.next() {
+                    Some(item) => { return Some(item); }
+                    None => true
+                },
+                None => true
+            };
+            if exhausted {
+                // Drop the old inner iterator before touching `iter`.
+                self.inner = None;
+                match self.iter.next() {
+                    None => { return None; }
+                    Some(item) => { self.inner = Some((self.f)(item)); }
+                }
+            }
+        }
+    }
+}
+
+/// An iterator which threads a mutable `state` through `f`, which is
+/// called on each item.  `f` can update the state (say, a running total
+/// or the previous line's key) and return either an item to yield, which
+/// may be the original borrowed item, or `None` to stop.
+pub struct Scan<'f, I, St, T, U> {
+    iter: I,
+    state: St,
+    f: |&mut St, T|: 'f -> Option<U>
+}
+
+impl<'f, I, St, T, U> Scan<'f, I, St, T, U> {
+    /// Create a new `Scan` with the specified initial state.
+    pub fn new(iter: I, initial: St, f: |&mut St, T|: 'f -> Option<U>)
+               -> Scan<'f, I, St, T, U> {
+        Scan{iter: iter, state: initial, f: f}
+    }
+
+    /// Our current state.
+    pub fn state<'b>(&'b self) -> &'b St { &self.state }
+}
+
+impl<'a, 'f, I, St, T, U> StreamingIterator<'a, U> for Scan<'f, I, St, T, U>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<U> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => (self.f)(&mut self.state, item)
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator which skips items equal to the item before them.  We keep
+/// an owned copy of the last item we yielded, of type `O`.  For byte
+/// slices, an `InlineBytes` copy won't allocate unless the item is long.
+///
+/// We may read several items per call, so they mustn't borrow from `iter`
+/// itself.  Slices of in-memory data, from an `IterAdapter`, are fine.
+pub struct Dedup<I, O> {
+    iter: I,
+    last: Option<O>
+}
+
+impl<I, O> Dedup<I, O> {
+    /// Remove consecutive duplicates from `iter`.
+    pub fn new(iter: I) -> Dedup<I, O> {
+        Dedup{iter: iter, last: None}
+    }
+}
+
+impl<'a, T, O, I> StreamingIterator<'a, T> for Dedup<I, O>
+    where T: OwnedCopy<O>, I: for<'b> StreamingIterator<'b, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        loop {
+            match self.iter.next() {
+                None => { return None; }
+                Some(item) => {
+                    let duplicate = match self.last {
+                        Some(ref last) => item.same_as(last),
+                        None => false
+                    };
+                    if duplicate { continue; }
+                    if self.last.is_some() {
+                        item.copy_into(self.last.as_mut().unwrap());
+                    } else {
+                        self.last = Some(item.owned_copy());
+                    }
+                    return Some(item);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator which splits `iter` into runs of consecutive items with
+/// equal keys, and yields a `Group` for each run.  Nothing is buffered
+/// except the first item of the next group, which we have to read to know
+/// that the current group has ended.  Since we hang onto that item while
+/// reading on, items mustn't borrow from `iter` itself.
+pub struct GroupBy<'f, I, K, T> {
+    iter: I,
+    key_fn: |&T|: 'f -> K,
+    key: Option<K>,
+    // The first item of the next group, if we've already read it.
+    pending: Option<T>,
+    // Is there a group whose remaining items are still in `iter`?
+    in_group: bool
+}
+
+impl<'f, I, K, T> GroupBy<'f, I, K, T> {
+    /// Group the items of `iter` by `key_fn`.
+    pub fn new(iter: I, key_fn: |&T|: 'f -> K) -> GroupBy<'f, I, K, T> {
+        GroupBy{iter: iter, key_fn: key_fn, key: None, pending: None,
+                in_group: false}
+    }
+}
+
+impl<'a, 'f, I, K, T> StreamingIterator<'a, Group<'a, 'f, I, K, T>>
+    for GroupBy<'f, I, K, T>
+    where I: for<'b> StreamingIterator<'b, T>, K: PartialEq {
+
+    fn next(&'a mut self) -> Option<Group<'a, 'f, I, K, T>> {
+        // Skip anything the caller didn't read from the previous group.
+        while self.in_group {
+            match self.iter.next() {
+                None => { self.in_group = false; }
+                Some(item) => {
+                    let key = (self.key_fn)(&item);
+                    if Some(&key) != self.key.as_ref() {
+                        self.key = Some(key);
+                        self.pending = Some(item);
+                        self.in_group = false;
+                    }
+                }
+            }
+        }
+        let first = match self.pending.take() {
+            Some(item) => item,
+            None => match self.iter.next() {
+                None => { return None; }
+                Some(item) => {
+                    self.key = Some((self.key_fn)(&item));
+                    item
+                }
+            }
+        };
+        self.in_group = true;
+        Some(Group{parent: self, first: Some(first)})
+    }
+}
+
+/// The items in one group of a `GroupBy`.
+pub struct Group<'g, 'f: 'g, I: 'g, K: 'g, T: 'g> {
+    parent: &'g mut GroupBy<'f, I, K, T>,
+    first: Option<T>
+}
+
+impl<'g, 'f, I, K, T> Group<'g, 'f, I, K, T> {
+    /// The key shared by every item in this group.
+    pub fn key<'b>(&'b self) -> &'b K {
+        self.parent.key.as_ref().unwrap()
+    }
+}
+
+impl<'a, 'g, 'f, I, K, T> StreamingIterator<'a, T> for Group<'g, 'f, I, K, T>
+    where I: for<'b> StreamingIterator<'b, T>, K: PartialEq {
+
+    fn next(&'a mut self) -> Option<T> {
+        match self.first.take() {
+            Some(item) => { return Some(item); }
+            None => {}
+        }
+        if !self.parent.in_group { return None; }
+        match self.parent.iter.next() {
+            None => {
+                self.parent.in_group = false;
+                None
+            }
+            Some(item) => {
+                let key = (self.parent.key_fn)(&item);
+                if Some(&key) == self.parent.key.as_ref() {
+                    Some(item)
+                } else {
+                    // This item starts the next group.
+                    self.parent.key = Some(key);
+                    self.parent.pending = Some(item);
+                    self.parent.in_group = false;
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// An iterator which copies up to `size` consecutive items into a
+/// caller-provided buffer, and yields them as a single batch.  The
+/// buffer's elements are reused from batch to batch, so once it has warmed
+/// up, copying a batch of `Vec<u8>`s doesn't allocate.  Since we read
+/// several items per batch, `iter` must be a `SliceStream`.
+pub struct Chunks<'b, I, O: 'b> {
+    iter: I,
+    size: uint,
+    buffer: &'b mut Vec<O>
+}
+
+impl<'b, I, O> Chunks<'b, I, O> {
+    /// Batch the items of `iter` into groups of `size`, using `buffer` as
+    /// storage.  The final batch may be smaller.
+    pub fn new(iter: I, size: uint, buffer: &'b mut Vec<O>)
+               -> Chunks<'b, I, O> {
+        assert!(size > 0);
+        Chunks{iter: iter, size: size, buffer: buffer}
+    }
+}
+
+impl<'a, 'b, O, I> StreamingIterator<'a, &'a [O]> for Chunks<'b, I, O>
+    where I: SliceStream, for<'c> &'c [u8]: OwnedCopy<O> {
+
+    fn next(&'a mut self) -> Option<&'a [O]> {
+        let mut filled = 0;
+        while filled < self.size {
+            match self.iter.next() {
+                None => { break; }
+                Some(item) => {
+                    if filled < self.buffer.len() {
+                        item.copy_into(&mut self.buffer.as_mut_slice()[filled]);
+                    } else {
+                        self.buffer.push(item.owned_copy());
+                    }
+                    filled += 1;
+                }
+            }
+        }
+        if filled == 0 { None } else { Some(self.buffer.slice_to(filled)) }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        let batches = |n: uint| (n + self.size - 1) / self.size;
+        (batches(lower), upper.map(batches))
+    }
+}
+
+/// An iterator which alternates between the items of two iterators,
+/// starting with the first.  Once either iterator runs out, we yield the
+/// rest of the other one.
+pub struct Interleave<I, J> {
+    a: I,
+    b: J,
+    // Should the next item come from `b`?
+    flag: bool
+}
+
+impl<I, J> Interleave<I, J> {
+    /// Interleave the items of `a` and `b`.
+    pub fn new(a: I, b: J) -> Interleave<I, J> {
+        Interleave{a: a, b: b, flag: false}
+    }
+}
+
+impl<'a, T, I, J> StreamingIterator<'a, T> for Interleave<I, J>
+    where I: StreamingIterator<'a, T>, J: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        self.flag = !self.flag;
+        if self.flag {
+            match self.a.next() {
+                None => self.b.next(),
+                item => item
+            }
+        } else {
+            match self.b.next() {
+                None => self.a.next(),
+                item => item
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        add_hints(self.a.size_hint(), self.b.size_hint())
+    }
+}
+
+/// An iterator which shows each item to two callbacks before yielding it,
+/// so you can (say) collect statistics and write a copy of the data while
+/// still processing the stream downstream, all in one pass.
+pub struct Tee<'f, I, T> {
+    iter: I,
+    first: |&T|: 'f,
+    second: |&T|: 'f
+}
+
+impl<'f, I, T> Tee<'f, I, T> {
+    /// Show each item of `iter` to `first` and then `second`.
+    pub fn new(iter: I, first: |&T|: 'f, second: |&T|: 'f) -> Tee<'f, I, T> {
+        Tee{iter: iter, first: first, second: second}
+    }
+}
+
+impl<'a, 'f, T, I> StreamingIterator<'a, T> for Tee<'f, I, T>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => {
+                (self.first)(&item);
+                (self.second)(&item);
+                Some(item)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// An iterator which writes a copy of each byte-slice item to `out`,
+/// followed by `terminator`, before yielding it.  Since we can't report
+/// errors through `next`, we stop writing at the first error and keep it
+/// for you to check with `error`.
+pub struct TeeWriter<'w, I> {
+    iter: I,
+    out: &'w mut Writer+'w,
+    terminator: Vec<u8>,
+    error: Option<IoError>
+}
+
+impl<'w, I> TeeWriter<'w, I> {
+    /// Copy each item of `iter` to `out`.
+    pub fn new(iter: I, out: &'w mut Writer, terminator: &[u8])
+               -> TeeWriter<'w, I> {
+        TeeWriter{iter: iter, out: out, terminator: terminator.to_vec(),
+                  error: None}
+    }
+
+    /// The first error we hit while writing, if any.
+    pub fn error(&self) -> Option<&IoError> { self.error.as_ref() }
+}
+
+impl<'a, 'w, I> StreamingIterator<'a, &'a [u8]> for TeeWriter<'w, I>
+    where I: StreamingIterator<'a, &'a [u8]> {
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        let item = self.iter.next();
+        match item {
+            Some(bytes) if self.error.is_none() => {
+                let result = self.out.write(bytes).and_then(|()| {
+                    self.out.write(self.terminator.as_slice())
+                });
+                match result {
+                    Ok(()) => {}
+                    Err(err) => { self.error = Some(err); }
+                }
+            }
+            _ => {}
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// A standard `Iterator` which yields an owned copy, of type `O`, of each
+/// item of a streaming iterator over byte slices.  Put this at the end of a
+/// zero-copy pipeline when you really do need `collect`, `sort` and
+/// friends, and are happy to pay for the copies.
+///
+/// `Iterator::next` can't borrow us for any particular lifetime, so we
+/// need an iterator which can be borrowed for every lifetime: a
+/// `SliceStream`.  For items which don't borrow at all, use `OwnedItems`.
+pub struct ToOwned<I, O> {
+    iter: I
+}
+
+impl<I, O> ToOwned<I, O> {
+    /// Copy each item of `iter`.
+    pub fn new(iter: I) -> ToOwned<I, O> {
+        ToOwned{iter: iter}
+    }
+
+    /// Get back the underlying streaming iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<O, I> Iterator<O> for ToOwned<I, O>
+    where I: SliceStream, for<'b> &'b [u8]: OwnedCopy<O> {
+
+    fn next(&mut self) -> Option<O> {
+        self.iter.next().map(|item| item.owned_copy())
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// An iterator which merges two iterators, each already sorted according
+/// to `cmp`, into a single sorted stream.  This is the merge step of an
+/// external sort.  We hold onto the next item of each input, and yield
+/// whichever is smaller, taking items from `a` first when they're equal.
+pub struct Merge<'f, I, J, T> {
+    a: I,
+    b: J,
+    cmp: |&T, &T|: 'f -> Ordering,
+    a_next: Option<T>,
+    b_next: Option<T>
+}
+
+impl<'f, I, J, T> Merge<'f, I, J, T> {
+    /// Merge the sorted iterators `a` and `b`.
+    pub fn new(a: I, b: J, cmp: |&T, &T|: 'f -> Ordering)
+               -> Merge<'f, I, J, T> {
+        Merge{a: a, b: b, cmp: cmp, a_next: None, b_next: None}
+    }
+}
+
+impl<'a, 'f, T, I, J> StreamingIterator<'a, T> for Merge<'f, I, J, T>
+    where I: StreamingIterator<'a, T>, J: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        if self.a_next.is_none() { self.a_next = self.a.next(); }
+        if self.b_next.is_none() { self.b_next = self.b.next(); }
+        let from_a = match (&self.a_next, &self.b_next) {
+            (&Some(ref a), &Some(ref b)) => (self.cmp)(a, b) != Greater,
+            (&Some(_), &None) => true,
+            (&None, _) => false
+        };
+        if from_a { self.a_next.take() } else { self.b_next.take() }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let buffered = self.a_next.is_some() as uint +
+            self.b_next.is_some() as uint;
+        let inputs = add_hints(self.a.size_hint(), self.b.size_hint());
+        add_hints(inputs, (buffered, Some(buffered)))
+    }
+}
+
+/// An iterator which yields overlapping windows of the last `size` items,
+/// like `slice::windows`.  We keep owned copies of the items in the
+/// current window, recycling the oldest copy for each new item, so once
+/// the window is full this doesn't allocate.  (With `InlineBytes` copies
+/// of byte slices, short items never allocate at all.)  A stream with
+/// fewer than `size` items has no windows at all.  Filling the first
+/// window takes several items, so `iter` must be a `SliceStream`.
+pub struct Windows<I, O> {
+    iter: I,
+    size: uint,
+    window: Vec<O>
+}
+
+impl<I, O> Windows<I, O> {
+    /// Yield each run of `size` consecutive items of `iter`.
+    pub fn new(iter: I, size: uint) -> Windows<I, O> {
+        assert!(size > 0);
+        Windows{iter: iter, size: size, window: Vec::with_capacity(size)}
+    }
+}
+
+impl<'a, O, I> StreamingIterator<'a, &'a [O]> for Windows<I, O>
+    where I: SliceStream, for<'b> &'b [u8]: OwnedCopy<O> {
+
+    fn next(&'a mut self) -> Option<&'a [O]> {
+        if self.window.len() == self.size {
+            // Slide along by one item, recycling the oldest copy.
+            match self.iter.next() {
+                None => { return None; }
+                Some(item) => {
+                    let mut oldest = self.window.remove(0).unwrap();
+                    item.copy_into(&mut oldest);
+                    self.window.push(oldest);
+                }
+            }
+        } else {
+            while self.window.len() < self.size {
+                match self.iter.next() {
+                    None => { return None; }
+                    Some(item) => { self.window.push(item.owned_copy()); }
+                }
+            }
+        }
+        Some(self.window.as_slice())
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        if self.window.len() == self.size { return (lower, upper); }
+        // We need this many more items before the first window.
+        let needed = self.size - self.window.len();
+        let windows = |n: uint| if n < needed { 0 } else { n - needed + 1 };
+        (windows(lower), upper.map(windows))
+    }
+}
+
+/// An iterator which maps each item with `f`, and stops for good the
+/// first time `f` returns `None`.  This is the natural way to say "parse
+/// records until one fails to decode".  `U` may borrow from the item.
+pub struct MapWhile<'f, I, T, U> {
+    iter: I,
+    f: |T|: 'f -> Option<U>,
+    done: bool
+}
+
+impl<'f, I, T, U> MapWhile<'f, I, T, U> {
+    /// Map the items of `iter` with `f`, until it returns `None`.
+    pub fn new(iter: I, f: |T|: 'f -> Option<U>) -> MapWhile<'f, I, T, U> {
+        MapWhile{iter: iter, f: f, done: false}
+    }
+}
+
+impl<'a, 'f, I, T, U> StreamingIterator<'a, U> for MapWhile<'f, I, T, U>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<U> {
+        if self.done { return None; }
+        let mapped = match self.iter.next() {
+            None => None,
+            Some(item) => (self.f)(item)
+        };
+        if mapped.is_none() { self.done = true; }
+        mapped
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        if self.done { return (0, Some(0)); }
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator over `Result`s which maps each `Ok` item with `f`, and
+/// passes errors through untouched.  Handy for sources which yield
+/// `IoResult`s, so every closure doesn't have to match on the error.
+pub struct MapOk<'f, I, T, U> {
+    iter: I,
+    f: |T|: 'f -> U
+}
+
+impl<'f, I, T, U> MapOk<'f, I, T, U> {
+    /// Map the `Ok` items of `iter` with `f`.
+    pub fn new(iter: I, f: |T|: 'f -> U) -> MapOk<'f, I, T, U> {
+        MapOk{iter: iter, f: f}
+    }
+}
+
+impl<'a, 'f, I, T, U, E> StreamingIterator<'a, Result<U, E>>
+    for MapOk<'f, I, T, U> where I: StreamingIterator<'a, Result<T, E>> {
+
+    fn next(&'a mut self) -> Option<Result<U, E>> {
+        match self.iter.next() {
+            None => None,
+            Some(Ok(item)) => Some(Ok((self.f)(item))),
+            Some(Err(err)) => Some(Err(err))
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// An iterator over `Result`s which skips `Ok` items for which `predicate`
+/// returns false.  Errors are always passed through.  As with `Filter`,
+/// the items mustn't borrow from `iter`.
+pub struct FilterOk<'f, I, T> {
+    iter: I,
+    predicate: |&T|: 'f -> bool
+}
+
+impl<'f, I, T> FilterOk<'f, I, T> {
+    /// Keep only the `Ok` items of `iter` which match `predicate`.
+    pub fn new(iter: I, predicate: |&T|: 'f -> bool) -> FilterOk<'f, I, T> {
+        FilterOk{iter: iter, predicate: predicate}
+    }
+}
+
+impl<'a, 'f, I, T, E> StreamingIterator<'a, Result<T, E>>
+    for FilterOk<'f, I, T>
+    where I: for<'b> StreamingIterator<'b, Result<T, E>> {
+
+    fn next(&'a mut self) -> Option<Result<T, E>> {
+        loop {
+            match self.iter.next() {
+                Some(Ok(item)) => {
+                    if (self.predicate)(&item) { return Some(Ok(item)); }
+                }
+                other => { return other; }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator over `Result`s which runs each `Ok` item through the
+/// fallible function `f`, so that parse errors and I/O errors come out of
+/// the same stream.
+pub struct AndThen<'f, I, T, U, E> {
+    iter: I,
+    f: |T|: 'f -> Result<U, E>
+}
+
+impl<'f, I, T, U, E> AndThen<'f, I, T, U, E> {
+    /// Run the `Ok` items of `iter` through `f`.
+    pub fn new(iter: I, f: |T|: 'f -> Result<U, E>)
+               -> AndThen<'f, I, T, U, E> {
+        AndThen{iter: iter, f: f}
+    }
+}
+
+impl<'a, 'f, I, T, U, E> StreamingIterator<'a, Result<U, E>>
+    for AndThen<'f, I, T, U, E>
+    where I: StreamingIterator<'a, Result<T, E>> {
+
+    fn next(&'a mut self) -> Option<Result<U, E>> {
+        match self.iter.next() {
+            None => None,
+            Some(Ok(item)) => Some((self.f)(item)),
+            Some(Err(err)) => Some(Err(err))
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// Streaming sources which can go back to their first item, such as
+/// in-memory test data or seekable files.  Lets `Cycle` replay a fixed
+/// input, and multi-pass algorithms, like inferring a schema before
+/// parsing, make a second pass without rebuilding the pipeline.  Adapters
+/// can be reset if their source can.
+pub trait Resettable {
+    /// Start over from the first item.
+    fn reset(&mut self);
+}
+
+impl<I: Resettable> Resettable for Fuse<I> {
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.done = false;
+    }
+}
+
+impl<I: Resettable> Resettable for StepBy<I> {
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.first = true;
+    }
+}
+
+impl<'b, I: Resettable> Resettable for ByRef<'b, I> {
+    fn reset(&mut self) { self.iter.reset(); }
+}
+
+impl<'f, I: Resettable, T, U> Resettable for Map<'f, I, T, U> {
+    fn reset(&mut self) { self.iter.reset(); }
+}
+
+impl<'f, I: Resettable, T> Resettable for Filter<'f, I, T> {
+    fn reset(&mut self) { self.iter.reset(); }
+}
+
+impl<I: Resettable> Resettable for Take<I> {
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.remaining = self.n;
+    }
+}
+
+/// An iterator which replays `iter` forever, resetting it each time it
+/// runs out.  Useful for benchmark drivers and test harnesses which need
+/// an endless supply of records without re-opening files.  If `iter` turns
+/// out to be empty, so are we.
+///
+/// We can't ask `iter` for an item, find it has none, reset it, and ask
+/// again all in one call, because the first request borrows it for as long
+/// as the item we return.  So instead we count its items up front, and
+/// reset it before it runs out.
+pub struct Cycle<I> {
+    iter: I,
+    len: uint,
+    // How many items we've yielded since the last reset.
+    pos: uint
+}
+
+impl<I: SliceStream + Resettable> Cycle<I> {
+    /// Replay `iter` forever.  This reads through `iter` once to count its
+    /// items, then resets it.
+    pub fn new(mut iter: I) -> Cycle<I> {
+        let len = iter.count();
+        iter.reset();
+        Cycle{iter: iter, len: len, pos: 0}
+    }
+}
+
+impl<'a, I> StreamingIterator<'a, &'a [u8]> for Cycle<I>
+    where I: SliceStream + Resettable {
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        if self.len == 0 { return None; }
+        if self.pos == self.len {
+            self.iter.reset();
+            self.pos = 0;
+        }
+        self.pos += 1;
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        if self.len == 0 { (0, Some(0)) } else { (uint::MAX, None) }
+    }
+}
+
+/// An iterator which skips items whose key we've already seen anywhere
+/// earlier in the stream.  The items themselves are yielded untouched, but
+/// we keep every distinct key, so `key_fn` should return something small
+/// and owned, like a copy of an ID column.  We may skip several items per
+/// call, so the items themselves mustn't borrow from `iter`.
+pub struct Unique<'f, I, T, K> {
+    iter: I,
+    key_fn: |&T|: 'f -> K,
+    seen: HashSet<K>
+}
+
+impl<'f, I, T, K: Hash + Eq> Unique<'f, I, T, K> {
+    /// Yield only the first item of `iter` with each key.
+    pub fn new(iter: I, key_fn: |&T|: 'f -> K) -> Unique<'f, I, T, K> {
+        Unique{iter: iter, key_fn: key_fn, seen: HashSet::new()}
+    }
+
+    /// How many distinct keys we've seen so far.
+    pub fn distinct(&self) -> uint { self.seen.len() }
+}
+
+impl<'a, 'f, T, I, K> StreamingIterator<'a, T> for Unique<'f, I, T, K>
+    where I: for<'b> StreamingIterator<'b, T>, K: Hash + Eq {
+
+    fn next(&'a mut self) -> Option<T> {
+        loop {
+            match self.iter.next() {
+                None => { return None; }
+                Some(item) => {
+                    if self.seen.insert((self.key_fn)(&item)) {
+                        return Some(item);
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator which merges each record with any following records for
+/// which `is_continuation` returns true, yielding one logical record per
+/// group.  This is how you handle log entries with stack traces, where
+/// indented lines belong to the entry above them.  The records are joined
+/// by copying them into a buffer which is reused from group to group.
+/// Continuation records at the very start of the stream form a group of
+/// their own.
+pub struct JoinContinuations<'f, I> {
+    iter: I,
+    is_continuation: |&[u8]|: 'f -> bool,
+    record: Vec<u8>,
+    // The first record of the next group, which we had to read to know
+    // that the current group was finished.
+    pending: Vec<u8>,
+    has_pending: bool
+}
+
+impl<'f, I> JoinContinuations<'f, I> {
+    /// Join records of `iter` with their continuations.
+    pub fn new(iter: I, is_continuation: |&[u8]|: 'f -> bool)
+               -> JoinContinuations<'f, I> {
+        JoinContinuations{iter: iter, is_continuation: is_continuation,
+                          record: vec![], pending: vec![],
+                          has_pending: false}
+    }
+}
+
+impl<'a, 'f, I: SliceStream> StreamingIterator<'a, &'a [u8]>
+    for JoinContinuations<'f, I> {
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        // We copy every record as soon as we get it, so each call to
+        // `iter.next()` only needs a short borrow, which `SliceStream`
+        // lets us have.
+        self.record.clear();
+        if self.has_pending {
+            self.has_pending = false;
+            swap(&mut self.record, &mut self.pending);
+        } else {
+            match self.iter.next() {
+                None => { return None; }
+                Some(record) => { self.record.push_all(record); }
+            }
+        }
+        loop {
+            match self.iter.next() {
+                None => { break; }
+                Some(record) => {
+                    if (self.is_continuation)(record) {
+                        self.record.push_all(record);
+                    } else {
+                        self.pending.clear();
+                        self.pending.push_all(record);
+                        self.has_pending = true;
+                        break;
+                    }
+                }
+            }
+        }
+        Some(self.record.as_slice())
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        let pending = if self.has_pending { 1 } else { 0 };
+        let lower = if lower + pending > 0 { 1 } else { 0 };
+        (lower, upper.map(|n| n + pending))
+    }
+}
+
+/// A streaming iterator built from a state value and a closure, for small
+/// ad-hoc sources which don't deserve a named struct.  Each call to `next`
+/// passes `f` the state, and `f` may return a reference into it.  See also
+/// the `streaming_iter!` macro.
+pub struct FromFn<'f, S, Sized? T> {
+    state: S,
+    f: |&mut S|: 'f -> Option<&T>
+}
+
+impl<'f, S, Sized? T> FromFn<'f, S, T> {
+    /// Yield whatever `f` returns, until it returns `None`.
+    pub fn new(state: S, f: |&mut S|: 'f -> Option<&T>) -> FromFn<'f, S, T> {
+        FromFn{state: state, f: f}
+    }
+
+    /// Get back our state.
+    pub fn unwrap(self) -> S { self.state }
+}
+
+impl<'a, 'f, S, Sized? T> StreamingIterator<'a, &'a T> for FromFn<'f, S, T> {
+    fn next(&'a mut self) -> Option<&'a T> { (self.f)(&mut self.state) }
+}
+
+/// An iterator which maps each item with `f`.  `U` may borrow from the
+/// item.
+pub struct Map<'f, I, T, U> {
+    iter: I,
+    f: |T|: 'f -> U
+}
+
+impl<'f, I, T, U> Map<'f, I, T, U> {
+    /// Map the items of `iter` with `f`.
+    pub fn new(iter: I, f: |T|: 'f -> U) -> Map<'f, I, T, U> {
+        Map{iter: iter, f: f}
+    }
+}
+
+impl<'a, 'f, I, T, U> StreamingIterator<'a, U> for Map<'f, I, T, U>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<U> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => Some((self.f)(item))
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// An iterator which skips items for which `predicate` returns false.  We
+/// may read several items per call, so they mustn't borrow from `iter`
+/// itself.  To filter byte slices from a buffered source, copy them with
+/// `ToOwned` and use the standard `filter`.
+pub struct Filter<'f, I, T> {
+    iter: I,
+    predicate: |&T|: 'f -> bool
+}
+
+impl<'f, I, T> Filter<'f, I, T> {
+    /// Keep only the items of `iter` which match `predicate`.
+    pub fn new(iter: I, predicate: |&T|: 'f -> bool) -> Filter<'f, I, T> {
+        Filter{iter: iter, predicate: predicate}
+    }
+}
+
+impl<'a, 'f, I, T> StreamingIterator<'a, T> for Filter<'f, I, T>
+    where I: for<'b> StreamingIterator<'b, T> {
+
+    fn next(&'a mut self) -> Option<T> {
+        loop {
+            match self.iter.next() {
+                None => { return None; }
+                Some(item) => {
+                    if (self.predicate)(&item) { return Some(item); }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// An iterator which yields at most `n` items, and then stops without
+/// asking the underlying iterator for more.
+pub struct Take<I> {
+    iter: I,
+    n: uint,
+    remaining: uint
+}
+
+impl<I> Take<I> {
+    /// Yield the first `n` items of `iter`.
+    pub fn new(iter: I, n: uint) -> Take<I> {
+        Take{iter: iter, n: n, remaining: n}
+    }
+}
+
+impl<'a, T, I: StreamingIterator<'a, T>> StreamingIterator<'a, T> for Take<I> {
+    fn next(&'a mut self) -> Option<T> {
+        if self.remaining == 0 { return None; }
+        self.remaining -= 1;
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let (lower, upper) = self.iter.size_hint();
+        let upper = upper.map_or(self.remaining, |n| min(n, self.remaining));
+        (min(lower, self.remaining), Some(upper))
+    }
+}
+
+/// Adapts a standard `Iterator` into a `StreamingIterator`, so that
+/// ordinary iterators, like `lines()` or a vector's `iter()`, can feed a
+/// streaming pipeline.
+pub struct IterAdapter<I> {
+    iter: I
+}
+
+impl<I> IterAdapter<I> {
+    /// Wrap `iter`.
+    pub fn new(iter: I) -> IterAdapter<I> {
+        IterAdapter{iter: iter}
+    }
+
+    /// Get back the underlying iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<'a, T, I: Iterator<T>> StreamingIterator<'a, T> for IterAdapter<I> {
+    fn next(&'a mut self) -> Option<T> { self.iter.next() }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// A standard `Iterator` over a streaming iterator whose items are already
+/// owned, like `uint` or `String`, so they don't borrow from the iterator
+/// and there's nothing to copy.  This gets you `collect`, `zip`, `for`
+/// loops and the rest of the standard adapters for free.  For borrowed
+/// items, use `ToOwned`.
+pub struct OwnedItems<I> {
+    iter: I
+}
+
+impl<I> OwnedItems<I> {
+    /// Iterate over the items of `iter`.
+    pub fn new(iter: I) -> OwnedItems<I> {
+        OwnedItems{iter: iter}
+    }
+
+    /// Get back the underlying streaming iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<T, I> Iterator<T> for OwnedItems<I>
+    where I: for<'a> StreamingIterator<'a, T> {
+
+    fn next(&mut self) -> Option<T> {
+        // `T` is the same at every lifetime, so it can't borrow from
+        // `iter`, and a short borrow is all we need.
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+/// Adapts a `TryStreamingIterator` into a `StreamingIterator` over
+/// `IoResult` items.  After yielding an error, it returns `None`.
+pub struct TryResults<I> {
+    iter: I,
+    failed: bool
+}
+
+impl<I> TryResults<I> {
+    /// Wrap `iter`.
+    pub fn new(iter: I) -> TryResults<I> {
+        TryResults{iter: iter, failed: false}
+    }
+}
+
+impl<'a, T, I> StreamingIterator<'a, IoResult<T>> for TryResults<I>
+    where I: TryStreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<T>> {
+        if self.failed { return None; }
+        match self.iter.next() {
+            Ok(None) => None,
+            Ok(Some(item)) => Some(Ok(item)),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Adapts a `StreamingIterator` over `IoResult` items, like
+/// `JournalReader`, into a `TryStreamingIterator`.
+pub struct FromResults<I> {
+    iter: I
+}
+
+impl<I> FromResults<I> {
+    /// Wrap `iter`.
+    pub fn new(iter: I) -> FromResults<I> {
+        FromResults{iter: iter}
+    }
+}
+
+impl<'a, T, I> TryStreamingIterator<'a, T> for FromResults<I>
+    where I: StreamingIterator<'a, IoResult<T>> {
+
+    fn next(&'a mut self) -> IoResult<Option<T>> {
+        match self.iter.next() {
+            None => Ok(None),
+            Some(Ok(item)) => Ok(Some(item)),
+            Some(Err(err)) => Err(err)
+        }
+    }
+}
+
+/// Adapts a `StreamingIterator` into a `TryStreamingIterator` which never
+/// fails.
+pub struct Infallible<I> {
+    iter: I
+}
+
+impl<I> Infallible<I> {
+    /// Wrap `iter`.
+    pub fn new(iter: I) -> Infallible<I> {
+        Infallible{iter: iter}
+    }
+}
+
+impl<'a, T, I> TryStreamingIterator<'a, T> for Infallible<I>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> IoResult<Option<T>> { Ok(self.iter.next()) }
+}
+
+/// An iterator which pairs each item with its index, counting from 0.
+pub struct Enumerate<I> {
+    iter: I,
+    count: uint
+}
+
+impl<I> Enumerate<I> {
+    /// Number the items of `iter`.
+    pub fn new(iter: I) -> Enumerate<I> {
+        Enumerate{iter: iter, count: 0}
+    }
+}
+
+impl<'a, T, I> StreamingIterator<'a, (uint, T)> for Enumerate<I>
+    where I: StreamingIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<(uint, T)> {
+        match self.iter.next() {
+            None => None,
+            Some(item) => {
+                self.count += 1;
+                Some((self.count - 1, item))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+impl<'a, T, I> ExactSizeStreamingIterator<'a, (uint, T)> for Enumerate<I>
+    where I: ExactSizeStreamingIterator<'a, T> {}
+
+impl<'a, T, I> ExactSizeStreamingIterator<'a, T> for Take<I>
+    where I: ExactSizeStreamingIterator<'a, T> {}
+
+impl<'a, T, I> ExactSizeStreamingIterator<'a, T> for Fuse<I>
+    where I: ExactSizeStreamingIterator<'a, T> {}
+
+impl<'a, 'f, T, U, I> ExactSizeStreamingIterator<'a, U> for Map<'f, I, T, U>
+    where I: ExactSizeStreamingIterator<'a, T> {}
+
+impl<'a, T, I> ExactSizeStreamingIterator<'a, T> for IterAdapter<I>
+    where I: ExactSize<T> {}
+
+/// An iterator which yields the items of a double-ended iterator in
+/// reverse.  `Take::new(Rev::new(lines), 10)` is `tail`.
+pub struct Rev<I> {
+    iter: I
+}
+
+impl<I> Rev<I> {
+    /// Reverse `iter`.
+    pub fn new(iter: I) -> Rev<I> {
+        Rev{iter: iter}
+    }
+
+    /// Get back the underlying iterator.
+    pub fn unwrap(self) -> I { self.iter }
+}
+
+impl<'a, T, I> StreamingIterator<'a, T> for Rev<I>
+    where I: StreamingDoubleEndedIterator<'a, T> {
+
+    fn next(&'a mut self) -> Option<T> { self.iter.next_back() }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+impl<'a, T, I> StreamingDoubleEndedIterator<'a, T> for Rev<I>
+    where I: StreamingDoubleEndedIterator<'a, T> {
+
+    fn next_back(&'a mut self) -> Option<T> { self.iter.next() }
+}
+
+/// Alternates between yielding items and returning `None`, forever.  Used
+/// to test adapters against badly-behaved sources.
+#[cfg(test)]
+struct Flaky {
+    count: uint
+}
+
+#[cfg(test)]
+impl<'a> StreamingIterator<'a, uint> for Flaky {
+    fn next(&'a mut self) -> Option<uint> {
+        self.count += 1;
+        if self.count % 2 == 0 { None } else { Some(self.count) }
+    }
+}
+
+/// Counts from 0 up to (but not including) `limit`.
+#[cfg(test)]
+struct Counter {
+    count: uint,
+    limit: uint
+}
+
+#[cfg(test)]
+impl<'a> StreamingIterator<'a, uint> for Counter {
+    fn next(&'a mut self) -> Option<uint> {
+        if self.count == self.limit { return None; }
+        self.count += 1;
+        Some(self.count - 1)
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.limit - self.count, Some(self.limit - self.count))
+    }
+}
+
+#[test]
+fn fuse_stays_exhausted() {
+    let mut fused = Fuse::new(Flaky{count: 0});
+    assert_eq!(Some(1), fused.next());
+    assert_eq!(None, fused.next());
+    assert_eq!(None, fused.next());
+    assert_eq!(None, fused.next());
+}
+
+#[test]
+fn step_by_samples_every_nth_item() {
+    let mut stepped = StepBy::new(Counter{count: 0, limit: 10}, 3);
+    assert_eq!(Some(0), stepped.next());
+    assert_eq!(Some(3), stepped.next());
+    assert_eq!(Some(6), stepped.next());
+    assert_eq!(Some(9), stepped.next());
+    assert_eq!(None, stepped.next());
+}
+
+#[test]
+fn fold_sums_items() {
+    let mut counter = Counter{count: 0, limit: 5};
+    assert_eq!(10, counter.fold(0, |acc, n| acc + n));
+    let records: &[&[u8]] = &[b"ab", b"", b"cde"];
+    assert_eq!(5, Records{records: records}.fold(0, |acc, r| acc + r.len()));
+}
+
+#[test]
+fn for_each_visits_every_item() {
+    let mut seen = vec![];
+    Counter{count: 0, limit: 3}.for_each(|n| seen.push(n));
+    assert_eq!(vec![0, 1, 2], seen);
+}
+
+#[test]
+fn count_drains_iterator() {
+    let mut counter = Counter{count: 0, limit: 7};
+    assert_eq!(7, counter.count());
+    assert_eq!(None, counter.next());
+}
+
+#[test]
+fn nth_skips_items() {
+    let mut counter = Counter{count: 0, limit: 10};
+    assert_eq!(Some(3), counter.nth(3));
+    assert_eq!(Some(4), counter.nth(0));
+    assert_eq!(None, counter.nth(10));
+    let records: &[&[u8]] = &[b"a", b"b", b"c"];
+    let mut records = Records{records: records};
+    assert_eq!(Some(b"b".to_vec()), records.nth(1).map(|r| r.to_vec()));
+    assert_eq!(None, records.nth(1));
+}
+
+#[test]
+fn find_returns_first_match() {
+    let mut counter = Counter{count: 0, limit: 10};
+    assert_eq!(Some(4), counter.find(|&n| n > 3));
+    assert_eq!(Some(5), counter.next());
+    assert_eq!(None, counter.find(|&n| n == 2));
+    let records: &[&[u8]] = &[b"a", b"bc", b"de"];
+    let mut records = Records{records: records};
+    let found: Option<Vec<u8>> = records.find(|r| r.len() > 1);
+    assert_eq!(Some(b"bc".to_vec()), found);
+    assert_eq!(Some(b"de".to_vec()), records.next().map(|r| r.to_vec()));
+}
+
+#[test]
+fn position_finds_index() {
+    let mut counter = Counter{count: 10, limit: 20};
+    assert_eq!(Some(3), counter.position(|n| n == 13));
+    assert_eq!(None, counter.position(|n| n == 13));
+    let records: &[&[u8]] = &[b"a", b"", b"b"];
+    assert_eq!(Some(1), Records{records: records}.position(|r| r.is_empty()));
+}
+
+#[test]
+fn all_and_any_short_circuit() {
+    let mut counter = Counter{count: 0, limit: 10};
+    assert!(!counter.all(|n| n < 3));
+    assert_eq!(Some(4), counter.next());
+    assert!(counter.any(|n| n == 7));
+    assert_eq!(Some(8), counter.next());
+    assert!(counter.all(|n| n > 8));
+    assert!(!counter.any(|_| true));
+    let records: &[&[u8]] = &[b"a", b"bc"];
+    assert!(Records{records: records}.all(|r| r.len() > 0));
+    assert!(!Records{records: records}.any(|r| r.len() > 2));
+}
+
+#[test]
+fn by_ref_leaves_iterator_usable() {
+    let mut counter = Counter{count: 0, limit: 5};
+    assert_eq!(Some(2), StepBy::new(counter.by_ref(), 2).nth(1));
+    assert_eq!(Some(3), counter.next());
+}
+
+#[test]
+fn flat_map_flattens_inner_iterators() {
+    let outer = Counter{count: 1, limit: 4};
+    let mut flat = FlatMap::new(outer, |n| Counter{count: 0, limit: n});
+    let mut items = vec![];
+    flat.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 0, 1, 0, 1, 2], items);
+}
+
+#[test]
+fn scan_threads_state() {
+    let counter = Counter{count: 1, limit: 6};
+    let mut totals = Scan::new(counter, 0u, |total, n| {
+        *total += n;
+        if *total > 6 { None } else { Some(*total) }
+    });
+    assert_eq!(Some(1), totals.next());
+    assert_eq!(Some(3), totals.next());
+    assert_eq!(Some(6), totals.next());
+    assert_eq!(None, totals.next());
+    assert_eq!(10, *totals.state());
+}
+
+#[test]
+fn dedup_skips_consecutive_duplicates() {
+    let halves = Scan::new(Counter{count: 0, limit: 7}, (),
+                           |_, n| Some(n / 2));
+    let mut deduped: Dedup<_, uint> = Dedup::new(halves);
+    let mut items = vec![];
+    deduped.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 1, 2, 3], items);
+}
+
+#[test]
+fn group_by_yields_runs() {
+    let mut groups = GroupBy::new(Counter{count: 0, limit: 8}, |n| *n / 3);
+    let mut result = vec![];
+    streaming_for!(mut group in groups, {
+        let mut items = vec![*group.key()];
+        // Only look at the first two items of each group.
+        match group.next() { Some(n) => items.push(n), None => {} }
+        match group.next() { Some(n) => items.push(n), None => {} }
+        result.push(items)
+    });
+    assert_eq!(vec![vec![0, 0, 1], vec![1, 3, 4], vec![2, 6, 7]], result);
+}
+
+#[test]
+fn chunks_batches_items() {
+    let records: &[&[u8]] = &[b"a", b"b", b"c", b"d", b"e"];
+    let mut buffer: Vec<Vec<u8>> = vec![];
+    let mut batches = Chunks::new(Records{records: records}, 2, &mut buffer);
+    let mut result = vec![];
+    streaming_for!(batch in batches, { result.push(batch.to_vec()) });
+    assert_eq!(vec![vec![b"a".to_vec(), b"b".to_vec()],
+                    vec![b"c".to_vec(), b"d".to_vec()],
+                    vec![b"e".to_vec()]],
+               result);
+}
+
+#[test]
+fn interleave_alternates() {
+    let mut both = Interleave::new(Counter{count: 0, limit: 2},
+                                   Counter{count: 10, limit: 14});
+    let mut items = vec![];
+    both.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 10, 1, 11, 12, 13], items);
+}
+
+#[test]
+fn tee_shows_items_to_both_callbacks() {
+    let mut sum = 0;
+    let mut max = 0;
+    let mut items = vec![];
+    {
+        let mut tee = Tee::new(Counter{count: 0, limit: 4},
+                               |n| sum += *n,
+                               |n| if *n > max { max = *n });
+        tee.for_each(|n| items.push(n));
+    }
+    assert_eq!(vec![0, 1, 2, 3], items);
+    assert_eq!(6, sum);
+    assert_eq!(3, max);
+}
+
+#[test]
+fn tee_writer_copies_items_until_an_error() {
+    let records: &[&[u8]] = &[b"ab", b"c", b"def"];
+    let mut out = MemWriter::new();
+    let mut items = vec![];
+    {
+        let mut tee = TeeWriter::new(Replay{records: records, pos: 0},
+                                     &mut out, b"\n");
+        tee.for_each(|item| items.push(item.to_vec()));
+        assert!(tee.error().is_none());
+    }
+    assert_eq!(vec![b"ab".to_vec(), b"c".to_vec(), b"def".to_vec()], items);
+    assert_eq!(b"ab\nc\ndef\n".to_vec(), out.unwrap());
+
+    // We keep yielding items after a write fails.
+    let mut buf = [0u8, ..4];
+    let mut small = BufWriter::new(&mut buf);
+    let mut tee = TeeWriter::new(Replay{records: records, pos: 0},
+                                 &mut small, b"\n");
+    let mut count = 0u;
+    tee.for_each(|_| count += 1);
+    assert_eq!(3, count);
+    assert!(tee.error().is_some());
+}
+
+#[test]
+fn to_owned_supports_std_iterator_methods() {
+    let fruit: &[&[u8]] = &[b"pear", b"fig", b"apple", b"kiwi"];
+    let copies: ToOwned<_, Vec<u8>> = ToOwned::new(Records{records: fruit});
+    let mut short: Vec<Vec<u8>> = copies.filter(|f| f.len() < 5).collect();
+    short.sort();
+    assert_eq!(vec![b"fig".to_vec(), b"kiwi".to_vec(), b"pear".to_vec()],
+               short);
+}
+
+#[test]
+fn collect_into_appends_copies() {
+    let mut items: Vec<uint> = Vec::with_capacity(8);
+    Counter{count: 0, limit: 3}.collect_into(&mut items);
+    Counter{count: 5, limit: 7}.collect_into(&mut items);
+    assert_eq!(vec![0, 1, 2, 5, 6], items);
+    assert_eq!(8, items.capacity());
+    let records: &[&[u8]] = &[b"a", b"bc"];
+    let mut copies: Vec<Vec<u8>> = vec![];
+    Records{records: records}.collect_into(&mut copies);
+    assert_eq!(vec![b"a".to_vec(), b"bc".to_vec()], copies);
+}
+
+#[test]
+fn results_copy_their_items_and_errors() {
+    let ok: Result<&[u8], uint> = Ok(b"abc");
+    let mut copy: Result<Vec<u8>, uint> = ok.owned_copy();
+    assert_eq!(Ok(b"abc".to_vec()), copy);
+    let err: Result<&[u8], uint> = Err(7);
+    assert!(!err.same_as(&copy));
+    err.copy_into(&mut copy);
+    assert_eq!(Err(7), copy);
+    assert!(err.same_as(&copy));
+    ok.copy_into(&mut copy);
+    assert!(ok.same_as(&copy));
+}
+
+#[test]
+fn max_and_min_by_key_pick_extremes() {
+    let mut counter = Counter{count: 0, limit: 7};
+    let max: Option<uint> = counter.max_by_key(|n| *n % 4);
+    assert_eq!(Some(3), max);
+    let mut counter = Counter{count: 1, limit: 7};
+    let min: Option<uint> = counter.min_by_key(|n| *n % 3);
+    assert_eq!(Some(3), min);
+    let mut empty = Counter{count: 0, limit: 0};
+    let none: Option<uint> = empty.max_by_key(|n| *n);
+    assert_eq!(None, none);
+    let records: &[&[u8]] = &[b"bb", b"a", b"cc"];
+    let longest: Option<Vec<u8>> = Records{records: records}
+        .max_by_key(|r| r.len());
+    assert_eq!(Some(b"cc".to_vec()), longest);
+    let shortest: Option<Vec<u8>> = Records{records: records}
+        .min_by_key(|r| r.len());
+    assert_eq!(Some(b"a".to_vec()), shortest);
+}
+
+#[test]
+fn last_returns_final_item() {
+    let mut counter = Counter{count: 0, limit: 5};
+    let last: Option<uint> = counter.last();
+    assert_eq!(Some(4), last);
+    let none: Option<uint> = counter.last();
+    assert_eq!(None, none);
+    let records: &[&[u8]] = &[b"header", b"body", b"trailer"];
+    let trailer: Option<Vec<u8>> = Records{records: records}.last();
+    assert_eq!(Some(b"trailer".to_vec()), trailer);
+}
+
+#[test]
+fn merge_interleaves_sorted_inputs() {
+    let evens = Scan::new(Counter{count: 0, limit: 4}, (), |_, n| Some(n * 2));
+    let mut merged = Merge::new(evens, Counter{count: 3, limit: 6},
+                                |a, b| a.cmp(b));
+    let mut items = vec![];
+    merged.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 2, 3, 4, 4, 5, 6], items);
+}
+
+#[test]
+fn windows_overlap() {
+    let records: &[&[u8]] = &[b"a", b"b", b"c", b"d"];
+    let mut windows: Windows<_, Vec<u8>> =
+        Windows::new(Records{records: records}, 3);
+    let mut result = vec![];
+    streaming_for!(window in windows, { result.push(window.to_vec()) });
+    assert_eq!(vec![vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+                    vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]],
+               result);
+
+    let mut short: Windows<_, Vec<u8>> =
+        Windows::new(Records{records: records.slice_to(2)}, 3);
+    assert!(short.next().is_none());
+}
+
+#[test]
+fn map_while_stops_at_first_none() {
+    let mut small = MapWhile::new(Counter{count: 0, limit: 10},
+                                  |n| if n < 3 { Some(n * 10) } else { None });
+    let mut items = vec![];
+    small.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 10, 20], items);
+    assert_eq!(None, small.next());
+}
+
+#[test]
+fn try_fold_stops_at_first_error() {
+    let mut counter = Counter{count: 0, limit: 5};
+    let sum: Result<uint, IoError> = counter.try_fold(0, |acc, n| Ok(acc + n));
+    assert_eq!(10, sum.unwrap());
+
+    let mut counter = Counter{count: 0, limit: 5};
+    let result = counter.try_fold(0, |acc, n| {
+        if n == 2 { Err(standard_error(InvalidInput)) } else { Ok(acc + n) }
+    });
+    assert_eq!(InvalidInput, result.unwrap_err().kind);
+    assert_eq!(Some(3), counter.next());
+
+    let records: &[&[u8]] = &[b"1", b"x", b"2"];
+    let mut records = Records{records: records};
+    let result: Result<uint, &[u8]> = records.try_fold(0, |acc, r| {
+        if r == b"x" { Err(b"x") } else { Ok(acc + r.len()) }
+    });
+    assert_eq!(Err(b"x"), result);
+    assert_eq!(Some(b"2".to_vec()), records.next().map(|r| r.to_vec()));
+}
+
+#[test]
+fn try_for_each_stops_at_first_error() {
+    let mut seen = vec![];
+    let mut counter = Counter{count: 0, limit: 5};
+    let result = counter.try_for_each(|n| {
+        if n == 3 { return Err(standard_error(InvalidInput)); }
+        seen.push(n);
+        Ok(())
+    });
+    assert_eq!(InvalidInput, result.unwrap_err().kind);
+    assert_eq!(vec![0, 1, 2], seen);
+    assert_eq!(Some(4), counter.next());
+
+    let records: &[&[u8]] = &[b"ab", b"c", b"def"];
+    let mut buf = [0u8, ..4];
+    let mut out = BufWriter::new(&mut buf);
+    let result = Records{records: records}.try_for_each(|r| out.write(r));
+    assert!(result.is_err());
+}
+
+#[cfg(test)]
+struct Results {
+    items: Vec<Result<uint, IoError>>
+}
+
+#[cfg(test)]
+impl<'a> StreamingIterator<'a, Result<uint, IoError>> for Results {
+    fn next(&'a mut self) -> Option<Result<uint, IoError>> {
+        self.items.remove(0)
+    }
+}
+
+#[test]
+fn result_adapters_pass_errors_through() {
+    let results = || Results{items: vec![Ok(1), Ok(2),
+                                         Err(standard_error(InvalidInput)),
+                                         Ok(3), Ok(4)]};
+    let mut kinds: Vec<Result<uint, IoErrorKind>> = vec![];
+
+    MapOk::new(results(), |n| n * 10)
+        .for_each(|item| kinds.push(item.map_err(|e| e.kind)));
+    assert_eq!(vec![Ok(10), Ok(20), Err(InvalidInput), Ok(30), Ok(40)], kinds);
+
+    kinds.clear();
+    FilterOk::new(results(), |n| *n % 2 == 0)
+        .for_each(|item| kinds.push(item.map_err(|e| e.kind)));
+    assert_eq!(vec![Ok(2), Err(InvalidInput), Ok(4)], kinds);
+
+    kinds.clear();
+    AndThen::new(results(), |n| {
+        if n == 3 { Err(standard_error(EndOfFile)) } else { Ok(n) }
+    }).for_each(|item| kinds.push(item.map_err(|e| e.kind)));
+    assert_eq!(vec![Ok(1), Ok(2), Err(InvalidInput), Err(EndOfFile), Ok(4)],
+               kinds);
+}
+
+#[test]
+fn size_hints_propagate_through_adapters() {
+    let counter = || Counter{count: 0, limit: 10};
+    assert_eq!((10, Some(10)), counter().size_hint());
+    assert_eq!((4, Some(4)), StepBy::new(counter(), 3).size_hint());
+    assert_eq!((20, Some(20)),
+               Interleave::new(counter(), counter()).size_hint());
+    assert_eq!((0, Some(10)),
+               MapWhile::new(counter(), |n| Some(n)).size_hint());
+    assert_eq!((0, None), Flaky{count: 0}.size_hint());
+
+    let ten: &[&[u8]] = &[b"", ..10];
+    let windows: Windows<_, Vec<u8>> = Windows::new(Records{records: ten}, 3);
+    assert_eq!((8, Some(8)), windows.size_hint());
+    let mut buffer: Vec<Vec<u8>> = vec![];
+    let mut chunks = Chunks::new(Records{records: ten}, 4, &mut buffer);
+    assert_eq!((3, Some(3)), chunks.size_hint());
+    chunks.next();
+    assert_eq!((2, Some(2)), chunks.size_hint());
+}
+
+#[cfg(test)]
+impl<'a> ExactSizeStreamingIterator<'a, uint> for Counter {}
+
+#[cfg(test)]
+impl Resettable for Counter {
+    fn reset(&mut self) { self.count = 0; }
+}
+
+// Like `Records`, but can start over.
+#[cfg(test)]
+struct Replay<'b> {
+    records: &'b [&'b [u8]],
+    pos: uint
+}
+
+#[cfg(test)]
+impl<'a, 'b> StreamingIterator<'a, &'a [u8]> for Replay<'b> {
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        if self.pos == self.records.len() { return None; }
+        self.pos += 1;
+        Some(self.records[self.pos - 1])
+    }
+}
+
+#[cfg(test)]
+impl<'b> Resettable for Replay<'b> {
+    fn reset(&mut self) { self.pos = 0; }
+}
+
+#[test]
+fn cycle_replays_resettable_sources() {
+    let records: &[&[u8]] = &[b"a", b"b", b"c"];
+    let mut cycle = Replay{records: records, pos: 0}.cycle();
+    let mut items = vec![];
+    for _ in range(0u, 7) { items.push(cycle.next().unwrap().to_vec()); }
+    assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(),
+                    b"a".to_vec(), b"b".to_vec(), b"c".to_vec(),
+                    b"a".to_vec()],
+               items);
+
+    let mut empty = Cycle::new(Replay{records: &[], pos: 0});
+    assert!(empty.next().is_none());
+}
+
+#[test]
+fn each_stops_when_asked() {
+    let mut seen = vec![];
+    let mut fused = Fuse::new(Counter{count: 0, limit: 5});
+    assert!(!fused.each(|n| { seen.push(n); n < 2 }));
+    assert_eq!(vec![0, 1, 2], seen);
+    assert!(fused.by_ref().each(|n| { seen.push(n); true }));
+    assert_eq!(vec![0, 1, 2, 3, 4], seen);
+    assert_eq!(None, fused.next());
+}
+
+#[test]
+fn unique_skips_keys_seen_before() {
+    let mut unique = Unique::new(Counter{count: 0, limit: 10}, |n| *n % 4);
+    let mut items = vec![];
+    unique.for_each(|n| items.push(n));
+    assert_eq!(vec![0, 1, 2, 3], items);
+    assert_eq!(4, unique.distinct());
+}
+
+#[test]
+fn partition_routes_items_to_two_sinks() {
+    let mut counter = Counter{count: 0, limit: 7};
+    let (mut evens, mut odds) = (vec![], vec![]);
+    let result: Result<(uint, uint), ()> =
+        counter.partition(|n| *n % 2 == 0,
+                          |n| { evens.push(n); Ok(()) },
+                          |n| { odds.push(n); Ok(()) });
+    assert_eq!(Ok((4, 3)), result);
+    assert_eq!(vec![0, 2, 4, 6], evens);
+    assert_eq!(vec![1, 3, 5], odds);
+
+    let mut counter = Counter{count: 0, limit: 7};
+    let result = counter.partition(|n| *n < 3, |_| Ok(()), |n| Err(n));
+    assert_eq!(Err(3), result);
+    assert_eq!(Some(4), counter.next());
+
+    let records: &[&[u8]] = &[b"#a", b"b", b"#c"];
+    let mut comments = vec![];
+    let result: Result<(uint, uint), ()> =
+        Records{records: records}.partition(|r| r.starts_with(b"#"),
+                                            |r| Ok(comments.push(r.to_vec())),
+                                            |_| Ok(()));
+    assert_eq!(Ok((2, 1)), result);
+    assert_eq!(vec![b"#a".to_vec(), b"#c".to_vec()], comments);
+}
+
+#[cfg(test)]
+struct Records<'b> {
+    records: &'b [&'b [u8]]
+}
+
+#[cfg(test)]
+impl<'a, 'b> StreamingIterator<'a, &'a [u8]> for Records<'b> {
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        if self.records.is_empty() { return None; }
+        let record = self.records[0];
+        self.records = self.records.slice_from(1);
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.records.len(), Some(self.records.len()))
+    }
+}
+
+#[test]
+fn join_continuations_merges_indented_lines() {
+    let lines: &[&[u8]] = &[b"  orphan\n", b"ERROR boom\n", b"  at a()\n",
+                            b"  at b()\n", b"INFO ok\n", b"WARN hmm\n",
+                            b"\tdetail"];
+    let mut joined = JoinContinuations::new(
+        Records{records: lines},
+        |line| line.len() > 0 && (line[0] == b' ' || line[0] == b'\t'));
+    let mut records = vec![];
+    streaming_for!(record in joined, { records.push(record.to_vec()); });
+    assert_eq!(vec![b"  orphan\n".to_vec(),
+                    b"ERROR boom\n  at a()\n  at b()\n".to_vec(),
+                    b"INFO ok\n".to_vec(),
+                    b"WARN hmm\n\tdetail".to_vec()],
+               records);
+}
+
+#[test]
+fn streaming_for_can_break_with_value() {
+    let mut counter = Counter{count: 0, limit: 10};
+    let found = streaming_for!(n in counter => found, {
+        if n * n > 20 { found = Some(n); break; }
+    });
+    assert_eq!(Some(5), found);
+    assert_eq!(Some(6), counter.next());
+    let missing: Option<uint> = streaming_for!(n in counter => missing, {
+        if n > 100 { missing = Some(n); break; }
+    });
+    assert_eq!(None, missing);
+}
+
+#[test]
+fn streaming_for_supports_labels() {
+    let mut outer = Counter{count: 0, limit: 5};
+    let mut pairs = vec![];
+    streaming_for!('outer: a in outer, {
+        let mut inner = Counter{count: 0, limit: 5};
+        streaming_for!(b in inner, {
+            if b > a { continue 'outer; }
+            if a + b == 6 { break 'outer; }
+            pairs.push((a, b));
+        });
+    });
+    assert_eq!(vec![(0, 0), (1, 0), (1, 1), (2, 0), (2, 1), (2, 2), (3, 0),
+                    (3, 1), (3, 2)],
+               pairs);
+    assert_eq!(Some(4), outer.next());
+
+    let mut counter = Counter{count: 0, limit: 5};
+    let found = streaming_for!('search: n in counter => found, {
+        if n == 3 { found = Some(n); break 'search; }
+    });
+    assert_eq!(Some(3), found);
+}
+
+#[test]
+fn streaming_for_else_runs_on_exhaustion() {
+    let mut counter = Counter{count: 0, limit: 3};
+    let mut total = 0;
+    let mut finished = 0u;
+    streaming_for!(n in counter, { total += n; } else {
+        finished += 1;
+        assert_eq!(None, counter.next());
+    });
+    assert_eq!((3, 1), (total, finished));
+
+    let mut counter = Counter{count: 0, limit: 3};
+    streaming_for!('outer: n in counter, { if n == 1 { break 'outer; } } else {
+        finished += 1;
+    });
+    assert_eq!(1, finished);
+    assert_eq!(Some(2), counter.next());
+}
+
+#[test]
+fn streaming_for_takes_any_block() {
+    let mut counter = Counter{count: 0, limit: 3};
+    let mut seen = vec![];
+    streaming_for!(n in counter, {
+        let doubled = n * 2;
+        seen.push(doubled);
+        doubled
+    });
+    assert_eq!(vec![0, 2, 4], seen);
+}
+
+#[test]
+fn streaming_iter_builds_sources_from_closures() {
+    let mut runs = streaming_iter!((0u, vec![]), |state| {
+        let (ref mut n, ref mut run) = *state;
+        if *n == 3 { return None; }
+        *n += 1;
+        run.push(b'x');
+        Some(run.as_slice())
+    });
+    let mut seen = vec![];
+    streaming_for!(run in runs, { seen.push(run.to_vec()); });
+    assert_eq!(vec![b"x".to_vec(), b"xx".to_vec(), b"xxx".to_vec()], seen);
+    let (count, _) = runs.unwrap();
+    assert_eq!(3, count);
+}
+
+#[test]
+fn pipeline_composes_adapters_left_to_right() {
+    let mut squares = pipeline!(Counter{count: 0, limit: 100}
+                                => filter(|n| *n % 3 == 0)
+                                => map(|n| n * n)
+                                => take(4));
+    let mut seen = vec![];
+    squares.for_each(|n| seen.push(n));
+    assert_eq!(vec![0, 9, 36, 81], seen);
+
+    let mut stepped = pipeline!(Counter{count: 0, limit: 10}
+                                => StepBy(3)
+                                => Fuse());
+    assert_eq!(4, stepped.count());
+}
+
+#[test]
+fn iter_adapter_wraps_std_iterators() {
+    let words = ["apple", "bob", "cherry"];
+    let mut iter = IterAdapter::new(words.iter().map(|w| *w));
+    assert_eq!((3, Some(3)), iter.size_hint());
+    let mut long = Filter::new(iter.by_ref(), |w: &&str| w.len() > 3);
+    assert_eq!(Some("apple"), long.next());
+    assert_eq!(Some("cherry"), long.next());
+    assert_eq!(None, long.next());
+}
+
+#[test]
+fn owned_items_work_with_std_adapters() {
+    let counter = Counter{count: 0, limit: 4};
+    let pairs: Vec<(uint, char)> =
+        OwnedItems::new(counter).zip("abc".chars()).collect();
+    assert_eq!(vec![(0, 'a'), (1, 'b'), (2, 'c')], pairs);
+    let mut total = 0;
+    for n in OwnedItems::new(Counter{count: 0, limit: 5}) { total += n; }
+    assert_eq!(10, total);
+}
+
+#[test]
+fn drive_feeds_every_item_to_callback() {
+    let mut counter = Counter{count: 0, limit: 4};
+    let mut seen = vec![];
+    drive(&mut counter, |n| seen.push(n));
+    assert_eq!(vec![0, 1, 2, 3], seen);
+}
+
+#[test]
+fn ext_methods_chain_adapters() {
+    let counter = Counter{count: 0, limit: 20};
+    let mut chained =
+        counter.filter(|&n| n % 2 == 0).step_by(2).map(|n| n * 10).take(3);
+    let mut seen = vec![];
+    streaming_for!(n in chained, { seen.push(n); });
+    assert_eq!(vec![0, 40, 80], seen);
+}
+
+#[test]
+fn try_iterators_convert_both_ways() {
+    let mut fallible = FromResults::new(Results{items: vec![
+        Ok(1), Err(standard_error(InvalidInput)), Ok(2)]});
+    assert_eq!(Some(1), fallible.next().unwrap());
+    assert_eq!(InvalidInput, fallible.next().unwrap_err().kind);
+
+    let mut results = TryResults::new(FromResults::new(Results{items: vec![
+        Ok(1), Err(standard_error(InvalidInput)), Ok(2)]}));
+    let mut kinds: Vec<Result<uint, IoErrorKind>> = vec![];
+    results.for_each(|item| kinds.push(item.map_err(|e| e.kind)));
+    assert_eq!(vec![Ok(1), Err(InvalidInput)], kinds);
+
+    let mut infallible = Infallible::new(Counter{count: 0, limit: 1});
+    assert_eq!(Some(0), infallible.next().unwrap());
+    assert_eq!(None, infallible.next().unwrap());
+}
+
+#[test]
+fn adapters_reset_with_their_source() {
+    let mut doubled = Map::new(Take::new(Counter{count: 0, limit: 10}, 3),
+                               |n| n * 2);
+    let mut first = vec![];
+    streaming_for!(n in doubled, { first.push(n); });
+    doubled.reset();
+    let mut second = vec![];
+    streaming_for!(n in doubled, { second.push(n); });
+    assert_eq!(vec![0, 2, 4], first);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn exact_sizes_pass_through_adapters() {
+    let counter = Counter{count: 0, limit: 10};
+    assert_eq!(10, counter.len());
+    let mut numbered = Enumerate::new(Take::new(counter, 3));
+    assert_eq!(3, numbered.len());
+    assert_eq!(Some((0, 0)), numbered.next());
+    assert_eq!(2, numbered.len());
+    assert_eq!(3, IterAdapter::new([1u, 2, 3].iter()).len());
+}
+
+// Spelled out with `for<'a>`, to show that the bound itself works.
+#[cfg(test)]
+fn count_slices<I>(mut iter: I) -> uint
+    where I: for<'a> StreamingIterator<'a, &'a [u8]> {
+
+    let mut n = 0;
+    streaming_for!(_ in iter, { n += 1; });
+    n
+}
+
+#[cfg(test)]
+fn longest<I: SliceStream>(iter: &mut I) -> Option<uint> {
+    let mut longest = None;
+    loop {
+        match iter.next() {
+            None => { return longest; }
+            Some(item) => {
+                if longest.map_or(true, |n| item.len() > n) {
+                    longest = Some(item.len());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn generic_over_stream() {
+    let lines = || CursorIter::new(LineCursor::new(BufReader::new(
+        b"one\nthree\ntwo\n")));
+    assert_eq!(3, count_slices(lines()));
+    let mut iter = lines();
+    assert_eq!(Some(6), longest(&mut iter));
+    assert_eq!(None, longest(&mut iter));
 }
@@ -0,0 +1,304 @@
+//! A crash-safe journal of records.
+//!
+//! Long-running ingestion jobs append their output to a journal, so that
+//! after a crash they can pick up where they left off without leaving
+//! corrupt partial records behind.  Records are grouped into segments,
+//! each of which is written with its length and a CRC-32 of its contents:
+//!
+//! ```text
+//! segment := payload_len:u32le crc32:u32le payload
+//! payload := (record_len:u32le record)*
+//! ```
+//!
+//! A crash can leave a torn segment at the end of the file.  The reader
+//! stops at the first segment which is short or fails its checksum, and
+//! `recover` truncates the file there, so that a new `JournalWriter` can
+//! safely append to it.  Only whole segments are ever recovered, so a
+//! segment is the unit of loss; `JournalWriter` syncs the file to disk
+//! every few segments to bound how much that can be.
+
+use std::io::{Append,EndOfFile,File,IoError,IoResult,Open,ReadWrite,SeekSet,
+              Write};
+use std::io::fs::PathExtensions;
+use std::io::util::LimitReader;
+
+use deflate::Crc32;
+use iter::{Resettable,StreamingIterator};
+use sinks::RecordSink;
+
+#[cfg(test)] use std::io::TempDir;
+
+// The length and checksum in front of each segment.
+static HEADER_LEN: uint = 8;
+
+fn push_le_u32(buf: &mut Vec<u8>, n: u32) {
+    for i in range(0u, 4) { buf.push((n >> (8 * i)) as u8); }
+}
+
+fn le_u32(buf: &[u8]) -> u32 {
+    range(0u, 4).fold(0, |n, i| n | (buf[i] as u32) << (8 * i))
+}
+
+/// A `RecordSink` which appends records to a journal file.
+pub struct JournalWriter {
+    file: File,
+    crc: Crc32,
+    segment: Vec<u8>,
+    segment_size: uint,
+    sync_every: uint,
+    unsynced: uint
+}
+
+impl JournalWriter {
+    /// Append to the journal at `path`, creating it if necessary.  If the
+    /// previous writer may have crashed, call `recover` first.  By default,
+    /// we write a segment every 64KiB of records, and sync after every
+    /// segment.
+    pub fn open(path: &Path) -> IoResult<JournalWriter> {
+        let file = try!(File::open_mode(path, Append, Write));
+        Ok(JournalWriter{file: file, crc: Crc32::new(), segment: vec![],
+                         segment_size: 64 * 1024, sync_every: 1,
+                         unsynced: 0})
+    }
+
+    /// Write a segment once we've buffered `size` bytes of records.
+    pub fn with_segment_size(mut self, size: uint) -> JournalWriter {
+        assert!(size > 0);
+        self.segment_size = size;
+        self
+    }
+
+    /// Sync the file to disk after every `segments` segments.  Records in
+    /// unsynced segments may be lost in a crash.
+    pub fn with_sync_every(mut self, segments: uint) -> JournalWriter {
+        assert!(segments > 0);
+        self.sync_every = segments;
+        self
+    }
+
+    /// Write out any buffered records as a segment, even if it's small.
+    pub fn write_segment(&mut self) -> IoResult<()> {
+        if self.segment.is_empty() { return Ok(()); }
+        self.crc.reset();
+        self.crc.update(self.segment.as_slice());
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        push_le_u32(&mut header, self.segment.len() as u32);
+        push_le_u32(&mut header, self.crc.value());
+        try!(self.file.write(header.as_slice()));
+        try!(self.file.write(self.segment.as_slice()));
+        self.segment.clear();
+        self.unsynced += 1;
+        if self.unsynced >= self.sync_every { try!(self.sync()); }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> IoResult<()> {
+        self.unsynced = 0;
+        self.file.fsync()
+    }
+}
+
+impl RecordSink for JournalWriter {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        push_le_u32(&mut self.segment, record.len() as u32);
+        self.segment.push_all(record);
+        if self.segment.len() >= self.segment_size {
+            try!(self.write_segment());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        try!(self.write_segment());
+        if self.unsynced > 0 { try!(self.sync()); }
+        Ok(())
+    }
+}
+
+// Read until `buf` is full or we reach the end of the input, and return
+// how much we read.
+fn read_full<R: Reader>(input: &mut R, buf: &mut [u8]) -> IoResult<uint> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(buf.slice_from_mut(len)) {
+            Ok(n) => { len += n; }
+            Err(IoError{kind: EndOfFile, ..}) => { break; }
+            Err(err) => { return Err(err); }
+        }
+    }
+    Ok(len)
+}
+
+/// Reads the records of a journal back, stopping before any torn or
+/// corrupt segment.
+pub struct JournalReader<R> {
+    input: R,
+    crc: Crc32,
+    segment: Vec<u8>,
+    pos: uint,
+    valid_len: u64,
+    torn: bool,
+    done: bool,
+    // An error from `reset`, to be reported by `next`.
+    seek_error: Option<IoError>
+}
+
+impl<R: Reader> JournalReader<R> {
+    /// Read a journal from `input`.
+    pub fn new(input: R) -> JournalReader<R> {
+        JournalReader{input: input, crc: Crc32::new(), segment: vec![],
+                      pos: 0, valid_len: 0, torn: false, done: false,
+                      seek_error: None}
+    }
+
+    /// The length of the intact part of the journal we've read so far.
+    pub fn valid_len(&self) -> u64 { self.valid_len }
+
+    /// Did we stop at a torn or corrupt segment, rather than at a clean
+    /// end of file?
+    pub fn torn(&self) -> bool { self.torn }
+
+    // Load the next intact segment, returning false if there isn't one.
+    fn next_segment(&mut self) -> IoResult<bool> {
+        let mut header = [0u8, ..8];
+        let read = try!(read_full(&mut self.input, header.as_mut_slice()));
+        if read < HEADER_LEN {
+            self.torn = read > 0;
+            return Ok(false);
+        }
+        let len = le_u32(header.slice_to(4)) as uint;
+        // A corrupt length could be huge, so grow the buffer as we read,
+        // rather than trusting it up front.
+        self.segment.clear();
+        let mut payload = LimitReader::new(self.input.by_ref(), len);
+        loop {
+            match payload.push(64 * 1024, &mut self.segment) {
+                Ok(_) => {}
+                Err(IoError{kind: EndOfFile, ..}) => { break; }
+                Err(err) => { return Err(err); }
+            }
+        }
+        let read = self.segment.len();
+        self.crc.reset();
+        self.crc.update(self.segment.as_slice());
+        if read < len || self.crc.value() != le_u32(header.slice_from(4)) {
+            self.torn = true;
+            return Ok(false);
+        }
+        self.pos = 0;
+        self.valid_len += (HEADER_LEN + len) as u64;
+        Ok(true)
+    }
+}
+
+impl<'a, R: Reader> StreamingIterator<'a, IoResult<&'a [u8]>>
+    for JournalReader<R> {
+
+    fn next(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        match self.seek_error.take() {
+            Some(err) => { self.done = true; return Some(Err(err)); }
+            None => {}
+        }
+        if self.done { return None; }
+        while self.pos == self.segment.len() {
+            match self.next_segment() {
+                Ok(true) => {}
+                Ok(false) => { self.done = true; return None; }
+                Err(err) => { self.done = true; return Some(Err(err)); }
+            }
+        }
+        // The checksum matched, so trust the record lengths, but don't
+        // run off the end of the segment if they're wrong anyway.
+        let start = self.pos + 4;
+        if start > self.segment.len() {
+            self.done = true;
+            return None;
+        }
+        let len = le_u32(self.segment.slice(self.pos, start)) as uint;
+        if start + len > self.segment.len() {
+            self.done = true;
+            return None;
+        }
+        self.pos = start + len;
+        Some(Ok(self.segment.slice(start, start + len)))
+    }
+}
+
+/// Rewinds to the start of the journal.  If we can't seek back, the next
+/// call to `next` returns the error.
+impl<R: Reader+Seek> Resettable for JournalReader<R> {
+    fn reset(&mut self) {
+        self.segment.clear();
+        self.pos = 0;
+        self.valid_len = 0;
+        self.torn = false;
+        self.done = false;
+        self.seek_error = self.input.seek(0, SeekSet).err();
+    }
+}
+
+/// Cut any torn segment off the end of the journal at `path`, so a new
+/// `JournalWriter` can append to it.  Returns the number of intact records
+/// which remain.  A missing journal has no records.
+pub fn recover(path: &Path) -> IoResult<u64> {
+    if !path.exists() { return Ok(0); }
+    let mut reader = JournalReader::new(try!(File::open(path)));
+    let mut records = 0u64;
+    loop {
+        match reader.next() {
+            None => { break; }
+            Some(Ok(_)) => { records += 1; }
+            Some(Err(err)) => { return Err(err); }
+        }
+    }
+    if reader.torn() {
+        let mut file = try!(File::open_mode(path, Open, ReadWrite));
+        try!(file.truncate(reader.valid_len() as i64));
+        try!(file.fsync());
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+fn read_journal(path: &Path) -> Vec<Vec<u8>> {
+    let mut reader = JournalReader::new(File::open(path).unwrap());
+    let mut records = vec![];
+    streaming_for!(record in reader, {
+        records.push(record.unwrap().to_vec());
+    });
+    records
+}
+
+#[test]
+fn journal_survives_torn_tail() {
+    let dir = TempDir::new("journal").unwrap();
+    let path = dir.path().join("out.journal");
+    assert_eq!(0, recover(&path).unwrap());
+
+    let mut writer =
+        JournalWriter::open(&path).unwrap().with_segment_size(8);
+    for record in ["one", "two", "three"].iter() {
+        writer.write_record(record.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+
+    // Simulate a crash partway through writing a segment.
+    {
+        let mut file = File::open_mode(&path, Append, Write).unwrap();
+        file.write(&[20, 0, 0, 0, 1, 2, 3, 4, 5, 0]).unwrap();
+    }
+    assert_eq!(vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()],
+               read_journal(&path));
+    assert_eq!(3, recover(&path).unwrap());
+
+    let mut writer = JournalWriter::open(&path).unwrap();
+    writer.write_record(b"four").unwrap();
+    writer.finish().unwrap();
+    let mut reader = JournalReader::new(File::open(&path).unwrap());
+    let mut count = 0u;
+    streaming_for!(_ in reader, { count += 1; });
+    assert_eq!(4, count);
+    assert!(!reader.torn());
+    reader.reset();
+    assert_eq!(b"one", reader.next().unwrap().unwrap());
+}
@@ -8,6 +8,7 @@
 #![deny(warnings)]
 
 #![feature(macro_rules)]
+#![cfg_attr(feature = "experimental", feature(associated_types))]
 
 #[cfg(test)] extern crate test;
 
@@ -16,3 +17,41 @@
 pub mod csv;
 pub mod iter;
 pub mod buffers;
+pub mod split;
+pub mod inline;
+pub mod frames;
+pub mod continuation;
+pub mod comments;
+pub mod indent;
+pub mod logfmt;
+pub mod ndjson;
+pub mod schema;
+pub mod pipeline;
+pub mod compress;
+pub mod sinks;
+pub mod retry;
+pub mod checkpoint;
+pub mod deflate;
+pub mod blocks;
+pub mod bzip2;
+pub mod lz4;
+pub mod snappy;
+pub mod dictionary;
+pub mod numbers;
+pub mod contract;
+pub mod manifest;
+pub mod cursor;
+pub mod tokens;
+pub mod detect;
+pub mod journal;
+pub mod utf8;
+pub mod width;
+pub mod multi;
+pub mod conll;
+pub mod conll_stats;
+pub mod inplace;
+pub mod tail;
+pub mod prelude;
+
+// Not covered by any stability promises.  See the module docs.
+#[cfg(feature = "experimental")] pub mod experimental;
@@ -0,0 +1,144 @@
+//! A zero-copy parser for logfmt-style structured logs.
+//!
+//! Each line looks like `level=info msg="hello world" took=12ms`.  We
+//! yield one `Pairs` iterator per line, which in turn yields `(key, value)`
+//! slices pointing straight into our line buffer.
+
+use std::io::{Buffer,IoResult};
+
+use buffers::read_until_into;
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::MemReader;
+
+/// An iterator over the `(key, value)` pairs in a single logfmt line.
+/// Quoted values are returned without their quotes, but any backslash
+/// escapes inside them are left alone; see `unescape`.  Keys without an
+/// `=` have an empty value.
+pub struct Pairs<'a> {
+    line: &'a [u8],
+    pos: uint
+}
+
+impl<'a> Pairs<'a> {
+    /// Parse the pairs in `line`.  A trailing newline is ignored.
+    pub fn new(line: &'a [u8]) -> Pairs<'a> {
+        Pairs{line: line, pos: 0}
+    }
+
+    // Advance while `pred` holds, and return what we skipped over.
+    fn take_while(&mut self, pred: |u8| -> bool) -> &'a [u8] {
+        let start = self.pos;
+        while self.pos < self.line.len() && pred(self.line[self.pos]) {
+            self.pos += 1;
+        }
+        self.line.slice(start, self.pos)
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\r' || b == b'\n'
+}
+
+impl<'a> Iterator<(&'a [u8], &'a [u8])> for Pairs<'a> {
+    fn next(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+        self.take_while(is_space);
+        if self.pos == self.line.len() { return None; }
+        let key = self.take_while(|b| b != b'=' && !is_space(b));
+        if self.pos == self.line.len() || self.line[self.pos] != b'=' {
+            return Some((key, self.line.slice(self.pos, self.pos)));
+        }
+        self.pos += 1;
+        if self.pos < self.line.len() && self.line[self.pos] == b'"' {
+            self.pos += 1;
+            let start = self.pos;
+            let mut escaped = false;
+            while self.pos < self.line.len() {
+                let b = self.line[self.pos];
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' {
+                    escaped = true;
+                } else if b == b'"' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            let value = self.line.slice(start, self.pos);
+            // Skip the closing quote, if the line has one.
+            if self.pos < self.line.len() { self.pos += 1; }
+            Some((key, value))
+        } else {
+            Some((key, self.take_while(|b| !is_space(b))))
+        }
+    }
+}
+
+/// Append `value` to `out`, with any backslash escapes removed.  Only
+/// needed for quoted values which actually contain a backslash.
+pub fn unescape(value: &[u8], out: &mut Vec<u8>) {
+    let mut escaped = false;
+    for &b in value.iter() {
+        if escaped {
+            out.push(match b { b'n' => b'\n', b't' => b'\t', _ => b });
+            escaped = false;
+        } else if b == b'\\' {
+            escaped = true;
+        } else {
+            out.push(b);
+        }
+    }
+}
+
+/// Yields the pairs in each line of `input`.
+pub struct LogfmtReader<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    line: Vec<u8>
+}
+
+impl<'a, T: Buffer+'a> LogfmtReader<'a, T> {
+    /// Read logfmt lines from `input`.
+    pub fn new(input: &'a mut T) -> LogfmtReader<'a, T> {
+        LogfmtReader{input: input, line: vec![]}
+    }
+}
+
+impl<'a, 'b, T: Buffer+'b> StreamingIterator<'a, IoResult<Pairs<'a>>>
+    for LogfmtReader<'b, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<Pairs<'a>>> {
+        self.line.clear();
+        match read_until_into(self.input, b'\n', &mut self.line) {
+            Ok(true) => Some(Ok(Pairs::new(self.line.as_slice()))),
+            Ok(false) => None,
+            Err(err) => Some(Err(err))
+        }
+    }
+}
+
+#[test]
+fn parses_pairs() {
+    let line = b"level=info msg=\"hello \\\"world\\\"\" flag took=12ms\n";
+    let pairs: Vec<(&[u8], &[u8])> = Pairs::new(line).collect();
+    assert_eq!(vec![(b"level", b"info"),
+                    (b"msg", b"hello \\\"world\\\""),
+                    (b"flag", b""),
+                    (b"took", b"12ms")],
+               pairs);
+    let (_, msg) = pairs[1];
+    let mut unescaped = vec![];
+    unescape(msg, &mut unescaped);
+    assert_eq!(b"hello \"world\"", unescaped.as_slice());
+}
+
+#[test]
+fn reads_lines() {
+    let text = "a=1 b=2\nc=\"x y\"\n";
+    let mut reader = MemReader::new(text.as_bytes().to_vec());
+    let mut lines = LogfmtReader::new(&mut reader);
+    let mut keys = vec![];
+    streaming_for!(pairs in lines, {
+        for (key, _) in pairs.unwrap() { keys.push(key.to_vec()); }
+    });
+    assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], keys);
+}
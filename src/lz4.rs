@@ -0,0 +1,415 @@
+//! Decompressing the LZ4 frame format.
+//!
+//! LZ4 decompresses several times faster than gzip, which makes it the
+//! usual choice for intermediate files passed between pipeline stages.
+//! An LZ4 frame holds a sequence of blocks, each of which is either
+//! LZ4-compressed or stored as is.  `Lz4Buffer` decodes one block at a
+//! time, keeping the last 64K of output around for blocks which refer back
+//! into their predecessors.
+//!
+//! We verify the header checksum, and the block and content checksums if
+//! the frame has them.  Concatenated frames are read straight through, and
+//! skippable frames are skipped.
+
+use std::cmp::min;
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError,standard_error};
+
+#[cfg(test)] use std::io::{File,MemReader};
+#[cfg(test)] use buffers::DribbleBuffer;
+#[cfg(test)] use compress::check_damaged_streams;
+
+static FRAME_MAGIC: u32 = 0x184d2204;
+
+/// Matches can refer back this far.
+static WINDOW_SIZE: uint = 64 * 1024;
+
+fn corrupt(detail: &str) -> IoError {
+    IoError{kind: OtherIoError, desc: "corrupt LZ4 data",
+            detail: Some(detail.to_string())}
+}
+
+// Running out of input anywhere except between frames means the input was
+// truncated.
+fn no_eof<T>(result: IoResult<T>) -> IoResult<T> {
+    match result {
+        Err(IoError{kind: EndOfFile, ..}) => {
+            Err(corrupt("unexpected end of compressed data"))
+        }
+        other => other
+    }
+}
+
+static PRIME1: u32 = 2654435761;
+static PRIME2: u32 = 2246822519;
+static PRIME3: u32 = 3266489917;
+static PRIME4: u32 = 668265263;
+static PRIME5: u32 = 374761393;
+
+fn rotl(x: u32, r: uint) -> u32 { x << r | x >> (32 - r) }
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | bytes[1] as u32 << 8 | bytes[2] as u32 << 16 |
+        bytes[3] as u32 << 24
+}
+
+// The xxHash32 checksum used by LZ4 frames, with a seed of 0, computed
+// incrementally.
+struct Xxh32 {
+    lanes: [u32, ..4],
+    pending: Vec<u8>,
+    total: u64
+}
+
+impl Xxh32 {
+    fn new() -> Xxh32 {
+        Xxh32{lanes: [PRIME1 + PRIME2, PRIME2, 0, 0 - PRIME1],
+              pending: Vec::with_capacity(16), total: 0}
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.pending.len() > 0 {
+            let needed = min(16 - self.pending.len(), data.len());
+            self.pending.push_all(data.slice_to(needed));
+            data = data.slice_from(needed);
+            if self.pending.len() < 16 { return; }
+            let stripe = self.pending.clone();
+            self.stripe(stripe.as_slice());
+            self.pending.clear();
+        }
+        while data.len() >= 16 {
+            self.stripe(data.slice_to(16));
+            data = data.slice_from(16);
+        }
+        self.pending.push_all(data);
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for i in range(0u, 4) {
+            let lane = le_u32(stripe.slice_from(i * 4));
+            self.lanes[i] = rotl(self.lanes[i] + lane * PRIME2, 13) * PRIME1;
+        }
+    }
+
+    fn digest(&self) -> u32 {
+        let mut h = if self.total >= 16 {
+            let l = self.lanes;
+            rotl(l[0], 1) + rotl(l[1], 7) + rotl(l[2], 12) + rotl(l[3], 18)
+        } else {
+            PRIME5
+        };
+        h += self.total as u32;
+        let mut rest = self.pending.as_slice();
+        while rest.len() >= 4 {
+            h = rotl(h + le_u32(rest) * PRIME3, 17) * PRIME4;
+            rest = rest.slice_from(4);
+        }
+        for &byte in rest.iter() {
+            h = rotl(h + byte as u32 * PRIME5, 11) * PRIME1;
+        }
+        h ^= h >> 15;
+        h *= PRIME2;
+        h ^= h >> 13;
+        h *= PRIME3;
+        h ^= h >> 16;
+        h
+    }
+}
+
+fn xxh32(data: &[u8]) -> u32 {
+    let mut hash = Xxh32::new();
+    hash.update(data);
+    hash.digest()
+}
+
+// Read the rest of a length which didn't fit in its 4-bit field.
+fn extended_length(src: &[u8], i: &mut uint, mut len: uint) -> IoResult<uint> {
+    if len == 15 {
+        loop {
+            if *i >= src.len() { return Err(corrupt("truncated length")); }
+            let byte = src[*i];
+            *i += 1;
+            len += byte as uint;
+            if byte != 255 { break; }
+        }
+    }
+    Ok(len)
+}
+
+// Decompress the LZ4 block `src`, appending the result to `out`, which
+// must not grow past `limit` bytes.  Matches may refer back into the
+// existing contents of `out`.
+fn decode_block(src: &[u8], out: &mut Vec<u8>, limit: uint) -> IoResult<()> {
+    let mut i = 0u;
+    loop {
+        if i >= src.len() { return Err(corrupt("block must end in literals")); }
+        let token = src[i];
+        i += 1;
+
+        let literals = try!(extended_length(src, &mut i, (token >> 4) as uint));
+        if i + literals > src.len() || out.len() + literals > limit {
+            return Err(corrupt("too many literals"));
+        }
+        out.push_all(src.slice(i, i + literals));
+        i += literals;
+        // The last sequence has no match.
+        if i == src.len() { return Ok(()); }
+
+        if i + 2 > src.len() { return Err(corrupt("truncated match")); }
+        let offset = src[i] as uint | (src[i + 1] as uint) << 8;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("bad match offset"));
+        }
+        let len = try!(extended_length(src, &mut i, (token & 0xf) as uint)) + 4;
+        if out.len() + len > limit { return Err(corrupt("match too long")); }
+        // Matches may overlap their own output, so copy a byte at a time.
+        for _ in range(0, len) {
+            let byte = out[out.len() - offset];
+            out.push(byte);
+        }
+    }
+}
+
+#[deriving(PartialEq)]
+enum State {
+    FrameHeader,
+    Blocks,
+    End
+}
+
+/// A `Buffer` which decompresses LZ4 frames from another `Buffer`.
+pub struct Lz4Buffer<R> {
+    input: R,
+    state: State,
+    block_checksums: bool,
+    content_hash: Option<Xxh32>,
+    block_max: uint,
+    compressed: Vec<u8>,
+    out: Vec<u8>,
+    pos: uint
+}
+
+impl<R: Buffer> Lz4Buffer<R> {
+    /// Decompress LZ4 frames from `input`.
+    pub fn new(input: R) -> Lz4Buffer<R> {
+        Lz4Buffer{input: input, state: FrameHeader, block_checksums: false,
+                  content_hash: None, block_max: 0, compressed: vec![],
+                  out: vec![], pos: 0}
+    }
+
+    /// Get back our underlying buffer.
+    pub fn unwrap(self) -> R { self.input }
+
+    fn at_eof(&mut self) -> IoResult<bool> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => { if buf.len() > 0 { return Ok(false); } }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(true); }
+                Err(err) => { return Err(err); }
+            }
+        }
+    }
+
+    // Skip `len` bytes of input.  The length comes from the stream, so we
+    // mustn't allocate a buffer that big.
+    fn skip(&mut self, mut len: uint) -> IoResult<()> {
+        while len > 0 {
+            let amt = {
+                let buf = try!(no_eof(self.input.fill_buf()));
+                min(buf.len(), len)
+            };
+            self.input.consume(amt);
+            len -= amt;
+        }
+        Ok(())
+    }
+
+    fn read_frame_header(&mut self) -> IoResult<()> {
+        let magic = try!(no_eof(self.input.read_le_u32()));
+        if magic & 0xfffffff0 == 0x184d2a50 {
+            // A skippable frame.
+            let len = try!(no_eof(self.input.read_le_u32())) as uint;
+            return self.skip(len);
+        }
+        if magic != FRAME_MAGIC { return Err(corrupt("bad frame magic")); }
+
+        let mut descriptor = try!(no_eof(self.input.read_exact(2)));
+        let flags = descriptor[0];
+        if flags >> 6 != 1 { return Err(corrupt("unsupported version")); }
+        let extra = (if flags & 0x08 != 0 { 8 } else { 0 }) +
+            (if flags & 0x01 != 0 { 4 } else { 0 });
+        let rest = try!(no_eof(self.input.read_exact(extra)));
+        descriptor.push_all(rest.as_slice());
+        let check = try!(no_eof(self.input.read_u8()));
+        if check != (xxh32(descriptor.as_slice()) >> 8) as u8 {
+            return Err(corrupt("header checksum mismatch"));
+        }
+
+        self.block_max = match (descriptor[1] >> 4) & 0x7 {
+            4 => 64 * 1024,
+            5 => 256 * 1024,
+            6 => 1024 * 1024,
+            7 => 4 * 1024 * 1024,
+            _ => { return Err(corrupt("bad maximum block size")); }
+        };
+        self.block_checksums = flags & 0x10 != 0;
+        self.content_hash =
+            if flags & 0x04 != 0 { Some(Xxh32::new()) } else { None };
+        self.out.clear();
+        self.pos = 0;
+        self.state = Blocks;
+        Ok(())
+    }
+
+    // Read the next block, returning false at the end of the frame.
+    fn read_block(&mut self) -> IoResult<bool> {
+        let size = try!(no_eof(self.input.read_le_u32()));
+        if size == 0 {
+            match self.content_hash {
+                Some(ref hash) => {
+                    let expected = try!(no_eof(self.input.read_le_u32()));
+                    if hash.digest() != expected {
+                        return Err(corrupt("content checksum mismatch"));
+                    }
+                }
+                None => {}
+            }
+            return Ok(false);
+        }
+
+        let stored = size & 0x80000000 != 0;
+        let len = (size & 0x7fffffff) as uint;
+        if len > self.block_max { return Err(corrupt("block too large")); }
+        self.compressed.clear();
+        try!(no_eof(self.input.push_at_least(len, len, &mut self.compressed)));
+        if self.block_checksums {
+            let expected = try!(no_eof(self.input.read_le_u32()));
+            if xxh32(self.compressed.as_slice()) != expected {
+                return Err(corrupt("block checksum mismatch"));
+            }
+        }
+
+        // Everything has been consumed, so we only need to keep enough
+        // history for matches in this block.
+        if self.out.len() > WINDOW_SIZE {
+            let drop = self.out.len() - WINDOW_SIZE;
+            for i in range(0, WINDOW_SIZE) {
+                let byte = self.out[drop + i];
+                self.out.as_mut_slice()[i] = byte;
+            }
+            self.out.truncate(WINDOW_SIZE);
+        }
+        let start = self.out.len();
+        if stored {
+            self.out.push_all(self.compressed.as_slice());
+        } else {
+            try!(decode_block(self.compressed.as_slice(), &mut self.out,
+                              start + self.block_max));
+        }
+        self.pos = start;
+        match self.content_hash {
+            Some(ref mut hash) => { hash.update(self.out.slice_from(start)); }
+            None => {}
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Buffer> Reader for Lz4Buffer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(data.len(), buf.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: Buffer> Buffer for Lz4Buffer<R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        while self.pos == self.out.len() {
+            match self.state {
+                End => { return Err(standard_error(EndOfFile)); }
+                FrameHeader => {
+                    if try!(self.at_eof()) {
+                        self.state = End;
+                    } else {
+                        try!(self.read_frame_header());
+                    }
+                }
+                Blocks => {
+                    if !try!(self.read_block()) {
+                        self.state = FrameHeader;
+                    }
+                }
+            }
+        }
+        Ok(self.out.slice_from(self.pos))
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.pos += amt;
+        assert!(self.pos <= self.out.len());
+    }
+}
+
+#[test]
+fn xxh32_matches_reference() {
+    assert_eq!(0x02cc5d05, xxh32(b""));
+    let data: Vec<u8> = range(0u, 100).map(|n| n as u8).collect();
+    let mut hash = Xxh32::new();
+    for chunk in data.as_slice().chunks(7) { hash.update(chunk); }
+    assert_eq!(xxh32(data.as_slice()), hash.digest());
+}
+
+#[test]
+fn decompresses_lz4_frames() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.lz4"))
+        .read_to_end().unwrap();
+    let mut reader = MemReader::new(compressed);
+    let mut lz4 = Lz4Buffer::new(DribbleBuffer::new(&mut reader));
+    assert_eq!(expected, lz4.read_to_end().unwrap());
+}
+
+#[test]
+fn reads_through_concatenated_and_skippable_frames() {
+    let frame = File::open(&Path::new("test_data/fr/sample.conllx.lz4"))
+        .read_to_end().unwrap();
+    let mut compressed = frame.clone();
+    compressed.push_all(&[0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+    compressed.push_all(frame.as_slice());
+    let mut lz4 = Lz4Buffer::new(MemReader::new(compressed));
+    assert_eq!(4367 * 2, lz4.read_to_end().unwrap().len());
+}
+
+#[test]
+fn rejects_corrupt_lz4() {
+    let mut compressed =
+        File::open(&Path::new("test_data/fr/sample.conllx.lz4"))
+        .read_to_end().unwrap();
+    compressed.as_mut_slice()[200] ^= 0x01;
+    let mut lz4 = Lz4Buffer::new(MemReader::new(compressed));
+    assert!(lz4.read_to_end().is_err());
+}
+
+#[test]
+fn rejects_truncated_skippable_frames() {
+    let compressed = vec![0x50, 0x2a, 0x4d, 0x18, 0xff, 0xff, 0xff, 0xff, 1];
+    let mut lz4 = Lz4Buffer::new(MemReader::new(compressed));
+    assert!(lz4.read_to_end().is_err());
+}
+
+#[test]
+fn survives_truncated_and_damaged_streams() {
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.lz4"))
+        .read_to_end().unwrap();
+    check_damaged_streams(compressed.as_slice(), |data| {
+        Lz4Buffer::new(MemReader::new(data)).read_to_end()
+    });
+}
@@ -0,0 +1,562 @@
+//! Checksum manifests, computed as a side effect of processing.
+//!
+//! After copying a large dataset, people often read every file a second
+//! time just to check its checksum.  If you're going to process the data
+//! anyway, wrap your input in a `DigestReader` and the checksum comes for
+//! free.  Digests can be saved in a `Manifest` on the sending side, and
+//! checked against it on the receiving side.
+//!
+//! Each file can optionally be digested in fixed-size chunks as well, so
+//! a failed check says which part of the file was damaged, and only that
+//! part needs to be sent again.  Manifests are plain text:
+//!
+//! ```text
+//! file <sha256> <size> <name>
+//! chunks <chunk size> <sha256> <sha256> ...
+//! ```
+//!
+//! The `chunks` line is only present for files digested in chunks.
+
+use std::cmp::min;
+use std::io::{Buffer,BufferedReader,File,InvalidInput,IoError,IoResult,
+              OtherIoError};
+
+use sinks::SafeFileWriter;
+
+#[cfg(test)] use std::io::{BufReader,MemReader,MemWriter,TempDir};
+#[cfg(test)] use std::rand::{Rng,SeedableRng,XorShiftRng};
+#[cfg(test)] use buffers::DribbleBuffer;
+
+static K: [u32, ..64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+fn rotr(x: u32, r: uint) -> u32 { x >> r | x << (32 - r) }
+
+/// Computes SHA-256 digests.
+pub struct Sha256 {
+    state: [u32, ..8],
+    block: [u8, ..64],
+    block_len: uint,
+    len: u64
+}
+
+impl Sha256 {
+    /// Start a new digest.
+    pub fn new() -> Sha256 {
+        Sha256{state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                       0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+               block: [0, ..64], block_len: 0, len: 0}
+    }
+
+    /// Add `data` to the digest.
+    pub fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        let mut data = data;
+        while !data.is_empty() {
+            let count = min(64 - self.block_len, data.len());
+            self.block.slice_mut(self.block_len, self.block_len + count)
+                .clone_from_slice(data.slice_to(count));
+            self.block_len += count;
+            data = data.slice_from(count);
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// The digest of all the data so far.
+    pub fn digest(&self) -> [u8, ..32] {
+        let bits = self.len * 8;
+        let mut sha = self.clone();
+        sha.update(&[0x80]);
+        while sha.block_len != 56 { sha.update(&[0]); }
+        let mut len = [0u8, ..8];
+        for i in range(0u, 8) { len[i] = (bits >> (56 - 8 * i)) as u8; }
+        sha.update(&len);
+        let mut digest = [0u8, ..32];
+        for i in range(0u, 32) {
+            digest[i] = (sha.state[i / 4] >> (24 - 8 * (i % 4))) as u8;
+        }
+        digest
+    }
+
+    // Mix a full block into our state.
+    fn compress(&mut self) {
+        let mut w = [0u32, ..64];
+        for i in range(0u, 16) {
+            let b = self.block.slice(4 * i, 4 * i + 4);
+            w[i] = b[0] as u32 << 24 | b[1] as u32 << 16 | b[2] as u32 << 8 |
+                b[3] as u32;
+        }
+        for i in range(16u, 64) {
+            let s0 = rotr(w[i-15], 7) ^ rotr(w[i-15], 18) ^ (w[i-15] >> 3);
+            let s1 = rotr(w[i-2], 17) ^ rotr(w[i-2], 19) ^ (w[i-2] >> 10);
+            w[i] = w[i-16] + s0 + w[i-7] + s1;
+        }
+        let mut h = self.state;
+        for i in range(0u, 64) {
+            let s1 = rotr(h[4], 6) ^ rotr(h[4], 11) ^ rotr(h[4], 25);
+            let choose = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let t1 = h[7] + s1 + choose + K[i] + w[i];
+            let s0 = rotr(h[0], 2) ^ rotr(h[0], 13) ^ rotr(h[0], 22);
+            let majority = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            h = [t1 + s0 + majority, h[0], h[1], h[2], h[3] + t1, h[4], h[5],
+                 h[6]];
+        }
+        for i in range(0u, 8) { self.state[i] += h[i]; }
+    }
+}
+
+impl Clone for Sha256 {
+    fn clone(&self) -> Sha256 {
+        Sha256{state: self.state, block: self.block,
+               block_len: self.block_len, len: self.len}
+    }
+}
+
+/// The size and digests of a single file.
+#[deriving(PartialEq, Clone, Show)]
+pub struct FileDigest {
+    /// The file's size, in bytes.
+    pub size: u64,
+    /// The SHA-256 digest of the whole file.
+    pub sha256: Vec<u8>,
+    /// The size of each chunk, if the file was digested in chunks.
+    pub chunk_size: Option<u64>,
+    /// The SHA-256 digest of each chunk.  The last chunk may be short.
+    pub chunks: Vec<Vec<u8>>
+}
+
+/// Digests data as you feed it in, optionally in fixed-size chunks.
+#[deriving(Clone)]
+pub struct Digester {
+    file: Sha256,
+    size: u64,
+    chunk_size: Option<u64>,
+    chunk: Sha256,
+    chunk_len: u64,
+    chunks: Vec<Vec<u8>>
+}
+
+impl Digester {
+    /// Digest data as a whole.
+    pub fn new() -> Digester {
+        Digester{file: Sha256::new(), size: 0, chunk_size: None,
+                 chunk: Sha256::new(), chunk_len: 0, chunks: vec![]}
+    }
+
+    /// Digest data as a whole, and in chunks of `chunk_size` bytes.
+    pub fn with_chunks(chunk_size: u64) -> Digester {
+        assert!(chunk_size > 0);
+        Digester{chunk_size: Some(chunk_size), ..Digester::new()}
+    }
+
+    /// Add `data` to our digests.
+    pub fn update(&mut self, data: &[u8]) {
+        self.file.update(data);
+        self.size += data.len() as u64;
+        let chunk_size = match self.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None => { return; }
+        };
+        let mut data = data;
+        while !data.is_empty() {
+            let room = chunk_size - self.chunk_len;
+            let count = if room < data.len() as u64 {
+                room as uint
+            } else {
+                data.len()
+            };
+            self.chunk.update(data.slice_to(count));
+            self.chunk_len += count as u64;
+            data = data.slice_from(count);
+            if self.chunk_len == chunk_size {
+                self.chunks.push(self.chunk.digest().as_slice().to_vec());
+                self.chunk = Sha256::new();
+                self.chunk_len = 0;
+            }
+        }
+    }
+
+    /// The digests of all the data so far.
+    pub fn digest(&self) -> FileDigest {
+        let mut chunks = self.chunks.clone();
+        if self.chunk_len > 0 {
+            chunks.push(self.chunk.digest().as_slice().to_vec());
+        }
+        FileDigest{size: self.size,
+                   sha256: self.file.digest().as_slice().to_vec(),
+                   chunk_size: self.chunk_size, chunks: chunks}
+    }
+}
+
+/// Digests everything read from `input`.  Works as either a `Reader` or,
+/// if `input` is one, a `Buffer`.
+pub struct DigestReader<R> {
+    input: R,
+    digester: Digester
+}
+
+impl<R: Reader> DigestReader<R> {
+    /// Digest everything read from `input` using `digester`.
+    pub fn new(input: R, digester: Digester) -> DigestReader<R> {
+        DigestReader{input: input, digester: digester}
+    }
+
+    /// The digests of everything read so far.
+    pub fn digest(&self) -> FileDigest { self.digester.digest() }
+
+    /// Get back our underlying input.
+    pub fn unwrap(self) -> R { self.input }
+}
+
+impl<R: Reader> Reader for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = try!(self.input.read(buf));
+        self.digester.update(buf.slice_to(count));
+        Ok(count)
+    }
+}
+
+impl<R: Buffer> Buffer for DigestReader<R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amt: uint) {
+        // The bytes are still in `input`'s buffer, so this doesn't read
+        // anything new.
+        match self.input.fill_buf() {
+            Ok(buf) => self.digester.update(buf.slice_to(amt)),
+            Err(_) => {}
+        }
+        self.input.consume(amt);
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for &b in bytes.iter() { hex.push_str(format!("{:02x}", b).as_slice()); }
+    hex
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    if b >= b'0' && b <= b'9' {
+        Some(b - b'0')
+    } else if b >= b'a' && b <= b'f' {
+        Some(b - b'a' + 10)
+    } else {
+        None
+    }
+}
+
+// Parse a SHA-256 digest written in hex.
+fn sha256_from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.as_bytes();
+    if hex.len() != 64 { return None; }
+    let mut bytes = vec![];
+    for pair in hex.chunks(2) {
+        match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(high), Some(low)) => bytes.push(high << 4 | low),
+            _ => { return None; }
+        }
+    }
+    Some(bytes)
+}
+
+// Split off the first space-separated word of `text`.
+fn split_word<'a>(text: &'a str) -> (&'a str, &'a str) {
+    match text.find(' ') {
+        Some(i) => (text.slice_to(i), text.slice_from(i + 1)),
+        None => (text, "")
+    }
+}
+
+// Parse the rest of a `chunks` line into `digest`, checking that there's
+// a digest for every chunk of the file.
+fn parse_chunks(text: &str, digest: &mut FileDigest) -> bool {
+    let mut words = text.split(' ');
+    let chunk_size = match words.next().and_then(|w| from_str::<u64>(w)) {
+        Some(chunk_size) if chunk_size > 0 => chunk_size,
+        _ => { return false; }
+    };
+    digest.chunk_size = Some(chunk_size);
+    for word in words {
+        match sha256_from_hex(word) {
+            Some(chunk) => { digest.chunks.push(chunk); }
+            None => { return false; }
+        }
+    }
+    let partial = if digest.size % chunk_size > 0 { 1 } else { 0 };
+    digest.chunks.len() as u64 == digest.size / chunk_size + partial
+}
+
+fn corrupt_manifest(line: &str) -> IoError {
+    IoError{kind: InvalidInput, desc: "corrupt manifest",
+            detail: Some(line.to_string())}
+}
+
+fn mismatch(desc: &'static str, detail: String) -> IoError {
+    IoError{kind: OtherIoError, desc: desc, detail: Some(detail)}
+}
+
+/// The expected digests of a set of files.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Manifest {
+    entries: Vec<(String, FileDigest)>
+}
+
+impl Manifest {
+    /// Create an empty manifest.
+    pub fn new() -> Manifest {
+        Manifest{entries: vec![]}
+    }
+
+    /// Record `digest` for the file `name`, replacing any earlier entry.
+    pub fn add(&mut self, name: &str, digest: FileDigest) {
+        let entry = (name.to_string(), digest);
+        match self.entries.iter().position(|&(ref n, _)| n.as_slice() == name) {
+            Some(i) => { self.entries.as_mut_slice()[i] = entry; }
+            None => { self.entries.push(entry); }
+        }
+    }
+
+    /// The expected digest of the file `name`, if we have one.
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a FileDigest> {
+        self.entries.iter().find(|&&(ref n, _)| n.as_slice() == name)
+            .map(|&(_, ref digest)| digest)
+    }
+
+    /// Check `actual` against the expected digest for the file `name`.
+    /// If both were digested in the same size chunks, the error says which
+    /// chunk is damaged.
+    pub fn verify(&self, name: &str, actual: &FileDigest) -> IoResult<()> {
+        let expected = match self.get(name) {
+            Some(expected) => expected,
+            None => {
+                return Err(IoError{kind: InvalidInput,
+                                   desc: "file not in manifest",
+                                   detail: Some(name.to_string())});
+            }
+        };
+        if expected.size != actual.size {
+            return Err(mismatch("size mismatch",
+                                format!("{}: expected {} bytes, found {}",
+                                        name, expected.size, actual.size)));
+        }
+        if expected.chunk_size.is_some() &&
+            expected.chunk_size == actual.chunk_size {
+            let chunk_size = expected.chunk_size.unwrap();
+            let chunks = expected.chunks.iter().zip(actual.chunks.iter());
+            for (i, (e, a)) in chunks.enumerate() {
+                if e != a {
+                    let start = i as u64 * chunk_size;
+                    let end = min(start + chunk_size, actual.size);
+                    let detail = format!("{}: chunk {} (bytes {} to {})",
+                                         name, i, start, end);
+                    return Err(mismatch("checksum mismatch", detail));
+                }
+            }
+        }
+        if expected.sha256 != actual.sha256 {
+            return Err(mismatch("checksum mismatch", name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Write this manifest to `out`.
+    pub fn write(&self, out: &mut Writer) -> IoResult<()> {
+        for &(ref name, ref digest) in self.entries.iter() {
+            let sha256 = to_hex(digest.sha256.as_slice());
+            try!(write!(out, "file {} {} {}\n", sha256, digest.size, name));
+            match digest.chunk_size {
+                Some(chunk_size) => {
+                    try!(write!(out, "chunks {}", chunk_size));
+                    for chunk in digest.chunks.iter() {
+                        try!(write!(out, " {}", to_hex(chunk.as_slice())));
+                    }
+                    try!(out.write(b"\n"));
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a manifest from `input`.  Every line must be complete, so a
+    /// truncated manifest is rejected unless it was cut between lines.
+    pub fn read<B: Buffer>(input: &mut B) -> IoResult<Manifest> {
+        let mut manifest = Manifest::new();
+        for line in input.lines() {
+            let line = try!(line);
+            if !line.as_slice().ends_with("\n") {
+                return Err(corrupt_manifest(line.as_slice()));
+            }
+            let line = line.as_slice().trim_right_chars('\n');
+            let (kind, rest) = split_word(line);
+            match kind {
+                "file" => {
+                    let (sha256, rest) = split_word(rest);
+                    let (size, name) = split_word(rest);
+                    match (sha256_from_hex(sha256), from_str::<u64>(size)) {
+                        (Some(sha256), Some(size)) if !name.is_empty() => {
+                            let digest = FileDigest{size: size, sha256: sha256,
+                                                    chunk_size: None,
+                                                    chunks: vec![]};
+                            manifest.entries.push((name.to_string(), digest));
+                        }
+                        _ => { return Err(corrupt_manifest(line)); }
+                    }
+                }
+                "chunks" => {
+                    let parsed = match manifest.entries.last_mut() {
+                        Some(entry) => {
+                            let (_, ref mut digest) = *entry;
+                            parse_chunks(rest, digest)
+                        }
+                        None => false
+                    };
+                    if !parsed { return Err(corrupt_manifest(line)); }
+                }
+                _ => { return Err(corrupt_manifest(line)); }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Atomically save this manifest to `path`.
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let mut writer = try!(SafeFileWriter::create(path));
+        try!(self.write(&mut writer));
+        writer.commit()
+    }
+
+    /// Load a manifest from `path`.
+    pub fn load(path: &Path) -> IoResult<Manifest> {
+        let file = try!(File::open(path));
+        Manifest::read(&mut BufferedReader::new(file))
+    }
+}
+
+#[test]
+fn sha256_matches_reference_values() {
+    let sha256 = |data: &[u8]| {
+        let mut sha = Sha256::new();
+        sha.update(data);
+        to_hex(&sha.digest())
+    };
+    assert_eq!(concat!("ba7816bf8f01cfea414140de5dae2223",
+                       "b00361a396177a9cb410ff61f20015ad"),
+               sha256(b"abc").as_slice());
+    let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    assert_eq!(concat!("248d6a61d20638b8e5c026930c3e6039",
+                       "a33ce45964ff2167f6ecedd419db06c1"),
+               sha256(long).as_slice());
+}
+
+#[test]
+fn digests_while_reading_and_verifies() {
+    let path = Path::new("test_data/fr/sample.conllx");
+    let data = File::open(&path).read_to_end().unwrap();
+    let mut input = BufReader::new(data.as_slice());
+    let mut reader = DigestReader::new(DribbleBuffer::new(&mut input),
+                                       Digester::with_chunks(1000));
+    let mut lines = 0u;
+    for line in reader.lines() { line.unwrap(); lines += 1; }
+    assert!(lines > 0);
+    let digest = reader.digest();
+    assert_eq!(data.len() as u64, digest.size);
+    assert_eq!((data.len() + 999) / 1000, digest.chunks.len());
+
+    let mut whole = Sha256::new();
+    whole.update(data.as_slice());
+    assert_eq!(whole.digest().as_slice().to_vec(), digest.sha256);
+
+    let dir = TempDir::new("manifest").unwrap();
+    let manifest_path = dir.path().join("MANIFEST");
+    let mut manifest = Manifest::new();
+    manifest.add("sample.conllx", digest.clone());
+    manifest.save(&manifest_path).unwrap();
+    let manifest = Manifest::load(&manifest_path).unwrap();
+    manifest.verify("sample.conllx", &digest).unwrap();
+    assert!(manifest.verify("other.conllx", &digest).is_err());
+
+    let mut damaged = data.clone();
+    damaged.as_mut_slice()[2500] ^= 1;
+    let mut reader = DigestReader::new(MemReader::new(damaged),
+                                       Digester::with_chunks(1000));
+    reader.read_to_end().unwrap();
+    let err = manifest.verify("sample.conllx", &reader.digest()).unwrap_err();
+    assert_eq!(Some("sample.conllx: chunk 2 (bytes 2000 to 3000)".to_string()),
+               err.detail);
+}
+
+#[test]
+fn rejects_corrupt_manifests() {
+    assert!(Manifest::read(&mut BufReader::new(b"file abc 12 x\n")).is_err());
+    assert!(Manifest::read(&mut BufReader::new(b"chunks 10\n")).is_err());
+}
+
+#[test]
+fn sha256_is_independent_of_how_data_is_split() {
+    let data: Vec<u8> = range(0u, 1000).map(|n| (n * 7) as u8).collect();
+    let mut whole = Sha256::new();
+    whole.update(data.as_slice());
+    let mut rng: XorShiftRng = SeedableRng::from_seed([5, 6, 7, 8]);
+    for _ in range(0u, 50) {
+        let mut sha = Sha256::new();
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let count = rng.gen_range(0, min(rest.len(), 150) + 1);
+            sha.update(rest.slice_to(count));
+            rest = rest.slice_from(count);
+        }
+        assert_eq!(whole.digest().as_slice().to_vec(),
+                   sha.digest().as_slice().to_vec());
+    }
+}
+
+#[test]
+fn rejects_truncated_and_damaged_manifests() {
+    let mut digester = Digester::with_chunks(100);
+    digester.update(&[7, ..250]);
+    let mut manifest = Manifest::new();
+    manifest.add("a", digester.digest());
+    manifest.add("b", Digester::new().digest());
+    let mut out = MemWriter::new();
+    manifest.write(&mut out).unwrap();
+    let text = out.unwrap();
+    assert_eq!(manifest,
+               Manifest::read(&mut BufReader::new(text.as_slice())).unwrap());
+
+    for cut in range(1, text.len()) {
+        let result = Manifest::read(&mut BufReader::new(text.slice_to(cut)));
+        assert!(result.is_err() == (text[cut - 1] != b'\n'),
+                "cut at {}", cut);
+    }
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    for _ in range(0u, 200) {
+        let mut damaged = text.clone();
+        let i = rng.gen_range(0, damaged.len());
+        damaged.as_mut_slice()[i] = rng.gen();
+        let _ = Manifest::read(&mut BufReader::new(damaged.as_slice()));
+    }
+
+    let zeros = "00".repeat(32);
+    let too_few = format!("file {} 250 a\nchunks 100 {}\n", zeros, zeros);
+    assert!(Manifest::read(&mut BufReader::new(too_few.as_bytes())).is_err());
+    let too_long = format!("file {}00 250 a\n", zeros);
+    assert!(Manifest::read(&mut BufReader::new(too_long.as_bytes())).is_err());
+}
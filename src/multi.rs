@@ -0,0 +1,141 @@
+//! Reading several streams as one.
+//!
+//! When a process tails several live logs, it wants a single stream of
+//! records, each labelled with where it came from.  `MultiReader` takes
+//! one line from each input in turn, round-robin, and tags it with the
+//! index of its input.  Inputs which run out, or fail, drop out of the
+//! rotation, and the others carry on.
+//!
+//! Round-robin means a slow input holds up the others while we wait for
+//! its next line.  For pipes and sockets which may go quiet for a while,
+//! give each its own task and feed the results through a channel instead.
+
+use std::io::{Buffer,IoError,IoResult};
+
+use buffers::read_until_into;
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::{BufReader,InvalidInput,standard_error};
+
+/// A record, and the index of the input it came from.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Tagged<'a> {
+    /// The index of the input, in the order passed to `add`.
+    pub stream: uint,
+    /// The line, without its newline.
+    pub record: &'a [u8]
+}
+
+/// Reads lines from several inputs in rotation.
+pub struct MultiReader<'b> {
+    // Inputs which haven't finished yet, with their indices.
+    inputs: Vec<(uint, Box<Buffer+'b>)>,
+    count: uint,
+    next: uint,
+    line: Vec<u8>
+}
+
+impl<'b> MultiReader<'b> {
+    /// Create a reader with no inputs.
+    pub fn new() -> MultiReader<'b> {
+        MultiReader{inputs: vec![], count: 0, next: 0, line: vec![]}
+    }
+
+    /// Add `input` to the rotation, returning the index its records will
+    /// be tagged with.
+    pub fn add(&mut self, input: Box<Buffer+'b>) -> uint {
+        let index = self.count;
+        self.inputs.push((index, input));
+        self.count += 1;
+        index
+    }
+
+    /// How many inputs are still being read.
+    pub fn live_inputs(&self) -> uint { self.inputs.len() }
+}
+
+impl<'a, 'b> StreamingIterator<'a, Result<Tagged<'a>, (uint, IoError)>>
+    for MultiReader<'b> {
+
+    /// Returns the next line from the next input in turn.  If an input
+    /// fails, we return its error, tagged with its index, and stop reading
+    /// from it.
+    fn next(&'a mut self) -> Option<Result<Tagged<'a>, (uint, IoError)>> {
+        while !self.inputs.is_empty() {
+            if self.next >= self.inputs.len() { self.next = 0; }
+            let i = self.next;
+            self.line.clear();
+            let result = {
+                let (_, ref mut input) = self.inputs.as_mut_slice()[i];
+                read_until_into(&mut **input, b'\n', &mut self.line)
+            };
+            let (stream, _) = self.inputs[i];
+            match result {
+                Ok(true) => {
+                    self.next = i + 1;
+                    if self.line.as_slice().ends_with(b"\n") {
+                        let len = self.line.len() - 1;
+                        self.line.truncate(len);
+                    }
+                    return Some(Ok(Tagged{stream: stream,
+                                          record: self.line.as_slice()}));
+                }
+                // Leave `next` alone, since the following input will slide
+                // into this slot.
+                Ok(false) => { self.inputs.remove(i); }
+                Err(err) => {
+                    self.inputs.remove(i);
+                    return Some(Err((stream, err)));
+                }
+            }
+        }
+        None
+    }
+}
+
+// Fails after returning `data`.
+#[cfg(test)]
+struct Broken<'a> {
+    data: BufReader<'a>
+}
+
+#[cfg(test)]
+impl<'a> Reader for Broken<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.data.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl<'a> Buffer for Broken<'a> {
+    fn fill_buf<'b>(&'b mut self) -> IoResult<&'b [u8]> {
+        match self.data.fill_buf() {
+            Ok(data) => Ok(data),
+            Err(_) => Err(standard_error(InvalidInput))
+        }
+    }
+
+    fn consume(&mut self, amt: uint) { self.data.consume(amt) }
+}
+
+#[test]
+fn multi_reader_tags_and_rotates() {
+    let mut multi = MultiReader::new();
+    assert_eq!(0, multi.add(box BufReader::new(b"a1\na2\na3\n")
+                            as Box<Buffer>));
+    assert_eq!(1, multi.add(box BufReader::new(b"b1") as Box<Buffer>));
+    assert_eq!(2, multi.add(box Broken{data: BufReader::new(b"c1\n")}
+                            as Box<Buffer>));
+    let mut seen = vec![];
+    streaming_for!(item in multi, {
+        seen.push(match item {
+            Ok(Tagged{stream, record}) => Ok((stream, record.to_vec())),
+            Err((stream, err)) => Err((stream, err.kind))
+        });
+    });
+    assert_eq!(vec![Ok((0, b"a1".to_vec())), Ok((1, b"b1".to_vec())),
+                    Ok((2, b"c1".to_vec())), Ok((0, b"a2".to_vec())),
+                    Err((2, InvalidInput)), Ok((0, b"a3".to_vec()))],
+               seen);
+    assert_eq!(0, multi.live_inputs());
+}
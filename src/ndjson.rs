@@ -0,0 +1,248 @@
+//! Pulling fields out of newline-delimited JSON without building a tree.
+//!
+//! For NDJSON, each line is a complete JSON document.  Most of the time we
+//! only want a handful of fields from each one, so `PathExtractor` scans
+//! each line once, notes where the values at some dotted paths (like
+//! `user.name`) live, and hands back a flat record of slices.  String
+//! values are returned without their quotes, but escapes are left alone.
+//! Other values (including objects and arrays) are returned as raw JSON.
+
+use std::io::{Buffer,InvalidInput,IoError,IoResult};
+
+use buffers::read_until_into;
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::io::MemReader;
+
+/// The values found at each of our paths in one NDJSON line.
+pub struct PathRecord<'a> {
+    line: &'a [u8],
+    spans: &'a [Option<(uint, uint)>]
+}
+
+impl<'a> PathRecord<'a> {
+    /// How many paths are we extracting?
+    pub fn len(&self) -> uint { self.spans.len() }
+
+    /// The value at the `i`th path, or `None` if this line doesn't have
+    /// one.
+    pub fn get(&self, i: uint) -> Option<&'a [u8]> {
+        self.spans[i].map(|(start, end)| self.line.slice(start, end))
+    }
+
+    /// All our fields, in order, with missing fields as empty slices.
+    /// This is the shape a CSV writer wants.
+    pub fn fields(&self) -> Vec<&'a [u8]> {
+        range(0, self.len()).map(|i| self.get(i).unwrap_or(&[])).collect()
+    }
+}
+
+/// Yields a `PathRecord` for each line of `input`.
+pub struct PathExtractor<'a, T: Buffer+'a> {
+    input: &'a mut T,
+    paths: Vec<Vec<Vec<u8>>>,
+    line: Vec<u8>,
+    spans: Vec<Option<(uint, uint)>>
+}
+
+impl<'a, T: Buffer+'a> PathExtractor<'a, T> {
+    /// Extract the values at each of `paths` (like `"user.name"`) from
+    /// each line of `input`.
+    pub fn new(input: &'a mut T, paths: &[&str]) -> PathExtractor<'a, T> {
+        let paths: Vec<Vec<Vec<u8>>> = paths.iter().map(|p| {
+            p.split('.').map(|s| s.as_bytes().to_vec()).collect()
+        }).collect();
+        let spans = Vec::from_elem(paths.len(), None);
+        PathExtractor{input: input, paths: paths, line: vec![],
+                      spans: spans}
+    }
+}
+
+impl<'a, 'b, T: Buffer+'b> StreamingIterator<'a, IoResult<PathRecord<'a>>>
+    for PathExtractor<'b, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<PathRecord<'a>>> {
+        loop {
+            self.line.clear();
+            match read_until_into(self.input, b'\n', &mut self.line) {
+                Ok(true) => {}
+                Ok(false) => { return None; }
+                Err(err) => { return Some(Err(err)); }
+            }
+            for span in self.spans.iter_mut() { *span = None; }
+            // The scanner borrows `spans` mutably, so it has to be gone
+            // before we can return them.
+            {
+                let mut scanner = Scanner{line: self.line.as_slice(), pos: 0,
+                                          path: vec![],
+                                          targets: self.paths.as_slice(),
+                                          spans: self.spans.as_mut_slice()};
+                scanner.skip_space();
+                // Skip blank lines.
+                if scanner.pos == scanner.line.len() { continue; }
+                match scanner.value() {
+                    Ok(()) => {}
+                    Err(err) => { return Some(Err(err)); }
+                }
+            }
+            return Some(Ok(PathRecord{line: self.line.as_slice(),
+                                      spans: self.spans.as_slice()}));
+        }
+    }
+}
+
+// Walks a single JSON document, recording the locations of values at our
+// target paths.
+struct Scanner<'a> {
+    line: &'a [u8],
+    pos: uint,
+    // The keys leading to the current value.
+    path: Vec<&'a [u8]>,
+    targets: &'a [Vec<Vec<u8>>],
+    spans: &'a mut [Option<(uint, uint)>]
+}
+
+fn syntax_error(detail: &str) -> IoError {
+    IoError{kind: InvalidInput, desc: "invalid JSON",
+            detail: Some(detail.to_string())}
+}
+
+impl<'a> Scanner<'a> {
+    fn skip_space(&mut self) {
+        while self.pos < self.line.len() &&
+            (self.line[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        if self.pos < self.line.len() {
+            Some(self.line[self.pos])
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> IoResult<()> {
+        self.skip_space();
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(syntax_error("unexpected character"))
+        }
+    }
+
+    // Scan a value, and record it if it's at one of our target paths.
+    fn value(&mut self) -> IoResult<()> {
+        self.skip_space();
+        let start = self.pos;
+        let span = match self.peek() {
+            Some(b'{') => { try!(self.object()); (start, self.pos) }
+            Some(b'[') => { try!(self.array()); (start, self.pos) }
+            Some(b'"') => { try!(self.string()); (start + 1, self.pos - 1) }
+            Some(_) => {
+                while self.pos < self.line.len() {
+                    match self.line[self.pos] {
+                        b',' | b'}' | b']' => { break; }
+                        b if (b as char).is_whitespace() => { break; }
+                        _ => { self.pos += 1; }
+                    }
+                }
+                if self.pos == start {
+                    return Err(syntax_error("expected a value"));
+                }
+                (start, self.pos)
+            }
+            None => { return Err(syntax_error("unexpected end of line")); }
+        };
+        for (i, target) in self.targets.iter().enumerate() {
+            if target.len() == self.path.len() &&
+                target.iter().zip(self.path.iter())
+                    .all(|(t, p)| t.as_slice() == *p) {
+                self.spans[i] = Some(span);
+            }
+        }
+        Ok(())
+    }
+
+    // Scan a string, and return its contents without the quotes.
+    fn string(&mut self) -> IoResult<&'a [u8]> {
+        try!(self.expect(b'"'));
+        let start = self.pos;
+        let mut escaped = false;
+        while self.pos < self.line.len() {
+            let b = self.line[self.pos];
+            self.pos += 1;
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                return Ok(self.line.slice(start, self.pos - 1));
+            }
+        }
+        Err(syntax_error("unterminated string"))
+    }
+
+    fn object(&mut self) -> IoResult<()> {
+        try!(self.expect(b'{'));
+        self.skip_space();
+        if self.peek() == Some(b'}') { self.pos += 1; return Ok(()); }
+        loop {
+            self.skip_space();
+            let key = try!(self.string());
+            try!(self.expect(b':'));
+            self.path.push(key);
+            try!(self.value());
+            self.path.pop();
+            self.skip_space();
+            match self.peek() {
+                Some(b',') => { self.pos += 1; }
+                Some(b'}') => { self.pos += 1; return Ok(()); }
+                _ => { return Err(syntax_error("expected ',' or '}'")); }
+            }
+        }
+    }
+
+    fn array(&mut self) -> IoResult<()> {
+        try!(self.expect(b'['));
+        self.skip_space();
+        if self.peek() == Some(b']') { self.pos += 1; return Ok(()); }
+        // Array elements can't be named by a dotted path, so hide them
+        // from our targets.
+        let saved = self.path.len();
+        self.path.push(b"[]");
+        loop {
+            try!(self.value());
+            self.skip_space();
+            match self.peek() {
+                Some(b',') => { self.pos += 1; }
+                Some(b']') => { self.pos += 1; break; }
+                _ => { return Err(syntax_error("expected ',' or ']'")); }
+            }
+        }
+        self.path.truncate(saved);
+        Ok(())
+    }
+}
+
+#[test]
+fn extracts_dotted_paths() {
+    let text = "{\"id\": 1, \"user\": {\"name\": \"Ann\", \
+                \"tags\": [1, {\"x\": 2}]}}\n\
+                \n\
+                {\"user\": {\"name\": \"B\\\"ob\"}, \"extra\": null}\n";
+    let mut reader = MemReader::new(text.as_bytes().to_vec());
+    let mut extractor =
+        PathExtractor::new(&mut reader, &["id", "user.name", "user.tags"]);
+    let mut rows = vec![];
+    streaming_for!(record in extractor, {
+        let fields = record.unwrap().fields();
+        rows.push(fields.iter().map(|f| f.to_vec()).collect::<Vec<Vec<u8>>>())
+    });
+    assert_eq!(vec![vec![b"1".to_vec(), b"Ann".to_vec(),
+                         b"[1, {\"x\": 2}]".to_vec()],
+                    vec![b"".to_vec(), b"B\\\"ob".to_vec(), b"".to_vec()]],
+               rows);
+}
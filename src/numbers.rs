@@ -0,0 +1,181 @@
+//! Parsing numbers the way people actually write them.
+//!
+//! Financial CSVs are full of values like `$1,234.50`, `(12.00)` and
+//! `1.234,50 €`.  A `NumberFormat` describes one such convention, and
+//! parses fields written in it without allocating.
+//!
+//! Thousands separators are only accepted between groups of three digits,
+//! so `1,5` isn't silently read as fifteen when you expected a decimal
+//! comma.
+
+use std::str::from_utf8;
+
+/// How numbers are written in some locale or report.
+#[deriving(PartialEq, Clone, Show)]
+pub struct NumberFormat {
+    /// Separates groups of thousands, if allowed.
+    pub thousands: Option<u8>,
+    /// Separates the integer part from the fraction.
+    pub decimal: u8,
+    /// Currency symbols or codes which may appear before or after the
+    /// number, such as `$` or `EUR`.
+    pub currencies: Vec<String>,
+    /// Do parentheses around a number make it negative, as accountants
+    /// like to write?
+    pub parens_negative: bool
+}
+
+impl NumberFormat {
+    /// Plain numbers, like `-1234.5`.
+    pub fn plain() -> NumberFormat {
+        NumberFormat{thousands: None, decimal: b'.', currencies: vec![],
+                     parens_negative: false}
+    }
+
+    /// US-style amounts, like `$1,234.50` or `(1,234.50)`.
+    pub fn us() -> NumberFormat {
+        NumberFormat{thousands: Some(b','), decimal: b'.',
+                     currencies: vec!["$".to_string(), "USD".to_string()],
+                     parens_negative: true}
+    }
+
+    /// Continental European amounts, like `1.234,50 €`.
+    pub fn european() -> NumberFormat {
+        NumberFormat{thousands: Some(b'.'), decimal: b',',
+                     currencies: vec!["€".to_string(), "EUR".to_string()],
+                     parens_negative: true}
+    }
+
+    /// Also accept the currency symbol `symbol`.
+    pub fn with_currency(mut self, symbol: &str) -> NumberFormat {
+        self.currencies.push(symbol.to_string());
+        self
+    }
+
+    /// Parse `field` as a floating point number.
+    pub fn parse_f64(&self, field: &[u8]) -> Option<f64> {
+        // Longer fields can't be sensible numbers.
+        let mut buf = [0u8, ..64];
+        self.normalize(field, &mut buf).and_then(|(len, _)| {
+            from_utf8(buf.slice_to(len)).and_then(|s| from_str::<f64>(s))
+        })
+    }
+
+    /// Parse `field` as an integer.  Anything with a fractional part,
+    /// even `.00`, is rejected.
+    pub fn parse_i64(&self, field: &[u8]) -> Option<i64> {
+        let mut buf = [0u8, ..64];
+        match self.normalize(field, &mut buf) {
+            Some((len, false)) => {
+                from_utf8(buf.slice_to(len)).and_then(|s| from_str::<i64>(s))
+            }
+            _ => None
+        }
+    }
+
+    // Rewrite `field` as a plain ASCII number in `buf`, returning its
+    // length and whether it has a fractional part.
+    fn normalize(&self, field: &[u8], buf: &mut [u8])
+                 -> Option<(uint, bool)> {
+        let mut rest = trim(field);
+        let mut negative = false;
+        if self.parens_negative && rest.starts_with(b"(") &&
+            rest.ends_with(b")") {
+            negative = true;
+            rest = trim(rest.slice(1, rest.len() - 1));
+        }
+        // The sign and currency symbol can come in either order.
+        rest = self.strip_currency(rest);
+        if rest.starts_with(b"-") || rest.starts_with(b"+") {
+            if negative { return None; }
+            negative = rest[0] == b'-';
+            rest = self.strip_currency(trim(rest.slice_from(1)));
+        }
+
+        let mut len = 0;
+        if negative {
+            buf[0] = b'-';
+            len = 1;
+        }
+        let mut any_digits = false;
+        let mut fraction = false;
+        // Digits since the last thousands separator, if we've seen one.
+        let mut group: Option<uint> = None;
+        for &b in rest.iter() {
+            if len == buf.len() { return None; }
+            if b >= b'0' && b <= b'9' {
+                buf[len] = b;
+                len += 1;
+                any_digits = true;
+                if !fraction { group = group.map(|n| n + 1); }
+            } else if !fraction && Some(b) == self.thousands {
+                if !any_digits || !complete_group(group) { return None; }
+                group = Some(0);
+            } else if !fraction && b == self.decimal {
+                if !complete_group(group) { return None; }
+                buf[len] = b'.';
+                len += 1;
+                fraction = true;
+            } else {
+                return None;
+            }
+        }
+        if !any_digits || (!fraction && !complete_group(group)) {
+            return None;
+        }
+        Some((len, fraction))
+    }
+
+    fn strip_currency<'a>(&self, field: &'a [u8]) -> &'a [u8] {
+        for currency in self.currencies.iter() {
+            let symbol = currency.as_bytes();
+            if field.starts_with(symbol) {
+                return trim(field.slice_from(symbol.len()));
+            } else if field.ends_with(symbol) {
+                return trim(field.slice_to(field.len() - symbol.len()));
+            }
+        }
+        field
+    }
+}
+
+// Is it OK to end a thousands group here?
+fn complete_group(group: Option<uint>) -> bool {
+    match group { None | Some(3) => true, _ => false }
+}
+
+fn is_space(b: u8) -> bool { b == b' ' || b == b'\t' }
+
+fn trim(field: &[u8]) -> &[u8] {
+    let start = field.iter().position(|&b| !is_space(b))
+        .unwrap_or(field.len());
+    let end = field.iter().rposition(|&b| !is_space(b))
+        .map_or(start, |i| i + 1);
+    field.slice(start, end)
+}
+
+#[test]
+fn parses_us_amounts() {
+    let us = NumberFormat::us();
+    assert_eq!(Some(1234.5), us.parse_f64(b" $1,234.50 "));
+    assert_eq!(Some(-1234.5), us.parse_f64(b"(1,234.50)"));
+    assert_eq!(Some(-5.0), us.parse_f64(b"-$5"));
+    assert_eq!(Some(-5.0), us.parse_f64(b"$-5"));
+    assert_eq!(Some(1000000), us.parse_i64(b"1,000,000 USD"));
+    assert_eq!(None, us.parse_i64(b"1.00"));
+    assert_eq!(None, us.parse_f64(b"1,5"));
+    assert_eq!(None, us.parse_f64(b"12,34,567"));
+    assert_eq!(None, us.parse_f64(b"$"));
+    assert_eq!(None, us.parse_f64(b"(-5)"));
+}
+
+#[test]
+fn parses_european_amounts() {
+    let eu = NumberFormat::european();
+    assert_eq!(Some(1234.5), eu.parse_f64("1.234,50 €".as_bytes()));
+    assert_eq!(Some(-12.0), eu.parse_f64("(12,00 €)".as_bytes()));
+    assert_eq!(Some(0.25), eu.parse_f64(b"0,25"));
+    let swiss = NumberFormat{thousands: Some(b'\''), decimal: b'.',
+                             ..NumberFormat::plain()}.with_currency("CHF");
+    assert_eq!(Some(1234567.0), swiss.parse_f64(b"CHF 1'234'567.00"));
+}
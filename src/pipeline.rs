@@ -0,0 +1,342 @@
+//! A builder for common read-filter-write pipelines.
+//!
+//! Nesting half a dozen buffer and iterator types by hand, each with its
+//! own lifetime, is painful.  Instead, you can write:
+//!
+//! ```ignore
+//! Pipeline::from_file(&path)
+//!     .decompress_auto()
+//!     .records(b"\n")
+//!     .csv(Dialect::csv())
+//!     .select(&["a", "b"])
+//!     .write_csv(&mut out)
+//! ```
+//!
+//! Each step returns a new type, so the compiler won't let you select
+//! columns before you've said how to split fields, or write output before
+//! you've said what a record is.  Nothing happens until the final
+//! `write_csv`, which sets up the buffers and runs the whole thing.
+//!
+//! To write to a file instead, use `write_csv_file`, which picks an output
+//! compression format based on the file's extension (`.gz`, `.sz`, ...).
+//! Pass `Some(format)` to override the guess.  Formats we can't write, like
+//! `.zst`, fail up front, leaving any existing file alone.
+
+use std::io::{Buffer,BufferedReader,BufferedWriter,File,InvalidInput,IoError,
+              IoResult};
+
+use buffers::read_until_into;
+use compress::{Compression,CompressWriter,check_encoder,compress,
+               decompress_auto,from_extension};
+use csv::{Dialect,split_fields};
+use utf8::{PassThrough,Utf8Policy};
+
+#[cfg(test)] use std::io::{MemWriter,OtherIoError,TempDir};
+#[cfg(test)] use compress::Xz;
+#[cfg(test)] use utf8::{Replace,Strict};
+
+/// The size of the I/O buffer we use when reading input files.
+pub static INPUT_BUFFER_SIZE: uint = 64 * 1024;
+
+/// The entry point for building pipelines.
+pub struct Pipeline;
+
+impl Pipeline {
+    /// Start a pipeline which reads from the file at `path`.
+    pub fn from_file(path: &Path) -> FileStage {
+        FileStage{path: path.clone(), decompress: false}
+    }
+}
+
+/// A pipeline which reads raw bytes from a file.
+pub struct FileStage {
+    path: Path,
+    decompress: bool
+}
+
+impl FileStage {
+    /// Detect compressed input by its magic bytes, and decompress it.
+    /// Uncompressed input is passed through.  See `compress`.
+    pub fn decompress_auto(self) -> FileStage {
+        FileStage{decompress: true, ..self}
+    }
+
+    /// Split the input into records, each ending with `boundary`.  The
+    /// boundary is not included in the records.
+    ///
+    /// This is a plain byte search, which knows nothing about quoting, so
+    /// a CSV field containing a quoted newline will be split in two by
+    /// `records(b"\n")`.  Unlike `split::split_ranges`, which tracks
+    /// quotes for `RecordFormat::Csv`, we don't handle such input yet.
+    pub fn records(self, boundary: &[u8]) -> RecordStage {
+        assert!(boundary.len() > 0);
+        RecordStage{file: self, boundary: boundary.to_vec(),
+                    utf8: PassThrough}
+    }
+}
+
+/// A pipeline which splits its input into records.
+pub struct RecordStage {
+    file: FileStage,
+    boundary: Vec<u8>,
+    utf8: Utf8Policy
+}
+
+impl RecordStage {
+    /// Handle records which aren't valid UTF-8 according to `policy`.  By
+    /// default, we pass them through untouched.
+    pub fn utf8(self, policy: Utf8Policy) -> RecordStage {
+        RecordStage{utf8: policy, ..self}
+    }
+
+    /// Treat each record as a row of CSV fields.  The first row is a
+    /// header.
+    pub fn csv(self, dialect: Dialect) -> CsvStage {
+        CsvStage{records: self, dialect: dialect}
+    }
+
+    /// Call `f` on each record.
+    pub fn for_each(self, f: |&[u8]| -> IoResult<()>) -> IoResult<()> {
+        let file = try!(File::open(&self.file.path));
+        let buffered = BufferedReader::with_capacity(INPUT_BUFFER_SIZE, file);
+        let mut input: Box<Buffer> = if self.file.decompress {
+            try!(decompress_auto(buffered))
+        } else {
+            box buffered as Box<Buffer>
+        };
+        let boundary = self.boundary.as_slice();
+        let last = boundary[boundary.len() - 1];
+        let mut record = vec![];
+        let mut scratch = vec![];
+        loop {
+            // Keep reading until the record ends with the whole boundary,
+            // not just its last byte.
+            let mut more = false;
+            loop {
+                if !try!(read_until_into(&mut *input, last, &mut record)) {
+                    break;
+                }
+                more = true;
+                if record.as_slice().ends_with(boundary) { break; }
+            }
+            if !more && record.len() == 0 { return Ok(()); }
+            if record.as_slice().ends_with(boundary) {
+                let len = record.len() - boundary.len();
+                record.truncate(len);
+            }
+            try!(f(try!(self.utf8.apply(record.as_slice(), &mut scratch))));
+            record.clear();
+        }
+    }
+}
+
+/// A pipeline which splits each record into CSV fields.
+pub struct CsvStage {
+    records: RecordStage,
+    dialect: Dialect
+}
+
+impl CsvStage {
+    /// Only keep the named columns, in the order given.
+    pub fn select(self, columns: &[&str]) -> SelectStage {
+        SelectStage{csv: self,
+                    columns: columns.iter().map(|c| c.to_string()).collect()}
+    }
+
+    /// Write every column to `out`, returning the number of data records
+    /// written.
+    pub fn write_csv(self, out: &mut Writer) -> IoResult<uint> {
+        run_csv(self, None, out)
+    }
+
+    /// Write every column to the file at `path`, compressed using
+    /// `compression` or, if that's `None`, a format chosen by `path`'s
+    /// extension.
+    pub fn write_csv_file(self, path: &Path, compression: Option<Compression>)
+                          -> IoResult<uint> {
+        let mut out = try!(open_output(path, compression));
+        let count = try!(self.write_csv(&mut out));
+        try!(out.finish());
+        Ok(count)
+    }
+}
+
+/// A pipeline which keeps only some CSV columns.
+pub struct SelectStage {
+    csv: CsvStage,
+    columns: Vec<String>
+}
+
+impl SelectStage {
+    /// Write the selected columns to `out`, returning the number of data
+    /// records written.
+    pub fn write_csv(self, out: &mut Writer) -> IoResult<uint> {
+        let SelectStage{csv, columns} = self;
+        run_csv(csv, Some(columns), out)
+    }
+
+    /// Write the selected columns to the file at `path`.  See
+    /// `CsvStage::write_csv_file`.
+    pub fn write_csv_file(self, path: &Path, compression: Option<Compression>)
+                          -> IoResult<uint> {
+        let mut out = try!(open_output(path, compression));
+        let count = try!(self.write_csv(&mut out));
+        try!(out.finish());
+        Ok(count)
+    }
+}
+
+// Open `path` for writing, compressing as requested.
+fn open_output(path: &Path, compression: Option<Compression>)
+               -> IoResult<CompressWriter<BufferedWriter<File>>> {
+    let format = compression.unwrap_or_else(|| from_extension(path));
+    // Don't truncate `path` unless we can actually write to it.
+    try!(check_encoder(format));
+    let file = BufferedWriter::new(try!(File::create(path)));
+    compress(file, format)
+}
+
+// Actually run a CSV pipeline.
+fn run_csv(csv: CsvStage, columns: Option<Vec<String>>, out: &mut Writer)
+           -> IoResult<uint> {
+    let CsvStage{records, dialect} = csv;
+    let mut spans = vec![];
+    let mut indices: Option<Vec<uint>> = None;
+    let mut count = 0u;
+    try!(records.for_each(|record| {
+        split_fields(record, &dialect, &mut spans);
+        if indices.is_none() {
+            // This is our header, so figure out which columns we want.
+            let names: Vec<&[u8]> =
+                spans.iter().map(|&(s, e)| record.slice(s, e)).collect();
+            let wanted = match columns {
+                None => range(0, names.len()).collect(),
+                Some(ref columns) => {
+                    let mut wanted = vec![];
+                    for column in columns.iter() {
+                        match names.iter()
+                            .position(|n| *n == column.as_bytes()) {
+                            Some(i) => wanted.push(i),
+                            None => {
+                                return Err(IoError{
+                                    kind: InvalidInput,
+                                    desc: "no such column",
+                                    detail: Some(column.clone())
+                                });
+                            }
+                        }
+                    }
+                    wanted
+                }
+            };
+            indices = Some(wanted);
+        } else {
+            count += 1;
+        }
+        let indices = indices.as_ref().unwrap();
+        for (n, &i) in indices.iter().enumerate() {
+            if n > 0 { try!(out.write_u8(dialect.delimiter)); }
+            let field = match spans.as_slice().get(i) {
+                Some(&(s, e)) => record.slice(s, e),
+                None => &[]
+            };
+            try!(dialect.write_field(out, field));
+        }
+        out.write_u8(b'\n')
+    }));
+    Ok(count)
+}
+
+#[test]
+fn pipeline_selects_columns() {
+    let dir = TempDir::new("pipeline").unwrap();
+    let path = dir.path().join("input.csv");
+    File::create(&path).write(b"a,b,c\n1,2,3\n4,\"x,y\",6\n").unwrap();
+    let mut out = MemWriter::new();
+    let count = Pipeline::from_file(&path)
+        .decompress_auto()
+        .records(b"\n")
+        .csv(Dialect::csv())
+        .select(&["c", "b"])
+        .write_csv(&mut out)
+        .unwrap();
+    assert_eq!(2, count);
+    assert_eq!(b"c,b\n3,2\n6,\"x,y\"\n", out.get_ref());
+}
+
+#[test]
+fn pipeline_keeps_quoted_delimiters_in_their_field() {
+    let dir = TempDir::new("pipeline").unwrap();
+    let path = dir.path().join("input.csv");
+    File::create(&path).write(b"name,\"city, state\",zip\n\
+                                \"Smith, J.\",\"Portland, OR\",97201\n")
+        .unwrap();
+    let mut out = MemWriter::new();
+    let count = Pipeline::from_file(&path)
+        .records(b"\n")
+        .csv(Dialect::csv())
+        .select(&["zip", "city, state"])
+        .write_csv(&mut out)
+        .unwrap();
+    assert_eq!(1, count);
+    assert_eq!(b"zip,\"city, state\"\n97201,\"Portland, OR\"\n",
+               out.get_ref());
+}
+
+#[test]
+fn pipeline_applies_utf8_policy() {
+    let dir = TempDir::new("pipeline").unwrap();
+    let path = dir.path().join("input.txt");
+    File::create(&path).write(b"ok\nbad \xff\n").unwrap();
+    let mut records = vec![];
+    Pipeline::from_file(&path).records(b"\n").utf8(Replace)
+        .for_each(|r| { records.push(r.to_vec()); Ok(()) })
+        .unwrap();
+    assert_eq!(vec![b"ok".to_vec(), b"bad \xef\xbf\xbd".to_vec()], records);
+    let result = Pipeline::from_file(&path).records(b"\n").utf8(Strict)
+        .for_each(|_| Ok(()));
+    assert_eq!(InvalidInput, result.unwrap_err().kind);
+}
+
+#[test]
+fn unsupported_output_compression_leaves_file_alone() {
+    let dir = TempDir::new("pipeline").unwrap();
+    let input = dir.path().join("input.csv");
+    File::create(&input).write(b"a,b\n1,2\n").unwrap();
+    let output = dir.path().join("output.csv.zst");
+    File::create(&output).write(b"precious").unwrap();
+    let err = Pipeline::from_file(&input)
+        .records(b"\n")
+        .csv(Dialect::csv())
+        .write_csv_file(&output, None)
+        .unwrap_err();
+    assert_eq!(OtherIoError, err.kind);
+    assert_eq!("no encoder for compression format", err.desc);
+    assert_eq!(b"precious".to_vec(),
+               File::open(&output).read_to_end().unwrap());
+
+    let plain = dir.path().join("output.csv");
+    File::create(&plain).write(b"precious").unwrap();
+    assert!(Pipeline::from_file(&input).records(b"\n").csv(Dialect::csv())
+            .write_csv_file(&plain, Some(Xz)).is_err());
+    assert_eq!(b"precious".to_vec(),
+               File::open(&plain).read_to_end().unwrap());
+}
+
+#[test]
+fn pipeline_writes_gzip_output() {
+    let dir = TempDir::new("pipeline").unwrap();
+    let input = dir.path().join("input.csv");
+    File::create(&input).write(b"a,b\n1,2\n3,4\n").unwrap();
+    let output = dir.path().join("output.csv.gz");
+    let count = Pipeline::from_file(&input)
+        .records(b"\n")
+        .csv(Dialect::csv())
+        .select(&["b"])
+        .write_csv_file(&output, None)
+        .unwrap();
+    assert_eq!(2, count);
+    let file = BufferedReader::new(File::open(&output).unwrap());
+    let mut decoded = decompress_auto(file).unwrap();
+    assert_eq!(b"b\n2\n4\n".to_vec(), decoded.read_to_end().unwrap());
+}
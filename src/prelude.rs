@@ -0,0 +1,16 @@
+//! The traits and types most programs need, in one place:
+//!
+//! ```ignore
+//! #[phase(plugin, link)] extern crate streaming;
+//! use streaming::prelude::*;
+//! ```
+//!
+//! Macros can't be re-exported, so `streaming_for!` and friends still come
+//! from the `#[phase(plugin)]` on the `extern crate` line.
+
+pub use buffers::{ChunkBuffer,GrowthPolicy,read_until_into};
+pub use iter::{ExactSizeStreamingIterator,FromFn,IterAdapter,OwnedCopy};
+pub use iter::{OwnedItems,OwnedStreamingIterator,Resettable};
+pub use iter::{SliceStream,StreamingIterator,StreamingIteratorExt,ToOwned};
+pub use iter::TryStreamingIterator;
+pub use sinks::{RecordSink,WriterSink};
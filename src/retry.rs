@@ -0,0 +1,312 @@
+//! Retrying transient errors from flaky byte sources.
+//!
+//! When you're streaming a multi-gigabyte file over the network, a single
+//! timeout shouldn't force you to start over.  `RetryBuffer` wraps a
+//! `Buffer` and retries errors which look transient, backing off a little
+//! more each time.
+//!
+//! If the source is a `RangeSource`, which can be reopened at any offset,
+//! `ResumingBuffer` goes one better: it throws away a broken connection and
+//! picks up where it left off on a fresh one.
+
+use std::cmp::min;
+use std::io::{Buffer,BufferedReader,ConnectionAborted,ConnectionReset,File,
+              Interrupted,IoError,IoErrorKind,IoResult,ResourceUnavailable,
+              SeekSet,TimedOut};
+use std::io::timer::sleep;
+use std::time::Duration;
+
+#[cfg(test)] use std::io::{MemReader,standard_error};
+
+/// Which errors to retry, and how hard to try.
+#[deriving(Clone, Show)]
+pub struct RetryPolicy {
+    /// Errors of these kinds are considered transient.
+    pub kinds: Vec<IoErrorKind>,
+    /// How many times to retry a single operation before giving up.
+    pub attempts: uint,
+    /// How long to wait before the first retry, in milliseconds.
+    pub initial_backoff_ms: i64,
+    /// How long to wait at most between retries, in milliseconds.  We
+    /// double our wait after each failed retry until we reach this.
+    pub max_backoff_ms: i64
+}
+
+impl RetryPolicy {
+    /// A reasonable default policy for network-backed sources.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy{kinds: vec![TimedOut, ConnectionReset, ConnectionAborted,
+                                ResourceUnavailable, Interrupted],
+                    attempts: 5, initial_backoff_ms: 100,
+                    max_backoff_ms: 10_000}
+    }
+
+    /// Should we retry `err` after `attempt` previous retries?
+    pub fn should_retry(&self, err: &IoError, attempt: uint) -> bool {
+        attempt < self.attempts && self.kinds.contains(&err.kind)
+    }
+
+    /// How long should we wait before retry number `attempt`?
+    pub fn backoff_ms(&self, attempt: uint) -> i64 {
+        let mut backoff = self.initial_backoff_ms;
+        for _ in range(0, attempt) {
+            backoff = min(backoff * 2, self.max_backoff_ms);
+        }
+        backoff
+    }
+}
+
+/// A buffer which retries transient errors from `input`.  Since a failed
+/// `fill_buf` doesn't consume anything, we always pick up from where we
+/// left off.
+pub struct RetryBuffer<T> {
+    input: T,
+    policy: RetryPolicy,
+    position: u64
+}
+
+impl<T: Buffer> RetryBuffer<T> {
+    /// Wrap `input`, retrying errors according to `policy`.
+    pub fn new(input: T, policy: RetryPolicy) -> RetryBuffer<T> {
+        RetryBuffer{input: input, policy: policy, position: 0}
+    }
+
+    /// How many bytes have been consumed so far?
+    pub fn position(&self) -> u64 { self.position }
+
+    /// Get back our underlying buffer.
+    pub fn unwrap(self) -> T { self.input }
+}
+
+impl<T: Buffer> Reader for RetryBuffer<T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let mut attempt = 0;
+        loop {
+            match self.input.read(buf) {
+                Ok(count) => {
+                    self.position += count as u64;
+                    return Ok(count);
+                }
+                Err(err) => {
+                    if !self.policy.should_retry(&err, attempt) {
+                        return Err(err);
+                    }
+                }
+            }
+            sleep(Duration::milliseconds(self.policy.backoff_ms(attempt)));
+            attempt += 1;
+        }
+    }
+}
+
+impl<T: Buffer> Buffer for RetryBuffer<T> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        let mut attempt = 0;
+        loop {
+            // Returning `read` from inside the loop would keep `input`
+            // borrowed for every later attempt (see rust-lang/rust#12147),
+            // so we only look for errors here, and ask for the data again
+            // once we know it's there.
+            match self.input.fill_buf() {
+                Ok(_) => { break; }
+                Err(err) => {
+                    if !self.policy.should_retry(&err, attempt) {
+                        return Err(err);
+                    }
+                }
+            }
+            sleep(Duration::milliseconds(self.policy.backoff_ms(attempt)));
+            attempt += 1;
+        }
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.position += amt as u64;
+        self.input.consume(amt);
+    }
+}
+
+/// A source of bytes which can be opened at any byte offset, such as a
+/// local file, or an object store which supports HTTP range requests.
+pub trait RangeSource<R: Buffer> {
+    /// Open a new stream which starts `offset` bytes into the data.
+    fn open_at(&mut self, offset: u64) -> IoResult<R>;
+}
+
+impl RangeSource<BufferedReader<File>> for Path {
+    fn open_at(&mut self, offset: u64) -> IoResult<BufferedReader<File>> {
+        let mut file = try!(File::open(self));
+        try!(file.seek(offset as i64, SeekSet));
+        Ok(BufferedReader::new(file))
+    }
+}
+
+/// A buffer which recovers from transient errors by reopening its
+/// `RangeSource` at the current position.
+pub struct ResumingBuffer<S, R> {
+    source: S,
+    input: R,
+    policy: RetryPolicy,
+    position: u64
+}
+
+impl<R: Buffer, S: RangeSource<R>> ResumingBuffer<S, R> {
+    /// Start reading `source` at `offset`, recovering from errors
+    /// according to `policy`.
+    pub fn new(mut source: S, offset: u64, policy: RetryPolicy)
+               -> IoResult<ResumingBuffer<S, R>> {
+        let input = try!(source.open_at(offset));
+        Ok(ResumingBuffer{source: source, input: input, policy: policy,
+                          position: offset})
+    }
+
+    /// Our position in the underlying data.  Save this in a checkpoint to
+    /// resume after a restart.
+    pub fn position(&self) -> u64 { self.position }
+
+    // Called after a transient error.  Wait, then try to reopen our
+    // source.
+    fn reopen(&mut self, mut attempt: uint) -> IoResult<()> {
+        loop {
+            sleep(Duration::milliseconds(self.policy.backoff_ms(attempt)));
+            attempt += 1;
+            match self.source.open_at(self.position) {
+                Ok(input) => {
+                    self.input = input;
+                    return Ok(());
+                }
+                Err(err) => {
+                    if !self.policy.should_retry(&err, attempt) {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: Buffer, S: RangeSource<R>> Reader for ResumingBuffer<S, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let mut attempt = 0;
+        loop {
+            match self.input.read(buf) {
+                Ok(count) => {
+                    self.position += count as u64;
+                    return Ok(count);
+                }
+                Err(err) => {
+                    if !self.policy.should_retry(&err, attempt) {
+                        return Err(err);
+                    }
+                }
+            }
+            try!(self.reopen(attempt));
+            attempt += 1;
+        }
+    }
+}
+
+impl<R: Buffer, S: RangeSource<R>> Buffer for ResumingBuffer<S, R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        let mut attempt = 0;
+        loop {
+            // As in `RetryBuffer::fill_buf`, only look for errors here.
+            match self.input.fill_buf() {
+                Ok(_) => { break; }
+                Err(err) => {
+                    if !self.policy.should_retry(&err, attempt) {
+                        return Err(err);
+                    }
+                }
+            }
+            try!(self.reopen(attempt));
+            attempt += 1;
+        }
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.position += amt as u64;
+        self.input.consume(amt);
+    }
+}
+
+/// Fails with `TimedOut` every other call to `fill_buf`.
+#[cfg(test)]
+struct FlakyBuffer {
+    input: MemReader,
+    fail: bool
+}
+
+#[cfg(test)]
+impl Reader for FlakyBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.input.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl Buffer for FlakyBuffer {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        self.fail = !self.fail;
+        if self.fail {
+            Err(standard_error(TimedOut))
+        } else {
+            self.input.fill_buf().map(|buf| buf.slice_to(min(buf.len(), 3)))
+        }
+    }
+
+    fn consume(&mut self, amt: uint) { self.input.consume(amt) }
+}
+
+#[cfg(test)]
+fn quick_policy() -> RetryPolicy {
+    RetryPolicy{initial_backoff_ms: 0, ..RetryPolicy::new()}
+}
+
+#[test]
+fn retry_buffer_survives_transient_errors() {
+    let flaky = FlakyBuffer{input: MemReader::new(b"hello\nworld\n".to_vec()),
+                            fail: false};
+    let mut retry = RetryBuffer::new(flaky, quick_policy());
+    assert_eq!("hello\n", retry.read_line().unwrap().as_slice());
+    assert_eq!(6, retry.position());
+    assert_eq!("world\n", retry.read_line().unwrap().as_slice());
+}
+
+#[test]
+fn retry_buffer_gives_up_on_other_errors() {
+    let flaky = FlakyBuffer{input: MemReader::new(b"hello".to_vec()),
+                            fail: false};
+    let policy = RetryPolicy{kinds: vec![], ..quick_policy()};
+    let mut retry = RetryBuffer::new(flaky, policy);
+    assert!(retry.fill_buf().is_err());
+}
+
+/// Opens a `FlakyBuffer` over `data`, counting how often it's reopened.
+#[cfg(test)]
+struct FlakySource {
+    data: Vec<u8>,
+    opened: uint
+}
+
+#[cfg(test)]
+impl RangeSource<FlakyBuffer> for FlakySource {
+    fn open_at(&mut self, offset: u64) -> IoResult<FlakyBuffer> {
+        self.opened += 1;
+        let rest = self.data.slice_from(offset as uint).to_vec();
+        // Start out healthy, so we fail on our second read.
+        Ok(FlakyBuffer{input: MemReader::new(rest), fail: true})
+    }
+}
+
+#[test]
+fn resuming_buffer_reopens_at_position() {
+    let source = FlakySource{data: b"hello\nworld\n".to_vec(), opened: 0};
+    let mut resuming = ResumingBuffer::new(source, 2, quick_policy()).unwrap();
+    assert_eq!("llo\n", resuming.read_line().unwrap().as_slice());
+    assert_eq!("world\n", resuming.read_line().unwrap().as_slice());
+    assert_eq!(12, resuming.position());
+    assert!(resuming.source.opened > 1);
+}
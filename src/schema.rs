@@ -0,0 +1,304 @@
+//! Named record layouts shared between parsers and writers.
+//!
+//! When a pipeline reads one format and writes another, it's easy for the
+//! two ends to disagree about which columns exist.  A `Registry` holds
+//! named `Layout`s, so both ends can be built from the same description.
+
+use std::collections::HashMap;
+use std::io::{Buffer,InvalidInput,IoError,IoResult};
+use std::str::from_utf8;
+
+use ndjson::PathExtractor;
+
+/// The type of a column's values.
+#[deriving(PartialEq, Clone, Show)]
+pub enum ColumnType {
+    /// Arbitrary bytes.
+    Bytes,
+    /// Valid UTF-8.
+    Text,
+    /// A base-10 integer, optionally signed.
+    Integer,
+    /// A floating point number.
+    Float,
+    /// `true` or `false`.
+    Boolean
+}
+
+impl ColumnType {
+    /// Does `value` look like a value of this type?
+    pub fn matches(&self, value: &[u8]) -> bool {
+        self.decode(value).is_some()
+    }
+
+    /// Decode `value` as a value of this type, if it is one.
+    pub fn decode<'a>(&self, value: &'a [u8]) -> Option<Value<'a>> {
+        let text = match from_utf8(value) {
+            Some(text) => text,
+            None if *self == Bytes => { return Some(BytesValue(value)); }
+            None => { return None; }
+        };
+        match *self {
+            Bytes => Some(BytesValue(value)),
+            Text => Some(TextValue(text)),
+            Integer => from_str::<i64>(text).map(|n| IntegerValue(n)),
+            Float => from_str::<f64>(text).map(|n| FloatValue(n)),
+            Boolean => match text {
+                "true" => Some(BooleanValue(true)),
+                "false" => Some(BooleanValue(false)),
+                _ => None
+            }
+        }
+    }
+}
+
+/// A decoded field value.  See `ColumnType::decode`.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Value<'a> {
+    /// Arbitrary bytes.
+    BytesValue(&'a [u8]),
+    /// Valid UTF-8.
+    TextValue(&'a str),
+    /// An integer.
+    IntegerValue(i64),
+    /// A floating point number.
+    FloatValue(f64),
+    /// A boolean.
+    BooleanValue(bool)
+}
+
+/// What to do when a field is missing.
+#[deriving(PartialEq, Clone, Show)]
+pub enum NullAction {
+    /// Decode it as `None`.
+    KeepNull,
+    /// Decode this value instead.
+    UseDefault(Vec<u8>),
+    /// Return an error.
+    RejectNull
+}
+
+/// Which field values mean "missing", and what to do about them.
+#[deriving(PartialEq, Clone, Show)]
+pub struct NullPolicy {
+    /// Values which mean "missing".  Compared exactly, so list each
+    /// capitalization you want to accept.
+    pub sentinels: Vec<Vec<u8>>,
+    /// What to do with missing values.
+    pub action: NullAction
+}
+
+impl NullPolicy {
+    /// Treat the usual suspects (`""`, `NA`, `\N` and `null`) as missing,
+    /// and decode them as `None`.
+    pub fn new() -> NullPolicy {
+        NullPolicy::with_action(KeepNull)
+    }
+
+    /// Treat the usual suspects as missing, and handle them using
+    /// `action`.
+    pub fn with_action(action: NullAction) -> NullPolicy {
+        let sentinels = [b"", b"NA", b"\\N", b"null"];
+        NullPolicy{sentinels: sentinels.iter().map(|s| s.to_vec()).collect(),
+                   action: action}
+    }
+
+    /// Is `value` one of our sentinels?
+    pub fn is_null(&self, value: &[u8]) -> bool {
+        self.sentinels.iter().any(|s| s.as_slice() == value)
+    }
+}
+
+/// A single named column.
+#[deriving(Clone)]
+pub struct Column {
+    /// The column's name.  For nested formats, this may be a dotted path.
+    pub name: String,
+    /// The type of the column's values.
+    pub ty: ColumnType,
+    /// An extra check to run on each value, if any.
+    pub validator: Option<fn(&[u8]) -> bool>
+}
+
+impl Column {
+    /// Create a new column with no validator.
+    pub fn new(name: &str, ty: ColumnType) -> Column {
+        Column{name: name.to_string(), ty: ty, validator: None}
+    }
+
+    /// Create a new column which also checks values with `validator`.
+    pub fn with_validator(name: &str, ty: ColumnType,
+                          validator: fn(&[u8]) -> bool) -> Column {
+        Column{name: name.to_string(), ty: ty, validator: Some(validator)}
+    }
+
+    /// Is `value` valid for this column?
+    pub fn is_valid(&self, value: &[u8]) -> bool {
+        self.ty.matches(value) &&
+            self.validator.map_or(true, |validator| validator(value))
+    }
+}
+
+/// The structure of a record: an ordered list of columns.
+#[deriving(Clone)]
+pub struct Layout {
+    /// Our columns, in order.
+    pub columns: Vec<Column>
+}
+
+impl Layout {
+    /// Create a new layout with the specified columns.
+    pub fn new(columns: Vec<Column>) -> Layout {
+        Layout{columns: columns}
+    }
+
+    /// The names of our columns, suitable for a header row.
+    pub fn names<'a>(&'a self) -> Vec<&'a str> {
+        self.columns.iter().map(|c| c.name.as_slice()).collect()
+    }
+
+    /// Check that `fields` has the right number of values, and that each
+    /// value is valid for its column.
+    pub fn check(&self, fields: &[&[u8]]) -> IoResult<()> {
+        if fields.len() != self.columns.len() {
+            return Err(IoError{
+                kind: InvalidInput, desc: "wrong number of fields",
+                detail: Some(format!("expected {}, found {}",
+                                     self.columns.len(), fields.len()))
+            });
+        }
+        for (column, field) in self.columns.iter().zip(fields.iter()) {
+            if !column.is_valid(*field) {
+                return Err(IoError{
+                    kind: InvalidInput, desc: "invalid field",
+                    detail: Some(format!("bad value for column {}",
+                                         column.name))
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode each of `fields` according to its column's type, storing
+    /// the values in `values` (which is cleared first).  Missing values
+    /// are handled according to `nulls`.  Non-missing values must also
+    /// pass their column's validator.
+    pub fn decode<'a>(&self, fields: &[&'a [u8]], nulls: &'a NullPolicy,
+                      values: &mut Vec<Option<Value<'a>>>) -> IoResult<()> {
+        values.clear();
+        if fields.len() != self.columns.len() {
+            return Err(IoError{
+                kind: InvalidInput, desc: "wrong number of fields",
+                detail: Some(format!("expected {}, found {}",
+                                     self.columns.len(), fields.len()))
+            });
+        }
+        for (column, &field) in self.columns.iter().zip(fields.iter()) {
+            let field = if nulls.is_null(field) {
+                match nulls.action {
+                    KeepNull => { values.push(None); continue; }
+                    UseDefault(ref default) => default.as_slice(),
+                    RejectNull => {
+                        return Err(IoError{
+                            kind: InvalidInput, desc: "missing field",
+                            detail: Some(format!("no value for column {}",
+                                                 column.name))
+                        });
+                    }
+                }
+            } else {
+                field
+            };
+            let value = column.ty.decode(field).and_then(|value| {
+                let valid = column.validator.map_or(true, |v| v(field));
+                if valid { Some(value) } else { None }
+            });
+            match value {
+                Some(value) => { values.push(Some(value)); }
+                None => {
+                    return Err(IoError{
+                        kind: InvalidInput, desc: "invalid field",
+                        detail: Some(format!("bad value for column {}",
+                                             column.name))
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A collection of named layouts.
+pub struct Registry {
+    layouts: HashMap<String, Layout>
+}
+
+impl Registry {
+    /// Create a new, empty registry.
+    pub fn new() -> Registry {
+        Registry{layouts: HashMap::new()}
+    }
+
+    /// Register `layout` under `name`, replacing any existing layout with
+    /// that name.
+    pub fn register(&mut self, name: &str, layout: Layout) {
+        self.layouts.insert(name.to_string(), layout);
+    }
+
+    /// Look up the layout named `name`.
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a Layout> {
+        self.layouts.find_equiv(&name)
+    }
+
+    /// Build an NDJSON extractor which pulls out the columns of the layout
+    /// named `name`, in order.
+    pub fn path_extractor<'a, T: Buffer+'a>(&self, name: &str,
+                                            input: &'a mut T)
+                                            -> Option<PathExtractor<'a, T>> {
+        self.get(name).map(|layout| {
+            PathExtractor::new(input, layout.names().as_slice())
+        })
+    }
+}
+
+#[cfg(test)]
+fn is_positive(value: &[u8]) -> bool {
+    !value.starts_with(b"-")
+}
+
+#[test]
+fn layouts_check_records() {
+    let mut registry = Registry::new();
+    registry.register("people", Layout::new(vec![
+        Column::new("name", Text),
+        Column::with_validator("age", Integer, is_positive)
+    ]));
+    let people = registry.get("people").unwrap();
+    assert_eq!(vec!["name", "age"], people.names());
+    assert!(people.check(&[b"Ann", b"42"]).is_ok());
+    assert!(people.check(&[b"Ann", b"old"]).is_err());
+    assert!(people.check(&[b"Ann", b"-1"]).is_err());
+    assert!(people.check(&[b"Ann"]).is_err());
+    assert!(registry.get("places").is_none());
+}
+
+#[test]
+fn null_policies_control_missing_values() {
+    let layout = Layout::new(vec![Column::new("name", Text),
+                                  Column::new("age", Integer)]);
+    let mut values = vec![];
+
+    let keep = NullPolicy::new();
+    layout.decode(&[b"Ann", b"NA"], &keep, &mut values).unwrap();
+    assert_eq!(vec![Some(TextValue("Ann")), None], values);
+    layout.decode(&[b"Ann", b"42"], &keep, &mut values).unwrap();
+    assert_eq!(vec![Some(TextValue("Ann")), Some(IntegerValue(42))], values);
+
+    let default = NullPolicy::with_action(UseDefault(b"0".to_vec()));
+    layout.decode(&[b"Ann", b"\\N"], &default, &mut values).unwrap();
+    assert_eq!(Some(IntegerValue(0)), values[1]);
+
+    let reject = NullPolicy::with_action(RejectNull);
+    assert!(layout.decode(&[b"", b"42"], &reject, &mut values).is_err());
+    assert!(layout.decode(&[b"Ann", b"n/a"], &keep, &mut values).is_err());
+}
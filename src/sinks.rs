@@ -0,0 +1,446 @@
+//! Places to send the output of a streaming job.
+
+use std::collections::TreeMap;
+use std::io::{BufferedWriter,File,InvalidInput,IoError,IoResult,
+              OtherIoError};
+use std::io::fs::{rename,unlink};
+use std::rand::{Rng,task_rng};
+use std::str::from_utf8;
+
+#[cfg(test)] use std::io::{MemWriter,TempDir,standard_error,BrokenPipe};
+#[cfg(test)] use std::io::fs::{PathExtensions,readdir};
+
+/// A writer which only creates its destination file once all the output
+/// has been written successfully.
+///
+/// Data is written to a temporary file in the same directory as `dest`.
+/// Calling `commit` flushes it and renames it into place.  If the writer is
+/// dropped without being committed (because of an error, or because the
+/// task failed), the temporary file is deleted.  Either way, nobody ever
+/// sees a truncated output file which looks complete.
+pub struct SafeFileWriter {
+    file: Option<BufferedWriter<File>>,
+    temp_path: Path,
+    dest: Path
+}
+
+impl SafeFileWriter {
+    /// Start writing a new file which will eventually be stored at `dest`.
+    pub fn create(dest: &Path) -> IoResult<SafeFileWriter> {
+        let name = dest.filename_str().unwrap_or("output");
+        let temp_name = format!(".{}.{:08x}.tmp", name,
+                                task_rng().gen::<u32>());
+        let temp_path = dest.dir_path().join(temp_name);
+        let file = try!(File::create(&temp_path));
+        Ok(SafeFileWriter{file: Some(BufferedWriter::new(file)),
+                          temp_path: temp_path, dest: dest.clone()})
+    }
+
+    /// Flush our output and move it into place at `dest`.
+    pub fn commit(mut self) -> IoResult<()> {
+        {
+            let file = self.file.as_mut().unwrap();
+            try!(file.flush());
+        }
+        // Close the file before renaming it, for the sake of Windows.
+        self.file = None;
+        match rename(&self.temp_path, &self.dest) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = unlink(&self.temp_path);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Writer for SafeFileWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for SafeFileWriter {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            // We were never committed, so clean up.  There's nobody to
+            // report an error to, so ignore it.
+            self.file = None;
+            let _ = unlink(&self.temp_path);
+        }
+    }
+}
+
+/// Something which accepts records, one at a time.
+pub trait RecordSink {
+    /// Write a single record.
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()>;
+
+    /// Flush any buffered records.  Call this once you're done.
+    fn finish(&mut self) -> IoResult<()> { Ok(()) }
+}
+
+impl<'b, S: RecordSink> RecordSink for &'b mut S {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        (**self).write_record(record)
+    }
+
+    fn finish(&mut self) -> IoResult<()> { (**self).finish() }
+}
+
+/// A `RecordSink` which writes each record, followed by a terminator, to a
+/// `Writer`.
+pub struct WriterSink<W> {
+    writer: W,
+    terminator: Vec<u8>
+}
+
+impl<W: Writer> WriterSink<W> {
+    /// Write records to `writer`, following each one with `terminator`.
+    pub fn new(writer: W, terminator: &[u8]) -> WriterSink<W> {
+        WriterSink{writer: writer, terminator: terminator.to_vec()}
+    }
+
+    /// Get back our writer.
+    pub fn unwrap(self) -> W { self.writer }
+}
+
+impl<W: Writer> RecordSink for WriterSink<W> {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        try!(self.writer.write(record));
+        self.writer.write(self.terminator.as_slice())
+    }
+
+    fn finish(&mut self) -> IoResult<()> { self.writer.flush() }
+}
+
+/// What should a `FanoutSink` do when one of its sinks fails?
+#[deriving(PartialEq, Clone, Show)]
+pub enum ErrorPolicy {
+    /// Return the error, stopping the whole pipeline.
+    AbortAll,
+    /// Stop writing to the failed sink, and keep going with the others.
+    DropSink
+}
+
+struct Output<'a> {
+    sink: Box<RecordSink+'a>,
+    policy: ErrorPolicy,
+    error: Option<IoError>
+}
+
+/// A sink which writes every record to several other sinks, such as a full
+/// archive, a filtered extract and a metrics collector.
+pub struct FanoutSink<'a> {
+    outputs: Vec<Output<'a>>
+}
+
+impl<'a> FanoutSink<'a> {
+    /// Create a new `FanoutSink` with no outputs.
+    pub fn new() -> FanoutSink<'a> {
+        FanoutSink{outputs: vec![]}
+    }
+
+    /// Add `sink` as an output, handling its errors according to
+    /// `policy`.
+    pub fn add(&mut self, sink: Box<RecordSink+'a>, policy: ErrorPolicy) {
+        self.outputs.push(Output{sink: sink, policy: policy, error: None});
+    }
+
+    /// For each output, in the order added, the error which caused it to
+    /// be dropped, if any.
+    pub fn errors(&self) -> Vec<Option<&IoError>> {
+        self.outputs.iter().map(|o| o.error.as_ref()).collect()
+    }
+
+    // Run `f` on each live output, applying our error policies.
+    fn each_output(&mut self, f: |&mut Box<RecordSink+'a>| -> IoResult<()>)
+                   -> IoResult<()> {
+        let mut live = 0u;
+        for output in self.outputs.iter_mut() {
+            if output.error.is_some() { continue; }
+            match f(&mut output.sink) {
+                Ok(()) => { live += 1; }
+                Err(err) => match output.policy {
+                    AbortAll => { return Err(err); }
+                    DropSink => { output.error = Some(err); }
+                }
+            }
+        }
+        if live == 0 && self.outputs.len() > 0 {
+            return Err(IoError{kind: OtherIoError,
+                               desc: "every output of fanout has failed",
+                               detail: None});
+        }
+        Ok(())
+    }
+}
+
+impl<'a> RecordSink for FanoutSink<'a> {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        self.each_output(|sink| sink.write_record(record))
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        self.each_output(|sink| sink.finish())
+    }
+}
+
+struct Route<'a> {
+    name: String,
+    predicate: |&[u8]|: 'a -> bool,
+    sink: Box<RecordSink+'a>,
+    count: u64
+}
+
+/// A sink which sends each record to the first of several sinks whose
+/// predicate it matches, or to a default sink if none match.  This is
+/// handy for splitting logs by severity, or setting aside records which
+/// need a human to look at them.
+pub struct RouterSink<'a> {
+    routes: Vec<Route<'a>>,
+    default: Box<RecordSink+'a>,
+    default_count: u64
+}
+
+impl<'a> RouterSink<'a> {
+    /// Create a router which sends everything to `default`, until some
+    /// routes are added.
+    pub fn new(default: Box<RecordSink+'a>) -> RouterSink<'a> {
+        RouterSink{routes: vec![], default: default, default_count: 0}
+    }
+
+    /// Send records matching `predicate` to `sink`, unless an earlier
+    /// route has already claimed them.  `name` is used by `counts`.
+    pub fn add(&mut self, name: &str, predicate: |&[u8]|: 'a -> bool,
+               sink: Box<RecordSink+'a>) {
+        self.routes.push(Route{name: name.to_string(), predicate: predicate,
+                               sink: sink, count: 0});
+    }
+
+    /// How many records each route has received, in the order added,
+    /// followed by the default sink, which is named `"default"`.
+    pub fn counts(&self) -> Vec<(&str, u64)> {
+        let mut counts: Vec<(&str, u64)> = self.routes.iter()
+            .map(|r| (r.name.as_slice(), r.count)).collect();
+        counts.push(("default", self.default_count));
+        counts
+    }
+}
+
+impl<'a> RecordSink for RouterSink<'a> {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        for route in self.routes.iter_mut() {
+            if (route.predicate)(record) {
+                route.count += 1;
+                return route.sink.write_record(record);
+            }
+        }
+        self.default_count += 1;
+        self.default.write_record(record)
+    }
+
+    /// Finish every sink, even if some fail, and return the first error.
+    fn finish(&mut self) -> IoResult<()> {
+        let mut result = Ok(());
+        for route in self.routes.iter_mut() {
+            let finished = route.sink.finish();
+            if result.is_ok() { result = finished; }
+        }
+        let finished = self.default.finish();
+        if result.is_ok() { result = finished; }
+        result
+    }
+}
+
+/// An aggregation sink which counts how many times each distinct record
+/// appears, such as the tags of a corpus or the status codes of a log.
+/// Records are only copied the first time we see them.
+pub struct CountSink {
+    counts: TreeMap<Vec<u8>, u64>,
+    total: u64
+}
+
+impl CountSink {
+    /// Create a sink with nothing counted yet.
+    pub fn new() -> CountSink {
+        CountSink{counts: TreeMap::new(), total: 0}
+    }
+
+    /// How many times we've seen each record, sorted by record.
+    pub fn counts(&self) -> &TreeMap<Vec<u8>, u64> { &self.counts }
+
+    /// How many records we've seen altogether.
+    pub fn total(&self) -> u64 { self.total }
+}
+
+impl RecordSink for CountSink {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        self.total += 1;
+        // Look up by slice first, so we only allocate for new records.
+        let found = match self.counts
+                              .find_mut_with(|k| record.cmp(&k.as_slice())) {
+            Some(count) => { *count += 1; true }
+            None => false
+        };
+        if !found { self.counts.insert(record.to_vec(), 1); }
+        Ok(())
+    }
+}
+
+/// An aggregation sink which builds a histogram of small non-negative
+/// numbers, such as lengths, with one bucket per value.  Values can be
+/// added directly, or written as records in decimal.
+pub struct HistogramSink {
+    counts: Vec<u64>
+}
+
+impl HistogramSink {
+    /// Create an empty histogram.
+    pub fn new() -> HistogramSink {
+        HistogramSink{counts: vec![]}
+    }
+
+    /// Count one occurrence of `value`.
+    pub fn add(&mut self, value: uint) {
+        if value >= self.counts.len() {
+            let extra = value + 1 - self.counts.len();
+            self.counts.grow(extra, 0);
+        }
+        self.counts.as_mut_slice()[value] += 1;
+    }
+
+    /// How many times we've seen each value, indexed by value.
+    pub fn counts(&self) -> &[u64] { self.counts.as_slice() }
+
+    /// How many values we've seen altogether.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().fold(0, |a, &b| a + b)
+    }
+}
+
+impl RecordSink for HistogramSink {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        match from_utf8(record).and_then(from_str) {
+            Some(value) => { self.add(value); Ok(()) }
+            None => Err(IoError{kind: InvalidInput,
+                                desc: "histogram record is not a number",
+                                detail: from_utf8(record)
+                                    .map(|r| r.to_string())})
+        }
+    }
+}
+
+#[cfg(test)]
+struct FailingSink;
+
+#[cfg(test)]
+impl RecordSink for FailingSink {
+    fn write_record(&mut self, _record: &[u8]) -> IoResult<()> {
+        Err(standard_error(BrokenPipe))
+    }
+}
+
+#[test]
+fn fanout_drops_failed_sinks() {
+    let mut first = WriterSink::new(MemWriter::new(), b"\n");
+    let mut second = WriterSink::new(MemWriter::new(), b";");
+    {
+        let mut fanout = FanoutSink::new();
+        fanout.add(box (&mut first) as Box<RecordSink>, AbortAll);
+        fanout.add(box FailingSink as Box<RecordSink>, DropSink);
+        fanout.add(box (&mut second) as Box<RecordSink>, DropSink);
+        fanout.write_record(b"a").unwrap();
+        fanout.write_record(b"b").unwrap();
+        fanout.finish().unwrap();
+        let errors = fanout.errors();
+        assert!(errors[0].is_none() && errors[1].is_some());
+    }
+    assert_eq!(b"a\nb\n", first.unwrap().get_ref());
+    assert_eq!(b"a;b;", second.unwrap().get_ref());
+}
+
+#[test]
+fn fanout_aborts_on_critical_failure() {
+    let mut fanout = FanoutSink::new();
+    fanout.add(box FailingSink as Box<RecordSink>, AbortAll);
+    assert!(fanout.write_record(b"a").is_err());
+}
+
+#[test]
+fn router_sends_records_to_first_match() {
+    let mut errors = WriterSink::new(MemWriter::new(), b"\n");
+    let mut warnings = WriterSink::new(MemWriter::new(), b"\n");
+    let mut rest = WriterSink::new(MemWriter::new(), b"\n");
+    {
+        let mut router = RouterSink::new(box (&mut rest) as Box<RecordSink>);
+        router.add("errors", |r| r.starts_with(b"ERROR"),
+                   box (&mut errors) as Box<RecordSink>);
+        router.add("warnings", |r| r.starts_with(b"WARN") ||
+                                   r.starts_with(b"ERROR"),
+                   box (&mut warnings) as Box<RecordSink>);
+        for record in ["INFO up", "ERROR disk", "WARN slow",
+                       "INFO down"].iter() {
+            router.write_record(record.as_bytes()).unwrap();
+        }
+        router.finish().unwrap();
+        assert_eq!(vec![("errors", 1), ("warnings", 1), ("default", 2)],
+                   router.counts());
+    }
+    assert_eq!(b"ERROR disk\n", errors.unwrap().get_ref());
+    assert_eq!(b"WARN slow\n", warnings.unwrap().get_ref());
+    assert_eq!(b"INFO up\nINFO down\n", rest.unwrap().get_ref());
+}
+
+#[test]
+fn count_sink_counts_distinct_records() {
+    let mut counts = CountSink::new();
+    for record in ["N", "V", "N", "PONCT", "N"].iter() {
+        counts.write_record(record.as_bytes()).unwrap();
+    }
+    assert_eq!(5, counts.total());
+    let seen: Vec<(Vec<u8>, u64)> = counts.counts().iter()
+        .map(|(k, &v)| (k.clone(), v)).collect();
+    assert_eq!(vec![(b"N".to_vec(), 3), (b"PONCT".to_vec(), 1),
+                    (b"V".to_vec(), 1)],
+               seen);
+}
+
+#[test]
+fn histogram_sink_counts_values() {
+    let mut histogram = HistogramSink::new();
+    histogram.add(2);
+    histogram.write_record(b"0").unwrap();
+    histogram.write_record(b"2").unwrap();
+    assert!(histogram.write_record(b"two").is_err());
+    assert_eq!(vec![1, 0, 2], histogram.counts().to_vec());
+    assert_eq!(3, histogram.total());
+}
+
+#[test]
+fn safe_file_writer_renames_on_commit() {
+    let dir = TempDir::new("sinks").unwrap();
+    let dest = dir.path().join("out.txt");
+    let mut writer = SafeFileWriter::create(&dest).unwrap();
+    writer.write(b"hello\n").unwrap();
+    assert!(!dest.exists());
+    writer.commit().unwrap();
+    assert_eq!(b"hello\n", File::open(&dest).read_to_end().unwrap().as_slice());
+    assert_eq!(1, readdir(dir.path()).unwrap().len());
+}
+
+#[test]
+fn safe_file_writer_cleans_up_without_commit() {
+    let dir = TempDir::new("sinks").unwrap();
+    let dest = dir.path().join("out.txt");
+    {
+        let mut writer = SafeFileWriter::create(&dest).unwrap();
+        writer.write(b"partial").unwrap();
+    }
+    assert!(!dest.exists());
+    assert_eq!(0, readdir(dir.path()).unwrap().len());
+}
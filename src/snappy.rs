@@ -0,0 +1,459 @@
+//! Reading and writing Snappy's framing format.
+//!
+//! A framed Snappy stream is a stream identifier followed by a sequence of
+//! chunks.  Each chunk has a type and a length, and data chunks start with
+//! a masked CRC-32C of their uncompressed contents.  The data itself may be
+//! Snappy-compressed, or stored as is.
+//!
+//! `SnappyFramedBuffer` decodes both kinds of data chunk, one chunk at a
+//! time, and verifies every checksum.  Chunks can't refer back into their
+//! predecessors, so we don't need to keep any history.  Padding and other
+//! skippable chunks are skipped, and concatenated streams are read
+//! straight through.
+//!
+//! `SnappyFramedWriter` only writes stored chunks, which every reader
+//! accepts.  Like `GzipWriter`, it produces the right container, without
+//! making anything smaller yet.
+
+use std::cmp::min;
+use std::io::{Buffer,EndOfFile,IoError,IoResult,OtherIoError,standard_error};
+
+use deflate::Crc32;
+
+#[cfg(test)] use std::io::{File,MemReader,MemWriter};
+#[cfg(test)] use buffers::DribbleBuffer;
+#[cfg(test)] use compress::check_damaged_streams;
+
+/// The chunk which starts every framed stream.
+static STREAM_IDENTIFIER: &'static [u8] =
+    &[0xff, 0x06, 0x00, 0x00, 0x73, 0x4e, 0x61, 0x50, 0x70, 0x59];
+
+static COMPRESSED_CHUNK: u8 = 0x00;
+static UNCOMPRESSED_CHUNK: u8 = 0x01;
+
+/// The most uncompressed data a chunk may hold.
+static MAX_CHUNK_DATA: uint = 65536;
+
+/// The most compressed data a chunk may hold: Snappy's worst case for
+/// `MAX_CHUNK_DATA` bytes of input.
+static MAX_COMPRESSED_DATA: uint = 32 + MAX_CHUNK_DATA + MAX_CHUNK_DATA / 6;
+
+fn corrupt(detail: &str) -> IoError {
+    IoError{kind: OtherIoError, desc: "corrupt Snappy data",
+            detail: Some(detail.to_string())}
+}
+
+// Running out of input anywhere except between chunks means the input was
+// truncated.
+fn no_eof<T>(result: IoResult<T>) -> IoResult<T> {
+    match result {
+        Err(IoError{kind: EndOfFile, ..}) => {
+            Err(corrupt("unexpected end of compressed data"))
+        }
+        other => other
+    }
+}
+
+// The CRC is rotated and offset, so that checksumming data which contains
+// embedded CRCs still works well.
+fn mask(crc: u32) -> u32 { (crc >> 15 | crc << 17) + 0xa282ead8 }
+
+// The masked CRC-32C of `data`, using `crc`'s table.
+fn checksum(crc: &mut Crc32, data: &[u8]) -> u32 {
+    crc.reset();
+    crc.update(data);
+    mask(crc.value())
+}
+
+// Read a little-endian number `bytes` long from the start of `src`.
+fn le_uint(src: &[u8], bytes: uint) -> uint {
+    let mut n = 0u;
+    for i in range(0, bytes) { n |= (src[i] as uint) << (8 * i); }
+    n
+}
+
+// Decompress the Snappy block `src`, appending the result to `out`, which
+// must start out empty.  A block is a varint giving its uncompressed
+// length, followed by a sequence of literals and copies.
+fn decode_block(src: &[u8], out: &mut Vec<u8>) -> IoResult<()> {
+    let mut len = 0u;
+    let mut i = 0u;
+    loop {
+        // A chunk's length always fits in three bytes.
+        if i >= src.len() || i == 3 { return Err(corrupt("bad block length")); }
+        let byte = src[i];
+        len |= ((byte & 0x7f) as uint) << (7 * i);
+        i += 1;
+        if byte & 0x80 == 0 { break; }
+    }
+    if len > MAX_CHUNK_DATA { return Err(corrupt("block too large")); }
+
+    while i < src.len() {
+        let tag = src[i];
+        i += 1;
+        // The low two bits give the element type, and the rest hold a
+        // length, or part of one.
+        let (extra, copy_len) = match tag & 0x3 {
+            0 => {
+                let mut literals = (tag >> 2) as uint;
+                if literals >= 60 {
+                    let bytes = literals - 59;
+                    if bytes > src.len() - i {
+                        return Err(corrupt("truncated literal length"));
+                    }
+                    literals = le_uint(src.slice_from(i), bytes);
+                    i += bytes;
+                }
+                // Stored minus one, so it can't be zero, but it might
+                // overflow if we added one first.
+                if literals >= src.len() - i || literals >= len - out.len() {
+                    return Err(corrupt("too many literals"));
+                }
+                out.push_all(src.slice(i, i + literals + 1));
+                i += literals + 1;
+                continue;
+            }
+            1 => (1, ((tag >> 2) & 0x7) as uint + 4),
+            2 => (2, (tag >> 2) as uint + 1),
+            _ => (4, (tag >> 2) as uint + 1)
+        };
+        if extra > src.len() - i { return Err(corrupt("truncated copy")); }
+        let offset = if extra == 1 {
+            ((tag >> 5) as uint) << 8 | src[i] as uint
+        } else {
+            le_uint(src.slice_from(i), extra)
+        };
+        i += extra;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("bad copy offset"));
+        }
+        if copy_len > len - out.len() { return Err(corrupt("copy too long")); }
+        // Copies may overlap their own output, so copy a byte at a time.
+        for _ in range(0, copy_len) {
+            let byte = out[out.len() - offset];
+            out.push(byte);
+        }
+    }
+    if out.len() != len { return Err(corrupt("block length mismatch")); }
+    Ok(())
+}
+
+/// A `Buffer` which decompresses Snappy's framing format from another
+/// `Buffer`.
+pub struct SnappyFramedBuffer<R> {
+    input: R,
+    crc: Crc32,
+    // Have we seen a stream identifier yet?
+    started: bool,
+    done: bool,
+    compressed: Vec<u8>,
+    out: Vec<u8>,
+    pos: uint
+}
+
+impl<R: Buffer> SnappyFramedBuffer<R> {
+    /// Decompress framed Snappy data from `input`.
+    pub fn new(input: R) -> SnappyFramedBuffer<R> {
+        SnappyFramedBuffer{input: input, crc: Crc32::castagnoli(),
+                           started: false, done: false, compressed: vec![],
+                           out: vec![], pos: 0}
+    }
+
+    /// Get back our underlying buffer.
+    pub fn unwrap(self) -> R { self.input }
+
+    fn at_eof(&mut self) -> IoResult<bool> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => { if buf.len() > 0 { return Ok(false); } }
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(true); }
+                Err(err) => { return Err(err); }
+            }
+        }
+    }
+
+    // Skip `len` bytes of input.
+    fn skip(&mut self, mut len: uint) -> IoResult<()> {
+        while len > 0 {
+            let amt = {
+                let buf = try!(no_eof(self.input.fill_buf()));
+                min(buf.len(), len)
+            };
+            self.input.consume(amt);
+            len -= amt;
+        }
+        Ok(())
+    }
+
+    // Read the next chunk, leaving any data it holds in `out`.
+    fn read_chunk(&mut self) -> IoResult<()> {
+        let header = try!(no_eof(self.input.read_exact(4)));
+        let kind = header[0];
+        let len = le_uint(header.slice_from(1), 3);
+        if kind == STREAM_IDENTIFIER[0] {
+            // The identifier may appear again, between concatenated
+            // streams, but it must always be intact.
+            if header.as_slice() != STREAM_IDENTIFIER.slice_to(4) {
+                return Err(corrupt("bad stream identifier"));
+            }
+            let body = try!(no_eof(self.input.read_exact(len)));
+            if body.as_slice() != STREAM_IDENTIFIER.slice_from(4) {
+                return Err(corrupt("bad stream identifier"));
+            }
+            self.started = true;
+            return Ok(());
+        }
+        if !self.started {
+            return Err(corrupt("missing stream identifier"));
+        }
+        if kind != COMPRESSED_CHUNK && kind != UNCOMPRESSED_CHUNK {
+            // 0x02 to 0x7f are reserved for chunks we'd need to understand,
+            // and 0x80 up are padding or safe to skip.
+            if kind < 0x80 { return Err(corrupt("unskippable chunk type")); }
+            return self.skip(len);
+        }
+
+        if len < 4 { return Err(corrupt("chunk too short")); }
+        let expected = try!(no_eof(self.input.read_le_u32()));
+        let len = len - 4;
+        self.out.clear();
+        self.pos = 0;
+        if kind == UNCOMPRESSED_CHUNK {
+            if len > MAX_CHUNK_DATA { return Err(corrupt("chunk too large")); }
+            try!(no_eof(self.input.push_at_least(len, len, &mut self.out)));
+        } else {
+            if len > MAX_COMPRESSED_DATA {
+                return Err(corrupt("chunk too large"));
+            }
+            self.compressed.clear();
+            try!(no_eof(self.input.push_at_least(len, len,
+                                                 &mut self.compressed)));
+            try!(decode_block(self.compressed.as_slice(), &mut self.out));
+        }
+        if checksum(&mut self.crc, self.out.as_slice()) != expected {
+            return Err(corrupt("checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Buffer> Reader for SnappyFramedBuffer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let count = {
+            let data = try!(self.fill_buf());
+            let count = min(data.len(), buf.len());
+            buf.slice_to_mut(count).clone_from_slice(data.slice_to(count));
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: Buffer> Buffer for SnappyFramedBuffer<R> {
+    fn fill_buf<'a>(&'a mut self) -> IoResult<&'a [u8]> {
+        while self.pos == self.out.len() {
+            if self.done { return Err(standard_error(EndOfFile)); }
+            if try!(self.at_eof()) {
+                if !self.started {
+                    return Err(corrupt("missing stream identifier"));
+                }
+                self.done = true;
+            } else {
+                try!(self.read_chunk());
+            }
+        }
+        Ok(self.out.slice_from(self.pos))
+    }
+
+    fn consume(&mut self, amt: uint) {
+        self.pos += amt;
+        assert!(self.pos <= self.out.len());
+    }
+}
+
+/// A `Writer` which produces Snappy's framing format, using stored chunks.
+/// Call `finish` when you're done, to write out the last chunk.
+pub struct SnappyFramedWriter<W> {
+    output: W,
+    // Data for the next chunk.
+    chunk: Vec<u8>,
+    crc: Crc32,
+    started: bool,
+    finished: bool
+}
+
+impl<W: Writer> SnappyFramedWriter<W> {
+    /// Write framed Snappy data to `output`.
+    pub fn new(output: W) -> SnappyFramedWriter<W> {
+        SnappyFramedWriter{output: output,
+                           chunk: Vec::with_capacity(MAX_CHUNK_DATA),
+                           crc: Crc32::castagnoli(), started: false,
+                           finished: false}
+    }
+
+    /// Write any buffered data, and flush the underlying writer.  Writing
+    /// anything else afterwards is an error.
+    pub fn finish(&mut self) -> IoResult<()> {
+        if self.finished { return Ok(()); }
+        try!(self.write_chunk());
+        self.finished = true;
+        self.output.flush()
+    }
+
+    /// Get back the underlying writer.
+    pub fn unwrap(self) -> W { self.output }
+
+    // Write out `chunk`, if it isn't empty, preceded by the stream
+    // identifier if we haven't written it yet.
+    fn write_chunk(&mut self) -> IoResult<()> {
+        if !self.started {
+            try!(self.output.write(STREAM_IDENTIFIER));
+            self.started = true;
+        }
+        if self.chunk.is_empty() { return Ok(()); }
+        let crc = checksum(&mut self.crc, self.chunk.as_slice());
+        // The length is 24 bits, and includes the CRC.
+        let len = self.chunk.len() + 4;
+        try!(self.output.write(&[UNCOMPRESSED_CHUNK, len as u8,
+                                 (len >> 8) as u8, (len >> 16) as u8]));
+        try!(self.output.write_le_u32(crc));
+        try!(self.output.write(self.chunk.as_slice()));
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl<W: Writer> Writer for SnappyFramedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        if self.finished {
+            return Err(IoError{kind: OtherIoError,
+                               desc: "Snappy stream already finished",
+                               detail: None});
+        }
+        let mut rest = buf;
+        while rest.len() > 0 {
+            let n = min(rest.len(), MAX_CHUNK_DATA - self.chunk.len());
+            self.chunk.push_all(rest.slice_to(n));
+            rest = rest.slice_from(n);
+            if self.chunk.len() == MAX_CHUNK_DATA {
+                try!(self.write_chunk());
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if !self.finished && self.chunk.len() > 0 {
+            try!(self.write_chunk());
+        }
+        self.output.flush()
+    }
+}
+
+#[test]
+fn writes_stored_chunks() {
+    let mut writer = SnappyFramedWriter::new(MemWriter::new());
+    writer.write(b"hello").unwrap();
+    writer.finish().unwrap();
+    assert!(writer.write(b"late").is_err());
+    let out = writer.unwrap().unwrap();
+    assert_eq!(STREAM_IDENTIFIER, out.slice_to(10));
+    assert_eq!(vec![UNCOMPRESSED_CHUNK, 9, 0, 0], out.slice(10, 14).to_vec());
+    let crc = checksum(&mut Crc32::castagnoli(), b"hello");
+    assert_eq!(vec![crc as u8, (crc >> 8) as u8, (crc >> 16) as u8,
+                    (crc >> 24) as u8],
+               out.slice(14, 18).to_vec());
+    assert_eq!(b"hello", out.slice_from(18));
+}
+
+#[test]
+fn splits_long_writes_into_chunks() {
+    let mut writer = SnappyFramedWriter::new(MemWriter::new());
+    writer.write(Vec::from_elem(70000, b'x').as_slice()).unwrap();
+    writer.finish().unwrap();
+    let out = writer.unwrap().unwrap();
+    assert_eq!(10 + 8 + 65536 + 8 + 4464, out.len());
+    // 4464 bytes of data, plus the CRC, is 0x1174.
+    assert_eq!(vec![UNCOMPRESSED_CHUNK, 0x74, 0x11, 0],
+               out.slice(10 + 8 + 65536, 10 + 8 + 65536 + 4).to_vec());
+
+    let mut empty = SnappyFramedWriter::new(MemWriter::new());
+    empty.finish().unwrap();
+    assert_eq!(STREAM_IDENTIFIER, empty.unwrap().unwrap().as_slice());
+}
+
+#[test]
+fn decompresses_snappy_frames() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.sz"))
+        .read_to_end().unwrap();
+    let mut reader = MemReader::new(compressed);
+    let mut snappy = SnappyFramedBuffer::new(DribbleBuffer::new(&mut reader));
+    assert_eq!(expected, snappy.read_to_end().unwrap());
+}
+
+#[test]
+fn decodes_long_literals_and_far_copies() {
+    // A 70-byte literal needs an extra length byte, and we copy its first
+    // 5 bytes using a 4-byte offset.
+    let literal: Vec<u8> = range(0u8, 70).collect();
+    let mut block = vec![75, 60 << 2, 69];
+    block.push_all(literal.as_slice());
+    block.push_all(&[3 | 4 << 2, 70, 0, 0, 0]);
+    let mut out = vec![];
+    decode_block(block.as_slice(), &mut out).unwrap();
+    assert_eq!(literal.as_slice(), out.slice_to(70));
+    assert_eq!(literal.slice_to(5), out.slice_from(70));
+
+    // Copying from before the start of the output is an error.
+    let mut out = vec![];
+    assert!(decode_block(&[5, 0, b'a', 3 << 2 | 2, 2, 0], &mut out).is_err());
+}
+
+#[test]
+fn reads_what_the_writer_writes() {
+    let expected = File::open(&Path::new("test_data/fr/sample.conllx"))
+        .read_to_end().unwrap();
+    let mut writer = SnappyFramedWriter::new(MemWriter::new());
+    for _ in range(0u, 20) { writer.write(expected.as_slice()).unwrap(); }
+    writer.finish().unwrap();
+    let mut framed = writer.unwrap().unwrap();
+    // Add some padding, and a second stream.
+    framed.push_all(&[0xfe, 3, 0, 0, 0, 0, 0]);
+    let mut second = SnappyFramedWriter::new(MemWriter::new());
+    second.write(b"the end").unwrap();
+    second.finish().unwrap();
+    framed.push_all(second.unwrap().unwrap().as_slice());
+
+    let mut snappy = SnappyFramedBuffer::new(MemReader::new(framed));
+    let decoded = snappy.read_to_end().unwrap();
+    assert_eq!(20 * expected.len() + 7, decoded.len());
+    assert_eq!(expected.as_slice(), decoded.slice_to(expected.len()));
+    assert_eq!(b"the end", decoded.slice_from(20 * expected.len()));
+}
+
+#[test]
+fn rejects_reserved_and_corrupt_chunks() {
+    let mut reserved = STREAM_IDENTIFIER.to_vec();
+    reserved.push_all(&[0x02, 1, 0, 0, 0]);
+    let mut snappy = SnappyFramedBuffer::new(MemReader::new(reserved));
+    assert!(snappy.read_to_end().is_err());
+
+    let mut compressed =
+        File::open(&Path::new("test_data/fr/sample.conllx.sz"))
+        .read_to_end().unwrap();
+    compressed.as_mut_slice()[200] ^= 0x01;
+    let mut snappy = SnappyFramedBuffer::new(MemReader::new(compressed));
+    assert!(snappy.read_to_end().is_err());
+}
+
+#[test]
+fn survives_truncated_and_damaged_streams() {
+    let compressed = File::open(&Path::new("test_data/fr/sample.conllx.sz"))
+        .read_to_end().unwrap();
+    check_damaged_streams(compressed.as_slice(), |data| {
+        SnappyFramedBuffer::new(MemReader::new(data)).read_to_end()
+    });
+}
@@ -0,0 +1,150 @@
+//! Splitting a file into record-aligned byte ranges.
+//!
+//! If you want to process one big file on several tasks (or machines), you
+//! need to cut it up so that every record lands in exactly one piece.
+//! `split_ranges` picks evenly spaced offsets, slides each one forward to
+//! the start of the next record, and hands back one reader per range.
+
+use std::io::{Buffer,BufferedReader,EndOfFile,File,IoError,IoResult,SeekSet};
+use std::io::util::LimitReader;
+
+#[cfg(test)] use std::io::Reader;
+
+/// How records are delimited within a file.
+#[deriving(PartialEq, Clone, Show)]
+pub enum RecordFormat {
+    /// One record per `\n`-terminated line.
+    Lines,
+    /// CSV records, which may contain newlines inside double-quoted
+    /// fields.
+    Csv
+}
+
+/// A reader over a single range returned by `split_ranges`.
+pub type RangeReader = LimitReader<BufferedReader<File>>;
+
+/// Split the file at `path` into `n` ranges which start and end on record
+/// boundaries, and return a reader for each.  Together, the ranges cover
+/// the entire file with no overlaps.  Some ranges may be empty if the file
+/// contains fewer than `n` records.
+pub fn split_ranges(path: &Path, n: uint, format: RecordFormat)
+                    -> IoResult<Vec<RangeReader>> {
+    assert!(n > 0);
+    let size = try!(try!(File::open(path)).stat()).size;
+    let starts = try!(record_starts(path, size, n, format));
+
+    let mut readers = Vec::with_capacity(n);
+    for (i, &start) in starts.iter().enumerate() {
+        let end = if i + 1 < starts.len() { starts[i+1] } else { size };
+        let mut file = try!(File::open(path));
+        try!(file.seek(start as i64, SeekSet));
+        readers.push(LimitReader::new(BufferedReader::new(file),
+                                      (end - start) as uint));
+    }
+    Ok(readers)
+}
+
+// Find the starting offset of each of our `n` ranges.
+fn record_starts(path: &Path, size: u64, n: uint, format: RecordFormat)
+                 -> IoResult<Vec<u64>> {
+    let mut starts = vec![0u64];
+    let mut file = try!(File::open(path));
+    let mut pos = 0u64;
+    let mut in_quotes = false;
+    for i in range(1, n) {
+        let target = size * i as u64 / n as u64;
+        if format == Lines && pos < target {
+            // Line boundaries don't depend on anything we've skipped, so
+            // jump straight to the byte before our target.  (We may land
+            // right after a newline, and that counts as a boundary.)
+            pos = target - 1;
+            try!(file.seek(pos as i64, SeekSet));
+        }
+        // We need to rebuild our buffer after every seek.  For CSV, we
+        // never seek, and we need to look at every byte anyway, because
+        // the quoting state depends on everything that came before.
+        let mut reader = BufferedReader::new(file);
+        try!(scan_to(&mut reader, &mut pos, target, format, &mut in_quotes));
+        file = reader.unwrap();
+        try!(file.seek(pos as i64, SeekSet));
+        starts.push(pos);
+    }
+    Ok(starts)
+}
+
+// Advance `pos` until it points at a record boundary at or after `target`,
+// or at the end of the file.
+fn scan_to<R: Buffer>(reader: &mut R, pos: &mut u64, target: u64,
+                      format: RecordFormat, in_quotes: &mut bool)
+                      -> IoResult<()> {
+    // We only ever stop on a boundary, so if we've already passed our
+    // target, we're done.
+    if *pos >= target { return Ok(()); }
+    loop {
+        let (used, found) = {
+            let buf = match reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(IoError{kind: EndOfFile, ..}) => { return Ok(()); }
+                Err(err) => { return Err(err); }
+            };
+            let mut used = 0u;
+            let mut found = false;
+            for &b in buf.iter() {
+                used += 1;
+                if b == b'"' && format == Csv {
+                    *in_quotes = !*in_quotes;
+                } else if b == b'\n' && !*in_quotes &&
+                    *pos + used as u64 >= target {
+                    found = true;
+                    break;
+                }
+            }
+            (used, found)
+        };
+        reader.consume(used);
+        *pos += used as u64;
+        if found { return Ok(()); }
+    }
+}
+
+#[cfg(test)]
+fn check_split(path: &Path, n: uint, format: RecordFormat) {
+    let original = File::open(path).read_to_end().unwrap();
+    let mut readers = split_ranges(path, n, format).unwrap();
+    assert_eq!(n, readers.len());
+    let mut joined = vec![];
+    for reader in readers.iter_mut() {
+        let range = reader.read_to_end().unwrap();
+        if joined.len() > 0 && range.len() > 0 {
+            assert_eq!(Some(&b'\n'), joined.last());
+        }
+        joined.push_all(range.as_slice());
+    }
+    assert_eq!(original, joined);
+}
+
+#[test]
+fn split_ranges_by_lines() {
+    let path = Path::new("test_data/fr/sample.conllx");
+    for n in range(1u, 8) { check_split(&path, n, Lines); }
+}
+
+#[test]
+fn split_ranges_as_csv() {
+    let path = Path::new("test_data/fr/sample.conllx");
+    for n in range(1u, 8) { check_split(&path, n, Csv); }
+}
+
+#[test]
+fn split_ranges_keeps_quoted_newlines_together() {
+    let path = Path::new("test_data/quoted_newlines.csv");
+    for n in range(1u, 12) {
+        check_split(&path, n, Csv);
+        // Every range must hold whole records, so its quotes balance.
+        for reader in split_ranges(&path, n, Csv).unwrap().iter_mut() {
+            let range = reader.read_to_end().unwrap();
+            let quotes = range.iter().filter(|&&b| b == b'"').count();
+            assert!(quotes % 2 == 0, "{} ranges", n);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Reading lines from either end.
+//!
+//! To show the last few records of a big log, there's no need to read the
+//! whole thing.  `SliceLines` and `SeekLines` are double-ended, so `Rev`
+//! can read them backwards, starting from the end:
+//!
+//! ```ignore
+//! let lines = try!(SeekLines::new(try!(File::open(&path))));
+//! let mut last = Take::new(Rev::new(lines), 10);
+//! ```
+//!
+//! Lines are yielded without their terminators.  A newline at the very end
+//! of the input ends the last line, rather than starting an empty one.
+
+use std::cmp::min;
+use std::io::{IoResult,SeekEnd,SeekSet};
+
+use iter::{StreamingDoubleEndedIterator,StreamingIterator};
+
+#[cfg(test)] use std::io::{File,MemReader,TempDir};
+#[cfg(test)] use iter::{Rev,Take};
+
+// How much we read at once while looking for a newline.
+static BLOCK_SIZE: uint = 4096;
+
+/// A double-ended iterator over the lines of an in-memory slice.
+pub struct SliceLines<'b> {
+    data: &'b [u8]
+}
+
+impl<'b> SliceLines<'b> {
+    /// Iterate over the lines of `data`.
+    pub fn new(data: &'b [u8]) -> SliceLines<'b> {
+        SliceLines{data: data}
+    }
+}
+
+impl<'a, 'b> StreamingIterator<'a, &'b [u8]> for SliceLines<'b> {
+    fn next(&'a mut self) -> Option<&'b [u8]> {
+        if self.data.is_empty() { return None; }
+        match self.data.iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                let line = self.data.slice_to(pos);
+                self.data = self.data.slice_from(pos + 1);
+                Some(line)
+            }
+            None => {
+                let line = self.data;
+                self.data = &[];
+                Some(line)
+            }
+        }
+    }
+}
+
+impl<'a, 'b> StreamingDoubleEndedIterator<'a, &'b [u8]> for SliceLines<'b> {
+    fn next_back(&'a mut self) -> Option<&'b [u8]> {
+        if self.data.is_empty() { return None; }
+        let mut end = self.data.len();
+        if self.data[end - 1] == b'\n' { end -= 1; }
+        let start = match self.data.slice_to(end).iter()
+            .rposition(|&b| b == b'\n') {
+            Some(pos) => pos + 1,
+            None => 0
+        };
+        let line = self.data.slice(start, end);
+        self.data = self.data.slice_to(start);
+        Some(line)
+    }
+}
+
+/// A double-ended iterator over the lines of a seekable reader, such as a
+/// `File`.  We keep track of the unread byte range, and only read the
+/// parts of it we need.  If an error occurs, we return it, and stop.
+pub struct SeekLines<R> {
+    input: R,
+    // The unread part of the input.  Both are at the start of a line, or
+    // the end of the input.
+    front: u64,
+    back: u64,
+    line: Vec<u8>,
+    block: Vec<u8>
+}
+
+impl<R: Reader+Seek> SeekLines<R> {
+    /// Iterate over the lines of `input`, from its current position to the
+    /// end.
+    pub fn new(mut input: R) -> IoResult<SeekLines<R>> {
+        let front = try!(input.tell());
+        try!(input.seek(0, SeekEnd));
+        let back = try!(input.tell());
+        Ok(SeekLines{input: input, front: front, back: back, line: vec![],
+                     block: vec![]})
+    }
+
+    // Read exactly `len` bytes at `pos` into `buf`, replacing its contents.
+    fn read_at(&mut self, pos: u64, len: uint, line: bool) -> IoResult<()> {
+        let buf = if line { &mut self.line } else { &mut self.block };
+        buf.clear();
+        try!(self.input.seek(pos as i64, SeekSet));
+        try!(self.input.push_at_least(len, len, buf));
+        Ok(())
+    }
+
+    // Read the first remaining line into `line`.
+    fn read_front(&mut self) -> IoResult<()> {
+        let mut pos = self.front;
+        let mut end = self.back;
+        let mut terminated = false;
+        while pos < self.back {
+            let len = min(BLOCK_SIZE as u64, self.back - pos) as uint;
+            try!(self.read_at(pos, len, false));
+            match self.block.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    end = pos + i as u64;
+                    terminated = true;
+                    break;
+                }
+                None => { pos += len as u64; }
+            }
+        }
+        let front = self.front;
+        try!(self.read_at(front, (end - front) as uint, true));
+        self.front = if terminated { end + 1 } else { end };
+        Ok(())
+    }
+
+    // Read the last remaining line into `line`.
+    fn read_back(&mut self) -> IoResult<()> {
+        let mut end = self.back;
+        try!(self.read_at(end - 1, 1, false));
+        if self.block[0] == b'\n' { end -= 1; }
+        let mut pos = end;
+        let mut start = self.front;
+        while pos > self.front {
+            let len = min(BLOCK_SIZE as u64, pos - self.front) as uint;
+            try!(self.read_at(pos - len as u64, len, false));
+            match self.block.iter().rposition(|&b| b == b'\n') {
+                Some(i) => {
+                    start = pos - len as u64 + i as u64 + 1;
+                    break;
+                }
+                None => { pos -= len as u64; }
+            }
+        }
+        try!(self.read_at(start, (end - start) as uint, true));
+        self.back = start;
+        Ok(())
+    }
+}
+
+impl<'a, R: Reader+Seek> StreamingIterator<'a, IoResult<&'a [u8]>>
+    for SeekLines<R> {
+
+    fn next(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        if self.front >= self.back { return None; }
+        match self.read_front() {
+            Ok(()) => Some(Ok(self.line.as_slice())),
+            Err(err) => { self.front = self.back; Some(Err(err)) }
+        }
+    }
+}
+
+impl<'a, R: Reader+Seek> StreamingDoubleEndedIterator<'a, IoResult<&'a [u8]>>
+    for SeekLines<R> {
+
+    fn next_back(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        if self.front >= self.back { return None; }
+        match self.read_back() {
+            Ok(()) => Some(Ok(self.line.as_slice())),
+            Err(err) => { self.back = self.front; Some(Err(err)) }
+        }
+    }
+}
+
+#[test]
+fn slice_lines_meet_in_the_middle() {
+    let mut lines = SliceLines::new(b"a\nbb\n\nccc\n");
+    assert_eq!(b"a", lines.next().unwrap());
+    assert_eq!(b"ccc", lines.next_back().unwrap());
+    assert_eq!(b"", lines.next_back().unwrap());
+    assert_eq!(b"bb", lines.next().unwrap());
+    assert!(lines.next().is_none() && lines.next_back().is_none());
+}
+
+#[test]
+fn seek_lines_tail_a_file() {
+    let dir = TempDir::new("tail").unwrap();
+    let path = dir.path().join("log");
+    let mut data = vec![];
+    for i in range(0u, 2000) {
+        data.push_all(format!("line {}\n", i).as_bytes());
+    }
+    data.push_all(b"no newline");
+    File::create(&path).write(data.as_slice()).unwrap();
+
+    let lines = SeekLines::new(File::open(&path).unwrap()).unwrap();
+    let mut last = Take::new(Rev::new(lines), 3);
+    let mut seen = vec![];
+    streaming_for!(line in last, {
+        seen.push(String::from_utf8(line.unwrap().to_vec()).unwrap());
+    });
+    assert_eq!(vec!["no newline".to_string(), "line 1999".to_string(),
+                    "line 1998".to_string()], seen);
+
+    let mut lines = SeekLines::new(MemReader::new(b"x\ny\n".to_vec())).unwrap();
+    assert_eq!(b"x", lines.next().unwrap().unwrap());
+    assert_eq!(b"y", lines.next_back().unwrap().unwrap());
+    assert!(lines.next().is_none() && lines.next_back().is_none());
+}
@@ -0,0 +1,230 @@
+//! A small lexer for recursive-descent parsers.
+//!
+//! `Tokens` splits its input into words, numbers, quoted strings and
+//! punctuation, one line at a time, and yields each token as a slice of
+//! its line buffer.  Recursive-descent parsers often need to look at a
+//! token, decide it belongs to their caller, and put it back.  Since the
+//! token borrows the lexer, you make an `OwnedToken` copy first, and hand
+//! that to `push_back`.  There's room for one pushed-back token, which is
+//! all an LL(1) grammar ever needs.
+
+use std::io::{Buffer,InvalidInput,IoError,IoResult};
+
+use buffers::read_until_into;
+use iter::{OwnedCopy,StreamingIterator};
+
+#[cfg(test)] use std::io::MemReader;
+
+/// What sort of token is this?
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum TokenKind {
+    /// Letters, digits and underscores, starting with a letter or
+    /// underscore.
+    Word,
+    /// Digits, with an optional fraction.
+    Number,
+    /// A double-quoted string.  The text includes the quotes, and any
+    /// backslash escapes are left alone.
+    Quoted,
+    /// Any other single byte.
+    Punct
+}
+
+/// A token, borrowed from the lexer's buffer.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Token<'a> {
+    /// What sort of token this is.
+    pub kind: TokenKind,
+    /// The token's text.
+    pub text: &'a [u8]
+}
+
+/// An owned copy of a `Token`.
+#[deriving(PartialEq, Clone, Show)]
+pub struct OwnedToken {
+    /// What sort of token this is.
+    pub kind: TokenKind,
+    /// The token's text.
+    pub text: Vec<u8>
+}
+
+impl<'a> OwnedCopy<OwnedToken> for Token<'a> {
+    fn owned_copy(&self) -> OwnedToken {
+        OwnedToken{kind: self.kind, text: self.text.to_vec()}
+    }
+
+    fn copy_into(&self, dest: &mut OwnedToken) {
+        dest.kind = self.kind;
+        dest.text.clear();
+        dest.text.push_all(self.text);
+    }
+
+    fn same_as(&self, copy: &OwnedToken) -> bool {
+        self.kind == copy.kind && self.text == copy.text.as_slice()
+    }
+}
+
+fn is_word_start(b: u8) -> bool {
+    (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') || b == b'_'
+}
+
+fn is_digit(b: u8) -> bool { b >= b'0' && b <= b'9' }
+
+fn is_space(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\r' || b == b'\n'
+}
+
+/// Splits `input` into tokens.  Whitespace, including newlines, separates
+/// tokens and is otherwise ignored.
+pub struct Tokens<'b, T: Buffer+'b> {
+    input: &'b mut T,
+    line: Vec<u8>,
+    pos: uint,
+    // The last pushed-back token.  We keep it around after replaying it, so
+    // its storage can be reused.
+    pushed: Option<OwnedToken>,
+    replay: bool
+}
+
+impl<'b, T: Buffer+'b> Tokens<'b, T> {
+    /// Read tokens from `input`.
+    pub fn new(input: &'b mut T) -> Tokens<'b, T> {
+        Tokens{input: input, line: vec![], pos: 0, pushed: None,
+               replay: false}
+    }
+
+    /// Make `token` the next one we return.  Fails if there's already a
+    /// pushed-back token which hasn't been read again.
+    pub fn push_back(&mut self, token: OwnedToken) {
+        assert!(!self.replay, "can only push back one token");
+        self.pushed = Some(token);
+        self.replay = true;
+    }
+
+    // Skip whitespace, reading more lines as needed.  Returns false at the
+    // end of the input.
+    fn skip_space(&mut self) -> IoResult<bool> {
+        loop {
+            while self.pos < self.line.len() && is_space(self.line[self.pos]) {
+                self.pos += 1;
+            }
+            if self.pos < self.line.len() { return Ok(true); }
+            self.line.clear();
+            self.pos = 0;
+            if !try!(read_until_into(self.input, b'\n', &mut self.line)) {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Find the kind and end of the token starting at `pos`.
+    fn scan(&self) -> IoResult<(TokenKind, uint)> {
+        let line = self.line.as_slice();
+        let start = self.pos;
+        let mut end = start + 1;
+        let first = line[start];
+        if is_word_start(first) {
+            while end < line.len() &&
+                (is_word_start(line[end]) || is_digit(line[end])) {
+                end += 1;
+            }
+            Ok((Word, end))
+        } else if is_digit(first) {
+            while end < line.len() && is_digit(line[end]) { end += 1; }
+            if end + 1 < line.len() && line[end] == b'.' &&
+                is_digit(line[end + 1]) {
+                end += 1;
+                while end < line.len() && is_digit(line[end]) { end += 1; }
+            }
+            Ok((Number, end))
+        } else if first == b'"' {
+            while end < line.len() && line[end] != b'"' {
+                if line[end] == b'\\' { end += 1; }
+                end += 1;
+            }
+            if end >= line.len() {
+                return Err(IoError{kind: InvalidInput,
+                                   desc: "unterminated string",
+                                   detail: None});
+            }
+            Ok((Quoted, end + 1))
+        } else {
+            Ok((Punct, end))
+        }
+    }
+}
+
+impl<'a, 'b, T: Buffer+'b> StreamingIterator<'a, IoResult<Token<'a>>>
+    for Tokens<'b, T> {
+
+    fn next(&'a mut self) -> Option<IoResult<Token<'a>>> {
+        if self.replay {
+            self.replay = false;
+            let pushed = self.pushed.as_ref().unwrap();
+            return Some(Ok(Token{kind: pushed.kind,
+                                 text: pushed.text.as_slice()}));
+        }
+        match self.skip_space() {
+            Ok(true) => {}
+            Ok(false) => { return None; }
+            Err(err) => { return Some(Err(err)); }
+        }
+        match self.scan() {
+            Ok((kind, end)) => {
+                let start = self.pos;
+                self.pos = end;
+                Some(Ok(Token{kind: kind, text: self.line.slice(start, end)}))
+            }
+            Err(err) => {
+                self.pos = self.line.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// Parse `word (, word)*`, stopping at anything else and leaving it for our
+// caller.
+#[cfg(test)]
+fn parse_list<'b>(tokens: &mut Tokens<'b, MemReader>) -> Vec<String> {
+    let mut words = vec![];
+    loop {
+        let owned = match tokens.next() {
+            Some(Ok(token)) => token.owned_copy(),
+            other => fail!("unexpected {}", other)
+        };
+        if owned.kind != Word {
+            tokens.push_back(owned);
+            return words;
+        }
+        words.push(String::from_utf8(owned.text).unwrap());
+        let separator = match tokens.next() {
+            None => { return words; }
+            Some(Ok(token)) => token.owned_copy(),
+            Some(Err(err)) => fail!("unexpected {}", err)
+        };
+        if separator.text.as_slice() != b"," {
+            tokens.push_back(separator);
+            return words;
+        }
+    }
+}
+
+#[test]
+fn pushed_back_tokens_are_returned_again() {
+    let mut input = MemReader::new(b"(a, b2,\n c) 12.5 \"x\\\"y\"".to_vec());
+    let mut tokens = Tokens::new(&mut input);
+    assert_eq!(Some(Ok(Token{kind: Punct, text: b"("})), tokens.next());
+    assert_eq!(vec!["a".to_string(), "b2".to_string(), "c".to_string()],
+               parse_list(&mut tokens));
+    assert_eq!(Some(Ok(Token{kind: Punct, text: b")"})), tokens.next());
+    assert_eq!(Some(Ok(Token{kind: Number, text: b"12.5"})), tokens.next());
+    assert_eq!(Some(Ok(Token{kind: Quoted, text: b"\"x\\\"y\""})),
+               tokens.next());
+    assert_eq!(None, tokens.next());
+
+    let mut bad = MemReader::new(b"\"open".to_vec());
+    let mut tokens = Tokens::new(&mut bad);
+    assert_eq!(InvalidInput, tokens.next().unwrap().unwrap_err().kind);
+    assert_eq!(None, tokens.next());
+}
@@ -0,0 +1,152 @@
+//! What to do about invalid UTF-8.
+//!
+//! Real-world corpora mix encodings, and a hard error on byte 80 billion is
+//! often the wrong default.  A `Utf8Policy` says how a pipeline should
+//! treat records which aren't valid UTF-8: reject them, replace each bad
+//! sequence with U+FFFD, or pass the bytes through untouched.  Valid
+//! records are never copied, and replacement only copies the records which
+//! need it, into a scratch buffer which is reused.
+//!
+//! Bad sequences are replaced the same way as `String::from_utf8_lossy`:
+//! each maximal prefix of a valid sequence becomes one U+FFFD.
+
+use std::io::{InvalidInput,IoError,IoResult};
+
+use iter::StreamingIterator;
+
+#[cfg(test)] use std::str::from_utf8;
+
+/// How to handle invalid UTF-8.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Utf8Policy {
+    /// Fail with an `InvalidInput` error.
+    Strict,
+    /// Replace each invalid sequence with U+FFFD.
+    Replace,
+    /// Leave the bytes alone.
+    PassThrough
+}
+
+static REPLACEMENT: &'static [u8] = b"\xef\xbf\xbd";
+
+impl Utf8Policy {
+    /// Apply this policy to `record`, returning either `record` itself, or
+    /// a repaired copy stored in `scratch`.
+    pub fn apply<'a>(&self, record: &'a [u8], scratch: &'a mut Vec<u8>)
+                     -> IoResult<&'a [u8]> {
+        if *self == PassThrough { return Ok(record); }
+        let (mut start, mut len) = match find_invalid(record) {
+            None => { return Ok(record); }
+            Some(bad) => bad
+        };
+        if *self == Strict {
+            return Err(IoError{kind: InvalidInput, desc: "invalid UTF-8",
+                               detail: Some(format!("at byte {}", start))});
+        }
+        scratch.clear();
+        let mut rest = record;
+        loop {
+            scratch.push_all(rest.slice_to(start));
+            scratch.push_all(REPLACEMENT);
+            rest = rest.slice_from(start + len);
+            match find_invalid(rest) {
+                None => { break; }
+                Some((s, l)) => { start = s; len = l; }
+            }
+        }
+        scratch.push_all(rest);
+        Ok(scratch.as_slice())
+    }
+}
+
+/// Find the first invalid sequence in `bytes`, and return its offset and
+/// length.
+pub fn find_invalid(bytes: &[u8]) -> Option<(uint, uint)> {
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b < 0x80 { i += 1; continue; }
+        // How many continuation bytes we need, and the allowed range of
+        // the first one, which rules out overlong forms and surrogates.
+        let (needed, lo, hi) =
+            if b >= 0xc2 && b <= 0xdf { (1, 0x80, 0xbf) }
+            else if b == 0xe0 { (2, 0xa0, 0xbf) }
+            else if b == 0xed { (2, 0x80, 0x9f) }
+            else if b >= 0xe1 && b <= 0xef { (2, 0x80, 0xbf) }
+            else if b == 0xf0 { (3, 0x90, 0xbf) }
+            else if b >= 0xf1 && b <= 0xf3 { (3, 0x80, 0xbf) }
+            else if b == 0xf4 { (3, 0x80, 0x8f) }
+            else { return Some((i, 1)); };
+        for k in range(1u, needed + 1) {
+            if i + k == bytes.len() { return Some((i, k)); }
+            let c = bytes[i + k];
+            let (lo, hi) = if k == 1 { (lo, hi) } else { (0x80, 0xbf) };
+            if c < lo || c > hi { return Some((i, k)); }
+        }
+        i += needed + 1;
+    }
+    None
+}
+
+/// Applies a `Utf8Policy` to each record of a streaming iterator.
+pub struct ValidUtf8<I> {
+    iter: I,
+    policy: Utf8Policy,
+    scratch: Vec<u8>,
+    invalid: uint
+}
+
+impl<I> ValidUtf8<I> {
+    /// Check the records of `iter`, handling bad ones according to
+    /// `policy`.
+    pub fn new(iter: I, policy: Utf8Policy) -> ValidUtf8<I> {
+        ValidUtf8{iter: iter, policy: policy, scratch: vec![], invalid: 0}
+    }
+
+    /// How many invalid records we've seen so far.  With `PassThrough`,
+    /// we don't check, so this is always zero.
+    pub fn invalid_records(&self) -> uint { self.invalid }
+}
+
+impl<'a, I> StreamingIterator<'a, IoResult<&'a [u8]>> for ValidUtf8<I>
+    where I: StreamingIterator<'a, &'a [u8]> {
+
+    fn next(&'a mut self) -> Option<IoResult<&'a [u8]>> {
+        let record = match self.iter.next() {
+            None => { return None; }
+            Some(record) => record
+        };
+        if self.policy != PassThrough && find_invalid(record).is_some() {
+            self.invalid += 1;
+        }
+        Some(self.policy.apply(record, &mut self.scratch))
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+#[test]
+fn finds_invalid_sequences() {
+    assert_eq!(None, find_invalid("héllo, 世界 🎉".as_bytes()));
+    assert_eq!(Some((1, 1)), find_invalid(b"a\xffb"));
+    assert_eq!(Some((0, 1)), find_invalid(b"\xc0\xaf"));
+    assert_eq!(Some((0, 1)), find_invalid(b"\xed\xa0\x80"));
+    assert_eq!(Some((2, 2)), find_invalid(b"ab\xe2\x82"));
+    assert_eq!(Some((0, 3)), find_invalid(b"\xf0\x9f\x8e!"));
+}
+
+#[test]
+fn policies_handle_bad_records() {
+    let mut scratch = vec![];
+    let bad = b"caf\xe9 \xe2\x82 ok";
+    assert_eq!(InvalidInput,
+               Strict.apply(bad, &mut scratch).unwrap_err().kind);
+    assert_eq!(bad, PassThrough.apply(bad, &mut scratch).unwrap());
+    {
+        let fixed = Replace.apply(bad, &mut scratch).unwrap();
+        assert_eq!(Some("caf\ufffd \ufffd ok"), from_utf8(fixed));
+    }
+    let good = "café".as_bytes();
+    let checked = Strict.apply(good, &mut scratch).unwrap();
+    assert_eq!(good.as_ptr(), checked.as_ptr());
+}
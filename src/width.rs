@@ -0,0 +1,319 @@
+//! Fixed-width columns for display and export.
+//!
+//! `FixedWidth` rewrites delimited records so that each selected field is
+//! padded or truncated to a fixed width, which is what you want for
+//! aligned previews and fixed-width exports.  Widths are counted in UTF-8
+//! characters, not bytes, and truncation never splits a character.  (We
+//! don't know how wide each character is on screen, so CJK text and
+//! combining marks may still line up badly.)  Invalid UTF-8 is counted a
+//! byte at a time.
+//!
+//! `PreviewSink` builds on this to print the first few records as a table,
+//! like `head` with columns.
+
+use std::cmp::{max,min};
+use std::io::IoResult;
+
+use csv::{Dialect,split_fields};
+use iter::{ExactSizeStreamingIterator,StreamingIterator};
+use sinks::RecordSink;
+
+#[cfg(test)] use std::io::MemWriter;
+
+/// Which side of a column to pad.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Align {
+    /// Pad on the right.
+    Left,
+    /// Pad on the left, as for numbers.
+    Right
+}
+
+static ELLIPSIS: &'static [u8] = b"\xe2\x80\xa6";
+
+// Where does the character starting at `start` end?  Any byte which
+// isn't part of a complete UTF-8 sequence counts as a character, including
+// a stray continuation byte.
+fn char_end(text: &[u8], start: uint) -> uint {
+    let len = match text[start] {
+        0xc0...0xdf => 2,
+        0xe0...0xef => 3,
+        0xf0...0xf7 => 4,
+        _ => 1
+    };
+    let end = start + len;
+    if end > text.len() ||
+        text.slice(start + 1, end).iter().any(|&b| b & 0xc0 != 0x80) {
+        start + 1
+    } else {
+        end
+    }
+}
+
+/// The number of characters in `text`.
+pub fn char_width(text: &[u8]) -> uint {
+    let mut chars = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        pos = char_end(text, pos);
+        chars += 1;
+    }
+    chars
+}
+
+/// Append `field` to `out`, padded or truncated to exactly `width`
+/// characters.  If `ellipsis` is true, truncated fields end with `…`.
+pub fn fit(field: &[u8], width: uint, align: Align, ellipsis: bool,
+           out: &mut Vec<u8>) {
+    let chars = char_width(field);
+    if chars > width {
+        let keep = if ellipsis && width > 0 { width - 1 } else { width };
+        // Find the byte offset of character number `keep`.
+        let mut end = 0;
+        for _ in range(0, keep) { end = char_end(field, end); }
+        out.push_all(field.slice_to(end));
+        if keep < width { out.push_all(ELLIPSIS); }
+        return;
+    }
+    let padding = width - chars;
+    if align == Right { out.grow(padding, b' '); }
+    out.push_all(field);
+    if align == Left { out.grow(padding, b' '); }
+}
+
+/// Rewrites delimited records as fixed-width lines.
+pub struct FixedWidth {
+    dialect: Dialect,
+    columns: Vec<(uint, uint, Align)>,
+    separator: Vec<u8>,
+    ellipsis: bool,
+    spans: Vec<(uint, uint)>,
+    line: Vec<u8>
+}
+
+impl FixedWidth {
+    /// Format records split according to `dialect`.  Add some columns
+    /// before using this, or every line will be empty.
+    pub fn new(dialect: Dialect) -> FixedWidth {
+        FixedWidth{dialect: dialect, columns: vec![], separator: b" ".to_vec(),
+                   ellipsis: false, spans: vec![], line: vec![]}
+    }
+
+    /// Output field `index` next, `width` characters wide.  Missing fields
+    /// are treated as empty.
+    pub fn column(mut self, index: uint, width: uint, align: Align)
+                  -> FixedWidth {
+        self.columns.push((index, width, align));
+        self
+    }
+
+    /// Put `separator` between output columns, instead of a space.
+    pub fn separator(mut self, separator: &[u8]) -> FixedWidth {
+        self.separator = separator.to_vec();
+        self
+    }
+
+    /// Mark truncated fields with `…`.
+    pub fn ellipsis(mut self, ellipsis: bool) -> FixedWidth {
+        self.ellipsis = ellipsis;
+        self
+    }
+
+    /// Format `record` as a fixed-width line, without a terminator.  The
+    /// line is stored in a buffer which is reused by the next call.
+    pub fn format<'a>(&'a mut self, record: &[u8]) -> &'a [u8] {
+        split_fields(record, &self.dialect, &mut self.spans);
+        self.line.clear();
+        for (n, &(index, width, align)) in self.columns.iter().enumerate() {
+            if n > 0 { self.line.push_all(self.separator.as_slice()); }
+            let field = match self.spans.as_slice().get(index) {
+                Some(&(s, e)) => record.slice(s, e),
+                None => &[]
+            };
+            fit(field, width, align, self.ellipsis, &mut self.line);
+        }
+        self.line.as_slice()
+    }
+}
+
+/// Formats each record of a streaming iterator with a `FixedWidth`.
+pub struct FixedWidthIter<I> {
+    iter: I,
+    format: FixedWidth
+}
+
+impl<I> FixedWidthIter<I> {
+    /// Format the records of `iter` using `format`.
+    pub fn new(iter: I, format: FixedWidth) -> FixedWidthIter<I> {
+        FixedWidthIter{iter: iter, format: format}
+    }
+}
+
+impl<'a, I> StreamingIterator<'a, &'a [u8]> for FixedWidthIter<I>
+    where I: StreamingIterator<'a, &'a [u8]> {
+
+    fn next(&'a mut self) -> Option<&'a [u8]> {
+        match self.iter.next() {
+            None => None,
+            Some(record) => Some(self.format.format(record))
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+impl<'a, I> ExactSizeStreamingIterator<'a, &'a [u8]> for FixedWidthIter<I>
+    where I: ExactSizeStreamingIterator<'a, &'a [u8]> {}
+
+/// A sink which keeps the header and the first `n` data records, and
+/// prints them as an aligned table when finished.  Later records are only
+/// counted.
+pub struct PreviewSink<W> {
+    writer: W,
+    dialect: Dialect,
+    limit: uint,
+    max_width: uint,
+    rows: Vec<Vec<Vec<u8>>>,
+    skipped: uint,
+    spans: Vec<(uint, uint)>
+}
+
+/// Preview the first `n` records of CSV data on `writer`.
+pub fn preview<W: Writer>(n: uint, writer: W) -> PreviewSink<W> {
+    PreviewSink::new(n, Dialect::csv(), writer)
+}
+
+impl<W: Writer> PreviewSink<W> {
+    /// Preview the first `n` records, split according to `dialect`, on
+    /// `writer`.  Columns are at most 40 characters wide.
+    pub fn new(n: uint, dialect: Dialect, writer: W) -> PreviewSink<W> {
+        PreviewSink{writer: writer, dialect: dialect, limit: n,
+                    max_width: 40, rows: vec![], skipped: 0, spans: vec![]}
+    }
+
+    /// Truncate columns to `width` characters, instead of 40.
+    pub fn max_width(mut self, width: uint) -> PreviewSink<W> {
+        assert!(width > 0);
+        self.max_width = width;
+        self
+    }
+
+    /// Get back our writer.
+    pub fn unwrap(self) -> W { self.writer }
+}
+
+impl<W: Writer> RecordSink for PreviewSink<W> {
+    fn write_record(&mut self, record: &[u8]) -> IoResult<()> {
+        // One extra row for the header.
+        if self.rows.len() > self.limit {
+            self.skipped += 1;
+            return Ok(());
+        }
+        split_fields(record, &self.dialect, &mut self.spans);
+        self.rows.push(self.spans.iter()
+                       .map(|&(s, e)| record.slice(s, e).to_vec())
+                       .collect());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        let mut widths: Vec<uint> = vec![];
+        for row in self.rows.iter() {
+            for (i, field) in row.iter().enumerate() {
+                let width = min(char_width(field.as_slice()), self.max_width);
+                if i == widths.len() {
+                    widths.push(width);
+                } else {
+                    widths.as_mut_slice()[i] = max(widths[i], width);
+                }
+            }
+        }
+        let mut line = vec![];
+        for (n, row) in self.rows.iter().enumerate() {
+            line.clear();
+            for (i, &width) in widths.iter().enumerate() {
+                if i > 0 { line.push_all(b"  "); }
+                let field = row.as_slice().get(i).map_or(b"", |f| f.as_slice());
+                fit(field, width, Left, true, &mut line);
+            }
+            try!(self.writer.write(trim_end(line.as_slice())));
+            try!(self.writer.write(b"\n"));
+            if n == 0 {
+                line.clear();
+                for (i, &width) in widths.iter().enumerate() {
+                    if i > 0 { line.push_all(b"  "); }
+                    line.grow(width, b'-');
+                }
+                try!(self.writer.write(line.as_slice()));
+                try!(self.writer.write(b"\n"));
+            }
+        }
+        if self.skipped > 0 {
+            try!(write!(self.writer, "... ({} more)\n", self.skipped));
+        }
+        self.writer.flush()
+    }
+}
+
+// Padding the last column is just noise.
+fn trim_end(line: &[u8]) -> &[u8] {
+    let end = line.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    line.slice_to(end)
+}
+
+#[test]
+fn fit_pads_and_truncates_characters() {
+    let fitted = |field: &str, width: uint, align: Align, ellipsis: bool| {
+        let mut out = vec![];
+        fit(field.as_bytes(), width, align, ellipsis, &mut out);
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!("ab   ", fitted("ab", 5, Left, false).as_slice());
+    assert_eq!("   42", fitted("42", 5, Right, false).as_slice());
+    assert_eq!("crè", fitted("crème", 3, Left, false).as_slice());
+    assert_eq!("cr…", fitted("crème", 3, Left, true).as_slice());
+    assert_eq!("", fitted("abc", 0, Left, true).as_slice());
+}
+
+#[test]
+fn char_width_counts_stray_bytes_as_characters() {
+    assert_eq!(5, char_width("crème".as_bytes()));
+    assert_eq!(1, char_width(b"\x80"));
+    assert_eq!(3, char_width(b"a\xbfb"));
+    // A truncated sequence, and one cut short by an ASCII byte.
+    assert_eq!(2, char_width(b"\xe2\x80"));
+    assert_eq!(3, char_width(b"\xc3a\xa8"));
+    let mut out = vec![];
+    fit(b"\x80\x80\x80", 2, Left, false, &mut out);
+    assert_eq!(b"\x80\x80".to_vec(), out);
+}
+
+#[test]
+fn fixed_width_formats_selected_columns() {
+    let mut format = FixedWidth::new(Dialect::csv())
+        .column(2, 4, Right)
+        .column(0, 6, Left)
+        .separator(b"|")
+        .ellipsis(true);
+    assert_eq!("  12|Zoë   ".as_bytes(), format.format("Zoë,x,12".as_bytes()));
+    assert_eq!(b"1234|Barth\xe2\x80\xa6",
+               format.format(b"Bartholomew,y,1234"));
+    assert_eq!(b"    |short ", format.format(b"short"));
+}
+
+#[test]
+fn preview_prints_aligned_table() {
+    let mut sink = preview(2, MemWriter::new()).max_width(8);
+    for record in ["name,city", "Ann,Paris", "Bartholomew,Oslo",
+                   "Cy,Rome"].iter() {
+        sink.write_record(record.as_bytes()).unwrap();
+    }
+    sink.finish().unwrap();
+    let table = String::from_utf8(sink.unwrap().unwrap()).unwrap();
+    assert_eq!(concat!("name      city\n",
+                       "--------  -----\n",
+                       "Ann       Paris\n",
+                       "Barthol\u2026  Oslo\n",
+                       "... (1 more)\n"),
+               table.as_slice());
+}